@@ -0,0 +1,71 @@
+// Times the tree-walking evaluator against the bytecode VM on a recursive fibonacci, and on a
+// loop that spends its time looking up variables through nested scopes. Programs are resolved and
+// folded first, as the command line does before running either engine.
+// Run with `cargo run --release --example benchmark`.
+use rmonk::compiler::Compiler;
+use rmonk::evaluator::Evaluator;
+use rmonk::lexer;
+use rmonk::object::environment::Environment;
+use rmonk::parser::Parser;
+use rmonk::resolver::Resolver;
+use rmonk::vm::VM;
+use std::time::Instant;
+
+const FIBONACCI: &str = "let fibonacci = fn(x) {
+    if (x < 2) { x } else { fibonacci(x - 1) + fibonacci(x - 2) }
+};
+fibonacci(30);";
+
+const LOOKUPS: &str = "let configuration_multiplier = 3;
+let accumulated_total_value = 0;
+let outer = fn(first_parameter_name) {
+    let middle = fn(second_parameter_name) {
+        let inner = fn(third_parameter_name) {
+            first_parameter_name + second_parameter_name * third_parameter_name
+                + configuration_multiplier
+        };
+        inner(second_parameter_name) + inner(first_parameter_name)
+    };
+    middle(first_parameter_name)
+};
+let loop_iteration_counter = 0;
+while (loop_iteration_counter < 300000) {
+    accumulated_total_value += outer(loop_iteration_counter % 7);
+    loop_iteration_counter += 1;
+}
+accumulated_total_value;";
+
+fn main() {
+    bench("fibonacci", FIBONACCI);
+    bench("lookups", LOOKUPS);
+}
+
+fn bench(name: &str, input: &str) {
+    let mut p = Parser::new(lexer::new(String::from(input)));
+    let mut program = p.parse_program();
+    Resolver::new()
+        .resolve_and_fold(&mut program, true)
+        .expect("benchmark doesn't resolve");
+
+    let started = Instant::now();
+    let result = Evaluator::new().eval_program(&program, &Environment::new());
+    println!(
+        "benchmark={}, engine=eval, result={}, duration={:?}",
+        name,
+        result.inspect(),
+        started.elapsed()
+    );
+
+    let started = Instant::now();
+    let mut compiler = Compiler::new();
+    compiler
+        .compile(&program)
+        .expect("benchmark doesn't compile");
+    let result = VM::new().run(&compiler.bytecode());
+    println!(
+        "benchmark={}, engine=vm, result={}, duration={:?}",
+        name,
+        result.inspect(),
+        started.elapsed()
+    );
+}
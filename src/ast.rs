@@ -1,16 +1,26 @@
+use crate::bigint::BigInt;
+use crate::symbol::Symbol;
 use crate::token;
-use std::collections::HashMap;
+use std::any::Any;
+use std::cell::OnceCell;
+use std::rc::Rc;
 
 pub trait Statement {
     fn token_literal(&self) -> String;
+    fn span(&self) -> token::Span;
     fn string(&self) -> String;
     fn statement_node(&self);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
 }
 
 pub trait Expression {
     fn token_literal(&self) -> String;
+    fn span(&self) -> token::Span;
     fn string(&self) -> String;
     fn expression_node(&self);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
 }
 
 pub struct Program {
@@ -19,22 +29,37 @@ pub struct Program {
 
 impl Statement for Program {
     fn token_literal(&self) -> String {
-        if self.statements.len() > 0 {
-            return self.statements[0].token_literal();
+        if !self.statements.is_empty() {
+            self.statements[0].token_literal()
         } else {
-            return String::from("");
+            String::from("")
+        }
+    }
+
+    fn span(&self) -> token::Span {
+        match self.statements.first() {
+            Some(stmt) => stmt.span(),
+            None => token::Span::default(),
         }
     }
     fn string(&self) -> String {
         let mut ret = String::new();
 
         for stmt in self.statements.iter() {
-            ret = String::from(format!("{}{}\n", ret, stmt.string()));
+            ret = format!("{}{}\n", ret, stmt.string());
         }
 
         ret
     }
     fn statement_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 // STATEMENTS
@@ -47,21 +72,31 @@ pub struct LetStatement {
 
 impl Statement for LetStatement {
     fn token_literal(&self) -> String {
-        self.token.literal.clone()
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
     }
 
     fn string(&self) -> String {
-        let ret = String::from(format!(
+        format!(
             "{} {} = {};",
             self.token_literal(),
             self.name.string(),
             self.value.string(),
-        ));
-
-        ret
+        )
     }
 
     fn statement_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 pub struct ReturnStatement {
@@ -71,16 +106,26 @@ pub struct ReturnStatement {
 
 impl Statement for ReturnStatement {
     fn token_literal(&self) -> String {
-        self.token.literal.clone()
+        self.token.literal.to_string()
     }
 
-    fn string(&self) -> String {
-        let ret = String::from(format!("{} {};", self.token_literal(), self.value.string()));
+    fn span(&self) -> token::Span {
+        self.token.span
+    }
 
-        ret
+    fn string(&self) -> String {
+        format!("{} {};", self.token_literal(), self.value.string())
     }
 
     fn statement_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 pub struct ExpressionStatement {
@@ -90,7 +135,11 @@ pub struct ExpressionStatement {
 
 impl Statement for ExpressionStatement {
     fn token_literal(&self) -> String {
-        self.token.literal.clone()
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
     }
 
     fn string(&self) -> String {
@@ -98,6 +147,14 @@ impl Statement for ExpressionStatement {
     }
 
     fn statement_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 pub struct BlockStatement {
@@ -107,7 +164,11 @@ pub struct BlockStatement {
 
 impl Statement for BlockStatement {
     fn token_literal(&self) -> String {
-        self.token.literal.clone()
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
     }
 
     fn string(&self) -> String {
@@ -120,25 +181,290 @@ impl Statement for BlockStatement {
     }
 
     fn statement_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+pub struct AssignStatement {
+    pub token: token::Token,
+    pub target: Box<dyn Expression>,
+    pub operator: Symbol,
+    pub value: Box<dyn Expression>,
+}
+
+impl Statement for AssignStatement {
+    fn token_literal(&self) -> String {
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
+    }
+
+    fn string(&self) -> String {
+        format!(
+            "{} {} {};",
+            self.target.string(),
+            self.operator,
+            self.value.string()
+        )
+    }
+
+    fn statement_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+pub struct WhileStatement {
+    pub token: token::Token,
+    pub condition: Box<dyn Expression>,
+    pub body: Box<BlockStatement>,
+}
+
+impl Statement for WhileStatement {
+    fn token_literal(&self) -> String {
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
+    }
+
+    fn string(&self) -> String {
+        format!("while {} {}", self.condition.string(), self.body.string())
+    }
+
+    fn statement_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+pub struct ForStatement {
+    pub token: token::Token,
+    pub variable: Identifier,
+    pub iterable: Box<dyn Expression>,
+    pub body: Box<BlockStatement>,
+}
+
+impl Statement for ForStatement {
+    fn token_literal(&self) -> String {
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
+    }
+
+    fn string(&self) -> String {
+        format!(
+            "for ({} in {}) {}",
+            self.variable.string(),
+            self.iterable.string(),
+            self.body.string()
+        )
+    }
+
+    fn statement_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+pub struct ThrowStatement {
+    pub token: token::Token,
+    pub value: Box<dyn Expression>,
+}
+
+impl Statement for ThrowStatement {
+    fn token_literal(&self) -> String {
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
+    }
+
+    fn string(&self) -> String {
+        format!("{} {};", self.token_literal(), self.value.string())
+    }
+
+    fn statement_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+pub struct TryStatement {
+    pub token: token::Token,
+    pub body: Box<BlockStatement>,
+    pub parameter: Identifier,
+    pub handler: Box<BlockStatement>,
+}
+
+impl Statement for TryStatement {
+    fn token_literal(&self) -> String {
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
+    }
+
+    fn string(&self) -> String {
+        format!(
+            "try {} catch ({}) {}",
+            self.body.string(),
+            self.parameter.string(),
+            self.handler.string()
+        )
+    }
+
+    fn statement_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+pub struct BreakStatement {
+    pub token: token::Token,
+}
+
+impl Statement for BreakStatement {
+    fn token_literal(&self) -> String {
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
+    }
+
+    fn string(&self) -> String {
+        format!("{};", self.token_literal())
+    }
+
+    fn statement_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+pub struct ContinueStatement {
+    pub token: token::Token,
+}
+
+impl Statement for ContinueStatement {
+    fn token_literal(&self) -> String {
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
+    }
+
+    fn string(&self) -> String {
+        format!("{};", self.token_literal())
+    }
+
+    fn statement_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 // EXPRESSIONS
 
+#[derive(Clone)]
 pub struct Identifier {
     pub token: token::Token,
-    pub value: String,
+    pub value: Symbol,
+    // Filled in by the resolver. Until then the name is looked up by its text.
+    pub binding: OnceCell<Binding>,
+}
+
+// Where a name is found at runtime.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Binding {
+    // A builtin function, by its place in the builtin table.
+    Builtin(usize),
+    // A top-level name, looked up by its text.
+    Global,
+    // The slots of enclosing scopes that may hold the name, innermost first. A `let` only binds
+    // its name once it runs, so when none of them holds it yet the name is looked up globally.
+    Local(Vec<Slot>),
+}
+
+// A slot `depth` scopes out from where the name is used. Depth 0 is the current scope.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
 }
 
 impl Expression for Identifier {
     fn token_literal(&self) -> String {
-        self.token.literal.clone()
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
     }
 
     fn string(&self) -> String {
-        self.value.clone()
+        self.value.to_string()
     }
 
     fn expression_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 pub struct IntegerLiteral {
@@ -148,32 +474,85 @@ pub struct IntegerLiteral {
 
 impl Expression for IntegerLiteral {
     fn token_literal(&self) -> String {
-        self.token.literal.clone()
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
     }
 
     fn string(&self) -> String {
-        self.token.literal.clone()
+        self.token.literal.to_string()
     }
 
     fn expression_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// An integer literal too large for i64.
+pub struct BigIntegerLiteral {
+    pub token: token::Token,
+    pub value: Rc<BigInt>,
+}
+
+impl Expression for BigIntegerLiteral {
+    fn token_literal(&self) -> String {
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
+    }
+
+    fn string(&self) -> String {
+        self.token.literal.to_string()
+    }
+
+    fn expression_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 pub struct StringLiteral {
     pub token: token::Token,
-    pub value: String,
+    pub value: Symbol,
 }
 
 impl Expression for StringLiteral {
     fn token_literal(&self) -> String {
-        self.token.literal.clone()
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
     }
 
     fn string(&self) -> String {
-        let ret = String::from(format!("\"{}\"", self.token.literal.clone()));
-        ret
+        format!("\"{}\"", self.token.literal)
     }
 
     fn expression_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 pub struct ArrayLiteral {
@@ -183,77 +562,100 @@ pub struct ArrayLiteral {
 
 impl Expression for ArrayLiteral {
     fn token_literal(&self) -> String {
-        self.token.literal.clone()
+        self.token.literal.to_string()
     }
 
-    fn string(&self) -> String {
-        let mut ret = String::new();
+    fn span(&self) -> token::Span {
+        self.token.span
+    }
 
-        ret.push_str("[");
+    fn string(&self) -> String {
+        let elements: Vec<String> = self.elements.iter().map(|e| e.string()).collect();
 
-        for elem in self.elements.iter() {
-            ret.push_str(format!("{}, ", elem.string()).as_str());
-        }
+        format!("[{}]", elements.join(", "))
+    }
 
-        ret.push_str("]");
+    fn expression_node(&self) {}
 
-        ret
+    fn as_any(&self) -> &dyn Any {
+        self
     }
 
-    fn expression_node(&self) {}
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 pub struct FunctionLiteral {
     pub token: token::Token,
-    pub parameters: Vec<Box<Identifier>>,
-    pub body: Box<BlockStatement>,
+    pub parameters: Vec<Identifier>,
+    pub body: Rc<BlockStatement>,
 }
 
 impl Expression for FunctionLiteral {
     fn token_literal(&self) -> String {
-        self.token.literal.clone()
+        self.token.literal.to_string()
     }
 
-    fn string(&self) -> String {
-        let mut ret = String::new();
+    fn span(&self) -> token::Span {
+        self.token.span
+    }
 
-        ret.push_str(format!("{}(", self.token_literal()).as_str());
+    fn string(&self) -> String {
+        let params: Vec<String> = self.parameters.iter().map(|p| p.string()).collect();
 
-        for param in self.parameters.iter() {
-            ret.push_str(format!("{}, ", param.string()).as_str());
-        }
+        format!(
+            "{}({}) {}",
+            self.token_literal(),
+            params.join(", "),
+            self.body.string()
+        )
+    }
 
-        ret.push_str(format!(") {}", self.body.string()).as_str());
+    fn expression_node(&self) {}
 
-        ret
+    fn as_any(&self) -> &dyn Any {
+        self
     }
 
-    fn expression_node(&self) {}
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 pub struct HashLiteral {
     pub token: token::Token,
-    pub pairs: HashMap<Box<dyn Expression>, Box<dyn Expression>>,
+    pub pairs: Vec<(Box<dyn Expression>, Box<dyn Expression>)>,
 }
 
 impl Expression for HashLiteral {
     fn token_literal(&self) -> String {
-        self.token.literal.clone()
+        self.token.literal.to_string()
     }
 
-    fn string(&self) -> String {
-        let mut ret = String::new();
+    fn span(&self) -> token::Span {
+        self.token.span
+    }
 
-        ret.push_str("{");
-        for (k, v) in self.pairs.iter() {
-            ret.push_str(format!("{}: {}, ", k.string(), v.string()).as_str());
-        }
-        ret.push_str("{");
+    fn string(&self) -> String {
+        let pairs: Vec<String> = self
+            .pairs
+            .iter()
+            .map(|(k, v)| format!("{}: {}", k.string(), v.string()))
+            .collect();
 
-        ret
+        format!("{{{}}}", pairs.join(", "))
     }
 
     fn expression_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 pub struct Boolean {
@@ -263,44 +665,106 @@ pub struct Boolean {
 
 impl Expression for Boolean {
     fn token_literal(&self) -> String {
-        self.token.literal.clone()
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
+    }
+
+    fn string(&self) -> String {
+        self.token.literal.to_string()
+    }
+
+    fn expression_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+pub struct NullLiteral {
+    pub token: token::Token,
+}
+
+impl Expression for NullLiteral {
+    fn token_literal(&self) -> String {
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
     }
 
     fn string(&self) -> String {
-        self.token.literal.clone()
+        self.token.literal.to_string()
     }
 
     fn expression_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 pub struct IfExpression {
     pub token: token::Token,
     pub condition: Box<dyn Expression>,
     pub consequence: Box<BlockStatement>,
-    pub alternative: Option<Box<BlockStatement>>,
+    pub alternative: Option<Alternative>,
+}
+
+// The branch taken when an `if` condition is falsy: either a plain `else { ... }` block or
+// another `if` for `else if` chains.
+pub enum Alternative {
+    Block(Box<BlockStatement>),
+    If(Box<IfExpression>),
 }
 
 impl Expression for IfExpression {
     fn token_literal(&self) -> String {
-        self.token.literal.clone()
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
     }
 
     fn string(&self) -> String {
-        let mut ret = String::from(format!(
+        let mut ret = format!(
             "if {} {}",
             self.condition.string(),
             self.consequence.string()
-        ));
+        );
 
         match &self.alternative {
-            Some(block) => ret.push_str(format!("else {}", block.string()).as_str()),
-            None => return ret,
+            Some(Alternative::Block(block)) => {
+                ret.push_str(format!("else {}", block.string()).as_str())
+            }
+            Some(Alternative::If(ie)) => ret.push_str(format!("else {}", ie.string()).as_str()),
+            None => (),
         }
 
         ret
     }
 
     fn expression_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 pub struct CallExpression {
@@ -311,77 +775,94 @@ pub struct CallExpression {
 
 impl Expression for CallExpression {
     fn token_literal(&self) -> String {
-        self.token.literal.clone()
+        self.token.literal.to_string()
     }
 
-    fn string(&self) -> String {
-        let mut ret = String::new();
+    fn span(&self) -> token::Span {
+        self.token.span
+    }
 
-        ret.push_str(format!("{}(", self.function.string()).as_str());
+    fn string(&self) -> String {
+        let args: Vec<String> = self.arguments.iter().map(|a| a.string()).collect();
 
-        for arg in self.arguments.iter() {
-            ret.push_str(format!("{} ,", arg.string()).as_str());
-        }
+        format!("{}({})", self.function.string(), args.join(", "))
+    }
 
-        ret.push_str(")");
+    fn expression_node(&self) {}
 
-        ret
+    fn as_any(&self) -> &dyn Any {
+        self
     }
 
-    fn expression_node(&self) {}
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 pub struct PrefixExpression {
     pub token: token::Token,
-    pub operator: String,
+    pub operator: Symbol,
     pub right: Box<dyn Expression>,
 }
 
 impl Expression for PrefixExpression {
     fn token_literal(&self) -> String {
-        self.token.literal.clone()
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
     }
 
     fn string(&self) -> String {
-        let mut ret = String::new();
+        format!("({}{})", self.operator, self.right.string())
+    }
 
-        ret.push_str(format!("({}{})", self.operator, self.right.string()).as_str());
+    fn expression_node(&self) {}
 
-        ret
+    fn as_any(&self) -> &dyn Any {
+        self
     }
 
-    fn expression_node(&self) {}
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 pub struct InfixExpression {
     pub token: token::Token,
-    pub operator: String,
+    pub operator: Symbol,
     pub left: Box<dyn Expression>,
     pub right: Box<dyn Expression>,
 }
 
 impl Expression for InfixExpression {
     fn token_literal(&self) -> String {
-        self.token.literal.clone()
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
     }
 
     fn string(&self) -> String {
-        let mut ret = String::new();
+        format!(
+            "({} {} {})",
+            self.left.string(),
+            self.operator,
+            self.right.string()
+        )
+    }
 
-        ret.push_str(
-            format!(
-                "({} {} {})",
-                self.left.string(),
-                self.operator,
-                self.right.string()
-            )
-            .as_str(),
-        );
+    fn expression_node(&self) {}
 
-        ret
+    fn as_any(&self) -> &dyn Any {
+        self
     }
 
-    fn expression_node(&self) {}
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 pub struct IndexExpression {
@@ -392,18 +873,75 @@ pub struct IndexExpression {
 
 impl Expression for IndexExpression {
     fn token_literal(&self) -> String {
-        self.token.literal.clone()
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
     }
 
     fn string(&self) -> String {
-        let mut ret = String::new();
+        format!("({}[{}])", self.left.string(), self.index.string())
+    }
 
-        ret.push_str(format!("{}[{}]", self.left.string(), self.index.string()).as_str());
+    fn expression_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+pub struct SliceExpression {
+    pub token: token::Token,
+    pub left: Box<dyn Expression>,
+    pub start: Option<Box<dyn Expression>>,
+    pub end: Option<Box<dyn Expression>>,
+    pub step: Option<Box<dyn Expression>>,
+}
+
+impl Expression for SliceExpression {
+    fn token_literal(&self) -> String {
+        self.token.literal.to_string()
+    }
+
+    fn span(&self) -> token::Span {
+        self.token.span
+    }
+
+    fn string(&self) -> String {
+        let bound = |b: &Option<Box<dyn Expression>>| match b {
+            Some(expr) => expr.string(),
+            None => String::new(),
+        };
+
+        let mut ret = format!(
+            "({}[{}:{}",
+            self.left.string(),
+            bound(&self.start),
+            bound(&self.end)
+        );
+
+        if self.step.is_some() {
+            ret.push_str(format!(":{}", bound(&self.step)).as_str());
+        }
+        ret.push_str("])");
 
         ret
     }
 
     fn expression_node(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 #[cfg(test)]
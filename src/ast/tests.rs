@@ -1,5 +1,6 @@
 use super::*;
 use crate::token;
+use std::cell::OnceCell;
 
 #[test]
 fn test_string() {
@@ -9,22 +10,26 @@ fn test_string() {
                 token: token::Token::new(token::LET, String::from("let")),
                 name: Box::new(Identifier {
                     token: token::Token::new(token::IDENT, String::from("myVar")),
-                    value: String::from("myVar"),
+                    value: Symbol::intern("myVar"),
+                    binding: OnceCell::new(),
                 }),
                 value: Box::new(Identifier {
                     token: token::Token::new(token::IDENT, String::from("anotherVar")),
-                    value: String::from("anotherVar"),
+                    value: Symbol::intern("anotherVar"),
+                    binding: OnceCell::new(),
                 }),
             }),
             Box::new(LetStatement {
                 token: token::Token::new(token::LET, String::from("let")),
                 name: Box::new(Identifier {
                     token: token::Token::new(token::IDENT, String::from("myVar")),
-                    value: String::from("myVar"),
+                    value: Symbol::intern("myVar"),
+                    binding: OnceCell::new(),
                 }),
                 value: Box::new(Identifier {
                     token: token::Token::new(token::IDENT, String::from("anotherVar")),
-                    value: String::from("anotherVar"),
+                    value: Symbol::intern("anotherVar"),
+                    binding: OnceCell::new(),
                 }),
             }),
         ],
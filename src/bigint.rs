@@ -0,0 +1,408 @@
+#[cfg(test)]
+mod tests;
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+// An integer of any size, for values past the range of i64. Kept as a sign and a magnitude in
+// base 2^32 digits, least significant first and with no zero digits on top, so every value has
+// exactly one representation and the derived equality and hashing are right. Zero is positive.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    digits: Vec<u32>,
+}
+
+const BASE: u64 = 1 << 32;
+
+// The largest power of ten in a digit, for converting to and from decimal nine digits at a time.
+const DECIMAL_CHUNK: u32 = 1_000_000_000;
+const DECIMAL_CHUNK_DIGITS: usize = 9;
+
+impl BigInt {
+    fn new(negative: bool, digits: Vec<u32>) -> BigInt {
+        let mut digits = digits;
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+
+        BigInt {
+            negative: negative && !digits.is_empty(),
+            digits,
+        }
+    }
+
+    // Decimal digits with an optional leading `-`.
+    pub fn parse(text: &str) -> Option<BigInt> {
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        if text.is_empty() || !text.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        let mut digits = Vec::new();
+        let first = match text.len() % DECIMAL_CHUNK_DIGITS {
+            0 => DECIMAL_CHUNK_DIGITS,
+            n => n,
+        };
+        let mut start = 0;
+        let mut end = first;
+        while start < text.len() {
+            let chunk: u32 = text[start..end].parse().ok()?;
+            mul_add_small(&mut digits, 10u32.pow((end - start) as u32), chunk);
+            start = end;
+            end += DECIMAL_CHUNK_DIGITS;
+        }
+
+        Some(BigInt::new(negative, digits))
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.digits.len() > 2 {
+            return None;
+        }
+
+        let magnitude = self
+            .digits
+            .iter()
+            .rev()
+            .fold(0u64, |acc, digit| (acc << 32) | *digit as u64);
+        if self.negative {
+            // -2^63 is the one magnitude that only fits negated.
+            (magnitude <= 1 << 63).then(|| (magnitude as i64).wrapping_neg())
+        } else {
+            i64::try_from(magnitude).ok()
+        }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    // Bits in the magnitude, not counting the sign.
+    pub fn bits(&self) -> usize {
+        match self.digits.last() {
+            Some(top) => self.digits.len() * 32 - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    // Truncating division, as i64's: the quotient rounds towards zero and the remainder takes
+    // the sign of the dividend. None when dividing by zero.
+    pub fn div_rem(&self, divisor: &BigInt) -> Option<(BigInt, BigInt)> {
+        if divisor.is_zero() {
+            return None;
+        }
+
+        let (quotient, remainder) = div_rem_digits(&self.digits, &divisor.digits);
+        Some((
+            BigInt::new(self.negative != divisor.negative, quotient),
+            BigInt::new(self.negative, remainder),
+        ))
+    }
+
+    pub fn pow(&self, exp: u64) -> BigInt {
+        let mut result = BigInt::from(1);
+        let mut base = self.clone();
+        let mut exp = exp;
+
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = &result * &base;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = &base * &base;
+            }
+        }
+
+        result
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(value: i64) -> BigInt {
+        let magnitude = value.unsigned_abs();
+        BigInt::new(value < 0, vec![magnitude as u32, (magnitude >> 32) as u32])
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_digits(&self.digits, &other.digits),
+            (true, true) => cmp_digits(&other.digits, &self.digits),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::new(!self.negative, self.digits.clone())
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(self.negative, add_digits(&self.digits, &other.digits));
+        }
+
+        // Opposite signs: the larger magnitude wins and keeps its sign.
+        match cmp_digits(&self.digits, &other.digits) {
+            Ordering::Less => BigInt::new(other.negative, sub_digits(&other.digits, &self.digits)),
+            _ => BigInt::new(self.negative, sub_digits(&self.digits, &other.digits)),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::new(
+            self.negative != other.negative,
+            mul_digits(&self.digits, &other.digits),
+        )
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return f.write_str("0");
+        }
+
+        let mut chunks = Vec::new();
+        let mut digits = self.digits.clone();
+        while !digits.is_empty() {
+            let (quotient, remainder) = div_rem_small(&digits, DECIMAL_CHUNK);
+            chunks.push(remainder);
+            digits = quotient;
+        }
+
+        if self.negative {
+            f.write_str("-")?;
+        }
+        let mut chunks = chunks.iter().rev();
+        if let Some(top) = chunks.next() {
+            write!(f, "{}", top)?;
+        }
+        for chunk in chunks {
+            write!(f, "{:0width$}", chunk, width = DECIMAL_CHUNK_DIGITS)?;
+        }
+
+        Ok(())
+    }
+}
+
+// The helpers below work on magnitudes, least significant digit first. Results may have zero
+// digits on top; BigInt::new trims them.
+
+fn cmp_digits(a: &[u32], b: &[u32]) -> Ordering {
+    let a = trimmed(a);
+    let b = trimmed(b);
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn trimmed(digits: &[u32]) -> &[u32] {
+    let len = digits.iter().rposition(|d| *d != 0).map_or(0, |i| i + 1);
+    &digits[..len]
+}
+
+fn add_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut sum = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+
+    for (i, digit) in long.iter().enumerate() {
+        let total = *digit as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+        sum.push(total as u32);
+        carry = total >> 32;
+    }
+    sum.push(carry as u32);
+
+    sum
+}
+
+// Needs a >= b.
+fn sub_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut difference = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+
+    for (i, digit) in a.iter().enumerate() {
+        let mut total = *digit as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+        if total < 0 {
+            total += BASE as i64;
+            borrow = 1;
+        }
+        difference.push(total as u32);
+    }
+
+    difference
+}
+
+fn mul_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut product = vec![0u32; a.len() + b.len()];
+
+    for (i, x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (digit, y) in product[i..i + b.len()].iter_mut().zip(b) {
+            let total = *x as u64 * *y as u64 + *digit as u64 + carry;
+            *digit = total as u32;
+            carry = total >> 32;
+        }
+        product[i + b.len()] = carry as u32;
+    }
+
+    product
+}
+
+fn mul_add_small(digits: &mut Vec<u32>, mul: u32, add: u32) {
+    let mut carry = add as u64;
+    for digit in digits.iter_mut() {
+        let total = *digit as u64 * mul as u64 + carry;
+        *digit = total as u32;
+        carry = total >> 32;
+    }
+    if carry > 0 {
+        digits.push(carry as u32);
+    }
+}
+
+fn div_rem_small(digits: &[u32], divisor: u32) -> (Vec<u32>, u32) {
+    let mut quotient = vec![0u32; digits.len()];
+    let mut remainder = 0u64;
+
+    for (i, digit) in digits.iter().enumerate().rev() {
+        let current = (remainder << 32) | *digit as u64;
+        quotient[i] = (current / divisor as u64) as u32;
+        remainder = current % divisor as u64;
+    }
+
+    let len = trimmed(&quotient).len();
+    quotient.truncate(len);
+    (quotient, remainder as u32)
+}
+
+// Long division, Knuth's Algorithm D.
+fn div_rem_digits(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    let a = trimmed(a);
+    let b = trimmed(b);
+
+    if cmp_digits(a, b) == Ordering::Less {
+        return (Vec::new(), a.to_vec());
+    }
+    if b.len() == 1 {
+        let (quotient, remainder) = div_rem_small(a, b[0]);
+        return (quotient, vec![remainder]);
+    }
+
+    // Shifting both so the divisor's top bit is set keeps each estimated quotient digit at most
+    // two too large.
+    let shift = b[b.len() - 1].leading_zeros();
+    let mut v = shift_left(b, shift);
+    v.pop();
+    let mut u = shift_left(a, shift);
+
+    let n = b.len();
+    let m = a.len() - n;
+    let mut quotient = vec![0u32; m + 1];
+    let top = v[n - 1] as u64;
+    let next = v[n - 2] as u64;
+
+    for j in (0..=m).rev() {
+        let numerator = ((u[j + n] as u64) << 32) | u[j + n - 1] as u64;
+        let mut qhat = numerator / top;
+        let mut rhat = numerator % top;
+        while qhat >= BASE || qhat * next > ((rhat << 32) | u[j + n - 2] as u64) {
+            qhat -= 1;
+            rhat += top;
+            if rhat >= BASE {
+                break;
+            }
+        }
+
+        // Subtract qhat times the divisor from the current window of u.
+        let mut borrow = 0i64;
+        let mut carry = 0u64;
+        for i in 0..n {
+            let product = qhat * v[i] as u64 + carry;
+            carry = product >> 32;
+            let total = u[i + j] as i64 - borrow - (product & 0xffff_ffff) as i64;
+            u[i + j] = total as u32;
+            borrow = (total < 0) as i64;
+        }
+        let total = u[j + n] as i64 - borrow - carry as i64;
+        u[j + n] = total as u32;
+
+        // The estimate was one too large: add the divisor back.
+        if total < 0 {
+            qhat -= 1;
+            let mut carry = 0u64;
+            for i in 0..n {
+                let sum = u[i + j] as u64 + v[i] as u64 + carry;
+                u[i + j] = sum as u32;
+                carry = sum >> 32;
+            }
+            u[j + n] = u[j + n].wrapping_add(carry as u32);
+        }
+
+        quotient[j] = qhat as u32;
+    }
+
+    // What's left of u is the remainder, still shifted.
+    let remainder = (0..n)
+        .map(|i| {
+            let high = if i + 1 < n { u[i + 1] } else { 0 };
+            ((((high as u64) << 32) | u[i] as u64) >> shift) as u32
+        })
+        .collect();
+
+    (quotient, remainder)
+}
+
+// Shifts left by fewer than 32 bits, into one more digit than the input.
+fn shift_left(digits: &[u32], shift: u32) -> Vec<u32> {
+    let mut shifted = Vec::with_capacity(digits.len() + 1);
+    let mut carry = 0u32;
+
+    for digit in digits.iter() {
+        let wide = (*digit as u64) << shift;
+        shifted.push(wide as u32 | carry);
+        carry = (wide >> 32) as u32;
+    }
+    shifted.push(carry);
+
+    shifted
+}
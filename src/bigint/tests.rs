@@ -0,0 +1,162 @@
+use super::*;
+
+fn big(text: &str) -> BigInt {
+    BigInt::parse(text).unwrap_or_else(|| panic!("could not parse {}", text))
+}
+
+// Values around the edges of u32, u64 and i64, and their negations.
+fn samples() -> Vec<i128> {
+    let mut samples = vec![0, 1, 2, 7, 10, 999_999_999, 1_000_000_000];
+    for edge in [
+        u32::MAX as i128,
+        i64::MAX as i128,
+        u64::MAX as i128,
+        i64::MAX as i128 * 3 + 12345,
+    ] {
+        samples.extend([edge - 1, edge, edge + 1]);
+    }
+    let negated: Vec<i128> = samples.iter().map(|v| -v).collect();
+    samples.extend(negated);
+    samples
+}
+
+fn from_i128(value: i128) -> BigInt {
+    big(&value.to_string())
+}
+
+#[test]
+fn test_parse_and_display() {
+    let tests = [
+        ("0", "0"),
+        ("-0", "0"),
+        ("000123", "123"),
+        ("-42", "-42"),
+        ("1000000000", "1000000000"),
+        ("9223372036854775808", "9223372036854775808"),
+        (
+            "-123456789012345678901234567890",
+            "-123456789012345678901234567890",
+        ),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_eq!(big(input).to_string(), *expected);
+    }
+
+    for input in ["", "-", "12a", "+1", "1.5", " 1"] {
+        assert_eq!(BigInt::parse(input), None, "input: {:?}", input);
+    }
+}
+
+#[test]
+fn test_i64_conversions() {
+    for value in [0, 1, -1, i64::MAX, i64::MIN, i64::MIN + 1, 1 << 32] {
+        let converted = BigInt::from(value);
+        assert_eq!(converted.to_string(), value.to_string());
+        assert_eq!(converted.to_i64(), Some(value));
+    }
+
+    assert_eq!(big("9223372036854775808").to_i64(), None);
+    assert_eq!(big("-9223372036854775809").to_i64(), None);
+    assert_eq!(big("18446744073709551616").to_i64(), None);
+}
+
+#[test]
+fn test_arithmetic_matches_i128() {
+    for a in samples() {
+        for b in samples() {
+            let (x, y) = (from_i128(a), from_i128(b));
+
+            assert_eq!((&x + &y).to_string(), (a + b).to_string(), "{} + {}", a, b);
+            assert_eq!((&x - &y).to_string(), (a - b).to_string(), "{} - {}", a, b);
+            assert_eq!(x.cmp(&y), a.cmp(&b), "{} <=> {}", a, b);
+            if let Some(product) = a.checked_mul(b) {
+                assert_eq!((&x * &y).to_string(), product.to_string(), "{} * {}", a, b);
+            }
+
+            match x.div_rem(&y) {
+                None => assert_eq!(b, 0),
+                Some((q, r)) => {
+                    assert_eq!(q.to_string(), (a / b).to_string(), "{} / {}", a, b);
+                    assert_eq!(r.to_string(), (a % b).to_string(), "{} % {}", a, b);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_long_division() {
+    let n = big("123456789012345678901234567890123456789012345678901234567890");
+    let divisors = [
+        "3",
+        "4294967296",
+        "98765432109876543210",
+        "-340282366920938463463374607431768211455",
+        "123456789012345678901234567890123456789012345678901234567891",
+    ];
+
+    for divisor in divisors.iter() {
+        let d = big(divisor);
+        let (q, r) = n.div_rem(&d).unwrap();
+
+        assert_eq!((&(&q * &d) + &r), n, "{} / {}", n, d);
+        assert!(!r.is_negative(), "{} % {} = {}", n, d, r);
+        let magnitude = if d.is_negative() { -&d } else { d.clone() };
+        assert!(r < magnitude, "{} % {} = {}", n, d, r);
+    }
+}
+
+#[test]
+fn test_division_round_trips() {
+    // A fixed linear congruential generator, for digits that are varied but repeatable. Digits
+    // near the top of the range make Algorithm D's estimate overshoot, the rarely taken path.
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = move || {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        match state >> 62 {
+            0 => u32::MAX,
+            1 => 0,
+            _ => (state >> 32) as u32,
+        }
+    };
+
+    for round in 0..500 {
+        let n = BigInt::new(round % 3 == 0, (0..1 + round % 9).map(|_| next()).collect());
+        let d = BigInt::new(round % 5 == 0, (0..1 + round % 4).map(|_| next()).collect());
+        if d.is_zero() {
+            continue;
+        }
+
+        let (q, r) = n.div_rem(&d).unwrap();
+        assert_eq!(&(&q * &d) + &r, n, "{} / {}", n, d);
+        assert!(r.is_zero() || r.is_negative() == n.is_negative());
+        let magnitude = if d.is_negative() { -&d } else { d.clone() };
+        let remainder = if r.is_negative() { -&r } else { r.clone() };
+        assert!(remainder < magnitude, "{} % {} = {}", n, d, r);
+    }
+}
+
+#[test]
+fn test_pow() {
+    assert_eq!(BigInt::from(2).pow(64).to_string(), "18446744073709551616");
+    assert_eq!(
+        BigInt::from(-3).pow(41).to_string(),
+        "-36472996377170786403"
+    );
+    assert_eq!(BigInt::from(12).pow(0).to_string(), "1");
+    assert_eq!(BigInt::from(0).pow(5).to_string(), "0");
+    assert_eq!(BigInt::from(2).pow(100).bits(), 101);
+}
+
+#[test]
+fn test_factorial() {
+    let mut factorial = BigInt::from(1);
+    for i in 1..=30 {
+        factorial = &factorial * &BigInt::from(i);
+    }
+
+    assert_eq!(factorial.to_string(), "265252859812191058636308480000000");
+}
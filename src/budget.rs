@@ -0,0 +1,156 @@
+use crate::object::{self, Object};
+use std::time::{Duration, Instant};
+
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+// Fuel, wall-clock and memory limits for one engine. Each engine counts steps its own way and
+// supplies a recount of its live memory when the running total looks too high.
+#[derive(Default)]
+pub struct Budget {
+    fuel: Option<u64>,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    steps: u64,
+    memory_limit: Option<usize>,
+    allocated: usize,
+    max_integer_bits: Option<usize>,
+}
+
+impl Budget {
+    pub fn new() -> Budget {
+        Budget::default()
+    }
+
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.allocated
+    }
+
+    pub fn set_max_integer_bits(&mut self, bits: Option<usize>) {
+        self.max_integer_bits = bits;
+    }
+
+    // Starts the clock for a run. Whatever the last run left behind and is no longer reachable
+    // doesn't count, so the caller passes what's live now.
+    pub fn start(&mut self, live: usize) {
+        self.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        self.allocated = live;
+    }
+
+    pub fn consume_fuel(&mut self) -> Option<Object> {
+        self.consume(1)
+    }
+
+    // Charges several steps at once, for work like a builtin copying a large array.
+    pub fn consume(&mut self, steps: u64) -> Option<Object> {
+        if let Some(fuel) = self.fuel {
+            if fuel < steps {
+                self.fuel = Some(0);
+                return Some(budget_exhausted(String::from("out of fuel")));
+            }
+            self.fuel = Some(fuel - steps);
+        }
+
+        // Reading the clock on every step would dominate small nodes, so only check periodically.
+        let before = self.steps;
+        self.steps = self.steps.wrapping_add(steps);
+        if before / DEADLINE_CHECK_INTERVAL != self.steps / DEADLINE_CHECK_INTERVAL
+            && let Some(deadline) = self.deadline
+            && Instant::now() >= deadline
+        {
+            return Some(budget_exhausted(String::from("deadline exceeded")));
+        }
+
+        None
+    }
+
+    pub fn reserve(&mut self, bytes: usize, recount: impl FnOnce() -> usize) -> Option<Object> {
+        let limit = self.memory_limit?;
+        if self.allocated.saturating_add(bytes) <= limit {
+            return None;
+        }
+
+        // The running total also includes garbage, so recount what's live before giving up. The
+        // count starts again from there, so recounts only come as often as the limit is reached.
+        self.allocated = recount();
+        if self.allocated.saturating_add(bytes) <= limit {
+            return None;
+        }
+
+        Some(Object::Error(Box::new(object::RuntimeError::with_kind(
+            object::ErrorKind::OutOfMemory,
+            format!("out of memory: limit of {} bytes exceeded", limit),
+        ))))
+    }
+
+    pub fn allocate(&mut self, bytes: usize, recount: impl FnOnce() -> usize) -> Option<Object> {
+        if let Some(err) = self.reserve(bytes, recount) {
+            return Some(err);
+        }
+        self.allocated += bytes;
+
+        None
+    }
+
+    // Pays for multiplying out an integer of at least `bits` bits before any of the `work` is done,
+    // so a runaway power fails on the budgets rather than running on unchecked.
+    pub fn reserve_integer(
+        &mut self,
+        bits: u64,
+        work: u64,
+        recount: impl FnOnce() -> usize,
+    ) -> Option<Object> {
+        if let Some(err) = self.check_integer_bits(bits) {
+            return Some(err);
+        }
+        if let Some(err) = self.consume(work) {
+            return Some(err);
+        }
+
+        self.reserve(usize::try_from(bits / 8).unwrap_or(usize::MAX), recount)
+    }
+
+    pub fn check_integer_bits(&self, bits: u64) -> Option<Object> {
+        match self.max_integer_bits {
+            Some(max) if bits > max as u64 => Some(Object::Error(Box::new(
+                object::RuntimeError::new(format!("integer too large: over {} bits", max)),
+            ))),
+            _ => None,
+        }
+    }
+
+    // Counts a freshly made value against the limit, or replaces it with the out-of-memory error.
+    pub fn charge(&mut self, obj: Object, recount: impl FnOnce() -> usize) -> Object {
+        let bytes = object::memory::shallow_size(&obj);
+        if bytes == 0 {
+            return obj;
+        }
+
+        match self.allocate(bytes, recount) {
+            Some(err) => err,
+            None => obj,
+        }
+    }
+}
+
+fn budget_exhausted(reason: String) -> Object {
+    Object::Error(Box::new(object::RuntimeError::with_kind(
+        object::ErrorKind::BudgetExhausted,
+        format!("budget exhausted: {}", reason),
+    )))
+}
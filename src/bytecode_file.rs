@@ -0,0 +1,518 @@
+#[cfg(test)]
+mod tests;
+
+use crate::bigint::BigInt;
+use crate::code::{self, Opcode};
+use crate::compiler::Bytecode;
+use crate::object::{Capture, CompiledFunction, Object, builtins};
+use crate::token::Span;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+// Layout of a .monkc file, all integers big-endian like instruction operands:
+//
+//   magic "MONKC\0", format version (u16), payload length (u32), payload, CRC-32 of payload (u32)
+//
+// The payload holds the global names, the constant pool and the top-level function. Bump
+// FORMAT_VERSION whenever the payload or the instruction set changes shape.
+pub const EXTENSION: &str = "monkc";
+pub const FORMAT_VERSION: u16 = 3;
+
+const MAGIC: &[u8; 6] = b"MONKC\0";
+const HEADER_LEN: usize = MAGIC.len() + 2 + 4;
+
+const TAG_INTEGER: u8 = 0;
+const TAG_STRING: u8 = 1;
+const TAG_FUNCTION: u8 = 2;
+const TAG_BIG_INTEGER: u8 = 3;
+
+// Locals and cells are addressed by two-byte operands, so a frame never needs more of either.
+const MAX_SLOTS: usize = 1 << 16;
+
+const CAPTURE_CELL: u8 = 0;
+const CAPTURE_FREE: u8 = 1;
+
+pub fn is_bytecode_file(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn encode(bytecode: &Bytecode) -> Result<Vec<u8>, String> {
+    let mut payload = Writer::default();
+
+    payload.u32(bytecode.globals.len())?;
+    for name in bytecode.globals.iter() {
+        payload.string(name)?;
+    }
+
+    payload.u32(bytecode.constants.len())?;
+    for constant in bytecode.constants.iter() {
+        match constant {
+            Object::Integer(value) => {
+                payload.u8(TAG_INTEGER);
+                payload.bytes.extend_from_slice(&value.to_be_bytes());
+            }
+            // In decimal: a big integer's digits aren't worth a format of their own.
+            Object::BigInteger(value) => {
+                payload.u8(TAG_BIG_INTEGER);
+                payload.string(&value.to_string())?;
+            }
+            Object::String(value) => {
+                payload.u8(TAG_STRING);
+                payload.string(value)?;
+            }
+            Object::CompiledFunction(function) => {
+                payload.u8(TAG_FUNCTION);
+                payload.function(function)?;
+            }
+            other => {
+                return Err(format!(
+                    "cannot serialize constant of type {}",
+                    other.object_type()
+                ));
+            }
+        }
+    }
+
+    payload.function(&bytecode.main)?;
+
+    let mut out = Writer::default();
+    out.bytes.extend_from_slice(MAGIC);
+    out.bytes.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+    out.u32(payload.bytes.len())?;
+    out.bytes.extend_from_slice(&payload.bytes);
+    out.bytes
+        .extend_from_slice(&crc32(&payload.bytes).to_be_bytes());
+
+    Ok(out.bytes)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Bytecode, String> {
+    if !is_bytecode_file(bytes) {
+        return Err(String::from("not a compiled Monkey file"));
+    }
+
+    let mut header = Reader::new(&bytes[MAGIC.len()..]);
+    let version = header.u16()?;
+    if version != FORMAT_VERSION {
+        return Err(format!(
+            "unsupported bytecode version {} (expected {})",
+            version, FORMAT_VERSION
+        ));
+    }
+
+    let len = header.u32()?;
+    let payload = bytes
+        .get(HEADER_LEN..HEADER_LEN.saturating_add(len))
+        .ok_or_else(|| String::from("unexpected end of file"))?;
+    let checksum = Reader::new(&bytes[HEADER_LEN + len..]).u32()?;
+    if bytes.len() != HEADER_LEN + len + 4 {
+        return Err(String::from("trailing data after checksum"));
+    }
+    if crc32(payload) as usize != checksum {
+        return Err(String::from("checksum mismatch: file is corrupt"));
+    }
+
+    let mut r = Reader::new(payload);
+
+    let mut globals = Vec::new();
+    for _ in 0..r.u32()? {
+        globals.push(r.string()?);
+    }
+
+    let mut constants = Vec::new();
+    for _ in 0..r.u32()? {
+        let constant = match r.u8()? {
+            TAG_INTEGER => Object::Integer(i64::from_be_bytes(r.array()?)),
+            TAG_STRING => Object::String(r.string()?.into()),
+            TAG_FUNCTION => Object::CompiledFunction(Rc::new(r.function()?)),
+            TAG_BIG_INTEGER => match BigInt::parse(&r.string()?) {
+                Some(value) => Object::from_big_integer(value),
+                None => return Err(String::from("invalid big integer constant")),
+            },
+            tag => return Err(format!("unknown constant tag {}", tag)),
+        };
+        constants.push(constant);
+    }
+
+    let main = Rc::new(r.function()?);
+    if !r.is_empty() {
+        return Err(String::from("trailing data in payload"));
+    }
+
+    let bytecode = Bytecode {
+        main,
+        constants,
+        globals,
+    };
+    verify(&bytecode)?;
+
+    Ok(bytecode)
+}
+
+// The checksum only catches damage, so check that every index an instruction uses is in range
+// before the VM trusts it.
+fn verify(bytecode: &Bytecode) -> Result<(), String> {
+    verify_function(bytecode, &bytecode.main)?;
+
+    for constant in bytecode.constants.iter() {
+        if let Object::CompiledFunction(function) = constant {
+            verify_function(bytecode, function)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn verify_function(bytecode: &Bytecode, function: &CompiledFunction) -> Result<(), String> {
+    let ins = &function.instructions;
+    let invalid = |offset: usize| format!("invalid instruction at {} in {}", offset, function.name);
+
+    if function.num_parameters > function.num_locals
+        || function.num_locals > MAX_SLOTS
+        || function.num_cells > MAX_SLOTS
+        || function.cell_names.len() != function.num_cells
+        || function.free_names.len() != function.captures.len()
+    {
+        return Err(format!("invalid frame layout in {}", function.name));
+    }
+
+    let mut decoded = Vec::new();
+    let mut offset = 0;
+    while offset < ins.len() {
+        let op = ins[offset];
+        let def = code::lookup(op).ok_or_else(|| invalid(offset))?;
+        let width: usize = def.operand_widths.iter().sum();
+        if offset + 1 + width > ins.len() {
+            return Err(invalid(offset));
+        }
+
+        let (operands, _) = code::read_operands(def, &ins[offset + 1..]);
+        decoded.push((offset, op, operands));
+        offset += 1 + width;
+    }
+
+    // Running off the end would leave the VM without an instruction to execute.
+    if !decoded.last().is_some_and(|(_, op, _)| {
+        matches!(*op, code::OP_RETURN_VALUE | code::OP_JUMP | code::OP_THROW)
+    }) {
+        return Err(format!(
+            "{} can run past its last instruction",
+            function.name
+        ));
+    }
+
+    // Jumps must land on the start of an instruction.
+    let starts: HashSet<usize> = decoded.iter().map(|(offset, _, _)| *offset).collect();
+
+    for (offset, op, operands) in decoded.iter() {
+        let valid = match *op {
+            code::OP_CONSTANT => operands[0] < bytecode.constants.len(),
+            code::OP_CLOSURE => match bytecode.constants.get(operands[0]) {
+                Some(Object::CompiledFunction(inner)) => {
+                    inner.captures.iter().all(|capture| match capture {
+                        Capture::Cell(i) => *i < function.num_cells,
+                        Capture::Free(i) => *i < function.captures.len(),
+                    })
+                }
+                _ => false,
+            },
+            code::OP_GET_GLOBAL | code::OP_SET_GLOBAL | code::OP_ASSIGN_GLOBAL => {
+                operands[0] < bytecode.globals.len()
+            }
+            code::OP_GET_LOCAL | code::OP_SET_LOCAL => operands[0] < function.num_locals,
+            code::OP_GET_CELL | code::OP_SET_CELL => operands[0] < function.num_cells,
+            code::OP_NEW_CELLS => operands[0] + operands[1] <= function.num_cells,
+            code::OP_GET_FREE | code::OP_SET_FREE => operands[0] < function.captures.len(),
+            code::OP_GET_BUILTIN => operands[0] < builtins::BUILTINS.len(),
+            code::OP_UPDATE_INDEX => {
+                (code::OP_ADD..=code::OP_RANGE).contains(&(operands[0] as Opcode))
+            }
+            code::OP_JUMP | code::OP_JUMP_NOT_TRUTHY | code::OP_SETUP_TRY | code::OP_ITER_NEXT => {
+                starts.contains(&operands[0])
+            }
+            _ => true,
+        };
+        if !valid {
+            return Err(invalid(*offset));
+        }
+    }
+
+    verify_stack(function, &decoded)
+}
+
+// Follows every path through the function, counting the values on the stack above its locals
+// and the `for` loops it has open. Wherever two paths meet they must agree on both, so each
+// instruction always finds the same counts, and none may take more than there are.
+fn verify_stack(
+    function: &CompiledFunction,
+    decoded: &[(usize, Opcode, Vec<usize>)],
+) -> Result<(), String> {
+    let positions: HashMap<usize, usize> = decoded
+        .iter()
+        .enumerate()
+        .map(|(i, (offset, _, _))| (*offset, i))
+        .collect();
+    let mut seen: Vec<Option<(usize, usize)>> = vec![None; decoded.len()];
+    let mut pending = vec![(0, (0, 0))];
+
+    while let Some((i, entry)) = pending.pop() {
+        let (offset, op, operands) = &decoded[i];
+        match seen[i] {
+            Some(counts) if counts == entry => continue,
+            Some(_) => {
+                return Err(format!(
+                    "inconsistent stack at {} in {}",
+                    offset, function.name
+                ));
+            }
+            None => seen[i] = Some(entry),
+        }
+
+        let (depth, loops) = entry;
+        let operand = operands.first().copied().unwrap_or(0);
+        let (pops, pushes) = match *op {
+            code::OP_POP
+            | code::OP_JUMP_NOT_TRUTHY
+            | code::OP_SET_GLOBAL
+            | code::OP_ASSIGN_GLOBAL
+            | code::OP_SET_LOCAL
+            | code::OP_SET_CELL
+            | code::OP_SET_FREE
+            | code::OP_RETURN_VALUE
+            | code::OP_THROW
+            | code::OP_ITER => (1, 0),
+            code::OP_ADD..=code::OP_RANGE | code::OP_INDEX => (2, 1),
+            code::OP_MINUS | code::OP_BANG | code::OP_BIT_NOT => (1, 1),
+            code::OP_SET_INDEX | code::OP_UPDATE_INDEX => (3, 0),
+            code::OP_SWAP => (2, 2),
+            code::OP_ARRAY => (operand, 1),
+            code::OP_HASH => (2 * operand, 1),
+            code::OP_SLICE => (1 + (operand as u8).count_ones() as usize, 1),
+            code::OP_CALL | code::OP_TAIL_CALL => (operand + 1, 1),
+            code::OP_CONSTANT
+            | code::OP_TRUE
+            | code::OP_FALSE
+            | code::OP_NULL
+            | code::OP_GET_GLOBAL
+            | code::OP_GET_LOCAL
+            | code::OP_GET_CELL
+            | code::OP_GET_FREE
+            | code::OP_GET_BUILTIN
+            | code::OP_CLOSURE => (0, 1),
+            _ => (0, 0),
+        };
+        if depth < pops {
+            return Err(format!(
+                "stack underflow at {} in {}",
+                offset, function.name
+            ));
+        }
+        if matches!(*op, code::OP_ITER_NEXT | code::OP_ITER_POP) && loops == 0 {
+            return Err(format!(
+                "no loop to iterate at {} in {}",
+                offset, function.name
+            ));
+        }
+
+        let after = (depth - pops + pushes, loops);
+        let mut next = |target: usize, counts: (usize, usize)| pending.push((target, counts));
+        match *op {
+            code::OP_RETURN_VALUE | code::OP_THROW => (),
+            code::OP_JUMP => next(positions[&operand], after),
+            code::OP_JUMP_NOT_TRUTHY => {
+                next(positions[&operand], after);
+                next(i + 1, after);
+            }
+            // The handler starts with the caught value pushed where the stack was at the setup.
+            code::OP_SETUP_TRY => {
+                next(positions[&operand], (depth + 1, loops));
+                next(i + 1, after);
+            }
+            code::OP_ITER => next(i + 1, (depth - 1, loops + 1)),
+            // The item is only pushed when there is one; otherwise the loop is over.
+            code::OP_ITER_NEXT => {
+                next(positions[&operand], after);
+                next(i + 1, (depth + 1, loops));
+            }
+            code::OP_ITER_POP => next(i + 1, (depth, loops - 1)),
+            _ => next(i + 1, after),
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u32(&mut self, value: usize) -> Result<(), String> {
+        let value =
+            u32::try_from(value).map_err(|_| format!("{} is too large to serialize", value))?;
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+        Ok(())
+    }
+
+    fn string(&mut self, value: &str) -> Result<(), String> {
+        self.u32(value.len())?;
+        self.bytes.extend_from_slice(value.as_bytes());
+        Ok(())
+    }
+
+    fn function(&mut self, function: &CompiledFunction) -> Result<(), String> {
+        self.string(&function.name)?;
+        self.u32(function.num_parameters)?;
+        self.u32(function.num_locals)?;
+        self.u32(function.num_cells)?;
+
+        self.u32(function.cell_names.len())?;
+        for name in function.cell_names.iter() {
+            self.string(name)?;
+        }
+
+        self.u32(function.free_names.len())?;
+        for name in function.free_names.iter() {
+            self.string(name)?;
+        }
+
+        self.u32(function.captures.len())?;
+        for capture in function.captures.iter() {
+            let (tag, index) = match capture {
+                Capture::Cell(index) => (CAPTURE_CELL, index),
+                Capture::Free(index) => (CAPTURE_FREE, index),
+            };
+            self.u8(tag);
+            self.u32(*index)?;
+        }
+
+        self.u32(function.instructions.len())?;
+        self.bytes.extend_from_slice(&function.instructions);
+
+        self.u32(function.source_map.len())?;
+        for (offset, span) in function.source_map.iter() {
+            self.u32(*offset)?;
+            self.u32(span.line)?;
+            self.u32(span.column)?;
+        }
+
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if len > self.bytes.len() {
+            return Err(String::from("unexpected end of file"));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<usize, String> {
+        Ok(u32::from_be_bytes(self.array()?) as usize)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u32()?;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| String::from("invalid UTF-8 in string"))
+    }
+
+    fn function(&mut self) -> Result<CompiledFunction, String> {
+        let name = self.string()?;
+        let num_parameters = self.u32()?;
+        let num_locals = self.u32()?;
+        let num_cells = self.u32()?;
+
+        let mut cell_names = Vec::new();
+        for _ in 0..self.u32()? {
+            cell_names.push(self.string()?);
+        }
+
+        let mut free_names = Vec::new();
+        for _ in 0..self.u32()? {
+            free_names.push(self.string()?);
+        }
+
+        let mut captures = Vec::new();
+        for _ in 0..self.u32()? {
+            let capture = match (self.u8()?, self.u32()?) {
+                (CAPTURE_CELL, index) => Capture::Cell(index),
+                (CAPTURE_FREE, index) => Capture::Free(index),
+                (tag, _) => return Err(format!("unknown capture tag {}", tag)),
+            };
+            captures.push(capture);
+        }
+
+        let len = self.u32()?;
+        let instructions = self.take(len)?.to_vec();
+
+        let mut source_map = Vec::new();
+        for _ in 0..self.u32()? {
+            let offset = self.u32()?;
+            let line = self.u32()?;
+            let column = self.u32()?;
+            source_map.push((offset, Span { line, column }));
+        }
+
+        Ok(CompiledFunction {
+            instructions,
+            num_locals,
+            num_cells,
+            num_parameters,
+            captures,
+            cell_names,
+            free_names,
+            name,
+            source_map,
+        })
+    }
+}
+
+// CRC-32 (IEEE), computed bit by bit; files are small enough that a table isn't worth it.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for byte in bytes.iter() {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
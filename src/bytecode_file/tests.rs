@@ -0,0 +1,213 @@
+use super::*;
+use crate::compiler::Compiler;
+use crate::test_helpers::parse;
+use crate::vm::VM;
+
+fn compile(input: &str) -> Bytecode {
+    let mut compiler = Compiler::new();
+    compiler.compile(&parse(input)).unwrap();
+    compiler.bytecode()
+}
+
+const PROGRAM: &str = "let greeting = \"hello\";
+let make = fn(n) { let count = n; fn() { count += 1; count } };
+let c = make(41);
+c();
+let r = c();
+if (r == 43) { greeting + \" world\" } else {
+  r + true
+}";
+
+#[test]
+fn test_round_trip() {
+    let bytecode = compile(PROGRAM);
+    let bytes = encode(&bytecode).unwrap();
+    assert!(is_bytecode_file(&bytes));
+
+    let decoded = decode(&bytes).unwrap();
+    assert_eq!(decoded.disassemble(), bytecode.disassemble());
+    assert_eq!(decoded.globals, bytecode.globals);
+    assert_eq!(decoded.main.source_map, bytecode.main.source_map);
+    assert_eq!(VM::new().run(&decoded).inspect(), "hello world");
+
+    // Source maps survive, so errors from a loaded file still point at the source.
+    let bytes = encode(&compile(&PROGRAM.replace("43", "0"))).unwrap();
+    match VM::new().run(&decode(&bytes).unwrap()) {
+        Object::Error(err) => assert_eq!(err.span, Some(Span { line: 7, column: 5 })),
+        other => panic!("object is not Error. got={}", other.inspect()),
+    }
+}
+
+#[test]
+fn test_round_trip_big_integers() {
+    let bytecode = compile("[99999999999999999999 * 2, -18446744073709551616]");
+    let decoded = decode(&encode(&bytecode).unwrap()).unwrap();
+    assert_eq!(
+        VM::new().run(&decoded).inspect(),
+        "[199999999999999999998, -18446744073709551616]"
+    );
+}
+
+#[test]
+fn test_decode_errors() {
+    let bytes = encode(&compile(PROGRAM)).unwrap();
+
+    let mut other_version = bytes.clone();
+    other_version[MAGIC.len() + 1] = 99;
+
+    let mut corrupt = bytes.clone();
+    let last = corrupt.len() - 10;
+    corrupt[last] ^= 0xff;
+
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+
+    let tests = [
+        (b"let x = 1;".to_vec(), "not a compiled Monkey file"),
+        (
+            other_version,
+            "unsupported bytecode version 99 (expected 3)",
+        ),
+        (bytes[..bytes.len() - 2].to_vec(), "unexpected end of file"),
+        (bytes[..8].to_vec(), "unexpected end of file"),
+        (corrupt, "checksum mismatch: file is corrupt"),
+        (trailing, "trailing data after checksum"),
+    ];
+
+    for (input, expected) in tests.iter() {
+        match decode(input) {
+            Ok(_) => panic!("expected error {:?}", expected),
+            Err(err) => assert_eq!(err, *expected),
+        }
+    }
+}
+
+#[test]
+fn test_verify_rejects_bad_instructions() {
+    let main = |instructions: Vec<u8>| Bytecode {
+        main: Rc::new(CompiledFunction {
+            instructions,
+            num_locals: 1,
+            num_cells: 0,
+            num_parameters: 0,
+            captures: Vec::new(),
+            cell_names: Vec::new(),
+            free_names: Vec::new(),
+            name: String::from("<main>"),
+            source_map: Vec::new(),
+        }),
+        constants: vec![Object::Integer(1)],
+        globals: Vec::new(),
+    };
+    let ret = code::make(code::OP_RETURN_VALUE, &[]);
+
+    let tests = [
+        (
+            vec![code::make(code::OP_CONSTANT, &[1]), ret.clone()],
+            "invalid instruction at 0 in <main>",
+        ),
+        (
+            vec![code::make(code::OP_GET_LOCAL, &[1]), ret.clone()],
+            "invalid instruction at 0 in <main>",
+        ),
+        (
+            vec![code::make(code::OP_GET_GLOBAL, &[0]), ret.clone()],
+            "invalid instruction at 0 in <main>",
+        ),
+        (
+            vec![code::make(code::OP_JUMP, &[2]), ret.clone()],
+            "invalid instruction at 0 in <main>",
+        ),
+        (
+            vec![code::make(code::OP_CLOSURE, &[0]), ret.clone()],
+            "invalid instruction at 0 in <main>",
+        ),
+        (
+            vec![vec![200], ret.clone()],
+            "invalid instruction at 0 in <main>",
+        ),
+        (
+            vec![vec![code::OP_JUMP, 0], ret.clone()],
+            "invalid instruction at 0 in <main>",
+        ),
+        (
+            vec![code::make(code::OP_NULL, &[])],
+            "<main> can run past its last instruction",
+        ),
+        (
+            vec![code::make(code::OP_POP, &[]), ret.clone()],
+            "stack underflow at 0 in <main>",
+        ),
+        (vec![ret.clone()], "stack underflow at 0 in <main>"),
+        (
+            vec![
+                code::make(code::OP_GET_BUILTIN, &[0]),
+                code::make(code::OP_CALL, &[1]),
+                ret.clone(),
+            ],
+            "stack underflow at 2 in <main>",
+        ),
+        (
+            // Loops back with one more value on the stack each time round.
+            vec![
+                code::make(code::OP_TRUE, &[]),
+                code::make(code::OP_JUMP, &[0]),
+            ],
+            "inconsistent stack at 0 in <main>",
+        ),
+        (
+            vec![
+                code::make(code::OP_ITER_NEXT, &[0]),
+                code::make(code::OP_NULL, &[]),
+                ret.clone(),
+            ],
+            "no loop to iterate at 0 in <main>",
+        ),
+    ];
+
+    for (instructions, expected) in tests.iter() {
+        let bytes = encode(&main(instructions.concat())).unwrap();
+        match decode(&bytes) {
+            Ok(_) => panic!("expected error {:?}", expected),
+            Err(err) => assert_eq!(err, *expected),
+        }
+    }
+
+    let bytes = encode(&main(
+        [code::make(code::OP_CONSTANT, &[0]), ret.clone()].concat(),
+    ))
+    .unwrap();
+    assert!(decode(&bytes).is_ok());
+}
+
+#[test]
+fn test_verify_rejects_bad_frames() {
+    let with_frame = |num_locals: usize, num_cells: usize| Bytecode {
+        main: Rc::new(CompiledFunction {
+            instructions: [
+                code::make(code::OP_NULL, &[]),
+                code::make(code::OP_RETURN_VALUE, &[]),
+            ]
+            .concat(),
+            num_locals,
+            num_cells,
+            num_parameters: 0,
+            captures: Vec::new(),
+            cell_names: vec![String::from("x"); num_cells.min(10)],
+            free_names: Vec::new(),
+            name: String::from("<main>"),
+            source_map: Vec::new(),
+        }),
+        constants: Vec::new(),
+        globals: Vec::new(),
+    };
+
+    for (num_locals, num_cells) in [(0xffff_ffff, 0), (0, 0xffff_ffff)] {
+        let bytes = encode(&with_frame(num_locals, num_cells)).unwrap();
+        match decode(&bytes) {
+            Ok(_) => panic!("expected error"),
+            Err(err) => assert_eq!(err, "invalid frame layout in <main>"),
+        }
+    }
+    assert!(decode(&encode(&with_frame(10, 10)).unwrap()).is_ok());
+}
@@ -0,0 +1,271 @@
+#[cfg(test)]
+mod tests;
+
+use crate::object::{Object, builtins};
+
+pub type Instructions = Vec<u8>;
+
+pub type Opcode = u8;
+
+pub const OP_CONSTANT: Opcode = 0;
+pub const OP_POP: Opcode = 1;
+pub const OP_TRUE: Opcode = 2;
+pub const OP_FALSE: Opcode = 3;
+pub const OP_NULL: Opcode = 4;
+pub const OP_ADD: Opcode = 5;
+pub const OP_SUB: Opcode = 6;
+pub const OP_MUL: Opcode = 7;
+pub const OP_DIV: Opcode = 8;
+pub const OP_MOD: Opcode = 9;
+pub const OP_POW: Opcode = 10;
+pub const OP_BIT_AND: Opcode = 11;
+pub const OP_BIT_OR: Opcode = 12;
+pub const OP_BIT_XOR: Opcode = 13;
+pub const OP_SHL: Opcode = 14;
+pub const OP_SHR: Opcode = 15;
+pub const OP_EQUAL: Opcode = 16;
+pub const OP_NOT_EQUAL: Opcode = 17;
+pub const OP_LESS: Opcode = 18;
+pub const OP_LESS_EQUAL: Opcode = 19;
+pub const OP_GREATER: Opcode = 20;
+pub const OP_GREATER_EQUAL: Opcode = 21;
+pub const OP_RANGE: Opcode = 22;
+pub const OP_MINUS: Opcode = 23;
+pub const OP_BANG: Opcode = 24;
+pub const OP_BIT_NOT: Opcode = 25;
+pub const OP_JUMP: Opcode = 26;
+pub const OP_JUMP_NOT_TRUTHY: Opcode = 27;
+pub const OP_GET_GLOBAL: Opcode = 28;
+pub const OP_SET_GLOBAL: Opcode = 29;
+pub const OP_ASSIGN_GLOBAL: Opcode = 30;
+pub const OP_GET_LOCAL: Opcode = 31;
+pub const OP_SET_LOCAL: Opcode = 32;
+pub const OP_GET_CELL: Opcode = 33;
+pub const OP_SET_CELL: Opcode = 34;
+pub const OP_NEW_CELLS: Opcode = 35;
+pub const OP_GET_FREE: Opcode = 36;
+pub const OP_SET_FREE: Opcode = 37;
+pub const OP_GET_BUILTIN: Opcode = 38;
+pub const OP_ARRAY: Opcode = 39;
+pub const OP_HASH: Opcode = 40;
+pub const OP_INDEX: Opcode = 41;
+pub const OP_SET_INDEX: Opcode = 42;
+pub const OP_UPDATE_INDEX: Opcode = 43;
+pub const OP_SLICE: Opcode = 44;
+pub const OP_SWAP: Opcode = 45;
+pub const OP_CLOSURE: Opcode = 46;
+pub const OP_CALL: Opcode = 47;
+pub const OP_TAIL_CALL: Opcode = 48;
+pub const OP_RETURN_VALUE: Opcode = 49;
+pub const OP_THROW: Opcode = 50;
+pub const OP_SETUP_TRY: Opcode = 51;
+pub const OP_POP_TRY: Opcode = 52;
+pub const OP_ITER: Opcode = 53;
+pub const OP_ITER_NEXT: Opcode = 54;
+pub const OP_ITER_POP: Opcode = 55;
+
+// Bits of the OP_SLICE operand saying which bounds were pushed.
+pub const SLICE_START: usize = 1;
+pub const SLICE_END: usize = 2;
+pub const SLICE_STEP: usize = 4;
+
+pub struct Definition {
+    pub name: &'static str,
+    pub operand_widths: &'static [usize],
+}
+
+const fn def(name: &'static str, operand_widths: &'static [usize]) -> Definition {
+    Definition {
+        name,
+        operand_widths,
+    }
+}
+
+// Indexed by opcode. Jump targets are 4 bytes wide so large scripts don't run out of address
+// space; everything else fits in 1 or 2.
+const DEFINITIONS: [Definition; 56] = [
+    def("OpConstant", &[2]),
+    def("OpPop", &[]),
+    def("OpTrue", &[]),
+    def("OpFalse", &[]),
+    def("OpNull", &[]),
+    def("OpAdd", &[]),
+    def("OpSub", &[]),
+    def("OpMul", &[]),
+    def("OpDiv", &[]),
+    def("OpMod", &[]),
+    def("OpPow", &[]),
+    def("OpBitAnd", &[]),
+    def("OpBitOr", &[]),
+    def("OpBitXor", &[]),
+    def("OpShl", &[]),
+    def("OpShr", &[]),
+    def("OpEqual", &[]),
+    def("OpNotEqual", &[]),
+    def("OpLess", &[]),
+    def("OpLessEqual", &[]),
+    def("OpGreater", &[]),
+    def("OpGreaterEqual", &[]),
+    def("OpRange", &[]),
+    def("OpMinus", &[]),
+    def("OpBang", &[]),
+    def("OpBitNot", &[]),
+    def("OpJump", &[4]),
+    def("OpJumpNotTruthy", &[4]),
+    def("OpGetGlobal", &[2]),
+    def("OpSetGlobal", &[2]),
+    def("OpAssignGlobal", &[2]),
+    def("OpGetLocal", &[2]),
+    def("OpSetLocal", &[2]),
+    def("OpGetCell", &[2]),
+    def("OpSetCell", &[2]),
+    def("OpNewCells", &[2, 2]),
+    def("OpGetFree", &[2]),
+    def("OpSetFree", &[2]),
+    def("OpGetBuiltin", &[1]),
+    def("OpArray", &[2]),
+    def("OpHash", &[2]),
+    def("OpIndex", &[]),
+    def("OpSetIndex", &[]),
+    def("OpUpdateIndex", &[1]),
+    def("OpSlice", &[1]),
+    def("OpSwap", &[]),
+    def("OpClosure", &[2]),
+    def("OpCall", &[1]),
+    def("OpTailCall", &[1]),
+    def("OpReturnValue", &[]),
+    def("OpThrow", &[]),
+    def("OpSetupTry", &[4]),
+    def("OpPopTry", &[]),
+    def("OpIter", &[]),
+    def("OpIterNext", &[4]),
+    def("OpIterPop", &[]),
+];
+
+// The bytes of operands after each opcode, so the VM can step over an instruction without
+// going through its definition.
+const OPERAND_BYTES: [usize; DEFINITIONS.len()] = {
+    let mut bytes = [0; DEFINITIONS.len()];
+    let mut op = 0;
+    while op < DEFINITIONS.len() {
+        let widths = DEFINITIONS[op].operand_widths;
+        let mut i = 0;
+        while i < widths.len() {
+            bytes[op] += widths[i];
+            i += 1;
+        }
+        op += 1;
+    }
+    bytes
+};
+
+pub fn lookup(op: Opcode) -> Option<&'static Definition> {
+    DEFINITIONS.get(op as usize)
+}
+
+pub fn operand_bytes(op: Opcode) -> Option<usize> {
+    OPERAND_BYTES.get(op as usize).copied()
+}
+
+pub fn make(op: Opcode, operands: &[usize]) -> Instructions {
+    let def = match lookup(op) {
+        Some(def) => def,
+        None => return Vec::new(),
+    };
+
+    let mut ins = vec![op];
+    for (operand, width) in operands.iter().zip(def.operand_widths) {
+        match width {
+            1 => ins.push(*operand as u8),
+            2 => ins.extend_from_slice(&(*operand as u16).to_be_bytes()),
+            4 => ins.extend_from_slice(&(*operand as u32).to_be_bytes()),
+            _ => unreachable!("operand width {}", width),
+        }
+    }
+
+    ins
+}
+
+// Decodes the operands that follow an opcode, returning them with the number of bytes read.
+pub fn read_operands(def: &Definition, ins: &[u8]) -> (Vec<usize>, usize) {
+    let mut operands = Vec::with_capacity(def.operand_widths.len());
+    let mut offset = 0;
+
+    for width in def.operand_widths.iter() {
+        operands.push(read_operand(*width, &ins[offset..]));
+        offset += width;
+    }
+
+    (operands, offset)
+}
+
+// Lists every instruction with its offset, one per line.
+pub fn disassemble(ins: &[u8], constants: &[Object]) -> String {
+    let mut out = String::new();
+    let mut offset = 0;
+
+    while offset < ins.len() {
+        let (line, width) = format_instruction(ins, offset, constants);
+        out.push_str(&line);
+        out.push('\n');
+        offset += width;
+    }
+
+    out
+}
+
+// Renders the instruction at `offset`, naming the constant or builtin it refers to, and returns
+// it with the instruction's width in bytes.
+pub fn format_instruction(ins: &[u8], offset: usize, constants: &[Object]) -> (String, usize) {
+    let op = ins[offset];
+    let def = match lookup(op) {
+        Some(def) => def,
+        None => return (format!("{:04} ERROR: unknown opcode {}", offset, op), 1),
+    };
+
+    let (operands, read) = read_operands(def, &ins[offset + 1..]);
+    let mut line = format!("{:04} {}", offset, def.name);
+    for operand in operands.iter() {
+        line.push_str(&format!(" {}", operand));
+    }
+
+    let comment = match op {
+        OP_CONSTANT | OP_CLOSURE => constants.get(operands[0]).map(Object::inspect),
+        OP_GET_BUILTIN => builtins::BUILTINS
+            .get(operands[0])
+            .map(|(name, _)| name.to_string()),
+        OP_UPDATE_INDEX => lookup(operands[0] as Opcode).map(|def| def.name.to_string()),
+        _ => None,
+    };
+    if let Some(comment) = comment {
+        line.push_str(&format!(" ; {}", comment));
+    }
+
+    (line, 1 + read)
+}
+
+pub fn read_operand(width: usize, ins: &[u8]) -> usize {
+    match width {
+        1 => ins[0] as usize,
+        2 => read_u16(ins) as usize,
+        4 => read_u32(ins) as usize,
+        _ => unreachable!("operand width {}", width),
+    }
+}
+
+pub fn read_u16(ins: &[u8]) -> u16 {
+    u16::from_be_bytes([ins[0], ins[1]])
+}
+
+pub fn read_u32(ins: &[u8]) -> u32 {
+    u32::from_be_bytes([ins[0], ins[1], ins[2], ins[3]])
+}
+
+// The widest value each operand width can hold.
+pub fn operand_limit(width: usize) -> usize {
+    match width {
+        1 => u8::MAX as usize,
+        2 => u16::MAX as usize,
+        _ => u32::MAX as usize,
+    }
+}
@@ -0,0 +1,79 @@
+use super::*;
+
+#[test]
+fn test_make() {
+    let tests: Vec<(Opcode, Vec<usize>, Vec<u8>)> = vec![
+        (OP_CONSTANT, vec![65534], vec![OP_CONSTANT, 255, 254]),
+        (OP_ADD, vec![], vec![OP_ADD]),
+        (OP_GET_BUILTIN, vec![255], vec![OP_GET_BUILTIN, 255]),
+        (OP_JUMP, vec![65536], vec![OP_JUMP, 0, 1, 0, 0]),
+        (OP_NEW_CELLS, vec![1, 2], vec![OP_NEW_CELLS, 0, 1, 0, 2]),
+    ];
+
+    for (op, operands, expected) in tests {
+        assert_eq!(
+            make(op, &operands),
+            expected,
+            "{}",
+            lookup(op).unwrap().name
+        );
+    }
+}
+
+#[test]
+fn test_read_operands() {
+    let tests: Vec<(Opcode, Vec<usize>, usize)> = vec![
+        (OP_CONSTANT, vec![65535], 2),
+        (OP_GET_BUILTIN, vec![255], 1),
+        (OP_SETUP_TRY, vec![70000], 4),
+        (OP_NEW_CELLS, vec![3, 4], 4),
+        (OP_POP, vec![], 0),
+    ];
+
+    for (op, operands, bytes_read) in tests {
+        let ins = make(op, &operands);
+        let def = lookup(op).unwrap();
+
+        let (read, n) = read_operands(def, &ins[1..]);
+        assert_eq!(n, bytes_read, "{}", def.name);
+        assert_eq!(read, operands, "{}", def.name);
+    }
+}
+
+#[test]
+fn test_definitions_match_opcodes() {
+    assert_eq!(lookup(OP_CONSTANT).unwrap().name, "OpConstant");
+    assert_eq!(lookup(OP_RANGE).unwrap().name, "OpRange");
+    assert_eq!(lookup(OP_GET_BUILTIN).unwrap().name, "OpGetBuiltin");
+    assert_eq!(lookup(OP_CLOSURE).unwrap().name, "OpClosure");
+    assert_eq!(lookup(OP_ITER_POP).unwrap().name, "OpIterPop");
+    assert!(lookup(OP_ITER_POP + 1).is_none());
+}
+
+#[test]
+fn test_disassemble() {
+    let ins = [
+        make(OP_CONSTANT, &[1]),
+        make(OP_GET_BUILTIN, &[0]),
+        make(OP_UPDATE_INDEX, &[OP_ADD as usize]),
+        make(OP_JUMP, &[70000]),
+        make(OP_NEW_CELLS, &[2, 3]),
+        make(OP_POP, &[]),
+    ]
+    .concat();
+    let constants = [Object::Integer(5), Object::String("hi".into())];
+
+    let expected = "0000 OpConstant 1 ; hi
+0003 OpGetBuiltin 0 ; len
+0005 OpUpdateIndex 5 ; OpAdd
+0007 OpJump 70000
+0012 OpNewCells 2 3
+0017 OpPop
+";
+    assert_eq!(disassemble(&ins, &constants), expected);
+
+    assert_eq!(
+        format_instruction(&[255], 0, &constants),
+        (String::from("0000 ERROR: unknown opcode 255"), 1)
+    );
+}
@@ -0,0 +1,993 @@
+mod peephole;
+pub mod symbol_table;
+#[cfg(test)]
+mod tests;
+
+use crate::ast::{self, Expression};
+use crate::code::{self, Instructions, Opcode};
+use crate::object::{self, Object};
+use crate::symbol::Symbol as Name;
+use crate::token::Span;
+use std::collections::HashSet;
+use std::mem;
+use std::rc::Rc;
+use symbol_table::{
+    BUILTIN_SCOPE, CELL_SCOPE, FREE_SCOPE, GLOBAL_SCOPE, LOCAL_SCOPE, Symbol, SymbolTable,
+};
+
+// A compiled program: the top level runs as a function of its own.
+pub struct Bytecode {
+    pub main: Rc<object::CompiledFunction>,
+    pub constants: Vec<Object>,
+    pub globals: Vec<String>,
+}
+
+impl Bytecode {
+    // The top level followed by every function in the constant pool, each under a header.
+    pub fn disassemble(&self) -> String {
+        let mut out = format!(
+            "== {} (locals={}, cells={}) ==\n",
+            self.main.name, self.main.num_locals, self.main.num_cells
+        );
+        out.push_str(&code::disassemble(&self.main.instructions, &self.constants));
+
+        for (index, constant) in self.constants.iter().enumerate() {
+            if let Object::CompiledFunction(function) = constant {
+                out.push_str(&format!(
+                    "\n== {} (constant {}, params={}, locals={}, cells={}, free={}) ==\n",
+                    function.name,
+                    index,
+                    function.num_parameters,
+                    function.num_locals,
+                    function.num_cells,
+                    function.captures.len()
+                ));
+                out.push_str(&code::disassemble(&function.instructions, &self.constants));
+            }
+        }
+
+        out
+    }
+}
+
+pub struct Compiler {
+    constants: Vec<Object>,
+    symbol_table: SymbolTable,
+    scopes: Vec<CompilationScope>,
+    main: Option<Rc<object::CompiledFunction>>,
+    // Span of the node being compiled, recorded in the source map for each instruction.
+    span: Span,
+    optimize: bool,
+}
+
+#[derive(Default)]
+struct CompilationScope {
+    instructions: Instructions,
+    source_map: Vec<(usize, Span)>,
+    // Values on the operand stack at this point, so `break` can drop whatever is pending.
+    depth: usize,
+    try_depth: usize,
+    loops: Vec<Loop>,
+}
+
+struct Loop {
+    depth: usize,
+    try_depth: usize,
+    continue_target: usize,
+    breaks: Vec<usize>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Compiler {
+        Compiler::new_with_state(SymbolTable::new(), Vec::new())
+    }
+
+    // Keeps globals and constants from earlier compilations, as the REPL does line by line.
+    pub fn new_with_state(symbol_table: SymbolTable, constants: Vec<Object>) -> Compiler {
+        Compiler {
+            constants,
+            symbol_table,
+            scopes: vec![CompilationScope::default()],
+            main: None,
+            span: Span::default(),
+            optimize: true,
+        }
+    }
+
+    // The peephole pass is on by default; turning it off keeps the bytecode as first emitted.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    pub fn into_state(self) -> (SymbolTable, Vec<Object>) {
+        (self.symbol_table, self.constants)
+    }
+
+    pub fn compile(&mut self, program: &ast::Program) -> Result<(), String> {
+        self.scopes = vec![CompilationScope::default()];
+        self.symbol_table.num_locals = 0;
+        self.symbol_table.num_cells = 0;
+        self.symbol_table.cell_names.clear();
+
+        let mut captured = HashSet::new();
+        for stmt in program.statements.iter() {
+            captured_names(Node::Statement(stmt.as_ref()), &mut captured);
+        }
+        self.symbol_table.set_captured(captured);
+
+        // Globals named like builtins get their slots up front, so code compiled before their
+        // `let` refers to them rather than to the builtin.
+        let mut names = HashSet::new();
+        for stmt in program.statements.iter() {
+            let_names(stmt.as_ref(), &mut names);
+        }
+        let mut shadowing: Vec<Name> = names
+            .into_iter()
+            .filter(|name| object::builtins::lookup_builtin(name.as_str()).is_some())
+            .collect();
+        shadowing.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        for name in shadowing {
+            self.symbol_table.define(name);
+        }
+
+        if let Err(err) = self.compile_statements(&program.statements, true, false) {
+            // Leave the globals usable for the next program, as the REPL compiles line by line.
+            while let Some(outer) = self.symbol_table.outer.take() {
+                self.symbol_table = *outer;
+            }
+            return Err(err);
+        }
+        self.emit(code::OP_RETURN_VALUE, &[])?;
+
+        let scope = self.scopes.pop().unwrap_or_default();
+        let (instructions, source_map) = self.finish_scope(scope);
+        self.main = Some(Rc::new(object::CompiledFunction {
+            instructions,
+            num_locals: self.symbol_table.num_locals,
+            num_cells: self.symbol_table.num_cells,
+            num_parameters: 0,
+            captures: Vec::new(),
+            cell_names: mem::take(&mut self.symbol_table.cell_names),
+            free_names: Vec::new(),
+            name: String::from("<main>"),
+            source_map,
+        }));
+
+        Ok(())
+    }
+
+    pub fn bytecode(&self) -> Bytecode {
+        Bytecode {
+            main: self.main.clone().expect("nothing compiled"),
+            constants: self.constants.clone(),
+            globals: self.symbol_table.global_names().to_vec(),
+        }
+    }
+
+    fn finish_scope(&self, scope: CompilationScope) -> (Instructions, Vec<(usize, Span)>) {
+        if !self.optimize {
+            return (scope.instructions, scope.source_map);
+        }
+
+        peephole::optimize(&scope.instructions, &scope.source_map)
+    }
+
+    fn scope(&mut self) -> &mut CompilationScope {
+        self.scopes.last_mut().expect("no compilation scope")
+    }
+
+    fn position(&self) -> usize {
+        self.scopes
+            .last()
+            .map_or(0, |scope| scope.instructions.len())
+    }
+
+    fn emit(&mut self, op: Opcode, operands: &[usize]) -> Result<usize, String> {
+        let def = code::lookup(op).ok_or_else(|| format!("opcode {} undefined", op))?;
+        for (operand, width) in operands.iter().zip(def.operand_widths) {
+            if *operand > code::operand_limit(*width) {
+                return Err(format!(
+                    "{} operand {} out of range at {}",
+                    def.name, operand, self.span
+                ));
+            }
+        }
+
+        let span = self.span;
+        let effect = stack_effect(op, operands);
+        let scope = self.scope();
+        let pos = scope.instructions.len();
+
+        if scope.source_map.last().map(|(_, s)| *s) != Some(span) {
+            scope.source_map.push((pos, span));
+        }
+        scope.instructions.extend(code::make(op, operands));
+        scope.depth = scope.depth.saturating_add_signed(effect);
+
+        Ok(pos)
+    }
+
+    // Rewrites the jump target of the instruction at `pos` to point at the current position.
+    fn patch_jump(&mut self, pos: usize) {
+        let target = (self.position() as u32).to_be_bytes();
+        self.scope().instructions[pos + 1..pos + 5].copy_from_slice(&target);
+    }
+
+    fn add_constant(&mut self, obj: Object) -> usize {
+        self.constants.push(obj);
+        self.constants.len() - 1
+    }
+
+    fn compile_statements(
+        &mut self,
+        stmts: &[Box<dyn ast::Statement>],
+        value: bool,
+        tail: bool,
+    ) -> Result<(), String> {
+        if stmts.is_empty() && value {
+            self.emit(code::OP_NULL, &[])?;
+        }
+
+        for (i, stmt) in stmts.iter().enumerate() {
+            let last = i == stmts.len() - 1;
+            self.compile_statement(stmt.as_ref(), value && last, tail && last)?;
+        }
+
+        Ok(())
+    }
+
+    // With `value` set, the statement leaves its value on the stack, as the last statement of a
+    // block does; otherwise it leaves the stack as it found it.
+    fn compile_statement(
+        &mut self,
+        stmt: &dyn ast::Statement,
+        value: bool,
+        tail: bool,
+    ) -> Result<(), String> {
+        let saved = mem::replace(&mut self.span, stmt.span());
+        self.compile_statement_node(stmt, value, tail)?;
+        self.span = saved;
+
+        Ok(())
+    }
+
+    fn compile_statement_node(
+        &mut self,
+        stmt: &dyn ast::Statement,
+        value: bool,
+        tail: bool,
+    ) -> Result<(), String> {
+        let any = stmt.as_any();
+
+        if let Some(stmt) = any.downcast_ref::<ast::ExpressionStatement>() {
+            if tail {
+                self.compile_tail_expression(stmt.expression.as_ref())?;
+            } else {
+                self.compile_expression(stmt.expression.as_ref())?;
+            }
+            if !value {
+                self.emit(code::OP_POP, &[])?;
+            }
+            return Ok(());
+        }
+
+        if let Some(stmt) = any.downcast_ref::<ast::LetStatement>() {
+            self.compile_let_statement(stmt)?;
+        } else if let Some(stmt) = any.downcast_ref::<ast::ReturnStatement>() {
+            if self.tail_calls_allowed() {
+                self.compile_tail_expression(stmt.value.as_ref())?;
+            } else {
+                self.compile_expression(stmt.value.as_ref())?;
+            }
+            self.emit(code::OP_RETURN_VALUE, &[])?;
+        } else if let Some(block) = any.downcast_ref::<ast::BlockStatement>() {
+            return self.compile_statements(&block.statements, value, tail);
+        } else if let Some(stmt) = any.downcast_ref::<ast::AssignStatement>() {
+            self.compile_assign_statement(stmt)?;
+        } else if let Some(ws) = any.downcast_ref::<ast::WhileStatement>() {
+            self.compile_while_statement(ws)?;
+        } else if let Some(fs) = any.downcast_ref::<ast::ForStatement>() {
+            self.compile_for_statement(fs)?;
+        } else if let Some(ts) = any.downcast_ref::<ast::TryStatement>() {
+            self.compile_try_statement(ts)?;
+            if !value {
+                self.emit(code::OP_POP, &[])?;
+            }
+            return Ok(());
+        } else if let Some(ts) = any.downcast_ref::<ast::ThrowStatement>() {
+            self.compile_expression(ts.value.as_ref())?;
+            self.emit(code::OP_THROW, &[])?;
+        } else if any.is::<ast::BreakStatement>() {
+            self.compile_loop_exit(true)?;
+        } else if any.is::<ast::ContinueStatement>() {
+            self.compile_loop_exit(false)?;
+        } else {
+            return Err(format!("cannot compile statement: {}", stmt.string()));
+        }
+
+        // Statements other than expressions and `try` have no value of their own.
+        if value {
+            self.emit(code::OP_NULL, &[])?;
+        }
+
+        Ok(())
+    }
+
+    fn compile_let_statement(&mut self, stmt: &ast::LetStatement) -> Result<(), String> {
+        let name = &stmt.name.value;
+        // A function is bound before its body is compiled, so it can call itself.
+        let symbol = match stmt.value.as_any().downcast_ref::<ast::FunctionLiteral>() {
+            Some(func) => {
+                let symbol = self.symbol_table.define(name.clone());
+                self.compile_function(func, name.as_str())?;
+                symbol
+            }
+            None => {
+                self.compile_expression(stmt.value.as_ref())?;
+                self.symbol_table.define(name.clone())
+            }
+        };
+
+        self.store_symbol(&symbol)
+    }
+
+    fn compile_assign_statement(&mut self, stmt: &ast::AssignStatement) -> Result<(), String> {
+        self.compile_expression(stmt.value.as_ref())?;
+
+        // Compound operators like `+=` apply the operator without its trailing `=`.
+        let operator = stmt.operator.as_str().strip_suffix('=').unwrap_or("");
+        let target = stmt.target.as_any();
+
+        if let Some(ident) = target.downcast_ref::<ast::Identifier>() {
+            let symbol = self.resolve_assignable(&ident.value);
+
+            if !operator.is_empty() {
+                self.load_symbol(&symbol)?;
+                self.emit(code::OP_SWAP, &[])?;
+                self.emit(infix_opcode(operator)?, &[])?;
+            }
+
+            match symbol.scope {
+                GLOBAL_SCOPE => self.emit(code::OP_ASSIGN_GLOBAL, &[symbol.index])?,
+                _ => return self.store_symbol(&symbol),
+            };
+        } else if let Some(ie) = target.downcast_ref::<ast::IndexExpression>() {
+            self.compile_expression(ie.left.as_ref())?;
+            self.compile_expression(ie.index.as_ref())?;
+
+            if operator.is_empty() {
+                self.emit(code::OP_SET_INDEX, &[])?;
+            } else {
+                self.emit(code::OP_UPDATE_INDEX, &[infix_opcode(operator)? as usize])?;
+            }
+        } else {
+            return Err(format!("cannot assign to {}", stmt.target.string()));
+        }
+
+        Ok(())
+    }
+
+    fn compile_while_statement(&mut self, ws: &ast::WhileStatement) -> Result<(), String> {
+        let start = self.position();
+        self.compile_expression(ws.condition.as_ref())?;
+        let exit = self.emit(code::OP_JUMP_NOT_TRUTHY, &[0])?;
+
+        self.enter_loop(start);
+        self.enter_block(&ws.body, None)?;
+        self.compile_statements(&ws.body.statements, false, false)?;
+        self.leave_block();
+        self.emit(code::OP_JUMP, &[start])?;
+
+        self.patch_jump(exit);
+        self.leave_loop();
+
+        Ok(())
+    }
+
+    fn compile_for_statement(&mut self, fs: &ast::ForStatement) -> Result<(), String> {
+        self.compile_expression(fs.iterable.as_ref())?;
+        self.emit(code::OP_ITER, &[])?;
+
+        let next = self.emit(code::OP_ITER_NEXT, &[0])?;
+        // OP_ITER_NEXT only pushes an item when there is one; the loop itself runs without it.
+        self.scope().depth -= 1;
+        self.enter_loop(next);
+        self.scope().depth += 1;
+
+        self.enter_block(&fs.body, Some(fs.variable.value.clone()))?;
+        let symbol = self.symbol_table.define(fs.variable.value.clone());
+        self.store_symbol(&symbol)?;
+        self.compile_statements(&fs.body.statements, false, false)?;
+        self.leave_block();
+        self.emit(code::OP_JUMP, &[next])?;
+
+        // Both running out of items and `break` land here, where the iterator is dropped.
+        self.patch_jump(next);
+        self.leave_loop();
+        self.emit(code::OP_ITER_POP, &[])?;
+
+        Ok(())
+    }
+
+    fn compile_try_statement(&mut self, ts: &ast::TryStatement) -> Result<(), String> {
+        let setup = self.emit(code::OP_SETUP_TRY, &[0])?;
+        let depth = self.scope().depth;
+
+        // A call made inside `try` must return here so its errors can be caught.
+        self.scope().try_depth += 1;
+        self.compile_statements(&ts.body.statements, true, false)?;
+        self.scope().try_depth -= 1;
+
+        self.emit(code::OP_POP_TRY, &[])?;
+        let end = self.emit(code::OP_JUMP, &[0])?;
+
+        // The handler starts with the caught value on the stack in place of the body's value.
+        self.patch_jump(setup);
+        self.scope().depth = depth + 1;
+        self.enter_block(&ts.handler, Some(ts.parameter.value.clone()))?;
+        let symbol = self.symbol_table.define(ts.parameter.value.clone());
+        self.store_symbol(&symbol)?;
+        self.compile_statements(&ts.handler.statements, true, false)?;
+        self.leave_block();
+
+        self.patch_jump(end);
+
+        Ok(())
+    }
+
+    // `break` and `continue` drop anything pending on the stack and leave any `try` entered
+    // inside the loop before jumping.
+    fn compile_loop_exit(&mut self, is_break: bool) -> Result<(), String> {
+        let scope = self.scopes.last().expect("no compilation scope");
+        let (pending, tries, target) = match scope.loops.last() {
+            Some(lp) => (
+                scope.depth - lp.depth,
+                scope.try_depth - lp.try_depth,
+                lp.continue_target,
+            ),
+            None => {
+                let keyword = if is_break { "break" } else { "continue" };
+                return Err(format!("{} outside loop", keyword));
+            }
+        };
+        let depth = scope.depth;
+
+        for _ in 0..pending {
+            self.emit(code::OP_POP, &[])?;
+        }
+        for _ in 0..tries {
+            self.emit(code::OP_POP_TRY, &[])?;
+        }
+
+        if is_break {
+            let pos = self.emit(code::OP_JUMP, &[0])?;
+            if let Some(lp) = self.scope().loops.last_mut() {
+                lp.breaks.push(pos);
+            }
+        } else {
+            self.emit(code::OP_JUMP, &[target])?;
+        }
+
+        // Nothing after the jump runs, but the code that follows expects the stack unchanged.
+        self.scope().depth = depth;
+
+        Ok(())
+    }
+
+    fn enter_loop(&mut self, continue_target: usize) {
+        let scope = self.scope();
+        let lp = Loop {
+            depth: scope.depth,
+            try_depth: scope.try_depth,
+            continue_target,
+            breaks: Vec::new(),
+        };
+        scope.loops.push(lp);
+    }
+
+    fn leave_loop(&mut self) {
+        if let Some(lp) = self.scope().loops.pop() {
+            for pos in lp.breaks {
+                self.patch_jump(pos);
+            }
+        }
+    }
+
+    // Loop bodies and catch handlers get fresh bindings each time they run: their captured
+    // variables live in cells that are replaced on entry.
+    fn enter_block(
+        &mut self,
+        block: &ast::BlockStatement,
+        variable: Option<Name>,
+    ) -> Result<(), String> {
+        let outer = mem::take(&mut self.symbol_table);
+        self.symbol_table = SymbolTable::new_block(outer);
+
+        let mut names = HashSet::new();
+        for stmt in block.statements.iter() {
+            let_names(stmt.as_ref(), &mut names);
+        }
+        if let Some(variable) = variable {
+            names.insert(variable);
+        }
+
+        let mut sorted: Vec<Name> = names.iter().cloned().collect();
+        sorted.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        self.symbol_table.set_pending(names);
+
+        let (start, count) = self.symbol_table.reserve_cells(&sorted);
+        if count > 0 {
+            self.emit(code::OP_NEW_CELLS, &[start, count])?;
+        }
+
+        Ok(())
+    }
+
+    fn leave_block(&mut self) {
+        let mut block = mem::take(&mut self.symbol_table);
+        self.symbol_table = *block.outer.take().expect("block without outer scope");
+    }
+
+    fn tail_calls_allowed(&self) -> bool {
+        self.scopes.len() > 1 && self.scopes.last().is_some_and(|scope| scope.try_depth == 0)
+    }
+
+    fn resolve(&mut self, name: &Name) -> Symbol {
+        match self.symbol_table.resolve(name) {
+            Some(symbol) => symbol,
+            None => self.symbol_table.define_global(name.clone()),
+        }
+    }
+
+    // Like `resolve`, but assigning to a builtin's name targets a global nothing has defined.
+    fn resolve_assignable(&mut self, name: &Name) -> Symbol {
+        match self.resolve(name) {
+            symbol if symbol.scope == BUILTIN_SCOPE => {
+                self.symbol_table.define_global(name.clone())
+            }
+            symbol => symbol,
+        }
+    }
+
+    fn load_symbol(&mut self, symbol: &Symbol) -> Result<usize, String> {
+        let op = match symbol.scope {
+            GLOBAL_SCOPE => code::OP_GET_GLOBAL,
+            LOCAL_SCOPE => code::OP_GET_LOCAL,
+            CELL_SCOPE => code::OP_GET_CELL,
+            FREE_SCOPE => code::OP_GET_FREE,
+            BUILTIN_SCOPE => code::OP_GET_BUILTIN,
+            scope => return Err(format!("cannot load {} symbol {}", scope, symbol.name)),
+        };
+
+        self.emit(op, &[symbol.index])
+    }
+
+    fn store_symbol(&mut self, symbol: &Symbol) -> Result<(), String> {
+        let op = match symbol.scope {
+            GLOBAL_SCOPE => code::OP_SET_GLOBAL,
+            LOCAL_SCOPE => code::OP_SET_LOCAL,
+            CELL_SCOPE => code::OP_SET_CELL,
+            FREE_SCOPE => code::OP_SET_FREE,
+            scope => return Err(format!("cannot store {} symbol {}", scope, symbol.name)),
+        };
+
+        self.emit(op, &[symbol.index])?;
+        Ok(())
+    }
+
+    fn compile_expression(&mut self, expr: &dyn ast::Expression) -> Result<(), String> {
+        let saved = mem::replace(&mut self.span, expr.span());
+        self.compile_expression_node(expr, false)?;
+        self.span = saved;
+
+        Ok(())
+    }
+
+    // Calls in tail position, directly or through `if` branches, reuse the caller's frame.
+    fn compile_tail_expression(&mut self, expr: &dyn ast::Expression) -> Result<(), String> {
+        let saved = mem::replace(&mut self.span, expr.span());
+        self.compile_expression_node(expr, true)?;
+        self.span = saved;
+
+        Ok(())
+    }
+
+    fn compile_expression_node(
+        &mut self,
+        expr: &dyn ast::Expression,
+        tail: bool,
+    ) -> Result<(), String> {
+        let any = expr.as_any();
+
+        if let Some(lit) = any.downcast_ref::<ast::IntegerLiteral>() {
+            let index = self.add_constant(Object::Integer(lit.value));
+            self.emit(code::OP_CONSTANT, &[index])?;
+        } else if let Some(lit) = any.downcast_ref::<ast::BigIntegerLiteral>() {
+            let index = self.add_constant(Object::BigInteger(Rc::clone(&lit.value)));
+            self.emit(code::OP_CONSTANT, &[index])?;
+        } else if let Some(lit) = any.downcast_ref::<ast::Boolean>() {
+            let op = if lit.value {
+                code::OP_TRUE
+            } else {
+                code::OP_FALSE
+            };
+            self.emit(op, &[])?;
+        } else if any.is::<ast::NullLiteral>() {
+            self.emit(code::OP_NULL, &[])?;
+        } else if let Some(lit) = any.downcast_ref::<ast::StringLiteral>() {
+            let index = self.add_constant(Object::String(lit.value.text()));
+            self.emit(code::OP_CONSTANT, &[index])?;
+        } else if let Some(lit) = any.downcast_ref::<ast::ArrayLiteral>() {
+            for element in lit.elements.iter() {
+                self.compile_expression(element.as_ref())?;
+            }
+            self.emit(code::OP_ARRAY, &[lit.elements.len()])?;
+        } else if let Some(lit) = any.downcast_ref::<ast::HashLiteral>() {
+            for (key, value) in lit.pairs.iter() {
+                self.compile_expression(key.as_ref())?;
+                self.compile_expression(value.as_ref())?;
+            }
+            self.emit(code::OP_HASH, &[lit.pairs.len()])?;
+        } else if let Some(ident) = any.downcast_ref::<ast::Identifier>() {
+            let symbol = self.resolve(&ident.value);
+            self.load_symbol(&symbol)?;
+        } else if let Some(prefix) = any.downcast_ref::<ast::PrefixExpression>() {
+            self.compile_expression(prefix.right.as_ref())?;
+            let op = match prefix.operator.as_str() {
+                "!" => code::OP_BANG,
+                "-" => code::OP_MINUS,
+                "~" => code::OP_BIT_NOT,
+                operator => return Err(format!("unknown operator: {}", operator)),
+            };
+            self.emit(op, &[])?;
+        } else if let Some(infix) = any.downcast_ref::<ast::InfixExpression>() {
+            self.compile_expression(infix.left.as_ref())?;
+            self.compile_expression(infix.right.as_ref())?;
+            self.emit(infix_opcode(infix.operator.as_str())?, &[])?;
+        } else if let Some(ie) = any.downcast_ref::<ast::IndexExpression>() {
+            self.compile_expression(ie.left.as_ref())?;
+            self.compile_expression(ie.index.as_ref())?;
+            self.emit(code::OP_INDEX, &[])?;
+        } else if let Some(se) = any.downcast_ref::<ast::SliceExpression>() {
+            self.compile_expression(se.left.as_ref())?;
+
+            let mut present = 0;
+            for (bit, bound) in [
+                (code::SLICE_START, &se.start),
+                (code::SLICE_END, &se.end),
+                (code::SLICE_STEP, &se.step),
+            ] {
+                if let Some(bound) = bound {
+                    self.compile_expression(bound.as_ref())?;
+                    present |= bit;
+                }
+            }
+            self.emit(code::OP_SLICE, &[present])?;
+        } else if let Some(ie) = any.downcast_ref::<ast::IfExpression>() {
+            self.compile_if_expression(ie, tail)?;
+        } else if let Some(func) = any.downcast_ref::<ast::FunctionLiteral>() {
+            self.compile_function(func, "fn")?;
+        } else if let Some(call) = any.downcast_ref::<ast::CallExpression>() {
+            self.compile_expression(call.function.as_ref())?;
+            for arg in call.arguments.iter() {
+                self.compile_expression(arg.as_ref())?;
+            }
+
+            let op = if tail && self.tail_calls_allowed() {
+                code::OP_TAIL_CALL
+            } else {
+                code::OP_CALL
+            };
+            self.emit(op, &[call.arguments.len()])?;
+        } else {
+            return Err(format!("cannot compile expression: {}", expr.string()));
+        }
+
+        Ok(())
+    }
+
+    fn compile_if_expression(&mut self, ie: &ast::IfExpression, tail: bool) -> Result<(), String> {
+        self.compile_expression(ie.condition.as_ref())?;
+        let jump_not_truthy = self.emit(code::OP_JUMP_NOT_TRUTHY, &[0])?;
+        let depth = self.scope().depth;
+
+        self.compile_statements(&ie.consequence.statements, true, tail)?;
+        let jump = self.emit(code::OP_JUMP, &[0])?;
+
+        self.patch_jump(jump_not_truthy);
+        self.scope().depth = depth;
+
+        match &ie.alternative {
+            Some(ast::Alternative::Block(block)) => {
+                self.compile_statements(&block.statements, true, tail)?;
+            }
+            Some(ast::Alternative::If(alt)) => {
+                let saved = mem::replace(&mut self.span, alt.span());
+                self.compile_if_expression(alt, tail)?;
+                self.span = saved;
+            }
+            None => {
+                self.emit(code::OP_NULL, &[])?;
+            }
+        }
+
+        self.patch_jump(jump);
+
+        Ok(())
+    }
+
+    fn compile_function(&mut self, func: &ast::FunctionLiteral, name: &str) -> Result<(), String> {
+        let mut captured = HashSet::new();
+        let mut pending = HashSet::new();
+        for stmt in func.body.statements.iter() {
+            captured_names(Node::Statement(stmt.as_ref()), &mut captured);
+            let_names(stmt.as_ref(), &mut pending);
+        }
+
+        let outer = mem::take(&mut self.symbol_table);
+        self.symbol_table = SymbolTable::new_enclosed(outer);
+        self.symbol_table.set_captured(captured);
+        self.symbol_table.set_pending(pending);
+        self.scopes.push(CompilationScope::default());
+
+        let result = self.compile_function_body(func);
+
+        let scope = self.scopes.pop().unwrap_or_default();
+        let mut table = mem::take(&mut self.symbol_table);
+        self.symbol_table = *table.outer.take().expect("function without outer scope");
+        result?;
+
+        let (instructions, source_map) = self.finish_scope(scope);
+        let captures = table
+            .free_symbols
+            .iter()
+            .map(|symbol| match symbol.scope {
+                CELL_SCOPE => Ok(object::Capture::Cell(symbol.index)),
+                FREE_SCOPE => Ok(object::Capture::Free(symbol.index)),
+                scope => Err(format!("cannot capture {} symbol {}", scope, symbol.name)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let compiled = object::CompiledFunction {
+            instructions,
+            num_locals: table.num_locals,
+            num_cells: table.num_cells,
+            num_parameters: func.parameters.len(),
+            captures,
+            cell_names: table.cell_names,
+            free_names: table
+                .free_symbols
+                .iter()
+                .map(|symbol| symbol.name.to_string())
+                .collect(),
+            name: name.to_string(),
+            source_map,
+        };
+
+        let index = self.add_constant(Object::CompiledFunction(Rc::new(compiled)));
+        self.emit(code::OP_CLOSURE, &[index])?;
+
+        Ok(())
+    }
+
+    fn compile_function_body(&mut self, func: &ast::FunctionLiteral) -> Result<(), String> {
+        // Captured parameters move from their argument slot into a cell before the body runs.
+        for param in func.parameters.iter() {
+            let (slot, symbol) = self.symbol_table.define_parameter(param.value.clone());
+            if symbol.scope == CELL_SCOPE {
+                self.emit(code::OP_GET_LOCAL, &[slot])?;
+                self.emit(code::OP_SET_CELL, &[symbol.index])?;
+            }
+        }
+
+        self.compile_statements(&func.body.statements, true, true)?;
+        self.emit(code::OP_RETURN_VALUE, &[])?;
+
+        Ok(())
+    }
+}
+
+fn infix_opcode(operator: &str) -> Result<Opcode, String> {
+    let op = match operator {
+        "+" => code::OP_ADD,
+        "-" => code::OP_SUB,
+        "*" => code::OP_MUL,
+        "/" => code::OP_DIV,
+        "%" => code::OP_MOD,
+        "**" => code::OP_POW,
+        "&" => code::OP_BIT_AND,
+        "|" => code::OP_BIT_OR,
+        "^" => code::OP_BIT_XOR,
+        "<<" => code::OP_SHL,
+        ">>" => code::OP_SHR,
+        "==" => code::OP_EQUAL,
+        "!=" => code::OP_NOT_EQUAL,
+        "<" => code::OP_LESS,
+        "<=" => code::OP_LESS_EQUAL,
+        ">" => code::OP_GREATER,
+        ">=" => code::OP_GREATER_EQUAL,
+        ".." => code::OP_RANGE,
+        _ => return Err(format!("unknown operator: {}", operator)),
+    };
+
+    Ok(op)
+}
+
+// How many values an instruction leaves on the stack compared to before it ran.
+fn stack_effect(op: Opcode, operands: &[usize]) -> isize {
+    let operand = operands.first().copied().unwrap_or(0) as isize;
+
+    match op {
+        code::OP_CONSTANT
+        | code::OP_TRUE
+        | code::OP_FALSE
+        | code::OP_NULL
+        | code::OP_GET_GLOBAL
+        | code::OP_GET_LOCAL
+        | code::OP_GET_CELL
+        | code::OP_GET_FREE
+        | code::OP_GET_BUILTIN
+        | code::OP_CLOSURE
+        | code::OP_ITER_NEXT => 1,
+        code::OP_ADD..=code::OP_RANGE => -1,
+        code::OP_POP
+        | code::OP_JUMP_NOT_TRUTHY
+        | code::OP_SET_GLOBAL
+        | code::OP_ASSIGN_GLOBAL
+        | code::OP_SET_LOCAL
+        | code::OP_SET_CELL
+        | code::OP_SET_FREE
+        | code::OP_INDEX
+        | code::OP_RETURN_VALUE
+        | code::OP_THROW
+        | code::OP_ITER => -1,
+        code::OP_SET_INDEX | code::OP_UPDATE_INDEX => -3,
+        code::OP_ARRAY => 1 - operand,
+        code::OP_HASH => 1 - 2 * operand,
+        code::OP_SLICE => -(operand.count_ones() as isize),
+        code::OP_CALL | code::OP_TAIL_CALL => -operand,
+        _ => 0,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Node<'a> {
+    Statement(&'a dyn ast::Statement),
+    Expression(&'a dyn ast::Expression),
+}
+
+// Calls `visit` on `node` and, as long as it returns true, on everything nested inside it.
+fn walk<'a>(node: Node<'a>, visit: &mut dyn FnMut(Node<'a>) -> bool) {
+    if !visit(node) {
+        return;
+    }
+
+    let mut statements: Vec<&'a dyn ast::Statement> = Vec::new();
+    let mut expressions: Vec<&'a dyn ast::Expression> = Vec::new();
+
+    match node {
+        Node::Statement(stmt) => {
+            let any = stmt.as_any();
+            if let Some(stmt) = any.downcast_ref::<ast::ExpressionStatement>() {
+                expressions.push(stmt.expression.as_ref());
+            } else if let Some(stmt) = any.downcast_ref::<ast::LetStatement>() {
+                expressions.push(stmt.name.as_ref());
+                expressions.push(stmt.value.as_ref());
+            } else if let Some(stmt) = any.downcast_ref::<ast::ReturnStatement>() {
+                expressions.push(stmt.value.as_ref());
+            } else if let Some(block) = any.downcast_ref::<ast::BlockStatement>() {
+                statements.extend(block.statements.iter().map(|s| s.as_ref()));
+            } else if let Some(stmt) = any.downcast_ref::<ast::AssignStatement>() {
+                expressions.push(stmt.target.as_ref());
+                expressions.push(stmt.value.as_ref());
+            } else if let Some(ws) = any.downcast_ref::<ast::WhileStatement>() {
+                expressions.push(ws.condition.as_ref());
+                statements.push(ws.body.as_ref());
+            } else if let Some(fs) = any.downcast_ref::<ast::ForStatement>() {
+                expressions.push(&fs.variable);
+                expressions.push(fs.iterable.as_ref());
+                statements.push(fs.body.as_ref());
+            } else if let Some(ts) = any.downcast_ref::<ast::ThrowStatement>() {
+                expressions.push(ts.value.as_ref());
+            } else if let Some(ts) = any.downcast_ref::<ast::TryStatement>() {
+                statements.push(ts.body.as_ref());
+                expressions.push(&ts.parameter);
+                statements.push(ts.handler.as_ref());
+            }
+        }
+        Node::Expression(expr) => {
+            let any = expr.as_any();
+            if let Some(lit) = any.downcast_ref::<ast::ArrayLiteral>() {
+                expressions.extend(lit.elements.iter().map(|e| e.as_ref()));
+            } else if let Some(lit) = any.downcast_ref::<ast::HashLiteral>() {
+                for (key, value) in lit.pairs.iter() {
+                    expressions.push(key.as_ref());
+                    expressions.push(value.as_ref());
+                }
+            } else if let Some(prefix) = any.downcast_ref::<ast::PrefixExpression>() {
+                expressions.push(prefix.right.as_ref());
+            } else if let Some(infix) = any.downcast_ref::<ast::InfixExpression>() {
+                expressions.push(infix.left.as_ref());
+                expressions.push(infix.right.as_ref());
+            } else if let Some(ie) = any.downcast_ref::<ast::IndexExpression>() {
+                expressions.push(ie.left.as_ref());
+                expressions.push(ie.index.as_ref());
+            } else if let Some(se) = any.downcast_ref::<ast::SliceExpression>() {
+                expressions.push(se.left.as_ref());
+                for bound in [&se.start, &se.end, &se.step].into_iter().flatten() {
+                    expressions.push(bound.as_ref());
+                }
+            } else if let Some(ie) = any.downcast_ref::<ast::IfExpression>() {
+                expressions.push(ie.condition.as_ref());
+                statements.push(ie.consequence.as_ref());
+                match &ie.alternative {
+                    Some(ast::Alternative::Block(block)) => statements.push(block.as_ref()),
+                    Some(ast::Alternative::If(alt)) => expressions.push(alt.as_ref()),
+                    None => (),
+                }
+            } else if let Some(func) = any.downcast_ref::<ast::FunctionLiteral>() {
+                expressions.extend(func.parameters.iter().map(|p| p as &dyn ast::Expression));
+                statements.push(func.body.as_ref());
+            } else if let Some(call) = any.downcast_ref::<ast::CallExpression>() {
+                expressions.push(call.function.as_ref());
+                expressions.extend(call.arguments.iter().map(|a| a.as_ref()));
+            }
+        }
+    }
+
+    for stmt in statements {
+        walk(Node::Statement(stmt), visit);
+    }
+    for expr in expressions {
+        walk(Node::Expression(expr), visit);
+    }
+}
+
+// Every identifier that appears inside a function nested in `node`.
+fn captured_names(node: Node, names: &mut HashSet<Name>) {
+    walk(node, &mut |node| {
+        let func = match node {
+            Node::Expression(expr) => expr.as_any().downcast_ref::<ast::FunctionLiteral>(),
+            Node::Statement(_) => None,
+        };
+        if func.is_none() {
+            return true;
+        }
+
+        walk(node, &mut |inner| {
+            if let Node::Expression(expr) = inner
+                && let Some(ident) = expr.as_any().downcast_ref::<ast::Identifier>()
+            {
+                names.insert(ident.value.clone());
+            }
+            true
+        });
+        false
+    });
+}
+
+// Names `let` binds in the scope `stmt` belongs to. `if` branches and `try` bodies share their
+// enclosing scope; loop bodies, catch handlers and functions have their own.
+fn let_names(stmt: &dyn ast::Statement, names: &mut HashSet<Name>) {
+    walk(Node::Statement(stmt), &mut |node| match node {
+        Node::Statement(stmt) => {
+            let any = stmt.as_any();
+            if let Some(stmt) = any.downcast_ref::<ast::LetStatement>() {
+                names.insert(stmt.name.value.clone());
+            } else if let Some(ts) = any.downcast_ref::<ast::TryStatement>() {
+                for stmt in ts.body.statements.iter() {
+                    let_names(stmt.as_ref(), names);
+                }
+                return false;
+            }
+            !any.is::<ast::WhileStatement>() && !any.is::<ast::ForStatement>()
+        }
+        Node::Expression(expr) => !expr.as_any().is::<ast::FunctionLiteral>(),
+    });
+}
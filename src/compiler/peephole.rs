@@ -0,0 +1,247 @@
+#[cfg(test)]
+mod tests;
+
+use crate::code::{self, Instructions, Opcode};
+use crate::token::Span;
+use std::collections::HashSet;
+
+// One decoded instruction. Jump operands hold the index of the target instruction rather than a
+// byte offset while the stream is being rewritten; the index one past the end means the end.
+struct Instruction {
+    op: Opcode,
+    operands: Vec<usize>,
+    span: Option<Span>,
+}
+
+// Rewrites a function's instructions without changing what they do: threads jumps to jumps,
+// settles jumps on constant conditions, drops values pushed only to be popped and removes code
+// nothing can reach. Returns the new instructions with a source map to match.
+pub fn optimize(
+    instructions: &[u8],
+    source_map: &[(usize, Span)],
+) -> (Instructions, Vec<(usize, Span)>) {
+    let mut stream = decode(instructions, source_map);
+
+    while thread_jumps(&mut stream) | simplify(&mut stream) | remove_unreachable(&mut stream) {}
+
+    encode(&stream)
+}
+
+fn is_jump(op: Opcode) -> bool {
+    matches!(
+        op,
+        code::OP_JUMP | code::OP_JUMP_NOT_TRUTHY | code::OP_SETUP_TRY | code::OP_ITER_NEXT
+    )
+}
+
+fn decode(instructions: &[u8], source_map: &[(usize, Span)]) -> Vec<Instruction> {
+    let mut stream = Vec::new();
+    let mut offsets = Vec::new();
+    let mut offset = 0;
+
+    while offset < instructions.len() {
+        let op = instructions[offset];
+        let def = code::lookup(op).expect("compiler emitted an unknown opcode");
+        let (operands, read) = code::read_operands(def, &instructions[offset + 1..]);
+
+        let i = source_map.partition_point(|(start, _)| *start <= offset);
+        let span = i.checked_sub(1).map(|i| source_map[i].1);
+
+        offsets.push(offset);
+        stream.push(Instruction { op, operands, span });
+        offset += 1 + read;
+    }
+
+    for ins in stream.iter_mut().filter(|ins| is_jump(ins.op)) {
+        ins.operands[0] = offsets.partition_point(|offset| *offset < ins.operands[0]);
+    }
+
+    stream
+}
+
+fn encode(stream: &[Instruction]) -> (Instructions, Vec<(usize, Span)>) {
+    let mut offsets = Vec::with_capacity(stream.len() + 1);
+    let mut offset = 0;
+    for ins in stream.iter() {
+        offsets.push(offset);
+        offset += 1 + code::lookup(ins.op).map_or(0, |def| def.operand_widths.iter().sum());
+    }
+    offsets.push(offset);
+
+    let mut instructions = Vec::with_capacity(offset);
+    let mut source_map: Vec<(usize, Span)> = Vec::new();
+
+    for (ins, offset) in stream.iter().zip(offsets.iter()) {
+        if let Some(span) = ins.span
+            && source_map.last().is_none_or(|(_, last)| *last != span)
+        {
+            source_map.push((*offset, span));
+        }
+
+        let mut operands = ins.operands.clone();
+        if is_jump(ins.op) {
+            operands[0] = offsets[operands[0]];
+        }
+        instructions.extend(code::make(ins.op, &operands));
+    }
+
+    (instructions, source_map)
+}
+
+fn jump_targets(stream: &[Instruction]) -> HashSet<usize> {
+    stream
+        .iter()
+        .filter(|ins| is_jump(ins.op))
+        .map(|ins| ins.operands[0])
+        .collect()
+}
+
+// Drops the marked instructions. Jumps to a dropped instruction go on to whatever follows it,
+// which is where control would have gone next.
+fn remove(stream: &mut Vec<Instruction>, removed: &[bool]) -> bool {
+    if !removed.contains(&true) {
+        return false;
+    }
+
+    let mut new_index = Vec::with_capacity(removed.len() + 1);
+    let mut kept = 0;
+    for is_removed in removed.iter() {
+        new_index.push(kept);
+        if !is_removed {
+            kept += 1;
+        }
+    }
+    new_index.push(kept);
+
+    let mut i = 0;
+    stream.retain(|_| {
+        i += 1;
+        !removed[i - 1]
+    });
+    for ins in stream.iter_mut().filter(|ins| is_jump(ins.op)) {
+        ins.operands[0] = new_index[ins.operands[0]];
+    }
+
+    true
+}
+
+// A jump that lands on an unconditional jump can go straight to where that one leads.
+fn thread_jumps(stream: &mut [Instruction]) -> bool {
+    let mut changed = false;
+
+    for i in 0..stream.len() {
+        if !is_jump(stream[i].op) {
+            continue;
+        }
+
+        let mut target = stream[i].operands[0];
+        let mut hops = 0;
+        while hops < stream.len()
+            && let Some(next) = stream.get(target)
+            && next.op == code::OP_JUMP
+        {
+            target = next.operands[0];
+            hops += 1;
+        }
+
+        // A chain that loops back on itself is an infinite loop; leave it as written.
+        if hops < stream.len() && target != stream[i].operands[0] {
+            stream[i].operands[0] = target;
+            changed = true;
+        }
+    }
+
+    changed
+}
+
+// Rewrites short sequences that do nothing, or whose outcome is known. The instructions after
+// the first in a sequence can't be jump targets, or another path would see the rewrite too.
+fn simplify(stream: &mut Vec<Instruction>) -> bool {
+    let targets = jump_targets(stream);
+    let mut removed = vec![false; stream.len()];
+    let mut changed = false;
+
+    let mut i = 0;
+    while i < stream.len() {
+        let op = stream[i].op;
+        let next = stream
+            .get(i + 1)
+            .filter(|_| !targets.contains(&(i + 1)))
+            .map(|ins| ins.op);
+
+        match (op, next) {
+            // Pushing a value with no side effects only to pop it again.
+            (
+                code::OP_CONSTANT
+                | code::OP_TRUE
+                | code::OP_FALSE
+                | code::OP_NULL
+                | code::OP_GET_LOCAL
+                | code::OP_GET_CELL
+                | code::OP_GET_FREE
+                | code::OP_GET_BUILTIN
+                | code::OP_CLOSURE,
+                Some(code::OP_POP),
+            ) => {
+                removed[i] = true;
+                removed[i + 1] = true;
+                i += 2;
+                continue;
+            }
+            // A condition that's always true never jumps.
+            (code::OP_TRUE, Some(code::OP_JUMP_NOT_TRUTHY)) => {
+                removed[i] = true;
+                removed[i + 1] = true;
+                i += 2;
+                continue;
+            }
+            // One that's always false always does.
+            (code::OP_FALSE | code::OP_NULL, Some(code::OP_JUMP_NOT_TRUTHY)) => {
+                removed[i] = true;
+                stream[i + 1].op = code::OP_JUMP;
+                changed = true;
+                i += 2;
+                continue;
+            }
+            // A jump to the very next instruction.
+            (code::OP_JUMP, _) if stream[i].operands[0] == i + 1 => removed[i] = true,
+            (code::OP_JUMP_NOT_TRUTHY, _) if stream[i].operands[0] == i + 1 => {
+                stream[i].op = code::OP_POP;
+                stream[i].operands.clear();
+                changed = true;
+            }
+            _ => (),
+        }
+
+        i += 1;
+    }
+
+    remove(stream, &removed) || changed
+}
+
+// Code after a return, throw or unconditional jump that no jump leads to never runs.
+fn remove_unreachable(stream: &mut Vec<Instruction>) -> bool {
+    let mut reachable = vec![false; stream.len()];
+    let mut pending = vec![0];
+
+    while let Some(i) = pending.pop() {
+        if i >= stream.len() || reachable[i] {
+            continue;
+        }
+        reachable[i] = true;
+
+        let ins = &stream[i];
+        if is_jump(ins.op) {
+            pending.push(ins.operands[0]);
+        }
+        if !matches!(
+            ins.op,
+            code::OP_JUMP | code::OP_RETURN_VALUE | code::OP_THROW
+        ) {
+            pending.push(i + 1);
+        }
+    }
+
+    let removed: Vec<bool> = reachable.iter().map(|reachable| !reachable).collect();
+    remove(stream, &removed)
+}
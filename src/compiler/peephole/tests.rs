@@ -0,0 +1,220 @@
+use super::*;
+use crate::code::make;
+use crate::compiler::Compiler;
+use crate::object::Object;
+use crate::test_helpers::{describe, parse};
+use crate::vm::VM;
+
+fn span(line: usize) -> Span {
+    Span { line, column: 1 }
+}
+
+// Lays out the instructions with the n-th one mapped to line n + 1, so the map shows where each
+// surviving instruction came from.
+fn optimized(instructions: Vec<Instructions>) -> (Instructions, Vec<(usize, Span)>) {
+    let mut source_map = Vec::new();
+    let mut offset = 0;
+    for (i, ins) in instructions.iter().enumerate() {
+        source_map.push((offset, span(i + 1)));
+        offset += ins.len();
+    }
+
+    optimize(&instructions.concat(), &source_map)
+}
+
+#[test]
+fn test_optimize() {
+    let tests = vec![
+        (
+            "values pushed only to be popped",
+            vec![
+                make(code::OP_CONSTANT, &[0]),
+                make(code::OP_POP, &[]),
+                make(code::OP_GET_LOCAL, &[0]),
+                make(code::OP_POP, &[]),
+                make(code::OP_NULL, &[]),
+                make(code::OP_RETURN_VALUE, &[]),
+            ],
+            vec![make(code::OP_NULL, &[]), make(code::OP_RETURN_VALUE, &[])],
+        ),
+        (
+            "a global lookup can fail, so it stays",
+            vec![
+                make(code::OP_GET_GLOBAL, &[0]),
+                make(code::OP_POP, &[]),
+                make(code::OP_NULL, &[]),
+                make(code::OP_RETURN_VALUE, &[]),
+            ],
+            vec![
+                make(code::OP_GET_GLOBAL, &[0]),
+                make(code::OP_POP, &[]),
+                make(code::OP_NULL, &[]),
+                make(code::OP_RETURN_VALUE, &[]),
+            ],
+        ),
+        (
+            "a condition that's always true",
+            vec![
+                make(code::OP_TRUE, &[]),
+                make(code::OP_JUMP_NOT_TRUTHY, &[10]),
+                make(code::OP_CONSTANT, &[0]),
+                make(code::OP_RETURN_VALUE, &[]),
+                make(code::OP_NULL, &[]),
+                make(code::OP_RETURN_VALUE, &[]),
+            ],
+            vec![
+                make(code::OP_CONSTANT, &[0]),
+                make(code::OP_RETURN_VALUE, &[]),
+            ],
+        ),
+        (
+            "a condition that's always false",
+            vec![
+                make(code::OP_FALSE, &[]),
+                make(code::OP_JUMP_NOT_TRUTHY, &[10]),
+                make(code::OP_CONSTANT, &[0]),
+                make(code::OP_RETURN_VALUE, &[]),
+                make(code::OP_NULL, &[]),
+                make(code::OP_RETURN_VALUE, &[]),
+            ],
+            vec![make(code::OP_NULL, &[]), make(code::OP_RETURN_VALUE, &[])],
+        ),
+        (
+            "jumps to jumps",
+            vec![
+                make(code::OP_GET_LOCAL, &[0]),
+                make(code::OP_JUMP_NOT_TRUTHY, &[12]),
+                make(code::OP_CONSTANT, &[0]),
+                make(code::OP_RETURN_VALUE, &[]),
+                make(code::OP_JUMP, &[17]),
+                make(code::OP_CONSTANT, &[1]),
+                make(code::OP_RETURN_VALUE, &[]),
+            ],
+            vec![
+                make(code::OP_GET_LOCAL, &[0]),
+                make(code::OP_JUMP_NOT_TRUTHY, &[12]),
+                make(code::OP_CONSTANT, &[0]),
+                make(code::OP_RETURN_VALUE, &[]),
+                make(code::OP_CONSTANT, &[1]),
+                make(code::OP_RETURN_VALUE, &[]),
+            ],
+        ),
+        (
+            "a jump to the next instruction",
+            vec![
+                make(code::OP_JUMP, &[5]),
+                make(code::OP_GET_LOCAL, &[0]),
+                make(code::OP_JUMP_NOT_TRUTHY, &[13]),
+                make(code::OP_NULL, &[]),
+                make(code::OP_RETURN_VALUE, &[]),
+            ],
+            vec![make(code::OP_NULL, &[]), make(code::OP_RETURN_VALUE, &[])],
+        ),
+        (
+            "code after a return",
+            vec![
+                make(code::OP_NULL, &[]),
+                make(code::OP_RETURN_VALUE, &[]),
+                make(code::OP_GET_GLOBAL, &[0]),
+                make(code::OP_RETURN_VALUE, &[]),
+            ],
+            vec![make(code::OP_NULL, &[]), make(code::OP_RETURN_VALUE, &[])],
+        ),
+        (
+            "an infinite loop is left alone",
+            vec![make(code::OP_JUMP, &[0])],
+            vec![make(code::OP_JUMP, &[0])],
+        ),
+    ];
+
+    for (name, input, expected) in tests {
+        let (instructions, _) = optimized(input);
+        assert_eq!(
+            code::disassemble(&instructions, &[]),
+            code::disassemble(&expected.concat(), &[]),
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_optimize_source_map() {
+    let (instructions, source_map) = optimized(vec![
+        make(code::OP_CONSTANT, &[0]),
+        make(code::OP_POP, &[]),
+        make(code::OP_TRUE, &[]),
+        make(code::OP_JUMP_NOT_TRUTHY, &[10]),
+        make(code::OP_GET_LOCAL, &[0]),
+        make(code::OP_GET_LOCAL, &[1]),
+        make(code::OP_ADD, &[]),
+        make(code::OP_RETURN_VALUE, &[]),
+    ]);
+
+    assert_eq!(
+        instructions,
+        [
+            make(code::OP_GET_LOCAL, &[0]),
+            make(code::OP_GET_LOCAL, &[1]),
+            make(code::OP_ADD, &[]),
+            make(code::OP_RETURN_VALUE, &[]),
+        ]
+        .concat()
+    );
+    assert_eq!(
+        source_map,
+        vec![(0, span(5)), (3, span(6)), (6, span(7)), (7, span(8))]
+    );
+}
+
+fn run(input: &str, optimize: bool) -> (Object, usize) {
+    let mut compiler = Compiler::new();
+    compiler.set_optimize(optimize);
+    compiler.compile(&parse(input)).unwrap();
+    let bytecode = compiler.bytecode();
+
+    let size = bytecode.main.instructions.len()
+        + bytecode
+            .constants
+            .iter()
+            .map(|constant| match constant {
+                Object::CompiledFunction(function) => function.instructions.len(),
+                _ => 0,
+            })
+            .sum::<usize>();
+    (VM::new().run(&bytecode), size)
+}
+
+#[test]
+fn test_optimize_preserves_behaviour() {
+    let tests = [
+        "let x = 1; x; x; x + 1",
+        "if (true) { 1 } else { 2 }",
+        "if (false) { 1 }",
+        "let n = 0; while (true) { n += 1; if (n == 10) { break; } } n",
+        "let n = 0; while (n < 10) { if (n % 2 == 0) { n += 3; continue; } n += 1; } n",
+        "let sum = 0; for (i in 0..10) { if (i == 7) { break; } sum += i; } sum",
+        "let f = fn(x) { if (x > 1) { return x; } else { return 0; } 99 }; f(5) + f(0)",
+        "let f = fn(n) { if (n == 0) { 0 } else { f(n - 1) } }; f(100)",
+        "let make = fn() { let c = 0; fn() { c += 1; c } }; let c = make(); c(); c(); c()",
+        "try { throw \"boom\"; 1 } catch (e) { \"caught \" + e }",
+        "let f = fn() {\n  let x = 1;\n  x;\n  x + true\n};\nf()",
+        "let f = fn(a) {\n  if (true) {\n    a[10]\n  }\n};\nf([1])",
+        "let a = [1, 2, 3]; a[0] += 5; a",
+        "let f = fn() { while (true) { return 4; } }; f()",
+        "undefined_name",
+    ];
+
+    for input in tests.iter() {
+        let (expected, plain_size) = run(input, false);
+        let (actual, optimized_size) = run(input, true);
+        assert_eq!(describe(&actual), describe(&expected), "input: {}", input);
+        assert!(
+            optimized_size <= plain_size,
+            "input: {} grew from {} to {} bytes",
+            input,
+            plain_size,
+            optimized_size
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use crate::object::builtins;
+use crate::symbol::Symbol as Name;
+use std::collections::{HashMap, HashSet};
+
+pub type SymbolScope = &'static str;
+
+pub const GLOBAL_SCOPE: SymbolScope = "GLOBAL";
+pub const LOCAL_SCOPE: SymbolScope = "LOCAL";
+// A local captured by a closure, kept in a shared cell so assignments are seen on both sides.
+pub const CELL_SCOPE: SymbolScope = "CELL";
+pub const FREE_SCOPE: SymbolScope = "FREE";
+pub const BUILTIN_SCOPE: SymbolScope = "BUILTIN";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: Name,
+    pub scope: SymbolScope,
+    pub index: usize,
+}
+
+// One table per scope. Function tables (and the top-level table) own a call frame's slots;
+// block tables, for loop bodies and catch handlers, hand out slots from their frame's table
+// and hide their names once the block ends.
+#[derive(Default)]
+pub struct SymbolTable {
+    pub outer: Option<Box<SymbolTable>>,
+    store: HashMap<Name, Symbol>,
+    block: bool,
+    // Names bound by `let` anywhere in this scope. A nested function may refer to one before
+    // its `let` has run, as mutually recursive local functions do.
+    pending: HashSet<Name>,
+    // Cells set aside when a block is entered, so they can all be renewed on each iteration.
+    reserved: HashMap<Name, usize>,
+    // Frame tables only: names some nested function refers to, which must live in cells.
+    captured: HashSet<Name>,
+    pub num_locals: usize,
+    pub num_cells: usize,
+    // Frame tables only: the variable each cell holds, for errors about reading it unset.
+    pub cell_names: Vec<String>,
+    pub free_symbols: Vec<Symbol>,
+    globals: Vec<String>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    pub fn new_enclosed(outer: SymbolTable) -> SymbolTable {
+        SymbolTable {
+            outer: Some(Box::new(outer)),
+            ..SymbolTable::default()
+        }
+    }
+
+    pub fn new_block(outer: SymbolTable) -> SymbolTable {
+        SymbolTable {
+            outer: Some(Box::new(outer)),
+            block: true,
+            ..SymbolTable::default()
+        }
+    }
+
+    pub fn is_global(&self) -> bool {
+        self.outer.is_none()
+    }
+
+    pub fn set_captured(&mut self, captured: HashSet<Name>) {
+        self.captured = captured;
+    }
+
+    pub fn set_pending(&mut self, pending: HashSet<Name>) {
+        self.pending = pending;
+    }
+
+    // Names of the global slots, by index.
+    pub fn global_names(&self) -> &[String] {
+        match &self.outer {
+            Some(outer) => outer.global_names(),
+            None => &self.globals,
+        }
+    }
+
+    fn frame_mut(&mut self) -> &mut SymbolTable {
+        if self.block {
+            self.outer
+                .as_mut()
+                .expect("block without frame")
+                .frame_mut()
+        } else {
+            self
+        }
+    }
+
+    pub fn is_captured(&self, name: &Name) -> bool {
+        match &self.outer {
+            Some(outer) if self.block => outer.is_captured(name),
+            _ => self.captured.contains(name),
+        }
+    }
+
+    // Sets aside a cell for each captured name and returns the range they occupy.
+    pub fn reserve_cells(&mut self, names: &[Name]) -> (usize, usize) {
+        let start = self.frame_mut().num_cells;
+
+        for name in names.iter() {
+            if self.is_captured(name) && !self.reserved.contains_key(name) {
+                let frame = self.frame_mut();
+                let index = frame.num_cells;
+                frame.num_cells += 1;
+                frame.cell_names.push(name.to_string());
+                self.reserved.insert(name.clone(), index);
+            }
+        }
+
+        (start, self.frame_mut().num_cells - start)
+    }
+
+    // Defining a name already bound in this scope reuses its slot, like `let` rebinding in place.
+    pub fn define(&mut self, name: Name) -> Symbol {
+        if let Some(symbol) = self.store.get(&name)
+            && symbol.scope != FREE_SCOPE
+        {
+            return symbol.clone();
+        }
+
+        let (scope, index) = if self.is_global() {
+            self.globals.push(name.to_string());
+            (GLOBAL_SCOPE, self.globals.len() - 1)
+        } else if let Some(index) = self.reserved.get(&name) {
+            (CELL_SCOPE, *index)
+        } else if self.is_captured(&name) {
+            let frame = self.frame_mut();
+            frame.num_cells += 1;
+            frame.cell_names.push(name.to_string());
+            (CELL_SCOPE, frame.num_cells - 1)
+        } else {
+            let frame = self.frame_mut();
+            frame.num_locals += 1;
+            (LOCAL_SCOPE, frame.num_locals - 1)
+        };
+
+        let symbol = Symbol {
+            name: name.clone(),
+            scope,
+            index,
+        };
+        self.store.insert(name, symbol.clone());
+        symbol
+    }
+
+    // Parameters arrive in the first local slots. Returns that slot along with the symbol the
+    // body uses, which is a cell if a closure captures the parameter.
+    pub fn define_parameter(&mut self, name: Name) -> (usize, Symbol) {
+        let slot = self.num_locals;
+        self.num_locals += 1;
+
+        let symbol = if self.captured.contains(&name) {
+            self.num_cells += 1;
+            self.cell_names.push(name.to_string());
+            Symbol {
+                name: name.clone(),
+                scope: CELL_SCOPE,
+                index: self.num_cells - 1,
+            }
+        } else {
+            Symbol {
+                name: name.clone(),
+                scope: LOCAL_SCOPE,
+                index: slot,
+            }
+        };
+
+        self.store.insert(name, symbol.clone());
+        (slot, symbol)
+    }
+
+    // A global slot for a name nothing defines yet, so top-level functions can refer to one
+    // another regardless of order. Reading it before it's set is a runtime error.
+    pub fn define_global(&mut self, name: Name) -> Symbol {
+        match &mut self.outer {
+            Some(outer) => outer.define_global(name),
+            None => self.define(name),
+        }
+    }
+
+    fn define_free(&mut self, original: Symbol) -> Symbol {
+        let symbol = Symbol {
+            name: original.name.clone(),
+            scope: FREE_SCOPE,
+            index: self.free_symbols.len(),
+        };
+
+        self.free_symbols.push(original);
+        self.store.insert(symbol.name.clone(), symbol.clone());
+        symbol
+    }
+
+    // User bindings shadow builtins.
+    pub fn resolve(&mut self, name: &Name) -> Option<Symbol> {
+        if let Some(symbol) = self.lookup(name, false) {
+            return Some(symbol);
+        }
+
+        let index = builtins::BUILTINS.iter().position(|(k, _)| name == k)?;
+        Some(Symbol {
+            name: name.clone(),
+            scope: BUILTIN_SCOPE,
+            index,
+        })
+    }
+
+    // `from_nested` is set once the lookup has left the function it started in.
+    fn lookup(&mut self, name: &Name, from_nested: bool) -> Option<Symbol> {
+        if let Some(symbol) = self.store.get(name) {
+            return Some(symbol.clone());
+        }
+        if from_nested && !self.is_global() && self.pending.contains(name) {
+            return Some(self.define(name.clone()));
+        }
+
+        let is_function = !self.block;
+        let symbol = self
+            .outer
+            .as_mut()?
+            .lookup(name, from_nested || is_function)?;
+
+        if !is_function || symbol.scope == GLOBAL_SCOPE {
+            return Some(symbol);
+        }
+
+        Some(self.define_free(symbol))
+    }
+}
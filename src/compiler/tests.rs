@@ -0,0 +1,539 @@
+use super::symbol_table::{CELL_SCOPE, FREE_SCOPE, LOCAL_SCOPE};
+use super::*;
+use crate::code::make;
+use crate::test_helpers::parse;
+
+fn compile(input: &str) -> Result<Bytecode, String> {
+    // These tests check code generation itself; the peephole pass has tests of its own.
+    let mut compiler = Compiler::new();
+    compiler.set_optimize(false);
+    compiler.compile(&parse(input))?;
+    Ok(compiler.bytecode())
+}
+
+fn concat(instructions: Vec<Instructions>) -> Instructions {
+    instructions.concat()
+}
+
+fn function_constant(obj: &Object) -> &object::CompiledFunction {
+    match obj {
+        Object::CompiledFunction(function) => function,
+        other => panic!("object is not CompiledFunction. got={}", other.inspect()),
+    }
+}
+
+fn opcodes(ins: &[u8]) -> Vec<Opcode> {
+    let mut ops = Vec::new();
+    let mut i = 0;
+    while i < ins.len() {
+        let def = code::lookup(ins[i]).unwrap();
+        let (_, read) = code::read_operands(def, &ins[i + 1..]);
+        ops.push(ins[i]);
+        i += 1 + read;
+    }
+    ops
+}
+
+fn assert_constants(constants: &[Object], expected: &[i64]) {
+    let integers: Vec<i64> = constants
+        .iter()
+        .filter_map(|c| match c {
+            Object::Integer(value) => Some(*value),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(integers, expected);
+}
+
+#[test]
+fn test_integer_arithmetic() {
+    let tests = vec![
+        (
+            "1 + 2",
+            vec![
+                make(code::OP_CONSTANT, &[0]),
+                make(code::OP_CONSTANT, &[1]),
+                make(code::OP_ADD, &[]),
+                make(code::OP_RETURN_VALUE, &[]),
+            ],
+        ),
+        (
+            "1; 2",
+            vec![
+                make(code::OP_CONSTANT, &[0]),
+                make(code::OP_POP, &[]),
+                make(code::OP_CONSTANT, &[1]),
+                make(code::OP_RETURN_VALUE, &[]),
+            ],
+        ),
+        (
+            "-1 ** 2",
+            vec![
+                make(code::OP_CONSTANT, &[0]),
+                make(code::OP_CONSTANT, &[1]),
+                make(code::OP_POW, &[]),
+                make(code::OP_MINUS, &[]),
+                make(code::OP_RETURN_VALUE, &[]),
+            ],
+        ),
+        (
+            "1 < 2 == !true",
+            vec![
+                make(code::OP_CONSTANT, &[0]),
+                make(code::OP_CONSTANT, &[1]),
+                make(code::OP_LESS, &[]),
+                make(code::OP_TRUE, &[]),
+                make(code::OP_BANG, &[]),
+                make(code::OP_EQUAL, &[]),
+                make(code::OP_RETURN_VALUE, &[]),
+            ],
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let bytecode = compile(input).unwrap();
+        assert_eq!(bytecode.main.instructions, concat(expected), "{}", input);
+        assert_constants(&bytecode.constants, &[1, 2]);
+    }
+}
+
+#[test]
+fn test_conditionals() {
+    let bytecode = compile("if (true) { 10 }; 3333;").unwrap();
+
+    let expected = concat(vec![
+        // 0000
+        make(code::OP_TRUE, &[]),
+        // 0001
+        make(code::OP_JUMP_NOT_TRUTHY, &[14]),
+        // 0006
+        make(code::OP_CONSTANT, &[0]),
+        // 0009
+        make(code::OP_JUMP, &[15]),
+        // 0014
+        make(code::OP_NULL, &[]),
+        // 0015
+        make(code::OP_POP, &[]),
+        // 0016
+        make(code::OP_CONSTANT, &[1]),
+        // 0019
+        make(code::OP_RETURN_VALUE, &[]),
+    ]);
+    assert_eq!(bytecode.main.instructions, expected);
+    assert_constants(&bytecode.constants, &[10, 3333]);
+
+    let bytecode = compile("if (false) { 1 } else if (true) { 2 } else { 3 }").unwrap();
+    let expected = concat(vec![
+        make(code::OP_FALSE, &[]),
+        make(code::OP_JUMP_NOT_TRUTHY, &[14]),
+        make(code::OP_CONSTANT, &[0]),
+        make(code::OP_JUMP, &[31]),
+        make(code::OP_TRUE, &[]),
+        make(code::OP_JUMP_NOT_TRUTHY, &[28]),
+        make(code::OP_CONSTANT, &[1]),
+        make(code::OP_JUMP, &[31]),
+        make(code::OP_CONSTANT, &[2]),
+        make(code::OP_RETURN_VALUE, &[]),
+    ]);
+    assert_eq!(bytecode.main.instructions, expected);
+}
+
+#[test]
+fn test_global_let_statements() {
+    let bytecode = compile("let one = 1; let two = 2; one = two; one").unwrap();
+
+    let expected = concat(vec![
+        make(code::OP_CONSTANT, &[0]),
+        make(code::OP_SET_GLOBAL, &[0]),
+        make(code::OP_CONSTANT, &[1]),
+        make(code::OP_SET_GLOBAL, &[1]),
+        make(code::OP_GET_GLOBAL, &[1]),
+        make(code::OP_ASSIGN_GLOBAL, &[0]),
+        make(code::OP_GET_GLOBAL, &[0]),
+        make(code::OP_RETURN_VALUE, &[]),
+    ]);
+    assert_eq!(bytecode.main.instructions, expected);
+    assert_eq!(bytecode.globals, vec!["one", "two"]);
+}
+
+#[test]
+fn test_late_bound_globals() {
+    let bytecode = compile("let f = fn() { g() }; let g = fn() { 1 };").unwrap();
+    assert_eq!(bytecode.globals, vec!["f", "g"]);
+
+    let f = function_constant(&bytecode.constants[0]);
+    let expected = concat(vec![
+        make(code::OP_GET_GLOBAL, &[1]),
+        make(code::OP_TAIL_CALL, &[0]),
+        make(code::OP_RETURN_VALUE, &[]),
+    ]);
+    assert_eq!(f.instructions, expected);
+}
+
+#[test]
+fn test_compound_assignment() {
+    let bytecode = compile("let x = 1; x += 2; let a = [1]; a[0] *= 3;").unwrap();
+
+    let expected = concat(vec![
+        make(code::OP_CONSTANT, &[0]),
+        make(code::OP_SET_GLOBAL, &[0]),
+        make(code::OP_CONSTANT, &[1]),
+        make(code::OP_GET_GLOBAL, &[0]),
+        make(code::OP_SWAP, &[]),
+        make(code::OP_ADD, &[]),
+        make(code::OP_ASSIGN_GLOBAL, &[0]),
+        make(code::OP_CONSTANT, &[2]),
+        make(code::OP_ARRAY, &[1]),
+        make(code::OP_SET_GLOBAL, &[1]),
+        make(code::OP_CONSTANT, &[3]),
+        make(code::OP_GET_GLOBAL, &[1]),
+        make(code::OP_CONSTANT, &[4]),
+        make(code::OP_UPDATE_INDEX, &[code::OP_MUL as usize]),
+        make(code::OP_NULL, &[]),
+        make(code::OP_RETURN_VALUE, &[]),
+    ]);
+    assert_eq!(bytecode.main.instructions, expected);
+}
+
+#[test]
+fn test_collections() {
+    let bytecode = compile("{\"a\": [1, 2][0], 3: s[1:]}").unwrap();
+
+    let expected = concat(vec![
+        make(code::OP_CONSTANT, &[0]),
+        make(code::OP_CONSTANT, &[1]),
+        make(code::OP_CONSTANT, &[2]),
+        make(code::OP_ARRAY, &[2]),
+        make(code::OP_CONSTANT, &[3]),
+        make(code::OP_INDEX, &[]),
+        make(code::OP_CONSTANT, &[4]),
+        make(code::OP_GET_GLOBAL, &[0]),
+        make(code::OP_CONSTANT, &[5]),
+        make(code::OP_SLICE, &[code::SLICE_START]),
+        make(code::OP_HASH, &[2]),
+        make(code::OP_RETURN_VALUE, &[]),
+    ]);
+    assert_eq!(bytecode.main.instructions, expected);
+}
+
+#[test]
+fn test_builtins() {
+    let bytecode = compile("len([]); puts(1)").unwrap();
+    let len = builtins_index("len");
+    let puts = builtins_index("puts");
+
+    let expected = concat(vec![
+        make(code::OP_GET_BUILTIN, &[len]),
+        make(code::OP_ARRAY, &[0]),
+        make(code::OP_CALL, &[1]),
+        make(code::OP_POP, &[]),
+        make(code::OP_GET_BUILTIN, &[puts]),
+        make(code::OP_CONSTANT, &[0]),
+        make(code::OP_CALL, &[1]),
+        make(code::OP_RETURN_VALUE, &[]),
+    ]);
+    assert_eq!(bytecode.main.instructions, expected);
+}
+
+fn builtins_index(name: &str) -> usize {
+    crate::object::builtins::BUILTINS
+        .iter()
+        .position(|(k, _)| *k == name)
+        .unwrap()
+}
+
+#[test]
+fn test_functions_and_closures() {
+    let bytecode = compile("fn(a) { fn(b) { a + b } }").unwrap();
+
+    let inner = function_constant(&bytecode.constants[0]);
+    assert_eq!(
+        inner.instructions,
+        concat(vec![
+            make(code::OP_GET_FREE, &[0]),
+            make(code::OP_GET_LOCAL, &[0]),
+            make(code::OP_ADD, &[]),
+            make(code::OP_RETURN_VALUE, &[]),
+        ])
+    );
+    assert_eq!(inner.captures, vec![object::Capture::Cell(0)]);
+    assert_eq!(inner.num_parameters, 1);
+
+    // The captured parameter moves into a cell before the body runs.
+    let outer = function_constant(&bytecode.constants[1]);
+    assert_eq!(
+        outer.instructions,
+        concat(vec![
+            make(code::OP_GET_LOCAL, &[0]),
+            make(code::OP_SET_CELL, &[0]),
+            make(code::OP_CLOSURE, &[0]),
+            make(code::OP_RETURN_VALUE, &[]),
+        ])
+    );
+    assert_eq!((outer.num_locals, outer.num_cells), (1, 1));
+
+    assert_eq!(
+        bytecode.main.instructions,
+        concat(vec![
+            make(code::OP_CLOSURE, &[1]),
+            make(code::OP_RETURN_VALUE, &[]),
+        ])
+    );
+}
+
+#[test]
+fn test_nested_free_variables() {
+    let bytecode = compile("fn(a) { let b = 1; fn() { fn() { a + b } } }").unwrap();
+
+    let innermost = function_constant(&bytecode.constants[1]);
+    assert_eq!(
+        innermost.captures,
+        vec![object::Capture::Free(0), object::Capture::Free(1)]
+    );
+
+    let middle = function_constant(&bytecode.constants[2]);
+    assert_eq!(
+        middle.captures,
+        vec![object::Capture::Cell(0), object::Capture::Cell(1)]
+    );
+}
+
+#[test]
+fn test_tail_calls() {
+    let bytecode =
+        compile("let f = fn(n) { if (n) { f(n) } else { return f(0); } }; f(1)").unwrap();
+
+    let f = opcodes(&function_constant(&bytecode.constants[1]).instructions);
+    assert_eq!(f.iter().filter(|op| **op == code::OP_TAIL_CALL).count(), 2);
+    assert!(!f.contains(&code::OP_CALL));
+
+    // Top-level calls and calls inside `try` return to their caller.
+    let main = opcodes(&bytecode.main.instructions);
+    assert!(main.contains(&code::OP_CALL));
+    assert!(!main.contains(&code::OP_TAIL_CALL));
+
+    let bytecode = compile("let f = fn(n) { try { f(n) } catch (e) { 0 } }").unwrap();
+    let f = opcodes(&function_constant(&bytecode.constants[1]).instructions);
+    assert!(f.contains(&code::OP_CALL));
+    assert!(!f.contains(&code::OP_TAIL_CALL));
+}
+
+#[test]
+fn test_loops() {
+    let bytecode = compile("while (true) { 1 + if (true) { break; } }").unwrap();
+
+    let expected = concat(vec![
+        // 0000
+        make(code::OP_TRUE, &[]),
+        // 0001
+        make(code::OP_JUMP_NOT_TRUTHY, &[35]),
+        // 0006
+        make(code::OP_CONSTANT, &[0]),
+        // 0009
+        make(code::OP_TRUE, &[]),
+        // 0010
+        make(code::OP_JUMP_NOT_TRUTHY, &[27]),
+        // 0015: `break` drops the pending 1 first
+        make(code::OP_POP, &[]),
+        // 0016
+        make(code::OP_JUMP, &[35]),
+        // 0021
+        make(code::OP_NULL, &[]),
+        // 0022
+        make(code::OP_JUMP, &[28]),
+        // 0027
+        make(code::OP_NULL, &[]),
+        // 0028
+        make(code::OP_ADD, &[]),
+        // 0029
+        make(code::OP_POP, &[]),
+        // 0030
+        make(code::OP_JUMP, &[0]),
+        // 0035
+        make(code::OP_NULL, &[]),
+        // 0036
+        make(code::OP_RETURN_VALUE, &[]),
+    ]);
+    assert_eq!(bytecode.main.instructions, expected);
+}
+
+#[test]
+fn test_for_loops() {
+    let bytecode = compile("for (x in [1]) { if (x) { continue; } }").unwrap();
+
+    let expected = concat(vec![
+        // 0000
+        make(code::OP_CONSTANT, &[0]),
+        // 0003
+        make(code::OP_ARRAY, &[1]),
+        // 0006
+        make(code::OP_ITER, &[]),
+        // 0007
+        make(code::OP_ITER_NEXT, &[41]),
+        // 0012
+        make(code::OP_SET_LOCAL, &[0]),
+        // 0015
+        make(code::OP_GET_LOCAL, &[0]),
+        // 0018
+        make(code::OP_JUMP_NOT_TRUTHY, &[34]),
+        // 0023: `continue` goes back to fetch the next item
+        make(code::OP_JUMP, &[7]),
+        // 0028
+        make(code::OP_NULL, &[]),
+        // 0029
+        make(code::OP_JUMP, &[35]),
+        // 0034
+        make(code::OP_NULL, &[]),
+        // 0035
+        make(code::OP_POP, &[]),
+        // 0036
+        make(code::OP_JUMP, &[7]),
+        // 0041
+        make(code::OP_ITER_POP, &[]),
+        // 0042
+        make(code::OP_NULL, &[]),
+        // 0043
+        make(code::OP_RETURN_VALUE, &[]),
+    ]);
+    assert_eq!(bytecode.main.instructions, expected);
+    assert_eq!(bytecode.main.num_locals, 1);
+}
+
+#[test]
+fn test_loop_cells_are_renewed() {
+    let bytecode = compile("let fs = []; for (i in 0..3) { fs = push(fs, fn() { i }); }").unwrap();
+
+    let main = &bytecode.main.instructions;
+    let next = main
+        .iter()
+        .position(|op| *op == code::OP_ITER_NEXT)
+        .unwrap();
+    assert_eq!(
+        main[next + 5..next + 10],
+        make(code::OP_NEW_CELLS, &[0, 1])[..]
+    );
+    assert_eq!(
+        main[next + 10..next + 13],
+        make(code::OP_SET_CELL, &[0])[..]
+    );
+    assert_eq!(bytecode.main.num_cells, 1);
+}
+
+#[test]
+fn test_try_catch() {
+    let bytecode = compile("try { throw 1; } catch (e) { e }").unwrap();
+
+    let expected = concat(vec![
+        // 0000
+        make(code::OP_SETUP_TRY, &[16]),
+        // 0005
+        make(code::OP_CONSTANT, &[0]),
+        // 0008
+        make(code::OP_THROW, &[]),
+        // 0009
+        make(code::OP_NULL, &[]),
+        // 0010
+        make(code::OP_POP_TRY, &[]),
+        // 0011
+        make(code::OP_JUMP, &[22]),
+        // 0016
+        make(code::OP_SET_LOCAL, &[0]),
+        // 0019
+        make(code::OP_GET_LOCAL, &[0]),
+        // 0022
+        make(code::OP_RETURN_VALUE, &[]),
+    ]);
+    assert_eq!(bytecode.main.instructions, expected);
+}
+
+#[test]
+fn test_recursive_local_functions() {
+    let input = "fn() { let even = fn(n) { odd(n) }; let odd = fn(n) { even(n) }; even }";
+    let bytecode = compile(input).unwrap();
+
+    // `odd` is captured before its `let`, and both live in cells of the enclosing function.
+    let even = function_constant(&bytecode.constants[0]);
+    assert_eq!(even.captures, vec![object::Capture::Cell(1)]);
+    let odd = function_constant(&bytecode.constants[1]);
+    assert_eq!(odd.captures, vec![object::Capture::Cell(0)]);
+
+    let outer = function_constant(&bytecode.constants[2]);
+    assert_eq!(outer.num_cells, 2);
+    assert_eq!(outer.num_locals, 0);
+}
+
+#[test]
+fn test_source_map() {
+    let bytecode = compile("let x = 1;\nx + true").unwrap();
+
+    let add = bytecode
+        .main
+        .instructions
+        .iter()
+        .position(|op| *op == code::OP_ADD)
+        .unwrap();
+    assert_eq!(bytecode.main.span_at(0), Some(Span { line: 1, column: 9 }));
+    assert_eq!(
+        bytecode.main.span_at(add),
+        Some(Span { line: 2, column: 3 })
+    );
+}
+
+#[test]
+fn test_symbol_table_scopes() {
+    let mut global = SymbolTable::new();
+    let a = global.define(Name::intern("a"));
+    assert_eq!((a.scope, a.index), (GLOBAL_SCOPE, 0));
+
+    let mut local = SymbolTable::new_enclosed(global);
+    local.set_captured([Name::intern("c")].into_iter().collect());
+    let b = local.define(Name::intern("b"));
+    let c = local.define(Name::intern("c"));
+    assert_eq!((b.scope, b.index), (LOCAL_SCOPE, 0));
+    assert_eq!((c.scope, c.index), (CELL_SCOPE, 0));
+    assert_eq!(
+        local.resolve(&Name::intern("a")).unwrap().scope,
+        GLOBAL_SCOPE
+    );
+    assert_eq!(
+        local.resolve(&Name::intern("len")).unwrap().scope,
+        BUILTIN_SCOPE
+    );
+
+    let mut block = SymbolTable::new_block(local);
+    let d = block.define(Name::intern("d"));
+    assert_eq!((d.scope, d.index), (LOCAL_SCOPE, 1));
+    assert_eq!(block.resolve(&Name::intern("b")), Some(b.clone()));
+
+    let mut nested = SymbolTable::new_enclosed(block);
+    let free = nested.resolve(&Name::intern("c")).unwrap();
+    assert_eq!((free.scope, free.index), (FREE_SCOPE, 0));
+    assert_eq!(nested.free_symbols, vec![c]);
+
+    let mut block = *nested.outer.take().unwrap();
+    let mut local = *block.outer.take().unwrap();
+    assert_eq!(local.resolve(&Name::intern("d")), None);
+    assert_eq!(local.num_locals, 2);
+}
+
+#[test]
+fn test_disassemble_bytecode() {
+    let bytecode = compile("let add = fn(a, b) { a + b }; add(1, 2)").unwrap();
+
+    let expected = "== <main> (locals=0, cells=0) ==
+0000 OpClosure 0 ; compiled function add
+0003 OpSetGlobal 0
+0006 OpGetGlobal 0
+0009 OpConstant 1 ; 1
+0012 OpConstant 2 ; 2
+0015 OpCall 2
+0017 OpReturnValue
+
+== add (constant 0, params=2, locals=2, cells=0, free=0) ==
+0000 OpGetLocal 0
+0003 OpGetLocal 1
+0006 OpAdd
+0007 OpReturnValue
+";
+    assert_eq!(bytecode.disassemble(), expected);
+}
@@ -0,0 +1,1303 @@
+#[cfg(test)]
+mod tests;
+
+use crate::ast::{self, Binding};
+use crate::bigint::BigInt;
+use crate::budget::Budget;
+use crate::object::builtins;
+use crate::object::environment::Environment;
+use crate::object::memory;
+use crate::object::{self, Object};
+use crate::resolver::Resolver;
+use crate::token::Span;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+type Env = Rc<RefCell<Environment>>;
+
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+// Deep enough for any call depth up to the default with room for ordinary nesting at each call,
+// and shallow enough for the 64 MiB interpreter thread even in debug builds.
+pub const DEFAULT_MAX_NESTING: usize = 10_000;
+
+pub struct Evaluator {
+    strict: bool,
+    // Set while evaluating a function body outside any `try`, where calls may be tail calls.
+    tail_calls: bool,
+    call_depth: usize,
+    max_call_depth: usize,
+    // Expressions, blocks and calls being evaluated, however they're mixed: each one is a level
+    // of native recursion.
+    nesting: usize,
+    max_nesting: usize,
+    budget: Budget,
+    // Live scopes, innermost last: the roots when counting what memory is still reachable.
+    scopes: Vec<Env>,
+    // Values evaluated but not yet stored where a scope reaches them, like the elements of an
+    // array literal still being collected. Recounts treat them as roots too.
+    temps: Vec<Object>,
+}
+
+impl Default for Evaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Evaluator {
+    pub fn new() -> Evaluator {
+        Evaluator {
+            strict: false,
+            tail_calls: false,
+            call_depth: 0,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            nesting: 0,
+            max_nesting: DEFAULT_MAX_NESTING,
+            budget: Budget::new(),
+            scopes: Vec::new(),
+            temps: Vec::new(),
+        }
+    }
+
+    // In strict mode, out-of-bounds index reads are errors instead of null.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    // Tail calls don't count towards the depth, since they don't nest.
+    pub fn set_max_call_depth(&mut self, max_call_depth: usize) {
+        self.max_call_depth = max_call_depth;
+    }
+
+    // Caps expressions, blocks and calls nested inside each other, so a deeply nested function
+    // called deeply can't exhaust the native stack either.
+    pub fn set_max_nesting(&mut self, max_nesting: usize) {
+        self.max_nesting = max_nesting;
+    }
+
+    // Every evaluated statement, expression and block burns one unit of fuel.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.budget.set_fuel(fuel);
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.budget.fuel()
+    }
+
+    // The wall-clock limit for each eval_program call.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.budget.set_timeout(timeout);
+    }
+
+    // Caps the approximate bytes held in strings, arrays and hashes.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.budget.set_memory_limit(limit);
+    }
+
+    // Integer results past this many bits fail. Off by default, leaving the fuel and memory
+    // limits to stop runaway arithmetic.
+    pub fn set_max_integer_bits(&mut self, bits: Option<usize>) {
+        self.budget.set_max_integer_bits(bits);
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.budget.memory_used()
+    }
+
+    pub fn eval_program(&mut self, program: &ast::Program, env: &Env) -> Object {
+        // Names the resolver can't find are reported when they're reached, like any runtime error.
+        let _ = Resolver::new().resolve(program);
+
+        self.scopes.push(Rc::clone(env));
+        self.temps.clear();
+        self.budget
+            .start(memory::reachable_size(&self.scopes, &self.temps));
+
+        let result = self.eval_program_statements(program, env);
+        self.scopes.pop();
+        self.temps.clear();
+
+        result
+    }
+
+    fn eval_program_statements(&mut self, program: &ast::Program, env: &Env) -> Object {
+        let mut result = Object::Null;
+
+        for stmt in program.statements.iter() {
+            result = self.eval_statement(stmt.as_ref(), env);
+
+            match result {
+                Object::ReturnValue(value) => return *value,
+                Object::Error(_) => return result,
+                _ => (),
+            }
+        }
+
+        result
+    }
+
+    fn reserve_memory(&mut self, bytes: usize) -> Option<Object> {
+        let (scopes, temps) = (&self.scopes, &self.temps);
+        self.budget
+            .reserve(bytes, || memory::reachable_size(scopes, temps))
+    }
+
+    fn charge(&mut self, obj: Object) -> Object {
+        let (scopes, temps) = (&self.scopes, &self.temps);
+        self.budget
+            .charge(obj, || memory::reachable_size(scopes, temps))
+    }
+
+    // Big integer products are paid for before they're worked out, and every result is counted.
+    fn eval_infix(&mut self, operator: &str, left: Object, right: Object) -> Object {
+        if let Some((bits, work)) = integer_growth(operator, &left, &right) {
+            self.temps.extend([left.clone(), right.clone()]);
+            let (scopes, temps) = (&self.scopes, &self.temps);
+            let err = self
+                .budget
+                .reserve_integer(bits, work, || memory::reachable_size(scopes, temps));
+            self.temps.truncate(self.temps.len() - 2);
+            if let Some(err) = err {
+                return err;
+            }
+        }
+
+        let result = eval_infix_expression(operator, left, right);
+        if let Object::BigInteger(value) = &result
+            && let Some(err) = self.budget.check_integer_bits(value.bits() as u64)
+        {
+            return err;
+        }
+        self.charge(result)
+    }
+
+    // Evaluates `expr` while `held` stays counted as live, and hands `held` back with the result.
+    fn eval_holding(
+        &mut self,
+        held: Object,
+        expr: &dyn ast::Expression,
+        env: &Env,
+    ) -> (Object, Object) {
+        self.temps.push(held);
+        let result = self.eval_expression(expr, env);
+        let held = self.temps.pop().expect("temporary value disappeared");
+        (held, result)
+    }
+
+    fn consume_fuel(&mut self) -> Option<Object> {
+        self.budget.consume_fuel()
+    }
+
+    // Runs `eval` one level of nesting further in.
+    fn nested(&mut self, eval: impl FnOnce(&mut Evaluator) -> Object) -> Object {
+        if self.nesting >= self.max_nesting {
+            return new_error(format!(
+                "maximum nesting depth of {} exceeded",
+                self.max_nesting
+            ));
+        }
+
+        self.nesting += 1;
+        let result = eval(self);
+        self.nesting -= 1;
+
+        result
+    }
+
+    fn eval_block_statement(&mut self, block: &ast::BlockStatement, env: &Env) -> Object {
+        if let Some(err) = self.consume_fuel() {
+            return err;
+        }
+
+        self.nested(|this| this.eval_block_statements(block, env))
+    }
+
+    fn eval_block_statements(&mut self, block: &ast::BlockStatement, env: &Env) -> Object {
+        let mut result = Object::Null;
+
+        for stmt in block.statements.iter() {
+            result = self.eval_statement(stmt.as_ref(), env);
+
+            if let Object::ReturnValue(_) | Object::Error(_) | Object::Break | Object::Continue =
+                result
+            {
+                return result;
+            }
+        }
+
+        result
+    }
+
+    // Like eval_block_statement, but a final expression statement is in tail position.
+    fn eval_tail_block(&mut self, block: &ast::BlockStatement, env: &Env) -> Object {
+        if let Some(err) = self.consume_fuel() {
+            return err;
+        }
+
+        self.nested(|this| this.eval_tail_block_statements(block, env))
+    }
+
+    fn eval_tail_block_statements(&mut self, block: &ast::BlockStatement, env: &Env) -> Object {
+        let (last, init) = match block.statements.split_last() {
+            Some(split) => split,
+            None => return Object::Null,
+        };
+
+        for stmt in init.iter() {
+            let result = self.eval_statement(stmt.as_ref(), env);
+
+            if let Object::ReturnValue(_) | Object::Error(_) | Object::Break | Object::Continue =
+                result
+            {
+                return result;
+            }
+        }
+
+        match last.as_any().downcast_ref::<ast::ExpressionStatement>() {
+            Some(stmt) => self.eval_tail_expression(stmt.expression.as_ref(), env),
+            None => self.eval_statement(last.as_ref(), env),
+        }
+    }
+
+    // Calls to Monkey functions in tail position become TailCalls for apply_function to run.
+    fn eval_tail_expression(&mut self, expr: &dyn ast::Expression, env: &Env) -> Object {
+        if !self.tail_calls {
+            return self.eval_expression(expr, env);
+        }
+
+        let any = expr.as_any();
+        let result = if let Some(call) = any.downcast_ref::<ast::CallExpression>() {
+            let function = self.eval_expression(call.function.as_ref(), env);
+            if function.is_error() {
+                return function;
+            }
+
+            let args = match self.eval_expressions(&call.arguments, env) {
+                Ok(args) => args,
+                Err(err) => return err,
+            };
+
+            match function {
+                Object::Function(function) => Object::TailCall(Box::new(object::TailCall {
+                    function,
+                    args,
+                    name: call.function.string(),
+                    span: call.token.span,
+                })),
+                function => trace_call(self.apply_function(function, args), call),
+            }
+        } else if let Some(ie) = any.downcast_ref::<ast::IfExpression>() {
+            self.eval_if_expression(ie, env, true)
+        } else {
+            return self.eval_expression(expr, env);
+        };
+
+        with_span(result, expr.span())
+    }
+
+    fn eval_statement(&mut self, stmt: &dyn ast::Statement, env: &Env) -> Object {
+        if let Some(err) = self.consume_fuel() {
+            return with_span(err, stmt.span());
+        }
+
+        let result = self.eval_statement_node(stmt, env);
+        with_span(result, stmt.span())
+    }
+
+    fn eval_statement_node(&mut self, stmt: &dyn ast::Statement, env: &Env) -> Object {
+        let any = stmt.as_any();
+
+        if let Some(stmt) = any.downcast_ref::<ast::ExpressionStatement>() {
+            self.eval_expression(stmt.expression.as_ref(), env)
+        } else if let Some(stmt) = any.downcast_ref::<ast::LetStatement>() {
+            let value = self.eval_expression(stmt.value.as_ref(), env);
+            if value.is_error() {
+                return value;
+            }
+
+            bind(env, &stmt.name, value);
+            Object::Null
+        } else if let Some(stmt) = any.downcast_ref::<ast::ReturnStatement>() {
+            let value = self.eval_tail_expression(stmt.value.as_ref(), env);
+            if value.is_error() {
+                return value;
+            }
+
+            Object::ReturnValue(Box::new(value))
+        } else if let Some(block) = any.downcast_ref::<ast::BlockStatement>() {
+            self.eval_block_statement(block, env)
+        } else if let Some(stmt) = any.downcast_ref::<ast::AssignStatement>() {
+            self.eval_assign_statement(stmt, env)
+        } else if let Some(ws) = any.downcast_ref::<ast::WhileStatement>() {
+            self.eval_while_statement(ws, env)
+        } else if let Some(fs) = any.downcast_ref::<ast::ForStatement>() {
+            self.eval_for_statement(fs, env)
+        } else if let Some(ts) = any.downcast_ref::<ast::TryStatement>() {
+            self.eval_try_statement(ts, env)
+        } else if let Some(ts) = any.downcast_ref::<ast::ThrowStatement>() {
+            let value = self.eval_expression(ts.value.as_ref(), env);
+            if value.is_error() {
+                return value;
+            }
+
+            Object::Error(Box::new(object::RuntimeError::thrown(value)))
+        } else if any.is::<ast::BreakStatement>() {
+            Object::Break
+        } else if any.is::<ast::ContinueStatement>() {
+            Object::Continue
+        } else {
+            new_error(format!("cannot evaluate statement: {}", stmt.string()))
+        }
+    }
+
+    fn eval_expression(&mut self, expr: &dyn ast::Expression, env: &Env) -> Object {
+        if let Some(err) = self.consume_fuel() {
+            return with_span(err, expr.span());
+        }
+
+        let result = self.nested(|this| this.eval_expression_node(expr, env));
+        with_span(result, expr.span())
+    }
+
+    fn eval_expression_node(&mut self, expr: &dyn ast::Expression, env: &Env) -> Object {
+        let any = expr.as_any();
+
+        if let Some(lit) = any.downcast_ref::<ast::IntegerLiteral>() {
+            Object::Integer(lit.value)
+        } else if let Some(lit) = any.downcast_ref::<ast::BigIntegerLiteral>() {
+            self.charge(Object::BigInteger(Rc::clone(&lit.value)))
+        } else if let Some(lit) = any.downcast_ref::<ast::Boolean>() {
+            Object::Boolean(lit.value)
+        } else if any.is::<ast::NullLiteral>() {
+            Object::Null
+        } else if let Some(lit) = any.downcast_ref::<ast::StringLiteral>() {
+            Object::String(lit.value.text())
+        } else if let Some(lit) = any.downcast_ref::<ast::ArrayLiteral>() {
+            match self.eval_expressions(&lit.elements, env) {
+                Ok(elements) => self.charge(Object::new_array(elements)),
+                Err(err) => err,
+            }
+        } else if let Some(lit) = any.downcast_ref::<ast::HashLiteral>() {
+            let hash = self.eval_hash_literal(lit, env);
+            self.charge(hash)
+        } else if let Some(ident) = any.downcast_ref::<ast::Identifier>() {
+            eval_identifier(ident, env)
+        } else if let Some(prefix) = any.downcast_ref::<ast::PrefixExpression>() {
+            let right = self.eval_expression(prefix.right.as_ref(), env);
+            if right.is_error() {
+                return right;
+            }
+
+            eval_prefix_expression(prefix.operator.as_str(), right)
+        } else if let Some(infix) = any.downcast_ref::<ast::InfixExpression>() {
+            let left = self.eval_expression(infix.left.as_ref(), env);
+            if left.is_error() {
+                return left;
+            }
+
+            let (left, right) = self.eval_holding(left, infix.right.as_ref(), env);
+            if right.is_error() {
+                return right;
+            }
+
+            self.eval_infix(infix.operator.as_str(), left, right)
+        } else if let Some(ie) = any.downcast_ref::<ast::IndexExpression>() {
+            let left = self.eval_expression(ie.left.as_ref(), env);
+            if left.is_error() {
+                return left;
+            }
+
+            let (left, index) = self.eval_holding(left, ie.index.as_ref(), env);
+            if index.is_error() {
+                return index;
+            }
+
+            index_value(left, index, self.strict)
+        } else if let Some(se) = any.downcast_ref::<ast::SliceExpression>() {
+            let slice = self.eval_slice_expression(se, env);
+            self.charge(slice)
+        } else if let Some(ie) = any.downcast_ref::<ast::IfExpression>() {
+            self.eval_if_expression(ie, env, false)
+        } else if let Some(func) = any.downcast_ref::<ast::FunctionLiteral>() {
+            Object::new_function(object::Function {
+                parameters: func.parameters.clone(),
+                body: Rc::clone(&func.body),
+                env: Rc::clone(env),
+            })
+        } else if let Some(call) = any.downcast_ref::<ast::CallExpression>() {
+            let function = self.eval_expression(call.function.as_ref(), env);
+            if function.is_error() {
+                return function;
+            }
+
+            let args = match self.eval_expressions(&call.arguments, env) {
+                Ok(args) => args,
+                Err(err) => return err,
+            };
+
+            let result = self.apply_function(function, args);
+            trace_call(result, call)
+        } else {
+            new_error(format!("cannot evaluate expression: {}", expr.string()))
+        }
+    }
+
+    fn eval_expressions(
+        &mut self,
+        exprs: &[Box<dyn ast::Expression>],
+        env: &Env,
+    ) -> Result<Vec<Object>, Object> {
+        let start = self.temps.len();
+
+        for expr in exprs.iter() {
+            let evaluated = self.eval_expression(expr.as_ref(), env);
+            if evaluated.is_error() {
+                self.temps.truncate(start);
+                return Err(evaluated);
+            }
+            self.temps.push(evaluated);
+        }
+
+        Ok(self.temps.split_off(start))
+    }
+
+    fn eval_hash_literal(&mut self, lit: &ast::HashLiteral, env: &Env) -> Object {
+        // Keys and values wait on `temps` until the hash is built, so recounts still see them.
+        let start = self.temps.len();
+        let mut hash_keys = Vec::with_capacity(lit.pairs.len());
+
+        for (key_expr, value_expr) in lit.pairs.iter() {
+            let key = self.eval_expression(key_expr.as_ref(), env);
+            if key.is_error() {
+                self.temps.truncate(start);
+                return key;
+            }
+            match key.hash_key() {
+                Some(hash_key) => hash_keys.push(hash_key),
+                None => {
+                    self.temps.truncate(start);
+                    return unusable_hash_key(&key);
+                }
+            }
+            self.temps.push(key);
+
+            let value = self.eval_expression(value_expr.as_ref(), env);
+            if value.is_error() {
+                self.temps.truncate(start);
+                return value;
+            }
+            self.temps.push(value);
+        }
+
+        let mut hash = object::Hash::new();
+        let mut items = self.temps.split_off(start).into_iter();
+        for hash_key in hash_keys {
+            let key = items.next().expect("key evaluated");
+            let value = items.next().expect("value evaluated");
+            hash.insert(hash_key, key, value);
+        }
+
+        Object::new_hash(hash)
+    }
+
+    fn eval_if_expression(&mut self, ie: &ast::IfExpression, env: &Env, tail: bool) -> Object {
+        let condition = self.eval_expression(ie.condition.as_ref(), env);
+        if condition.is_error() {
+            return condition;
+        }
+
+        let block = if condition.is_truthy() {
+            &ie.consequence
+        } else {
+            match &ie.alternative {
+                Some(ast::Alternative::Block(block)) => block,
+                Some(ast::Alternative::If(alt)) => return self.eval_if_expression(alt, env, tail),
+                None => return Object::Null,
+            }
+        };
+
+        if tail {
+            self.eval_tail_block(block, env)
+        } else {
+            self.eval_block_statement(block, env)
+        }
+    }
+
+    fn eval_assign_statement(&mut self, stmt: &ast::AssignStatement, env: &Env) -> Object {
+        let value = self.eval_expression(stmt.value.as_ref(), env);
+        if value.is_error() {
+            return value;
+        }
+
+        // Compound operators like `+=` apply the operator without its trailing `=`.
+        let operator = stmt.operator.as_str().strip_suffix('=').unwrap_or("");
+        let target = stmt.target.as_any();
+
+        if let Some(ident) = target.downcast_ref::<ast::Identifier>() {
+            let value = if operator.is_empty() {
+                value
+            } else {
+                let current = eval_identifier(ident, env);
+                if current.is_error() {
+                    return current;
+                }
+                self.eval_infix(operator, current, value)
+            };
+            if value.is_error() {
+                return value;
+            }
+
+            if !assign(env, ident, value) {
+                return new_error(format!(
+                    "cannot assign to undeclared identifier: {}",
+                    ident.value
+                ));
+            }
+        } else if let Some(ie) = target.downcast_ref::<ast::IndexExpression>() {
+            let (value, container) = self.eval_holding(value, ie.left.as_ref(), env);
+            if container.is_error() {
+                return container;
+            }
+
+            self.temps.push(value);
+            let (container, index) = self.eval_holding(container, ie.index.as_ref(), env);
+            let value = self.temps.pop().expect("temporary value disappeared");
+            if index.is_error() {
+                return index;
+            }
+
+            let value = if operator.is_empty() {
+                value
+            } else {
+                let current = index_value(container.clone(), index.clone(), self.strict);
+                if current.is_error() {
+                    return current;
+                }
+                self.eval_infix(operator, current, value)
+            };
+            if value.is_error() {
+                return value;
+            }
+
+            let (scopes, temps) = (&self.scopes, &self.temps);
+            let budget = &mut self.budget;
+            let result = assign_index(container, index, value, |bytes| {
+                budget.allocate(bytes, || memory::reachable_size(scopes, temps))
+            });
+            if result.is_error() {
+                return result;
+            }
+        } else {
+            return new_error(format!("cannot assign to {}", stmt.target.string()));
+        }
+
+        Object::Null
+    }
+
+    fn eval_slice_expression(&mut self, se: &ast::SliceExpression, env: &Env) -> Object {
+        let left = self.eval_expression(se.left.as_ref(), env);
+        if left.is_error() {
+            return left;
+        }
+
+        let mut bounds = [None; 3];
+        for (bound, expr) in bounds.iter_mut().zip([&se.start, &se.end, &se.step]) {
+            match self.eval_slice_bound(expr, env) {
+                Ok(value) => *bound = value,
+                Err(err) => return err,
+            }
+        }
+
+        slice_value(&left, bounds)
+    }
+
+    fn eval_slice_bound(
+        &mut self,
+        bound: &Option<Box<dyn ast::Expression>>,
+        env: &Env,
+    ) -> Result<Option<i64>, Object> {
+        match bound {
+            Some(expr) => slice_bound(self.eval_expression(expr.as_ref(), env)),
+            None => Ok(None),
+        }
+    }
+
+    fn eval_while_statement(&mut self, ws: &ast::WhileStatement, env: &Env) -> Object {
+        loop {
+            let condition = self.eval_expression(ws.condition.as_ref(), env);
+            if condition.is_error() {
+                return condition;
+            }
+            if !condition.is_truthy() {
+                break;
+            }
+
+            let body_env = Environment::new_enclosed(Rc::clone(env));
+
+            self.scopes.push(Rc::clone(&body_env));
+            let result = self.eval_block_statement(&ws.body, &body_env);
+            self.scopes.pop();
+
+            match result {
+                Object::Break => break,
+                result @ (Object::ReturnValue(_) | Object::Error(_)) => return result,
+                _ => (),
+            }
+        }
+
+        Object::Null
+    }
+
+    fn eval_for_statement(&mut self, fs: &ast::ForStatement, env: &Env) -> Object {
+        let iterable = self.eval_expression(fs.iterable.as_ref(), env);
+
+        let items: Box<dyn Iterator<Item = Object>> = match iterable {
+            Object::Array(elements) => {
+                Box::new((0..).map_while(move |i| elements.borrow().get(i).cloned()))
+            }
+            Object::String(value) => Box::new(
+                value
+                    .chars()
+                    .collect::<Vec<char>>()
+                    .into_iter()
+                    .map(|c| Object::String(c.to_string().into())),
+            ),
+            Object::Range(start, end) => Box::new((start..end).map(Object::Integer)),
+            Object::Hash(hash) => {
+                let keys: Vec<Object> = hash.borrow().iter().map(|pair| pair.key.clone()).collect();
+                Box::new(keys.into_iter())
+            }
+            Object::Error(_) => return iterable,
+            other => return new_error(format!("not iterable: {}", other.object_type())),
+        };
+
+        for item in items {
+            let body_env = Environment::new_enclosed(Rc::clone(env));
+            bind(&body_env, &fs.variable, item);
+
+            self.scopes.push(Rc::clone(&body_env));
+            let result = self.eval_block_statement(&fs.body, &body_env);
+            self.scopes.pop();
+
+            match result {
+                Object::Break => break,
+                result @ (Object::ReturnValue(_) | Object::Error(_)) => return result,
+                _ => (),
+            }
+        }
+
+        Object::Null
+    }
+
+    fn eval_try_statement(&mut self, ts: &ast::TryStatement, env: &Env) -> Object {
+        // A call made inside `try` must return here so its errors can be caught.
+        let tail_calls = std::mem::replace(&mut self.tail_calls, false);
+        let result = self.eval_block_statement(&ts.body, env);
+        self.tail_calls = tail_calls;
+
+        let err = match result {
+            Object::Error(err) if err.is_catchable() => err,
+            result => return result,
+        };
+
+        let handler_env = Environment::new_enclosed(Rc::clone(env));
+        bind(&handler_env, &ts.parameter, err.caught_value());
+
+        self.scopes.push(Rc::clone(&handler_env));
+        let result = self.eval_block_statement(&ts.handler, &handler_env);
+        self.scopes.pop();
+
+        result
+    }
+
+    fn call_function(&mut self, func: &object::Function, args: Vec<Object>) -> Object {
+        if func.parameters.len() != args.len() {
+            return new_error(format!(
+                "wrong number of arguments: want={}, got={}",
+                func.parameters.len(),
+                args.len()
+            ));
+        }
+
+        let env = Environment::new_enclosed(Rc::clone(&func.env));
+        for (param, arg) in func.parameters.iter().zip(args) {
+            bind(&env, param, arg);
+        }
+
+        let tail_calls = std::mem::replace(&mut self.tail_calls, true);
+        self.scopes.push(Rc::clone(&env));
+        let result = self.eval_tail_block(&func.body, &env);
+        self.scopes.pop();
+        self.tail_calls = tail_calls;
+
+        match result {
+            Object::ReturnValue(value) => *value,
+            result => result,
+        }
+    }
+
+    // Trampoline: tail calls come back as TailCall and run here in a loop.
+    fn run_function(&mut self, func: Rc<object::Function>, args: Vec<Object>) -> Object {
+        let mut func = func;
+        let mut args = args;
+        let mut tail_call: Option<(String, Span)> = None;
+
+        loop {
+            match self.call_function(&func, args) {
+                Object::TailCall(call) => {
+                    let call = *call;
+                    func = call.function;
+                    args = call.args;
+                    tail_call = Some((call.name, call.span));
+                }
+                result => {
+                    return match tail_call {
+                        Some((name, span)) => trace_tail_call(result, name, span),
+                        None => result,
+                    };
+                }
+            }
+        }
+    }
+
+    fn apply_function(&mut self, function: Object, args: Vec<Object>) -> Object {
+        match function {
+            Object::Function(func) => {
+                if self.call_depth >= self.max_call_depth {
+                    return new_error(format!(
+                        "maximum call depth of {} exceeded",
+                        self.max_call_depth
+                    ));
+                }
+
+                self.call_depth += 1;
+                let result = self.nested(|this| this.run_function(func, args));
+                self.call_depth -= 1;
+
+                result
+            }
+            Object::Builtin(_, builtin) => {
+                let start = self.temps.len();
+                self.temps.extend(args.iter().cloned());
+                let result = builtin(self, args);
+                self.temps.truncate(start);
+
+                self.charge(result)
+            }
+            other => new_error(format!("not a function: {}", other.object_type())),
+        }
+    }
+}
+
+impl builtins::Caller for Evaluator {
+    fn call(&mut self, function: &Object, args: Vec<Object>) -> Object {
+        self.apply_function(function.clone(), args)
+    }
+
+    fn reserve(&mut self, bytes: usize) -> Option<Object> {
+        self.reserve_memory(bytes)
+    }
+
+    fn consume(&mut self, steps: u64) -> Option<Object> {
+        self.budget.consume(steps)
+    }
+}
+
+// User bindings shadow builtins. A name that nothing has bound yet reads as the builtin, if any.
+fn eval_identifier(ident: &ast::Identifier, env: &Env) -> Object {
+    match ident.binding.get() {
+        Some(Binding::Builtin(index)) => {
+            let (name, builtin) = builtins::BUILTINS[*index];
+            return Object::Builtin(name, builtin);
+        }
+        Some(Binding::Local(slots)) => {
+            for slot in slots.iter() {
+                if let Some(value) = env.borrow().get_slot(slot.depth, slot.index) {
+                    return value;
+                }
+            }
+        }
+        Some(Binding::Global) | None => (),
+    }
+
+    if let Some(value) = env.borrow().get(&ident.value) {
+        return value;
+    }
+    match builtins::lookup_builtin(ident.value.as_str()) {
+        Some(builtin) => builtin,
+        None => new_error(format!("identifier not found: {}", ident.value)),
+    }
+}
+
+// Binds a name in the innermost scope, in the slot the resolver gave it if it has one.
+fn bind(env: &Env, ident: &ast::Identifier, value: Object) {
+    match ident.binding.get() {
+        Some(Binding::Local(slots)) => env.borrow_mut().set_slot(slots[0].index, value),
+        _ => env.borrow_mut().set(ident.value.clone(), value),
+    }
+}
+
+fn assign(env: &Env, ident: &ast::Identifier, value: Object) -> bool {
+    let mut value = value;
+    if let Some(Binding::Local(slots)) = ident.binding.get() {
+        for slot in slots.iter() {
+            match env.borrow_mut().assign_slot(slot.depth, slot.index, value) {
+                Ok(()) => return true,
+                Err(unassigned) => value = unassigned,
+            }
+        }
+    }
+
+    env.borrow_mut().assign(&ident.value, value)
+}
+
+pub(crate) fn index_value(left: Object, index: Object, strict: bool) -> Object {
+    if let Object::Hash(hash) = &left {
+        let key = match index.hash_key() {
+            Some(key) => key,
+            None => return unusable_hash_key(&index),
+        };
+
+        return match hash.borrow().get(&key) {
+            Some(value) => value.clone(),
+            None if strict => new_error(format!("key not found: {}", index.inspect())),
+            None => Object::Null,
+        };
+    }
+
+    let (element, len) = match (&left, &index) {
+        (Object::Array(elements), Object::Integer(i)) => {
+            let elements = elements.borrow();
+            let element = resolve_index(*i, elements.len()).map(|i| elements[i].clone());
+
+            (element, elements.len())
+        }
+        (Object::String(value), Object::Integer(i)) => {
+            let len = value.chars().count();
+            let element = resolve_index(*i, len)
+                .and_then(|i| value.chars().nth(i))
+                .map(|ch| Object::String(ch.to_string().into()));
+
+            (element, len)
+        }
+        // Too large to be in range.
+        (Object::Array(elements), Object::BigInteger(_)) => (None, elements.borrow().len()),
+        (Object::String(value), Object::BigInteger(_)) => (None, value.chars().count()),
+        _ => {
+            return new_error(format!(
+                "index operator not supported: {}[{}]",
+                left.object_type(),
+                index.object_type()
+            ));
+        }
+    };
+
+    match element {
+        Some(element) => element,
+        None if strict => new_error(format!(
+            "index out of range: {} (length {})",
+            index.inspect(),
+            len
+        )),
+        None => Object::Null,
+    }
+}
+
+pub(crate) fn slice_value(left: &Object, bounds: [Option<i64>; 3]) -> Object {
+    let [start, end, step] = bounds;
+
+    if step == Some(0) {
+        return new_error(String::from("slice step cannot be zero"));
+    }
+    let step = step.unwrap_or(1);
+
+    match left {
+        Object::Array(elements) => {
+            let elements = elements.borrow();
+            let sliced = slice_indices(elements.len(), start, end, step)
+                .map(|i| elements[i].clone())
+                .collect();
+
+            Object::new_array(sliced)
+        }
+        Object::String(value) => {
+            let chars: Vec<char> = value.chars().collect();
+
+            Object::String(
+                slice_indices(chars.len(), start, end, step)
+                    .map(|i| chars[i])
+                    .collect::<String>()
+                    .into(),
+            )
+        }
+        _ => new_error(format!(
+            "slice operator not supported: {}",
+            left.object_type()
+        )),
+    }
+}
+
+pub(crate) fn slice_bound(value: Object) -> Result<Option<i64>, Object> {
+    match value {
+        Object::Integer(value) => Ok(Some(value)),
+        // Bounds are clamped to the length anyway, so these act as the furthest i64 can reach.
+        Object::BigInteger(value) if value.is_negative() => Ok(Some(i64::MIN)),
+        Object::BigInteger(_) => Ok(Some(i64::MAX)),
+        Object::Null => Ok(None),
+        err @ Object::Error(_) => Err(err),
+        other => Err(new_error(format!(
+            "slice bound must be INTEGER, got {}",
+            other.object_type()
+        ))),
+    }
+}
+
+// `allocate` is asked for room before a hash grows by a new key.
+pub(crate) fn assign_index(
+    container: Object,
+    index: Object,
+    value: Object,
+    allocate: impl FnOnce(usize) -> Option<Object>,
+) -> Object {
+    match (&container, &index) {
+        (Object::Array(elements), Object::Integer(i)) => {
+            let mut elements = elements.borrow_mut();
+            let len = elements.len();
+            match resolve_index(*i, len) {
+                Some(i) => {
+                    elements[i] = value;
+                    Object::Null
+                }
+                None => new_error(format!("index out of range: {} (length {})", i, len)),
+            }
+        }
+        (Object::Array(elements), Object::BigInteger(i)) => new_error(format!(
+            "index out of range: {} (length {})",
+            i,
+            elements.borrow().len()
+        )),
+        (Object::Hash(hash), _) => match index.hash_key() {
+            Some(key) => {
+                if !hash.borrow().contains_key(&key)
+                    && let Some(err) = allocate(memory::hash_entry_size())
+                {
+                    return err;
+                }
+
+                hash.borrow_mut().insert(key, index, value);
+                Object::Null
+            }
+            None => unusable_hash_key(&index),
+        },
+        _ => new_error(format!(
+            "index assignment not supported: {}[{}]",
+            container.object_type(),
+            index.object_type()
+        )),
+    }
+}
+
+// Maps a possibly negative index onto `0..len`, counting negative indices from the end.
+pub(crate) fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    let resolved = if index < 0 {
+        index.checked_add(len as i64)?
+    } else {
+        index
+    };
+
+    usize::try_from(resolved).ok().filter(|i| *i < len)
+}
+
+// Yields the element positions selected by `[start:end:step]`, following Python's rules:
+// negative bounds count from the end, bounds are clamped, and a negative step walks backwards
+// with defaults covering the whole sequence.
+pub(crate) fn slice_indices(
+    len: usize,
+    start: Option<i64>,
+    end: Option<i64>,
+    step: i64,
+) -> impl Iterator<Item = usize> {
+    let len = len as i64;
+    let (lower, upper) = if step > 0 { (0, len) } else { (-1, len - 1) };
+
+    let clamp = |bound: i64| {
+        let bound = if bound < 0 {
+            bound.saturating_add(len)
+        } else {
+            bound
+        };
+        bound.clamp(lower, upper)
+    };
+
+    let start = start.map_or(if step > 0 { lower } else { upper }, clamp);
+    let end = end.map_or(if step > 0 { upper } else { lower }, clamp);
+
+    let mut i = start;
+    std::iter::from_fn(move || {
+        if (step > 0 && i < end) || (step < 0 && i > end) {
+            let current = i as usize;
+            i = i.saturating_add(step);
+            Some(current)
+        } else {
+            None
+        }
+    })
+}
+
+pub(crate) fn eval_prefix_expression(operator: &str, right: Object) -> Object {
+    match operator {
+        "!" => Object::Boolean(!right.is_truthy()),
+        "-" => match right {
+            Object::Integer(value) => match value.checked_neg() {
+                Some(value) => Object::Integer(value),
+                None => Object::from_big_integer(-&BigInt::from(value)),
+            },
+            Object::BigInteger(value) => Object::from_big_integer(-value.as_ref()),
+            _ => new_error(format!("unknown operator: -{}", right.object_type())),
+        },
+        "~" => match right {
+            Object::Integer(value) => Object::Integer(!value),
+            _ => new_error(format!("unknown operator: ~{}", right.object_type())),
+        },
+        _ => new_error(format!(
+            "unknown operator: {}{}",
+            operator,
+            right.object_type()
+        )),
+    }
+}
+
+pub(crate) fn eval_infix_expression(operator: &str, left: Object, right: Object) -> Object {
+    if let (Object::Integer(l), Object::Integer(r)) = (&left, &right) {
+        return eval_integer_infix_expression(operator, *l, *r);
+    }
+    if let (Some(l), Some(r)) = (left.as_big_integer(), right.as_big_integer()) {
+        return eval_big_integer_infix_expression(operator, &l, &r);
+    }
+
+    match (&left, &right) {
+        (Object::String(l), Object::String(r)) => eval_string_infix_expression(operator, l, r),
+        (Object::Boolean(l), Object::Boolean(r)) => match operator {
+            "==" => Object::Boolean(l == r),
+            "!=" => Object::Boolean(l != r),
+            _ => new_error(format!(
+                "unknown operator: {} {} {}",
+                left.object_type(),
+                operator,
+                right.object_type()
+            )),
+        },
+        (Object::Null, _) | (_, Object::Null) if operator == "==" || operator == "!=" => {
+            let equal = matches!((&left, &right), (Object::Null, Object::Null));
+            Object::Boolean(equal == (operator == "=="))
+        }
+        _ if left.object_type() != right.object_type() => new_error(format!(
+            "type mismatch: {} {} {}",
+            left.object_type(),
+            operator,
+            right.object_type()
+        )),
+        _ => new_error(format!(
+            "unknown operator: {} {} {}",
+            left.object_type(),
+            operator,
+            right.object_type()
+        )),
+    }
+}
+
+// Arithmetic that overflows is redone on big integers.
+fn eval_integer_infix_expression(operator: &str, left: i64, right: i64) -> Object {
+    let checked = |result: Option<i64>| match result {
+        Some(result) => Object::Integer(result),
+        None => {
+            eval_big_integer_infix_expression(operator, &BigInt::from(left), &BigInt::from(right))
+        }
+    };
+
+    match operator {
+        "+" => checked(left.checked_add(right)),
+        "-" => checked(left.checked_sub(right)),
+        "*" => checked(left.checked_mul(right)),
+        "/" | "%" if right == 0 => new_error(String::from("division by zero")),
+        "/" => checked(left.checked_div(right)),
+        "%" => checked(left.checked_rem(right)),
+        "**" if right < 0 => new_error(format!("negative exponent: {} ** {}", left, right)),
+        "**" => checked(
+            u32::try_from(right)
+                .ok()
+                .and_then(|exp| left.checked_pow(exp)),
+        ),
+        "&" => Object::Integer(left & right),
+        "|" => Object::Integer(left | right),
+        "^" => Object::Integer(left ^ right),
+        "<<" | ">>" if !(0..64).contains(&right) => new_error(format!(
+            "shift amount out of range: {} {} {}",
+            left, operator, right
+        )),
+        "<<" => Object::Integer(left << right),
+        ">>" => Object::Integer(left >> right),
+        "<" => Object::Boolean(left < right),
+        ">" => Object::Boolean(left > right),
+        "<=" => Object::Boolean(left <= right),
+        ">=" => Object::Boolean(left >= right),
+        "==" => Object::Boolean(left == right),
+        "!=" => Object::Boolean(left != right),
+        ".." => Object::Range(left, right),
+        _ => new_error(format!("unknown operator: INTEGER {} INTEGER", operator)),
+    }
+}
+
+fn eval_string_infix_expression(operator: &str, left: &str, right: &str) -> Object {
+    match operator {
+        "+" => Object::String(format!("{}{}", left, right).into()),
+        "==" => Object::Boolean(left == right),
+        "!=" => Object::Boolean(left != right),
+        "<" => Object::Boolean(left < right),
+        ">" => Object::Boolean(left > right),
+        "<=" => Object::Boolean(left <= right),
+        ">=" => Object::Boolean(left >= right),
+        _ => new_error(format!("unknown operator: STRING {} STRING", operator)),
+    }
+}
+
+// Results that fit back in i64 come back as plain integers. Bitwise operators and ranges only
+// work within i64.
+fn eval_big_integer_infix_expression(operator: &str, left: &BigInt, right: &BigInt) -> Object {
+    match operator {
+        "+" => Object::from_big_integer(left + right),
+        "-" => Object::from_big_integer(left - right),
+        "*" => Object::from_big_integer(left * right),
+        "/" | "%" => match left.div_rem(right) {
+            Some((quotient, _)) if operator == "/" => Object::from_big_integer(quotient),
+            Some((_, remainder)) => Object::from_big_integer(remainder),
+            None => new_error(String::from("division by zero")),
+        },
+        "**" if right.is_negative() => {
+            new_error(format!("negative exponent: {} ** {}", left, right))
+        }
+        "**" => big_integer_pow(left, right),
+        "<" => Object::Boolean(left < right),
+        ">" => Object::Boolean(left > right),
+        "<=" => Object::Boolean(left <= right),
+        ">=" => Object::Boolean(left >= right),
+        "==" => Object::Boolean(left == right),
+        "!=" => Object::Boolean(left != right),
+        _ => new_error(format!(
+            "integer out of range for operator {}: {} {} {}",
+            operator, left, operator, right
+        )),
+    }
+}
+
+fn big_integer_pow(base: &BigInt, exp: &BigInt) -> Object {
+    // 0, 1 and -1 stay small however large the exponent.
+    if base.bits() <= 1 {
+        let odd = exp
+            .div_rem(&BigInt::from(2))
+            .is_some_and(|(_, r)| !r.is_zero());
+        let exp = if exp.is_zero() {
+            0
+        } else if odd {
+            1
+        } else {
+            2
+        };
+        return Object::from_big_integer(base.pow(exp));
+    }
+
+    // Anything past i64 has more bits than memory can hold.
+    match exp.to_i64() {
+        Some(exp) => Object::from_big_integer(base.pow(exp as u64)),
+        None => new_error(format!("exponent too large: {}", exp)),
+    }
+}
+
+// A lower bound on the bits in the result of multiplying or raising to a power, and the limb
+// multiplications working it out takes, before doing any of it. Only these grow faster than
+// their operands; other operators, and results that fit in i64, give None.
+pub(crate) fn integer_growth(operator: &str, left: &Object, right: &Object) -> Option<(u64, u64)> {
+    let (left_bits, right_bits) = (integer_bits(left)?, integer_bits(right)?);
+    let limbs = |bits: u64| bits.div_ceil(64);
+
+    let (bits, work) = match (operator, right) {
+        ("*", _) => (
+            (left_bits + right_bits).saturating_sub(1),
+            limbs(left_bits) * limbs(right_bits),
+        ),
+        // Powers of 0, 1 and -1 stay small, and a negative exponent is an error.
+        ("**", _) if left_bits <= 1 => return None,
+        ("**", Object::Integer(exp)) if *exp < 0 => return None,
+        ("**", Object::BigInteger(exp)) if exp.is_negative() => return None,
+        ("**", _) => {
+            // Exponents too large for i64 are too large for anything.
+            let exp = match right {
+                Object::Integer(exp) => *exp as u64,
+                _ => u64::MAX,
+            };
+            let bits = (left_bits - 1).saturating_mul(exp).saturating_add(1);
+            (bits, limbs(bits).saturating_mul(limbs(bits)))
+        }
+        _ => return None,
+    };
+
+    (bits > 64).then_some((bits, work))
+}
+
+fn integer_bits(obj: &Object) -> Option<u64> {
+    match obj {
+        Object::Integer(value) => Some((u64::BITS - value.unsigned_abs().leading_zeros()) as u64),
+        Object::BigInteger(value) => Some(value.bits() as u64),
+        _ => None,
+    }
+}
+
+// Errors get the span of the innermost node they surface from.
+fn with_span(mut result: Object, span: Span) -> Object {
+    if let Object::Error(err) = &mut result
+        && err.span.is_none()
+    {
+        err.span = Some(span);
+    }
+
+    result
+}
+
+// An error that already has a span was raised inside the callee, so this call joins its traceback.
+fn trace_call(mut result: Object, call: &ast::CallExpression) -> Object {
+    if let Object::Error(err) = &mut result
+        && err.span.is_some()
+    {
+        err.traceback.push(object::TraceFrame {
+            function: call.function.string(),
+            span: call.token.span,
+        });
+    }
+
+    result
+}
+
+// Errors from a function reached by tail calls only keep the last call in the chain.
+fn trace_tail_call(mut result: Object, name: String, span: Span) -> Object {
+    if let Object::Error(err) = &mut result {
+        match err.span {
+            Some(_) => err.traceback.push(object::TraceFrame {
+                function: name,
+                span,
+            }),
+            None => err.span = Some(span),
+        }
+    }
+
+    result
+}
+
+pub(crate) fn unusable_hash_key(key: &Object) -> Object {
+    new_error(format!("unusable as hash key: {}", key.object_type()))
+}
+
+pub(crate) fn new_error(message: String) -> Object {
+    Object::Error(Box::new(object::RuntimeError::new(message)))
+}
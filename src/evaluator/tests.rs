@@ -0,0 +1,1303 @@
+use super::*;
+use crate::test_helpers::parse;
+
+fn test_eval(input: &str) -> Object {
+    let env = Environment::new();
+    Evaluator::new().eval_program(&parse(input), &env)
+}
+
+fn assert_integer(obj: &Object, expected: i64) {
+    match obj {
+        Object::Integer(value) => assert_eq!(*value, expected),
+        other => panic!("object is not Integer. got={}", other.inspect()),
+    }
+}
+
+fn assert_boolean(obj: &Object, expected: bool) {
+    match obj {
+        Object::Boolean(value) => assert_eq!(*value, expected),
+        other => panic!("object is not Boolean. got={}", other.inspect()),
+    }
+}
+
+fn assert_error(obj: &Object, expected: &str) {
+    match obj {
+        Object::Error(err) => assert_eq!(err.message, expected),
+        other => panic!("no error object returned. got={}", other.inspect()),
+    }
+}
+
+#[test]
+fn test_eval_integer_expression() {
+    let tests = vec![
+        ("5", 5),
+        ("-10", -10),
+        ("5 + 5 + 5 + 5 - 10", 10),
+        ("2 * (5 + 10)", 30),
+        ("50 / 2 * 2 + 10", 60),
+        ("(5 + 10 * 2 + 15 / 3) * 2 + -10", 50),
+        ("17 % 5", 2),
+        ("-17 % 5", -2),
+        ("2 ** 10", 1024),
+        ("2 ** 3 ** 2", 512),
+        ("-2 ** 2", -4),
+        ("5 ** 0", 1),
+        ("12 & 10", 8),
+        ("12 | 10", 14),
+        ("12 ^ 10", 6),
+        ("~0", -1),
+        ("~5 & 7", 2),
+        ("1 << 10", 1024),
+        ("-16 >> 2", -4),
+        ("1 << 63 >> 63", -1),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_integer(&test_eval(input), *expected);
+    }
+}
+
+#[test]
+fn test_eval_boolean_expression() {
+    let tests = vec![
+        ("true", true),
+        ("1 < 2", true),
+        ("1 >= 2", false),
+        ("2 <= 2", true),
+        ("1 == 1", true),
+        ("(1 < 2) == true", true),
+        ("!true", false),
+        ("!!5", true),
+        ("3 & 1 == 1", true),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_boolean(&test_eval(input), *expected);
+    }
+}
+
+#[test]
+fn test_if_else_expressions() {
+    assert_integer(&test_eval("if (1 < 2) { 10 } else { 20 }"), 10);
+    assert_integer(&test_eval("if (1 > 2) { 10 } else { 20 }"), 20);
+    assert!(matches!(test_eval("if (false) { 10 }"), Object::Null));
+}
+
+#[test]
+fn test_return_statements() {
+    assert_integer(&test_eval("9; return 2 * 5; 9;"), 10);
+    assert_integer(
+        &test_eval("if (10 > 1) { if (10 > 1) { return 10; } return 1; }"),
+        10,
+    );
+}
+
+#[test]
+fn test_let_and_functions() {
+    assert_integer(&test_eval("let a = 5; let b = a * 2; b;"), 10);
+    assert_integer(
+        &test_eval("let add = fn(x, y) { x + y }; add(5, add(5, 5));"),
+        15,
+    );
+    assert_integer(
+        &test_eval("let adder = fn(x) { fn(y) { x + y } }; let addTwo = adder(2); addTwo(3);"),
+        5,
+    );
+}
+
+#[test]
+fn test_big_integers() {
+    let tests = [
+        ("9223372036854775807 + 1", "9223372036854775808"),
+        ("-9223372036854775807 - 2", "-9223372036854775809"),
+        ("-9223372036854775808", "-9223372036854775808"),
+        ("-(-9223372036854775807 - 1)", "9223372036854775808"),
+        ("(-9223372036854775807 - 1) / -1", "9223372036854775808"),
+        ("(-9223372036854775807 - 1) % -1", "0"),
+        ("4294967296 * 4294967296", "18446744073709551616"),
+        ("2 ** 64 - 1", "18446744073709551615"),
+        ("(2 ** 64 + 5) / 2 ** 32", "4294967296"),
+        ("-(2 ** 70) % 1000", "-424"),
+        ("99999999999999999999 - 99999999999999999998", "1"),
+        (
+            "123456789012345678901234567890",
+            "123456789012345678901234567890",
+        ),
+        (
+            "[2 ** 64 > 2 ** 63, 2 ** 64 == 18446744073709551616, 5 < 2 ** 80]",
+            "[true, true, true]",
+        ),
+        ("1 ** 100000000000 + (-1) ** 100000000001", "0"),
+        (
+            "let f = fn(n) { if (n < 2) { 1 } else { n * f(n - 1) } }; f(25)",
+            "15511210043330985984000000",
+        ),
+        ("{2 ** 64: \"big\"}[18446744073709551616]", "big"),
+        ("[1, 2][2 ** 64]", "null"),
+        ("[1, 2, 3][-(2 ** 64):2 ** 64]", "[1, 2, 3]"),
+        (
+            "sort([2 ** 65, 3, -(2 ** 70)])",
+            "[-1180591620717411303424, 3, 36893488147419103232]",
+        ),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_eq!(test_eval(input).inspect(), *expected, "input: {}", input);
+    }
+
+    assert_error(&test_eval("2 ** 64 / 0"), "division by zero");
+    assert_error(
+        &test_eval("2 ** 64 & 1"),
+        "integer out of range for operator &: 18446744073709551616 & 1",
+    );
+    assert_integer(&test_eval("let x = 2 ** 200000; x * x / 2 ** 399990"), 1024);
+    assert_error(
+        &test_eval("2 ** (2 ** 64)"),
+        "exponent too large: 18446744073709551616",
+    );
+    assert_error(
+        &test_eval("range(2 ** 64)"),
+        "argument to `range` out of range: 18446744073709551616",
+    );
+}
+
+#[test]
+fn test_error_handling() {
+    let tests = vec![
+        ("5 + true;", "type mismatch: INTEGER + BOOLEAN"),
+        ("-true", "unknown operator: -BOOLEAN"),
+        ("~true", "unknown operator: ~BOOLEAN"),
+        ("true + false; 5", "unknown operator: BOOLEAN + BOOLEAN"),
+        (
+            "if (10 > 1) { return true % false; }",
+            "unknown operator: BOOLEAN % BOOLEAN",
+        ),
+        ("foobar", "identifier not found: foobar"),
+        ("10 / 0", "division by zero"),
+        ("10 % (5 - 5)", "division by zero"),
+        ("2 ** -1", "negative exponent: 2 ** -1"),
+        ("1 << 64", "shift amount out of range: 1 << 64"),
+        ("1 >> -1", "shift amount out of range: 1 >> -1"),
+        ("5(1)", "not a function: INTEGER"),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_error(&test_eval(input), expected);
+    }
+}
+
+#[test]
+fn test_while_loops() {
+    assert!(matches!(test_eval("while (false) { 1 }"), Object::Null));
+    assert_integer(
+        &test_eval("let f = fn() { while (true) { return 7; } }; f();"),
+        7,
+    );
+    assert_integer(&test_eval("while (true) { break; } 3"), 3);
+    assert_error(
+        &test_eval("while (true) { 1 + true; }"),
+        "type mismatch: INTEGER + BOOLEAN",
+    );
+}
+
+#[test]
+fn test_for_loops() {
+    let find = "let find = fn(xs, limit) { for (x in xs) { if (x > limit) { return x; } } -1 };";
+
+    assert_integer(&test_eval(&format!("{} find([1, 5, 9], 3)", find)), 5);
+    assert_integer(&test_eval(&format!("{} find([1, 2], 3)", find)), -1);
+    assert_integer(
+        &test_eval(&format!("{} find(0..100000, 99998)", find)),
+        99999,
+    );
+    assert_integer(
+        &test_eval("let f = fn() { for (i in 0..10) { if (i < 4) { continue; } return i; } }; f()"),
+        4,
+    );
+    assert_integer(
+        &test_eval("let f = fn() { for (i in 0..10) { if (i == 2) { break; } } 42 }; f()"),
+        42,
+    );
+    assert_error(&test_eval("for (x in 5) { x }"), "not iterable: INTEGER");
+}
+
+#[test]
+fn test_string_literals_share_their_text() {
+    let strings = match test_eval("let f = fn() { \"key\" }; [f(), f()]") {
+        Object::Array(elements) => elements.borrow().to_vec(),
+        other => panic!("object is not Array. got={}", other.inspect()),
+    };
+    match (&strings[0], &strings[1]) {
+        (Object::String(a), Object::String(b)) => assert!(Rc::ptr_eq(a, b)),
+        _ => panic!("expected two strings"),
+    }
+    match (strings[0].hash_key(), &strings[1]) {
+        (Some(object::HashKey::String(key)), Object::String(value)) => {
+            assert!(Rc::ptr_eq(&key, value))
+        }
+        _ => panic!("expected a string key"),
+    }
+}
+
+#[test]
+fn test_for_loop_string_iteration() {
+    match test_eval("let f = fn() { for (c in \"hey\") { return c; } }; f()") {
+        Object::String(value) => assert_eq!(&*value, "h"),
+        other => panic!("object is not String. got={}", other.inspect()),
+    }
+}
+
+#[test]
+fn test_assignment() {
+    let tests = vec![
+        ("let x = 1; x = 5; x", 5),
+        ("let x = 1; x += 4; x", 5),
+        ("let x = 10; x -= 4; x *= 3; x /= 2; x", 9),
+        ("let x = 17; x %= 5; x **= 3; x", 8),
+        ("let x = 6; x &= 3; x |= 8; x ^= 1; x", 11),
+        ("let x = 1; x <<= 4; x >>= 2; x", 4),
+        (
+            "let sum = 0; for (i in 0..100001) { sum += i; } sum",
+            5000050000,
+        ),
+        ("let i = 0; while (i < 10) { i += 1; } i", 10),
+        (
+            "let counter = fn() { let n = 0; fn() { n += 1; n } }; let c = counter(); c(); c(); c()",
+            3,
+        ),
+        (
+            "let x = 1; let f = fn() { let x = 2; x = 3; x }; f() + x",
+            4,
+        ),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_integer(&test_eval(input), *expected);
+    }
+}
+
+#[test]
+fn test_index_assignment() {
+    let tests = [
+        ("let a = [1, 2, 3]; a[1] = 20; a[1]", 20),
+        ("let a = [1, 2, 3]; a[2] += 5; a[2]", 8),
+        ("let a = [[1, 2], [3, 4]]; a[1][0] = 30; a[1][0]", 30),
+        ("let a = [1, 2]; let b = a; b[0] = 9; a[0]", 9),
+        (
+            "let a = [0, 0, 0]; for (i in 0..3) { a[i] = i * i; } a[2]",
+            4,
+        ),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_integer(&test_eval(input), *expected);
+    }
+}
+
+#[test]
+fn test_inspect_cycles_and_deep_nesting() {
+    let tests = [
+        ("let a = [1]; a[0] = a; a", "[[...]]"),
+        ("let h = {}; h[\"s\"] = h; h", "{s: {...}}"),
+        (
+            "let a = [1, 2]; let h = {\"a\": a}; a[1] = h; a",
+            "[1, {a: [...]}]",
+        ),
+        // Shared without a cycle still prints in full each time.
+        ("let a = [1, 2]; [a, a]", "[[1, 2], [1, 2]]"),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_eq!(test_eval(input).inspect(), *expected, "input: {}", input);
+    }
+
+    // Printing and freeing values nested this deep would overflow the stack if either recursed.
+    let deep = test_eval("let d = []; for (i in 0..100000) { d = [d]; } d");
+    assert_eq!(deep.inspect().len(), 200_002);
+    let deep = test_eval("let d = {}; for (i in 0..100000) { d = {1: d}; } d");
+    assert_eq!(deep.inspect().len(), 500_002);
+    assert_eq!(
+        test_eval("let a = [1]; a[0] = a; throw a;").inspect(),
+        "ERROR: uncaught exception: [[...]]"
+    );
+}
+
+#[test]
+fn test_assignment_errors() {
+    let tests = [
+        ("y = 5;", "cannot assign to undeclared identifier: y"),
+        ("y += 5;", "identifier not found: y"),
+        ("let x = true; x += 1;", "type mismatch: BOOLEAN + INTEGER"),
+        ("let a = [1]; a[3] = 2;", "index out of range: 3 (length 1)"),
+        (
+            "let a = 5; a[0] = 1;",
+            "index assignment not supported: INTEGER[INTEGER]",
+        ),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_error(&test_eval(input), expected);
+    }
+}
+
+#[test]
+fn test_else_if_chains() {
+    let classify = "let classify = fn(n) { if (n < 0) { -1 } else if (n == 0) { 0 } else if (n < 10) { 1 } else { 2 } };";
+
+    let tests = [("-5", -1), ("0", 0), ("7", 1), ("100", 2)];
+
+    for (arg, expected) in tests.iter() {
+        assert_integer(
+            &test_eval(&format!("{} classify({})", classify, arg)),
+            *expected,
+        );
+    }
+
+    assert!(matches!(
+        test_eval("if (false) { 1 } else if (false) { 2 }"),
+        Object::Null
+    ));
+}
+
+#[test]
+fn test_null_literal() {
+    assert!(matches!(test_eval("null"), Object::Null));
+    assert!(matches!(test_eval("let x = null; x"), Object::Null));
+    assert_boolean(&test_eval("null == null"), true);
+    assert_boolean(&test_eval("null != null"), false);
+    assert_boolean(&test_eval("1 == null"), false);
+    assert_boolean(&test_eval("null != true"), true);
+    assert_boolean(&test_eval("!null"), true);
+    assert_boolean(&test_eval("if (false) { 1 } == null"), true);
+    assert_error(&test_eval("null + 1"), "type mismatch: NULL + INTEGER");
+}
+
+fn assert_array(obj: &Object, expected: &[i64]) {
+    match obj {
+        Object::Array(elements) => {
+            let elements = elements.borrow();
+            assert_eq!(elements.len(), expected.len());
+            for (element, expected) in elements.iter().zip(expected.iter()) {
+                assert_integer(element, *expected);
+            }
+        }
+        other => panic!("object is not Array. got={}", other.inspect()),
+    }
+}
+
+#[test]
+fn test_builtin_functions() {
+    let tests = [
+        ("len(\"\")", 0),
+        ("len(\"four\")", 4),
+        ("len(\"héllo\")", 5),
+        ("len([1, 2, 3])", 3),
+        ("len([])", 0),
+        ("first([7, 8, 9])", 7),
+        ("last([7, 8, 9])", 9),
+        ("len(rest([7, 8, 9]))", 2),
+        ("let a = [1]; let b = push(a, 2); len(a) * 10 + len(b)", 12),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_integer(&test_eval(input), *expected);
+    }
+
+    assert_array(&test_eval("rest([1, 2, 3])"), &[2, 3]);
+    assert_array(&test_eval("push([1, 2], 3)"), &[1, 2, 3]);
+    assert!(matches!(test_eval("first([])"), Object::Null));
+    assert!(matches!(test_eval("last([])"), Object::Null));
+    assert!(matches!(test_eval("rest([])"), Object::Null));
+    assert!(matches!(test_eval("puts(\"hello\", 1)"), Object::Null));
+}
+
+#[test]
+fn test_builtin_errors() {
+    let tests = [
+        ("len(1)", "argument to `len` not supported, got INTEGER"),
+        (
+            "len(\"one\", \"two\")",
+            "wrong number of arguments: want=1, got=2",
+        ),
+        ("first(1)", "argument to `first` must be ARRAY, got INTEGER"),
+        (
+            "last(\"abc\")",
+            "argument to `last` must be ARRAY, got STRING",
+        ),
+        (
+            "rest(true)",
+            "argument to `rest` must be ARRAY, got BOOLEAN",
+        ),
+        (
+            "push(1, 1)",
+            "argument to `push` must be ARRAY, got INTEGER",
+        ),
+        ("push([])", "wrong number of arguments: want=2, got=1"),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_error(&test_eval(input), expected);
+    }
+}
+
+fn assert_string(obj: &Object, expected: &str) {
+    match obj {
+        Object::String(value) => assert_eq!(&**value, expected),
+        other => panic!("object is not String. got={}", other.inspect()),
+    }
+}
+
+#[test]
+fn test_string_operations() {
+    let tests = [
+        ("\"Hello\" + \" \" + \"World!\"", "Hello World!"),
+        ("let s = \"ab\"; s += \"cd\"; s", "abcd"),
+        ("\"héllo\"[1]", "é"),
+        ("\"hello\"[1:3]", "el"),
+        ("\"hello\"[:2]", "he"),
+        ("\"hello\"[3:]", "lo"),
+        ("\"hello\"[:]", "hello"),
+        ("\"hello\"[4:2]", ""),
+        ("\"hello\"[2:100]", "llo"),
+        ("\"a\\tb\\n\\\"c\\\"\"", "a\tb\n\"c\""),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_string(&test_eval(input), expected);
+    }
+
+    assert!(matches!(test_eval("\"abc\"[3]"), Object::Null));
+
+    let comparisons = [
+        ("\"a\" == \"a\"", true),
+        ("\"a\" != \"b\"", true),
+        ("\"apple\" < \"banana\"", true),
+        ("\"b\" > \"a\"", true),
+        ("\"a\" <= \"a\"", true),
+        ("\"a\" >= \"b\"", false),
+    ];
+
+    for (input, expected) in comparisons.iter() {
+        assert_boolean(&test_eval(input), *expected);
+    }
+}
+
+#[test]
+fn test_string_builtins() {
+    let tests = [
+        ("join(split(\"a,b,c\", \",\"), \"-\")", "a-b-c"),
+        ("join(split(\"abc\", \"\"), \" \")", "a b c"),
+        ("join([1, \"x\", true], \"\")", "1xtrue"),
+        ("trim(\"  hi \\n\")", "hi"),
+        ("upper(\"MiXed\")", "MIXED"),
+        ("lower(\"MiXed\")", "mixed"),
+        ("replace(\"a-b-c\", \"-\", \"+\")", "a+b+c"),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_string(&test_eval(input), expected);
+    }
+
+    assert_integer(&test_eval("len(split(\"a b  c\", \" \"))"), 4);
+    assert_boolean(&test_eval("contains(\"haystack\", \"st\")"), true);
+    assert_boolean(&test_eval("contains(\"haystack\", \"needle\")"), false);
+    assert_boolean(&test_eval("starts_with(\"prefix_rest\", \"prefix\")"), true);
+    assert_boolean(&test_eval("starts_with(\"rest\", \"prefix\")"), false);
+
+    let errors = [
+        ("\"a\" - \"b\"", "unknown operator: STRING - STRING"),
+        ("\"a\" + 1", "type mismatch: STRING + INTEGER"),
+        (
+            "upper(1)",
+            "argument to `upper` must be STRING, got INTEGER",
+        ),
+        (
+            "split(\"a\", 1)",
+            "argument to `split` must be STRING, got INTEGER",
+        ),
+        (
+            "replace(\"a\", \"b\")",
+            "wrong number of arguments: want=3, got=2",
+        ),
+        ("\"abc\"[\"x\":]", "slice bound must be INTEGER, got STRING"),
+        ("5[1:2]", "slice operator not supported: INTEGER"),
+    ];
+
+    for (input, expected) in errors.iter() {
+        assert_error(&test_eval(input), expected);
+    }
+}
+
+fn test_eval_strict(input: &str) -> Object {
+    let mut evaluator = Evaluator::new();
+    evaluator.set_strict(true);
+    evaluator.eval_program(&parse(input), &Environment::new())
+}
+
+#[test]
+fn test_array_indexing() {
+    let tests = [
+        ("[1, 2, 3][0]", 1),
+        ("[1, 2, 3][2]", 3),
+        ("let i = 0; [1][i]", 1),
+        ("[1, 2, 3][1 + 1]", 3),
+        ("let a = [1, 2, 3]; a[0] + a[1] + a[2]", 6),
+        ("[1, 2, 3][-1]", 3),
+        ("[1, 2, 3][-3]", 1),
+        ("let a = [1, 2, 3]; a[-1] = 30; a[2]", 30),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_integer(&test_eval(input), *expected);
+    }
+
+    assert_string(&test_eval("\"hello\"[-2]"), "l");
+    assert!(matches!(test_eval("[1, 2, 3][3]"), Object::Null));
+    assert!(matches!(test_eval("[1, 2, 3][-4]"), Object::Null));
+    assert_error(
+        &test_eval_strict("[1, 2, 3][3]"),
+        "index out of range: 3 (length 3)",
+    );
+    assert_error(
+        &test_eval_strict("\"ab\"[-3]"),
+        "index out of range: -3 (length 2)",
+    );
+    assert_integer(&test_eval_strict("[1, 2, 3][-1]"), 3);
+}
+
+#[test]
+fn test_array_slicing() {
+    let tests: [(&str, &[i64]); 12] = [
+        ("[1, 2, 3, 4, 5][1:3]", &[2, 3]),
+        ("[1, 2, 3, 4, 5][:2]", &[1, 2]),
+        ("[1, 2, 3, 4, 5][3:]", &[4, 5]),
+        ("[1, 2, 3, 4, 5][-2:]", &[4, 5]),
+        ("[1, 2, 3, 4, 5][:-3]", &[1, 2]),
+        ("[1, 2, 3, 4, 5][::2]", &[1, 3, 5]),
+        ("[1, 2, 3, 4, 5][1::2]", &[2, 4]),
+        ("[1, 2, 3, 4, 5][::-1]", &[5, 4, 3, 2, 1]),
+        ("[1, 2, 3, 4, 5][3:0:-1]", &[4, 3, 2]),
+        ("[1, 2, 3, 4, 5][-1:-4:-2]", &[5, 3]),
+        ("[1, 2, 3, 4, 5][10:20]", &[]),
+        ("[1, 2, 3][-100:100]", &[1, 2, 3]),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_array(&test_eval(input), expected);
+    }
+
+    assert_string(&test_eval("\"hello\"[::-1]"), "olleh");
+    assert_string(&test_eval("\"hello\"[-3:]"), "llo");
+    assert_array(&test_eval_strict("[1, 2][5:9]"), &[]);
+    assert_error(&test_eval("[1, 2][::0]"), "slice step cannot be zero");
+}
+
+#[test]
+fn test_collection_builtins() {
+    let tests: [(&str, &[i64]); 17] = [
+        ("map([1, 2, 3], fn(x) { x * 2 })", &[2, 4, 6]),
+        ("map(0..4, fn(x) { x * x })", &[0, 1, 4, 9]),
+        ("let k = 10; map([1], fn(x) { x + k })", &[11]),
+        ("filter([1, 2, 3, 4], fn(x) { x % 2 == 0 })", &[2, 4]),
+        ("sort([3, 1, 2])", &[1, 2, 3]),
+        ("sort([3, 1, 2], fn(a, b) { b - a })", &[3, 2, 1]),
+        ("let a = [2, 1]; let b = sort(a); a", &[2, 1]),
+        ("reverse([1, 2, 3])", &[3, 2, 1]),
+        ("range(4)", &[0, 1, 2, 3]),
+        ("range(2, 5)", &[2, 3, 4]),
+        ("range(0, 10, 3)", &[0, 3, 6, 9]),
+        ("range(5, 0, -2)", &[5, 3, 1]),
+        ("range(3, 1)", &[]),
+        ("flatten([[1, 2], [], [3], 4])", &[1, 2, 3, 4]),
+        ("flatten([[1, [2]]])[0:1]", &[1]),
+        (
+            "map(zip([1, 2, 3], [10, 20]), fn(p) { p[0] + p[1] })",
+            &[11, 22],
+        ),
+        (
+            "map(enumerate([5, 6]), fn(p) { p[0] * 10 + p[1] })",
+            &[5, 16],
+        ),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_array(&test_eval(input), expected);
+    }
+
+    assert_integer(
+        &test_eval("reduce([1, 2, 3, 4], fn(acc, x) { acc + x })"),
+        10,
+    );
+    assert_integer(
+        &test_eval("reduce([1, 2, 3], fn(acc, x) { acc * x }, 10)"),
+        60,
+    );
+    assert_integer(&test_eval("reduce([], fn(acc, x) { acc + x }, 7)"), 7);
+    assert_integer(&test_eval("len(map(range(100000), fn(x) { x }))"), 100000);
+    assert_string(
+        &test_eval("join(sort([\"pear\", \"apple\"]), \",\")"),
+        "apple,pear",
+    );
+    assert_string(&test_eval("reverse(\"abc\")"), "cba");
+    assert_boolean(&test_eval("any([1, 3, 4], fn(x) { x % 2 == 0 })"), true);
+    assert_boolean(&test_eval("any([])"), false);
+    assert_boolean(&test_eval("all([1, 3], fn(x) { x % 2 == 1 })"), true);
+    assert_boolean(&test_eval("all([1, false])"), false);
+    assert_array(&test_eval("map([\"a\", \"bb\"], len)"), &[1, 2]);
+}
+
+#[test]
+fn test_collection_builtin_errors() {
+    let tests = [
+        (
+            "map([1], 5)",
+            "argument to `map` must be FUNCTION, got INTEGER",
+        ),
+        (
+            "map(5, fn(x) { x })",
+            "argument to `map` must be iterable, got INTEGER",
+        ),
+        (
+            "map([1, true], fn(x) { x + 1 })",
+            "type mismatch: BOOLEAN + INTEGER",
+        ),
+        (
+            "filter([1], fn(x, y) { x })",
+            "wrong number of arguments: want=2, got=1",
+        ),
+        (
+            "reduce([], fn(a, b) { a })",
+            "reduce of empty sequence with no initial value",
+        ),
+        ("sort([1, \"a\"])", "cannot compare INTEGER and STRING"),
+        (
+            "sort([1, 2], fn(a, b) { true })",
+            "sort comparator must return INTEGER, got BOOLEAN",
+        ),
+        ("range(0, 5, 0)", "range step cannot be zero"),
+        (
+            "range(1, 2, 3, 4)",
+            "wrong number of arguments: want=1..3, got=4",
+        ),
+        ("zip([1])", "wrong number of arguments: want=2.., got=1"),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_error(&test_eval(input), expected);
+    }
+}
+
+#[test]
+fn test_hash_literals_and_indexing() {
+    let input = "let two = \"two\";
+    let h = {\"one\": 10 - 9, two: 1 + 1, \"thr\" + \"ee\": 6 / 2, 4: 4, true: 5, false: 6};
+    [h[\"one\"], h[\"two\"], h[\"three\"], h[4], h[true], h[false]]";
+    assert_array(&test_eval(input), &[1, 2, 3, 4, 5, 6]);
+
+    assert_string(
+        &test_eval("let h = {\"b\": 1, \"a\": 2, \"b\": 3}; join(keys(h), \",\")"),
+        "b,a",
+    );
+    assert_integer(
+        &test_eval("let h = {\"b\": 1, \"a\": 2, \"b\": 3}; h[\"b\"]"),
+        3,
+    );
+    assert!(matches!(test_eval("{1: 2}[5]"), Object::Null));
+    assert_error(&test_eval_strict("{1: 2}[5]"), "key not found: 5");
+    assert_integer(
+        &test_eval("let h = {}; h[\"x\"] = 1; h[\"x\"] += 4; h[\"x\"]"),
+        5,
+    );
+    assert_integer(
+        &test_eval("let h = {\"a\": 1, \"b\": 2}; let t = 0; for (k in h) { t += h[k]; } t"),
+        3,
+    );
+    assert_integer(&test_eval("len({1: 1, 2: 2})"), 2);
+    assert_eq!(
+        test_eval("{\"a\": 1, 2: [true]}").inspect(),
+        "{a: 1, 2: [true]}"
+    );
+}
+
+#[test]
+fn test_hash_builtins() {
+    let setup = "let h = {\"a\": 1, \"b\": 2, \"c\": 3};";
+    let tests = [
+        ("values(h)", "[1, 2, 3]"),
+        ("keys(delete(h, \"b\"))", "[a, c]"),
+        ("delete(h, \"b\"); len(h)", "3"),
+        ("has(h, \"a\")", "true"),
+        ("has(h, 1)", "false"),
+        (
+            "merge(h, {\"b\": 20, \"d\": 4})",
+            "{a: 1, b: 20, c: 3, d: 4}",
+        ),
+        ("entries({1: true})", "[[1, true]]"),
+        ("map(entries(h), fn(e) { e[1] })", "[1, 2, 3]"),
+    ];
+
+    for (input, expected) in tests.iter() {
+        let result = test_eval(&format!("{} {}", setup, input));
+        assert_eq!(result.inspect(), *expected, "input: {}", input);
+    }
+}
+
+#[test]
+fn test_hash_errors() {
+    let tests = [
+        ("{fn(x) { x }: 1}", "unusable as hash key: FUNCTION"),
+        ("{1: 2}[[1]]", "unusable as hash key: ARRAY"),
+        ("let h = {}; h[{}] = 1", "unusable as hash key: HASH"),
+        ("has({}, [])", "unusable as hash key: ARRAY"),
+        ("keys([1])", "argument to `keys` must be HASH, got ARRAY"),
+        (
+            "merge({}, 1)",
+            "argument to `merge` must be HASH, got INTEGER",
+        ),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_error(&test_eval(input), expected);
+    }
+}
+
+#[test]
+fn test_error_spans_and_traceback() {
+    let input = "let inner = fn(x) {
+  x + true
+};
+let outer = fn(n) {
+  inner(n)
+};
+outer(1);";
+
+    let err = match test_eval(input) {
+        Object::Error(err) => err,
+        other => panic!("object is not Error. got={}", other.inspect()),
+    };
+
+    assert_eq!(err.message, "type mismatch: INTEGER + BOOLEAN");
+    assert_eq!(err.span, Some(Span { line: 2, column: 5 }));
+
+    let frames: Vec<(String, Span)> = err
+        .traceback
+        .iter()
+        .map(|frame| (frame.function.clone(), frame.span))
+        .collect();
+    assert_eq!(
+        frames,
+        [
+            (String::from("inner"), Span { line: 5, column: 8 }),
+            (String::from("outer"), Span { line: 7, column: 6 }),
+        ]
+    );
+    assert_eq!(
+        err.report(),
+        "error: type mismatch: INTEGER + BOOLEAN\n  at 2:5\n  in inner, called at 5:8\n  in outer, called at 7:6"
+    );
+}
+
+#[test]
+fn test_error_span_at_call_site() {
+    let tests = [
+        ("let x = 1;\nx(2)", Span { line: 2, column: 2 }),
+        ("len(1, 2)", Span { line: 1, column: 4 }),
+        ("\n  missing", Span { line: 2, column: 3 }),
+    ];
+
+    for (input, expected) in tests.iter() {
+        match test_eval(input) {
+            Object::Error(err) => {
+                assert_eq!(err.span, Some(*expected), "input: {}", input);
+                assert!(err.traceback.is_empty(), "input: {}", input);
+            }
+            other => panic!("object is not Error. got={}", other.inspect()),
+        }
+    }
+}
+
+#[test]
+fn test_try_catch() {
+    let tests = [
+        ("let r = 0; try { r = 1; } catch (e) { r = 2; } r", "1"),
+        (
+            "let r = 0; try { throw 5; r = 1; } catch (e) { r = e * 2; } r",
+            "10",
+        ),
+        (
+            "let r = \"\"; try { 5 + true; } catch (e) { r = e; } r",
+            "type mismatch: INTEGER + BOOLEAN",
+        ),
+        (
+            "let r = \"\"; try { len(1); } catch (e) { r = e; } r",
+            "argument to `len` not supported, got INTEGER",
+        ),
+        (
+            "let r = null; try { throw {\"code\": 7}; } catch (e) { r = e[\"code\"]; } r",
+            "7",
+        ),
+        (
+            "let f = fn(x) { if (x > 2) { throw \"too big\"; } x };
+            let total = 0;
+            for (x in [1, 2, 3, 2]) {
+                try { total += f(x); } catch (e) { total += 100; }
+            }
+            total",
+            "105",
+        ),
+        (
+            "let f = fn() { try { return 1; } catch (e) { 2 } 3 }; f()",
+            "1",
+        ),
+        (
+            "let r = 0; try { try { throw 1; } catch (e) { throw e + 1; } } catch (e) { r = e; } r",
+            "2",
+        ),
+        (
+            "let n = 0; while (true) { try { n += 1; if (n == 3) { break; } } catch (e) { } } n",
+            "3",
+        ),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_eq!(test_eval(input).inspect(), *expected, "input: {}", input);
+    }
+}
+
+#[test]
+fn test_uncaught_throw() {
+    let err = match test_eval("let f = fn() {\n  throw \"boom\";\n};\nf()") {
+        Object::Error(err) => err,
+        other => panic!("object is not Error. got={}", other.inspect()),
+    };
+
+    assert_eq!(err.message, "uncaught exception: boom");
+    assert_eq!(err.span, Some(Span { line: 2, column: 3 }));
+    assert_eq!(err.traceback.len(), 1);
+
+    assert_error(
+        &test_eval("try { 1 } catch (e) { e + true } 1 + null"),
+        "type mismatch: INTEGER + NULL",
+    );
+}
+
+#[test]
+fn test_tail_calls_run_in_constant_stack() {
+    let tests = [
+        (
+            "let count = fn(n, acc) { if (n == 0) { acc } else { count(n - 1, acc + 1) } };
+            count(50000, 0)",
+            50000,
+        ),
+        (
+            "let even = fn(n) { if (n == 0) { return true; } return odd(n - 1); };
+            let odd = fn(n) { if (n == 0) { return false; } return even(n - 1); };
+            if (even(50001)) { 1 } else { 0 }",
+            0,
+        ),
+        (
+            "let sum = fn(xs, i, acc) {
+                if (i == len(xs)) { return acc; }
+                sum(xs, i + 1, acc + xs[i])
+            };
+            sum(range(50000), 0, 0)",
+            1249975000,
+        ),
+        (
+            "let find = fn(n) { while (true) { if (n > 50000) { break; } return find(n + 1); } n };
+            find(0)",
+            50001,
+        ),
+        (
+            "let loop = fn(n) { if (n == 0) { 0 } else if (n % 2 == 0) { loop(n - 1) } else { loop(n - 1) } };
+            loop(50000)",
+            0,
+        ),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_integer(&test_eval(input), *expected);
+    }
+}
+
+#[test]
+fn test_calls_outside_tail_position() {
+    assert_integer(
+        &test_eval(
+            "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15)",
+        ),
+        610,
+    );
+    assert_integer(
+        &test_eval(
+            "let f = fn(n) { if (n == 0) { throw \"done\"; } f(n - 1) };
+            let g = fn() { try { return f(3); } catch (e) { return 42; } };
+            g()",
+        ),
+        42,
+    );
+
+    let err = match test_eval("let f = fn(n) { if (n == 0) { n + true } else { f(n - 1) } };\nf(3)")
+    {
+        Object::Error(err) => err,
+        other => panic!("object is not Error. got={}", other.inspect()),
+    };
+    let frames: Vec<&str> = err
+        .traceback
+        .iter()
+        .map(|frame| frame.function.as_str())
+        .collect();
+    assert_eq!(frames, ["f", "f"]);
+}
+
+fn test_eval_with_call_depth(input: &str, max_call_depth: usize) -> Object {
+    let mut evaluator = Evaluator::new();
+    evaluator.set_max_call_depth(max_call_depth);
+    evaluator.eval_program(&parse(input), &Environment::new())
+}
+
+#[test]
+fn test_call_depth_limit() {
+    let recurse = "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } };";
+
+    assert_integer(
+        &test_eval_with_call_depth(&format!("{} f(49)", recurse), 50),
+        49,
+    );
+    assert_error(
+        &test_eval_with_call_depth(&format!("{} f(50)", recurse), 50),
+        "maximum call depth of 50 exceeded",
+    );
+    assert_error(
+        &test_eval_with_call_depth("let g = fn(xs) { map(xs, fn(x) { g(xs) }) }; g([1])", 50),
+        "maximum call depth of 50 exceeded",
+    );
+    assert_integer(
+        &test_eval_with_call_depth(
+            &format!(
+                "{} let r = 0; try {{ f(100); }} catch (e) {{ r = f(40); }} r",
+                recurse
+            ),
+            50,
+        ),
+        40,
+    );
+    assert_integer(
+        &test_eval_with_call_depth(
+            "let count = fn(n) { if (n == 0) { 0 } else { count(n - 1) } }; count(5000)",
+            50,
+        ),
+        0,
+    );
+
+    let report = match test_eval_with_call_depth(&format!("{}\nf(100)", recurse), 50) {
+        Object::Error(err) => err.report(),
+        other => panic!("object is not Error. got={}", other.inspect()),
+    };
+    assert_eq!(
+        report,
+        "error: maximum call depth of 50 exceeded\n  at 1:47\n  in f, called at 1:47\n  ... repeated 48 more times\n  in f, called at 2:2"
+    );
+}
+
+#[test]
+fn test_default_call_depth_limit() {
+    // The default limit assumes an interpreter thread with a generous stack, as the CLI uses.
+    let handle = std::thread::Builder::new()
+        .stack_size(64 * 1024 * 1024)
+        .spawn(|| {
+            let result = test_eval("let f = fn(n) { 1 + f(n + 1) }; f(0)");
+            match result {
+                Object::Error(err) => err.message,
+                other => other.inspect(),
+            }
+        })
+        .unwrap();
+
+    assert_eq!(
+        handle.join().unwrap(),
+        format!("maximum call depth of {} exceeded", DEFAULT_MAX_CALL_DEPTH)
+    );
+}
+
+#[test]
+fn test_nesting_limit() {
+    // Each call only nests so far, but together they'd still overflow the native stack.
+    let input = format!(
+        "let f = fn(n) {{ if (n == 0) {{ 0 }} else {{ {}f(n - 1){} }} }}; f(999)",
+        "[".repeat(200),
+        "]".repeat(200)
+    );
+    let handle = std::thread::Builder::new()
+        .stack_size(64 * 1024 * 1024)
+        .spawn(move || match test_eval(&input) {
+            Object::Error(err) => err.message,
+            other => other.inspect(),
+        })
+        .unwrap();
+    assert_eq!(
+        handle.join().unwrap(),
+        format!("maximum nesting depth of {} exceeded", DEFAULT_MAX_NESTING)
+    );
+
+    let mut evaluator = Evaluator::new();
+    evaluator.set_max_nesting(50);
+    assert_error(
+        &eval_with(
+            &mut evaluator,
+            "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } }; f(20)",
+        ),
+        "maximum nesting depth of 50 exceeded",
+    );
+    assert_integer(
+        &eval_with(
+            &mut evaluator,
+            "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } }; f(5)",
+        ),
+        5,
+    );
+}
+
+fn eval_with(evaluator: &mut Evaluator, input: &str) -> Object {
+    evaluator.eval_program(&parse(input), &Environment::new())
+}
+
+fn assert_budget_exhausted(obj: &Object, expected: &str) {
+    match obj {
+        Object::Error(err) => {
+            assert_eq!(err.kind, object::ErrorKind::BudgetExhausted);
+            assert_eq!(err.message, expected);
+        }
+        other => panic!("object is not Error. got={}", other.inspect()),
+    }
+}
+
+#[test]
+fn test_fuel_budget() {
+    let mut evaluator = Evaluator::new();
+    evaluator.set_fuel(Some(1000));
+    assert_budget_exhausted(
+        &eval_with(&mut evaluator, "while (true) { }"),
+        "budget exhausted: out of fuel",
+    );
+    assert_eq!(evaluator.fuel(), Some(0));
+
+    // `1 + 2` is a statement and three expressions.
+    let mut evaluator = Evaluator::new();
+    evaluator.set_fuel(Some(10));
+    assert_integer(&eval_with(&mut evaluator, "1 + 2"), 3);
+    assert_eq!(evaluator.fuel(), Some(6));
+
+    let mut evaluator = Evaluator::new();
+    evaluator.set_fuel(Some(500));
+    assert_budget_exhausted(
+        &eval_with(
+            &mut evaluator,
+            "let f = fn() { while (true) { } }; let r = 0; try { f(); } catch (e) { r = 1; } r",
+        ),
+        "budget exhausted: out of fuel",
+    );
+}
+
+#[test]
+fn test_builtins_consume_fuel() {
+    let fuel_used = |input: &str| {
+        let mut evaluator = Evaluator::new();
+        evaluator.set_fuel(Some(1_000_000));
+        eval_with(&mut evaluator, input);
+        1_000_000 - evaluator.fuel().unwrap()
+    };
+
+    // A step per item, on top of the call itself.
+    assert_eq!(fuel_used("range(1000)") - fuel_used("range(0)"), 1000);
+    assert_eq!(
+        fuel_used("reverse(0..1000)") - fuel_used("reverse(0..0)"),
+        1000
+    );
+
+    let mut evaluator = Evaluator::new();
+    evaluator.set_fuel(Some(20));
+    assert_budget_exhausted(
+        &eval_with(
+            &mut evaluator,
+            "let x = range(0, 100000000); sort(reverse(x))",
+        ),
+        "budget exhausted: out of fuel",
+    );
+    assert_eq!(evaluator.fuel(), Some(0));
+}
+
+#[test]
+fn test_deadline() {
+    let mut evaluator = Evaluator::new();
+    evaluator.set_timeout(Some(std::time::Duration::from_millis(20)));
+
+    let started = std::time::Instant::now();
+    assert_budget_exhausted(
+        &eval_with(&mut evaluator, "try { while (true) { } } catch (e) { 0 }"),
+        "budget exhausted: deadline exceeded",
+    );
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+    // Builtins check it too, partway through their work.
+    let started = std::time::Instant::now();
+    assert_budget_exhausted(
+        &eval_with(
+            &mut evaluator,
+            "let x = range(0, 100000000); sort(reverse(x))",
+        ),
+        "budget exhausted: deadline exceeded",
+    );
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+    // Each program gets a fresh deadline.
+    assert_integer(&eval_with(&mut evaluator, "1 + 1"), 2);
+}
+
+fn assert_out_of_memory(obj: &Object) {
+    match obj {
+        Object::Error(err) => assert_eq!(err.kind, object::ErrorKind::OutOfMemory),
+        other => panic!("object is not Error. got={}", other.inspect()),
+    }
+}
+
+#[test]
+fn test_memory_limit() {
+    let tests = [
+        "let s = \"x\"; while (true) { s += s; }",
+        "let a = []; while (true) { a = push(a, [1, 2, 3]); }",
+        "let h = {}; let i = 0; while (true) { h[i] = i; i += 1; }",
+        "range(1000000000000)",
+        "map(0..1000000000000, fn(x) { x })",
+        "let s = \"x\"; try { while (true) { s += s; } } catch (e) { 0 }",
+    ];
+
+    for input in tests.iter() {
+        let mut evaluator = Evaluator::new();
+        evaluator.set_memory_limit(Some(64 * 1024));
+        assert_out_of_memory(&eval_with(&mut evaluator, input));
+    }
+}
+
+#[test]
+fn test_memory_limit_counts_loop_bodies() {
+    // Everything lives in the loop body's own scope, which the recount has to see.
+    let input = format!(
+        "let n = 0;
+        while (n == 0) {{
+            let h = {{}};
+            let i = 0;
+            while (i < 2000) {{ h[i] = \"{}\" + \"!\"; i += 1; }}
+            n = len(h);
+        }}
+        n",
+        "x".repeat(100)
+    );
+
+    let mut evaluator = Evaluator::new();
+    evaluator.set_memory_limit(Some(100_000));
+    assert_out_of_memory(&eval_with(&mut evaluator, &input));
+}
+
+#[test]
+fn test_memory_limit_counts_big_integers() {
+    // Recounts would forget the big integers, leaving just the small arrays holding them.
+    let input =
+        "let xs = []; let i = 0; while (i < 200) { xs = push(xs, 2 ** 4000 + i); i += 1; } len(xs)";
+
+    let mut evaluator = Evaluator::new();
+    evaluator.set_memory_limit(Some(64 * 1024));
+    assert_out_of_memory(&eval_with(&mut evaluator, input));
+}
+
+#[test]
+fn test_big_integer_limits() {
+    for input in ["2 ** 300000", "let x = 2 ** 200000; x * x"] {
+        let mut evaluator = Evaluator::new();
+        evaluator.set_max_integer_bits(Some(1 << 18));
+        assert_error(
+            &eval_with(&mut evaluator, input),
+            "integer too large: over 262144 bits",
+        );
+    }
+
+    // Products are paid for before they're worked out, so these fail straight away.
+    let mut evaluator = Evaluator::new();
+    evaluator.set_memory_limit(Some(64 * 1024));
+    assert_out_of_memory(&eval_with(&mut evaluator, "2 ** 1000000000000"));
+
+    let mut evaluator = Evaluator::new();
+    evaluator.set_fuel(Some(1000));
+    assert_budget_exhausted(
+        &eval_with(&mut evaluator, "let x = 2 ** 10000; x * x * x"),
+        "budget exhausted: out of fuel",
+    );
+}
+
+#[test]
+fn test_memory_limit_counts_values_being_evaluated() {
+    // Each string is garbage to a recount until the array or call it's part of is done.
+    let mk = "let mk = fn(n) { let s = \"a\"; let i = 0; while (i < n) { s = s + s; i += 1; } s };";
+    let tests = [
+        format!("{} let a = [{}]; len(a)", mk, vec!["mk(12)"; 40].join(", ")),
+        format!(
+            "{} let h = {{{}}}; len(h)",
+            mk,
+            (0..40)
+                .map(|i| format!("{}: mk(12)", i))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        format!(
+            "{} let f = fn({}) {{ 1 }}; f({})",
+            mk,
+            (0..40)
+                .map(|i| format!("a{}", i))
+                .collect::<Vec<_>>()
+                .join(", "),
+            vec!["mk(12)"; 40].join(", ")
+        ),
+    ];
+
+    for input in tests.iter() {
+        let mut evaluator = Evaluator::new();
+        evaluator.set_memory_limit(Some(64 * 1024));
+        assert_out_of_memory(&eval_with(&mut evaluator, input));
+    }
+}
+
+#[test]
+fn test_memory_limit_recounts_before_refusing() {
+    // Most of the limit stays live, and each loop leaves a little garbage behind.
+    let input =
+        "let mk = fn(n) { let s = \"a\"; let i = 0; while (i < n) { s = s + s; i += 1; } s };
+    let kept = [mk(15), mk(14)];
+    let s = mk(10);
+    let i = 0;
+    while (i < 200) { let t = s + s + s + s + s; i += 1; }
+    i";
+
+    let mut evaluator = Evaluator::new();
+    evaluator.set_memory_limit(Some(64 * 1024));
+    assert_integer(&eval_with(&mut evaluator, input), 200);
+}
+
+#[test]
+fn test_memory_limit_counts_live_data() {
+    let mut evaluator = Evaluator::new();
+    evaluator.set_memory_limit(Some(64 * 1024));
+
+    // Plenty of garbage in total, but little of it is live at once.
+    let input = "let total = 0;
+    for (i in 0..5000) {
+        let s = \"abcdefghijklmnopqrstuvwxyz\" + \"abcdefghijklmnopqrstuvwxyz\";
+        total += len(split(s, \"\"));
+    }
+    total";
+    assert_integer(&eval_with(&mut evaluator, input), 260000);
+
+    let env = Environment::new();
+    let program = parse("let kept = join(map(range(1000), fn(x) { \"0123456789\" }), \"\");");
+    evaluator.eval_program(&program, &env);
+    assert!(evaluator.memory_used() >= 10000);
+}
@@ -4,16 +4,23 @@ mod tests;
 use crate::token;
 
 pub struct Lexer {
-    input: String,
+    input: Vec<char>,
     position: usize,
     read_position: usize,
     ch: char,
+    line: usize,
+    column: usize,
 }
 
 impl Lexer {
     pub fn next_token(&mut self) -> token::Token {
-        let tok: token::Token;
+        let mut tok: token::Token;
         self.skip_whitespace();
+        let span = token::Span {
+            line: self.line,
+            column: self.column,
+        };
+
         match self.ch {
             '=' => {
                 if self.peek_char() == '=' {
@@ -35,54 +42,89 @@ impl Lexer {
             }
             '<' => {
                 if self.peek_char() == '=' {
-                    let ch = self.ch;
-                    self.read_char();
-                    tok = token::Token::new(token::LT_EQ, format!("{}{}", ch, self.ch));
+                    tok = self.read_operator(token::LT_EQ, 2);
+                } else if self.peek_char() == '<' {
+                    if self.peek_char_at(1) == '=' {
+                        tok = self.read_operator(token::LSHIFT_ASSIGN, 3);
+                    } else {
+                        tok = self.read_operator(token::LSHIFT, 2);
+                    }
                 } else {
                     tok = token::Token::new(token::LT, format!("{}", self.ch));
                 }
             }
             '>' => {
                 if self.peek_char() == '=' {
+                    tok = self.read_operator(token::GT_EQ, 2);
+                } else if self.peek_char() == '>' {
+                    if self.peek_char_at(1) == '=' {
+                        tok = self.read_operator(token::RSHIFT_ASSIGN, 3);
+                    } else {
+                        tok = self.read_operator(token::RSHIFT, 2);
+                    }
+                } else {
+                    tok = token::Token::new(token::GT, format!("{}", self.ch));
+                }
+            }
+            '*' => {
+                if self.peek_char() == '*' {
+                    if self.peek_char_at(1) == '=' {
+                        tok = self.read_operator(token::POWER_ASSIGN, 3);
+                    } else {
+                        tok = self.read_operator(token::POWER, 2);
+                    }
+                } else if self.peek_char() == '=' {
+                    tok = self.read_operator(token::ASTERISK_ASSIGN, 2);
+                } else {
+                    tok = token::Token::new(token::ASTERISK, format!("{}", self.ch));
+                }
+            }
+            '+' => tok = self.read_with_assign(token::PLUS, token::PLUS_ASSIGN),
+            '-' => tok = self.read_with_assign(token::MINUS, token::MINUS_ASSIGN),
+            '/' => tok = self.read_with_assign(token::SLASH, token::SLASH_ASSIGN),
+            '%' => tok = self.read_with_assign(token::PERCENT, token::PERCENT_ASSIGN),
+            '&' => tok = self.read_with_assign(token::AMPERSAND, token::AMPERSAND_ASSIGN),
+            '|' => tok = self.read_with_assign(token::PIPE, token::PIPE_ASSIGN),
+            '^' => tok = self.read_with_assign(token::CARET, token::CARET_ASSIGN),
+            '~' => tok = token::Token::new(token::TILDE, format!("{}", self.ch)),
+            '.' => {
+                if self.peek_char() == '.' {
                     let ch = self.ch;
                     self.read_char();
-                    tok = token::Token::new(token::GT_EQ, format!("{}{}", ch, self.ch));
+                    tok = token::Token::new(token::DOTDOT, format!("{}{}", ch, self.ch));
                 } else {
-                    tok = token::Token::new(token::GT, format!("{}", self.ch));
+                    tok = token::Token::new(token::ILLEGAL, format!("{}", self.ch));
                 }
             }
-            '+' => tok = token::Token::new(token::PLUS, format!("{}", self.ch)),
-            '-' => tok = token::Token::new(token::MINUS, format!("{}", self.ch)),
-            '/' => tok = token::Token::new(token::SLASH, format!("{}", self.ch)),
-            '*' => tok = token::Token::new(token::ASTERISK, format!("{}", self.ch)),
             ';' => tok = token::Token::new(token::SEMICOLON, format!("{}", self.ch)),
             ':' => tok = token::Token::new(token::COLON, format!("{}", self.ch)),
             ',' => tok = token::Token::new(token::COMMA, format!("{}", self.ch)),
-            '"' => {
-                let t = token::STRING;
-                let literal = self.read_string();
-                tok = token::Token::new(t, literal);
-            }
+            '"' => match self.read_string() {
+                Some(literal) => tok = token::Token::new(token::STRING, literal),
+                None => tok = token::Token::new(token::ILLEGAL, String::from("\"")),
+            },
             '(' => tok = token::Token::new(token::LPAREN, format!("{}", self.ch)),
             ')' => tok = token::Token::new(token::RPAREN, format!("{}", self.ch)),
             '[' => tok = token::Token::new(token::LBRACKET, format!("{}", self.ch)),
             ']' => tok = token::Token::new(token::RBRACKET, format!("{}", self.ch)),
             '{' => tok = token::Token::new(token::LBRACE, format!("{}", self.ch)),
             '}' => tok = token::Token::new(token::RBRACE, format!("{}", self.ch)),
-            '\0' => tok = token::Token::new(token::EOF, format!("")),
+            '\0' => tok = token::Token::new(token::EOF, String::new()),
             _ => {
-                if self.ch.is_ascii_alphabetic() {
+                if is_identifier_start(self.ch) {
                     let literal = self.read_identifier();
                     let lookup = literal.clone();
                     let t = token::lookup_ident(lookup);
 
                     tok = token::Token::new(t, literal);
+                    tok.span = span;
                     return tok;
                 } else if self.ch.is_ascii_digit() {
                     let t = token::INT;
                     let literal = self.read_number();
 
                     tok = token::Token::new(t, literal);
+                    tok.span = span;
                     return tok;
                 } else {
                     tok = token::Token::new(token::ILLEGAL, format!("{}", self.ch));
@@ -90,62 +132,107 @@ impl Lexer {
             }
         }
 
+        tok.span = span;
         self.read_char();
-        return tok;
+        tok
     }
 
     fn peek_char(&self) -> char {
-        let mut temp = self.input.chars();
+        self.peek_char_at(0)
+    }
+
+    fn peek_char_at(&self, offset: usize) -> char {
+        match self.input.get(self.read_position + offset) {
+            Some(ch) => *ch,
+            None => '\0',
+        }
+    }
 
-        if self.read_position >= self.input.len() {
-            return '\0';
+    // Consumes an operator spanning `len` characters starting at the current one.
+    fn read_operator(&mut self, tt: token::TokenType, len: usize) -> token::Token {
+        let mut literal = String::from(self.ch);
+
+        for _ in 1..len {
+            self.read_char();
+            literal.push(self.ch);
+        }
+
+        token::Token::new(tt, literal)
+    }
+
+    fn read_with_assign(&mut self, tt: token::TokenType, assign: token::TokenType) -> token::Token {
+        if self.peek_char() == '=' {
+            self.read_operator(assign, 2)
         } else {
-            return temp.nth(self.read_position).unwrap();
+            self.read_operator(tt, 1)
         }
     }
 
     fn read_char(&mut self) {
-        let mut temp = self.input.chars();
-
-        if self.read_position >= self.input.len() {
-            self.ch = '\0';
+        if self.ch == '\n' {
+            self.line += 1;
+            self.column = 1;
         } else {
-            self.ch = temp.nth(self.read_position).unwrap();
+            self.column += 1;
         }
 
+        self.ch = match self.input.get(self.read_position) {
+            Some(ch) => *ch,
+            None => '\0',
+        };
+
         self.position = self.read_position;
-        self.read_position = self.read_position + 1;
+        self.read_position += 1;
     }
 
     fn read_number(&mut self) -> String {
         let position = self.position;
 
-        while self.ch.is_digit(10) {
+        while self.ch.is_ascii_digit() {
             self.read_char()
         }
 
-        return self.input[position..self.position].to_string();
+        self.input[position..self.position].iter().collect()
     }
 
-    fn read_string(&mut self) -> String {
-        self.read_char();
-        let position = self.position;
+    // Returns None if the input ends before the closing quote.
+    fn read_string(&mut self) -> Option<String> {
+        let mut ret = String::new();
 
-        while self.ch != '"' {
+        loop {
             self.read_char();
-        }
 
-        return self.input[position..self.position].to_string();
+            match self.ch {
+                '"' => return Some(ret),
+                '\0' => return None,
+                '\\' => {
+                    self.read_char();
+                    match self.ch {
+                        'n' => ret.push('\n'),
+                        't' => ret.push('\t'),
+                        'r' => ret.push('\r'),
+                        '"' => ret.push('"'),
+                        '\\' => ret.push('\\'),
+                        '\0' => return None,
+                        other => {
+                            ret.push('\\');
+                            ret.push(other);
+                        }
+                    }
+                }
+                ch => ret.push(ch),
+            }
+        }
     }
 
     fn read_identifier(&mut self) -> String {
         let position = self.position;
 
-        while self.ch.is_ascii_alphabetic() && self.ch != ';' {
+        while is_identifier_start(self.ch) || self.ch.is_ascii_digit() {
             self.read_char();
         }
 
-        return self.input[position..self.position].to_string();
+        self.input[position..self.position].iter().collect()
     }
 
     fn skip_whitespace(&mut self) {
@@ -155,15 +242,20 @@ impl Lexer {
     }
 }
 
+fn is_identifier_start(ch: char) -> bool {
+    ch.is_ascii_alphabetic() || ch == '_'
+}
+
 pub fn new(input: String) -> Lexer {
-    let ch = input.clone().chars().nth(0).unwrap();
     let mut ret = Lexer {
-        input,
+        input: input.chars().collect(),
         position: 0,
         read_position: 0,
-        ch,
+        ch: '\0',
+        line: 1,
+        column: 0,
     };
 
     ret.read_char();
-    return ret;
+    ret
 }
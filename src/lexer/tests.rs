@@ -386,6 +386,157 @@ fn test_next_token() {
         );
 
         assert_eq!(tok.token_type, test.expected_type);
-        assert_eq!(tok.literal, test.expected_literal);
+        assert_eq!(tok.literal.as_str(), test.expected_literal);
+    }
+}
+
+#[test]
+fn test_arithmetic_and_bitwise_operators() {
+    let input = String::from("10 % 3 ** 2; a & b | c ^ ~d; 1 << 4 >> 2;");
+
+    let tests = vec![
+        (token::INT, "10"),
+        (token::PERCENT, "%"),
+        (token::INT, "3"),
+        (token::POWER, "**"),
+        (token::INT, "2"),
+        (token::SEMICOLON, ";"),
+        (token::IDENT, "a"),
+        (token::AMPERSAND, "&"),
+        (token::IDENT, "b"),
+        (token::PIPE, "|"),
+        (token::IDENT, "c"),
+        (token::CARET, "^"),
+        (token::TILDE, "~"),
+        (token::IDENT, "d"),
+        (token::SEMICOLON, ";"),
+        (token::INT, "1"),
+        (token::LSHIFT, "<<"),
+        (token::INT, "4"),
+        (token::RSHIFT, ">>"),
+        (token::INT, "2"),
+        (token::SEMICOLON, ";"),
+        (token::EOF, ""),
+    ];
+
+    let mut l = new(input);
+
+    for (expected_type, expected_literal) in tests.iter() {
+        let tok = l.next_token();
+
+        assert_eq!(tok.token_type, *expected_type);
+        assert_eq!(tok.literal, *expected_literal);
+    }
+}
+
+#[test]
+fn test_loop_keywords() {
+    let input = String::from("while for in break continue 0..10");
+
+    let tests = vec![
+        (token::WHILE, "while"),
+        (token::FOR, "for"),
+        (token::IN, "in"),
+        (token::BREAK, "break"),
+        (token::CONTINUE, "continue"),
+        (token::INT, "0"),
+        (token::DOTDOT, ".."),
+        (token::INT, "10"),
+        (token::EOF, ""),
+    ];
+
+    let mut l = new(input);
+
+    for (expected_type, expected_literal) in tests.iter() {
+        let tok = l.next_token();
+
+        assert_eq!(tok.token_type, *expected_type);
+        assert_eq!(tok.literal, *expected_literal);
+    }
+}
+
+#[test]
+fn test_assignment_operators() {
+    let input = String::from("+= -= *= /= %= **= &= |= ^= <<= >>= = ==");
+
+    let tests = vec![
+        (token::PLUS_ASSIGN, "+="),
+        (token::MINUS_ASSIGN, "-="),
+        (token::ASTERISK_ASSIGN, "*="),
+        (token::SLASH_ASSIGN, "/="),
+        (token::PERCENT_ASSIGN, "%="),
+        (token::POWER_ASSIGN, "**="),
+        (token::AMPERSAND_ASSIGN, "&="),
+        (token::PIPE_ASSIGN, "|="),
+        (token::CARET_ASSIGN, "^="),
+        (token::LSHIFT_ASSIGN, "<<="),
+        (token::RSHIFT_ASSIGN, ">>="),
+        (token::ASSIGN, "="),
+        (token::EQ, "=="),
+        (token::EOF, ""),
+    ];
+
+    let mut l = new(input);
+
+    for (expected_type, expected_literal) in tests.iter() {
+        let tok = l.next_token();
+
+        assert_eq!(tok.token_type, *expected_type);
+        assert_eq!(tok.literal, *expected_literal);
+    }
+}
+
+#[test]
+fn test_identifiers_and_string_escapes() {
+    let input = String::from("starts_with _tmp x1 \"a\\\"b\\n\" \"open");
+
+    let tests = [
+        (token::IDENT, "starts_with"),
+        (token::IDENT, "_tmp"),
+        (token::IDENT, "x1"),
+        (token::STRING, "a\"b\n"),
+        (token::ILLEGAL, "\""),
+        (token::EOF, ""),
+    ];
+
+    let mut l = new(input);
+
+    for (expected_type, expected_literal) in tests.iter() {
+        let tok = l.next_token();
+
+        assert_eq!(tok.token_type, *expected_type);
+        assert_eq!(tok.literal, *expected_literal);
+    }
+}
+
+#[test]
+fn test_token_spans() {
+    let input = String::from("let x = 10;\n  \"héllo\" >>= y\n");
+
+    let tests = [
+        (token::LET, 1, 1),
+        (token::IDENT, 1, 5),
+        (token::ASSIGN, 1, 7),
+        (token::INT, 1, 9),
+        (token::SEMICOLON, 1, 11),
+        (token::STRING, 2, 3),
+        (token::RSHIFT_ASSIGN, 2, 11),
+        (token::IDENT, 2, 15),
+        (token::EOF, 3, 1),
+    ];
+
+    let mut l = new(input);
+
+    for (expected_type, line, column) in tests.iter() {
+        let tok = l.next_token();
+
+        assert_eq!(tok.token_type, *expected_type);
+        assert_eq!(
+            tok.span,
+            token::Span {
+                line: *line,
+                column: *column
+            }
+        );
     }
 }
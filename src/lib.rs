@@ -0,0 +1,19 @@
+pub mod ast;
+pub mod bigint;
+pub mod budget;
+pub mod bytecode_file;
+pub mod code;
+pub mod compiler;
+pub mod evaluator;
+pub mod lexer;
+pub mod object;
+pub mod optimizer;
+pub mod parser;
+pub mod repl;
+pub mod resolver;
+pub mod symbol;
+pub mod token;
+pub mod vm;
+
+#[cfg(test)]
+mod test_helpers;
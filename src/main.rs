@@ -1,15 +1,273 @@
-mod ast;
-mod lexer;
-mod parser;
-mod repl;
-mod token;
+use rmonk::ast;
+use rmonk::bytecode_file;
+use rmonk::compiler::{Bytecode, Compiler};
+use rmonk::evaluator::Evaluator;
+use rmonk::lexer;
+use rmonk::object::environment::Environment;
+use rmonk::object::{Object, gc};
+use rmonk::parser::Parser;
+use rmonk::repl;
+use rmonk::resolver::Resolver;
+use rmonk::vm::VM;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::Duration;
 
-use std::collections::HashMap;
-use std::io::{self, Write};
+const USAGE: &str = "usage: rmonk [options] [file]
+       rmonk run [options] file
+       rmonk build [--output=PATH] file
+options: --vm --trace --disasm --no-optimize --gc-stats --strict --fuel=STEPS --timeout=MS --memory-limit=BYTES";
+
+// Enough native stack for the default call depth and nesting limits, even in debug builds.
+const INTERPRETER_STACK_SIZE: usize = 64 * 1024 * 1024;
 
 fn main() {
-    let mut stdin = io::stdin();
-    let mut stdout = io::stdout();
+    let interpreter = thread::Builder::new()
+        .stack_size(INTERPRETER_STACK_SIZE)
+        .spawn(run)
+        .expect("couldn't start interpreter thread");
+
+    if interpreter.join().is_err() {
+        process::exit(101);
+    }
+}
+
+// Settings shared by both engines.
+#[derive(Default)]
+struct Options {
+    vm: bool,
+    trace: bool,
+    disasm: bool,
+    no_optimize: bool,
+    gc_stats: bool,
+    strict: bool,
+    fuel: Option<u64>,
+    timeout: Option<Duration>,
+    memory_limit: Option<usize>,
+    output: Option<String>,
+}
+
+fn run() {
+    let mut options = Options::default();
+    let mut args = Vec::new();
+
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--vm" => options.vm = true,
+            // Tracing follows the VM's instructions, so it implies --vm.
+            "--trace" => {
+                options.vm = true;
+                options.trace = true;
+            }
+            "--disasm" => options.disasm = true,
+            // Leaves code as written, so --disasm and --trace line up with the source.
+            "--no-optimize" => options.no_optimize = true,
+            "--gc-stats" => options.gc_stats = true,
+            "--strict" => options.strict = true,
+            _ if arg.starts_with("--fuel=") => {
+                options.fuel = Some(parse_number(&arg, "--fuel="));
+            }
+            _ if arg.starts_with("--memory-limit=") => {
+                let limit = parse_number(&arg, "--memory-limit=");
+                options.memory_limit = Some(limit as usize);
+            }
+            _ if arg.starts_with("--timeout=") => {
+                let millis = parse_number(&arg, "--timeout=");
+                options.timeout = Some(Duration::from_millis(millis));
+            }
+            _ if arg.starts_with("--output=") => {
+                options.output = Some(arg["--output=".len()..].to_string());
+            }
+            _ if !arg.starts_with('-') => args.push(arg),
+            _ => {
+                eprintln!("unknown argument: {}", arg);
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+        }
+    }
+
+    let command = match args.first().map(String::as_str) {
+        Some("build" | "run") => Some(args.remove(0)),
+        _ => None,
+    };
+    let build = command.as_deref() == Some("build");
+    if args.len() > 1 {
+        eprintln!("unexpected argument: {}", args[1]);
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    let path = match args.pop() {
+        Some(path) => path,
+        None if command.is_none() && !options.disasm => {
+            if options.vm {
+                repl::start_vm(&mut io::stdin(), new_vm(&options));
+            } else {
+                repl::start(&mut io::stdin(), new_evaluator(&options));
+            }
+            return;
+        }
+        None => {
+            eprintln!("missing file");
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let input = match fs::read(&path) {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("could not read {}: {}", path, err);
+            process::exit(1);
+        }
+    };
+
+    // A .monkc file is always loaded as bytecode, so a damaged one is reported rather than parsed.
+    let compiled = bytecode_file::is_bytecode_file(&input)
+        || Path::new(&path).extension() == Some(bytecode_file::EXTENSION.as_ref());
+
+    if build {
+        let output = options.output.clone().unwrap_or_else(|| {
+            let output = Path::new(&path).with_extension(bytecode_file::EXTENSION);
+            output.to_string_lossy().into_owned()
+        });
+        write_bytecode(&output, &load_bytecode(input, compiled, &options));
+    } else if options.disasm {
+        print!("{}", load_bytecode(input, compiled, &options).disassemble());
+    } else {
+        let result = if options.vm || compiled {
+            run_vm(&load_bytecode(input, compiled, &options), new_vm(&options))
+        } else {
+            run_evaluator(&parse_source(input, &options), new_evaluator(&options))
+        };
+
+        if options.gc_stats {
+            print_gc_stats();
+        }
+        if let Object::Error(err) = result {
+            eprintln!("{}", err.report());
+            process::exit(1);
+        }
+    }
+}
+
+fn new_evaluator(options: &Options) -> Evaluator {
+    let mut evaluator = Evaluator::new();
+    evaluator.set_strict(options.strict);
+    evaluator.set_fuel(options.fuel);
+    evaluator.set_timeout(options.timeout);
+    evaluator.set_memory_limit(options.memory_limit);
+    evaluator
+}
+
+fn new_vm(options: &Options) -> VM {
+    let mut vm = VM::new();
+    vm.set_strict(options.strict);
+    vm.set_fuel(options.fuel);
+    vm.set_timeout(options.timeout);
+    vm.set_memory_limit(options.memory_limit);
+    if options.trace {
+        vm.set_trace(Some(Box::new(io::stderr())));
+    }
+    vm
+}
+
+fn parse_number(arg: &str, flag: &str) -> u64 {
+    match arg[flag.len()..].parse() {
+        Ok(value) => value,
+        Err(_) => {
+            eprintln!("invalid value for {}: {}", &flag[..flag.len() - 1], arg);
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    }
+}
+
+fn parse_source(input: Vec<u8>, options: &Options) -> ast::Program {
+    let input = match String::from_utf8(input) {
+        Ok(input) => input,
+        Err(_) => {
+            eprintln!("source file is not valid UTF-8");
+            process::exit(1);
+        }
+    };
+
+    let mut p = Parser::new(lexer::new(input));
+    let mut program = p.parse_program();
+    if !p.errors().is_empty() {
+        exit_with_errors("parser errors:", p.errors());
+    }
+
+    if let Err(errors) = Resolver::new().resolve_and_fold(&mut program, !options.no_optimize) {
+        exit_with_errors("resolver errors:", &errors);
+    }
+    program
+}
+
+fn exit_with_errors(heading: &str, errors: &[String]) -> ! {
+    eprintln!("{}", heading);
+    for msg in errors.iter() {
+        eprintln!("\t{msg}");
+    }
+    process::exit(1);
+}
+
+// Accepts either a compiled .monkc file or source to compile.
+fn load_bytecode(input: Vec<u8>, compiled: bool, options: &Options) -> Bytecode {
+    if compiled {
+        return match bytecode_file::decode(&input) {
+            Ok(bytecode) => bytecode,
+            Err(err) => {
+                eprintln!("could not load bytecode: {}", err);
+                process::exit(1);
+            }
+        };
+    }
+
+    let mut compiler = Compiler::new();
+    compiler.set_optimize(!options.no_optimize);
+    if let Err(err) = compiler.compile(&parse_source(input, options)) {
+        eprintln!("compile error: {}", err);
+        process::exit(1);
+    }
+
+    compiler.bytecode()
+}
+
+fn write_bytecode(path: &str, bytecode: &Bytecode) {
+    let bytes = match bytecode_file::encode(bytecode) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("could not serialize bytecode: {}", err);
+            process::exit(1);
+        }
+    };
+
+    if let Err(err) = fs::write(path, bytes) {
+        eprintln!("could not write {}: {}", path, err);
+        process::exit(1);
+    }
+}
+
+fn run_evaluator(program: &ast::Program, mut evaluator: Evaluator) -> Object {
+    evaluator.eval_program(program, &Environment::new())
+}
+
+fn run_vm(bytecode: &Bytecode, mut vm: VM) -> Object {
+    vm.run(bytecode)
+}
 
-    repl::start(&mut stdin);
+// Collects first, so the numbers show whether the program left any cycles behind.
+fn print_gc_stats() {
+    gc::collect();
+    let stats = gc::stats();
+    eprintln!(
+        "gc: {} collections, {} objects freed, {} objects live",
+        stats.collections, stats.freed, stats.objects
+    );
 }
@@ -0,0 +1,535 @@
+pub mod builtins;
+pub mod environment;
+pub mod gc;
+pub mod memory;
+
+use crate::ast;
+use crate::ast::Statement;
+use crate::bigint::BigInt;
+use crate::code;
+use crate::token::Span;
+use builtins::BuiltinFunction;
+use environment::Environment;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+pub type ObjectType = &'static str;
+
+pub const INTEGER_OBJ: &str = "INTEGER";
+pub const BOOLEAN_OBJ: &str = "BOOLEAN";
+pub const NULL_OBJ: &str = "NULL";
+pub const STRING_OBJ: &str = "STRING";
+pub const ARRAY_OBJ: &str = "ARRAY";
+pub const RANGE_OBJ: &str = "RANGE";
+pub const HASH_OBJ: &str = "HASH";
+pub const RETURN_VALUE_OBJ: &str = "RETURN_VALUE";
+pub const ERROR_OBJ: &str = "ERROR";
+pub const FUNCTION_OBJ: &str = "FUNCTION";
+pub const BUILTIN_OBJ: &str = "BUILTIN";
+pub const BREAK_OBJ: &str = "BREAK";
+pub const CONTINUE_OBJ: &str = "CONTINUE";
+pub const TAIL_CALL_OBJ: &str = "TAIL_CALL";
+pub const COMPILED_FUNCTION_OBJ: &str = "COMPILED_FUNCTION";
+
+#[derive(Clone)]
+pub enum Object {
+    Integer(i64),
+    // An integer outside the range of i64. Smaller ones are always Integer.
+    BigInteger(Rc<BigInt>),
+    Boolean(bool),
+    Null,
+    // Immutable and shared, so copying the value or using it as a hash key doesn't copy the text.
+    String(Rc<str>),
+    Array(Rc<RefCell<Elements>>),
+    Range(i64, i64),
+    Hash(Rc<RefCell<Hash>>),
+    ReturnValue(Box<Object>),
+    Error(Box<RuntimeError>),
+    Function(Rc<Function>),
+    Builtin(&'static str, BuiltinFunction),
+    Break,
+    Continue,
+    TailCall(Box<TailCall>),
+    CompiledFunction(Rc<CompiledFunction>),
+    Closure(Rc<Closure>),
+}
+
+// What's left to print of a value: the values still to come, the separators between them, and
+// the brackets closing the arrays and hashes they're in.
+enum Inspect {
+    Value(Object),
+    Text(&'static str),
+    Close(*const (), &'static str),
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum HashKey {
+    Integer(i64),
+    BigInteger(BigInt),
+    String(Rc<str>),
+    Boolean(bool),
+}
+
+#[derive(Clone)]
+pub struct HashPair {
+    pub key: Object,
+    pub value: Object,
+}
+
+// An insertion-ordered map. `index` points each key at its position in `pairs`.
+#[derive(Clone, Default)]
+pub struct Hash {
+    pairs: Vec<HashPair>,
+    index: HashMap<HashKey, usize>,
+}
+
+impl Hash {
+    pub fn new() -> Hash {
+        Hash::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    pub fn get(&self, key: &HashKey) -> Option<&Object> {
+        self.index.get(key).map(|i| &self.pairs[*i].value)
+    }
+
+    pub fn contains_key(&self, key: &HashKey) -> bool {
+        self.index.contains_key(key)
+    }
+
+    // Overwriting an existing key keeps its original position.
+    pub fn insert(&mut self, hash_key: HashKey, key: Object, value: Object) {
+        match self.index.get(&hash_key) {
+            Some(i) => self.pairs[*i].value = value,
+            None => {
+                self.index.insert(hash_key, self.pairs.len());
+                self.pairs.push(HashPair { key, value });
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &HashKey) -> Option<HashPair> {
+        let i = self.index.remove(key)?;
+        let pair = self.pairs.remove(i);
+
+        for position in self.index.values_mut() {
+            if *position > i {
+                *position -= 1;
+            }
+        }
+
+        Some(pair)
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &HashPair> + ExactSizeIterator {
+        self.pairs.iter()
+    }
+}
+
+impl Drop for Hash {
+    fn drop(&mut self) {
+        if self.pairs.iter().any(|pair| pair.value.holds_values()) {
+            let pairs = std::mem::take(&mut self.pairs);
+            drop_nested(
+                pairs
+                    .into_iter()
+                    .flat_map(|pair| [pair.key, pair.value])
+                    .collect(),
+            );
+        }
+    }
+}
+
+// An array's elements. It's a Vec<Object> in all but how it's freed.
+#[derive(Default)]
+pub struct Elements(Vec<Object>);
+
+impl Deref for Elements {
+    type Target = Vec<Object>;
+
+    fn deref(&self) -> &Vec<Object> {
+        &self.0
+    }
+}
+
+impl DerefMut for Elements {
+    fn deref_mut(&mut self) -> &mut Vec<Object> {
+        &mut self.0
+    }
+}
+
+impl Drop for Elements {
+    fn drop(&mut self) {
+        if self.0.iter().any(Object::holds_values) {
+            drop_nested(std::mem::take(&mut self.0));
+        }
+    }
+}
+
+// Freeing an array frees its elements, so freeing an array of arrays nested a million deep would
+// recurse a million deep. Arrays and hashes holding others hand them here instead, and any held
+// only by them are taken apart in a loop.
+fn drop_nested(mut pending: Vec<Object>) {
+    while let Some(value) = pending.pop() {
+        match value {
+            Object::Array(array) => {
+                if let Ok(array) = Rc::try_unwrap(array) {
+                    pending.append(&mut array.into_inner().0);
+                }
+            }
+            Object::Hash(hash) => {
+                if let Ok(hash) = Rc::try_unwrap(hash) {
+                    let pairs = std::mem::take(&mut hash.into_inner().pairs);
+                    pending.extend(pairs.into_iter().flat_map(|pair| [pair.key, pair.value]));
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+// Errors other than Runtime stop the whole program: `try` can't catch them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    Runtime,
+    BudgetExhausted,
+    OutOfMemory,
+}
+
+// A failed evaluation: where it happened and the Monkey calls that led there, innermost first.
+// `thrown` holds the value of a Monkey `throw`; other errors come from the runtime itself.
+#[derive(Clone)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub span: Option<Span>,
+    pub traceback: Vec<TraceFrame>,
+    pub thrown: Option<Object>,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct TraceFrame {
+    pub function: String,
+    pub span: Span,
+}
+
+impl RuntimeError {
+    pub fn new(message: String) -> RuntimeError {
+        RuntimeError {
+            kind: ErrorKind::Runtime,
+            message,
+            span: None,
+            traceback: Vec::new(),
+            thrown: None,
+        }
+    }
+
+    pub fn thrown(value: Object) -> RuntimeError {
+        RuntimeError {
+            thrown: Some(value.clone()),
+            ..RuntimeError::new(format!("uncaught exception: {}", value.inspect()))
+        }
+    }
+
+    pub fn with_kind(kind: ErrorKind, message: String) -> RuntimeError {
+        RuntimeError {
+            kind,
+            ..RuntimeError::new(message)
+        }
+    }
+
+    pub fn is_catchable(&self) -> bool {
+        self.kind == ErrorKind::Runtime
+    }
+
+    // What a `catch` clause binds: the thrown value, or the message of a runtime error.
+    pub fn caught_value(&self) -> Object {
+        match &self.thrown {
+            Some(value) => value.clone(),
+            None => Object::String(self.message.as_str().into()),
+        }
+    }
+
+    pub fn report(&self) -> String {
+        let mut ret = format!("error: {}", self.message);
+
+        if let Some(span) = self.span {
+            ret.push_str(&format!("\n  at {}", span));
+        }
+        // Runaway recursion repeats the same frame; print each run of repeats once.
+        let mut frames = self.traceback.iter().peekable();
+        while let Some(frame) = frames.next() {
+            ret.push_str(&format!(
+                "\n  in {}, called at {}",
+                frame.function, frame.span
+            ));
+
+            let mut repeats = 0;
+            while frames.next_if(|next| *next == frame).is_some() {
+                repeats += 1;
+            }
+            if repeats > 0 {
+                ret.push_str(&format!("\n  ... repeated {} more times", repeats));
+            }
+        }
+
+        ret
+    }
+}
+
+// A call in tail position, handed back to the caller's trampoline instead of growing the stack.
+#[derive(Clone)]
+pub struct TailCall {
+    pub function: Rc<Function>,
+    pub args: Vec<Object>,
+    pub name: String,
+    pub span: Span,
+}
+
+pub struct Function {
+    pub parameters: Vec<ast::Identifier>,
+    pub body: Rc<ast::BlockStatement>,
+    pub env: Rc<RefCell<Environment>>,
+}
+
+// A function body lowered to bytecode. Closures over it fill their free variables as `captures`
+// says, from the cells or free variables of the frame that creates them.
+pub struct CompiledFunction {
+    pub instructions: code::Instructions,
+    pub num_locals: usize,
+    pub num_cells: usize,
+    pub num_parameters: usize,
+    pub captures: Vec<Capture>,
+    // The variables held by each cell and each captured slot, by index.
+    pub cell_names: Vec<String>,
+    pub free_names: Vec<String>,
+    pub name: String,
+    // (instruction offset, span) pairs in offset order; each span covers instructions up to the next.
+    pub source_map: Vec<(usize, Span)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capture {
+    Cell(usize),
+    Free(usize),
+}
+
+// A compiled function with the variables it captured, shared with the frames that created them.
+pub struct Closure {
+    pub function: Rc<CompiledFunction>,
+    pub free: Vec<Cell>,
+}
+
+pub fn new_closure(closure: Closure) -> Rc<Closure> {
+    let closure = Rc::new(closure);
+    gc::track_closure(&closure);
+    closure
+}
+
+// A variable captured by a closure, shared between it and the frame that declared it. It's empty
+// until the variable's `let` runs.
+pub type Cell = Rc<RefCell<Option<Object>>>;
+
+pub fn new_cell() -> Cell {
+    let cell = Rc::new(RefCell::new(None));
+    gc::track_cell(&cell);
+    cell
+}
+
+impl CompiledFunction {
+    pub fn span_at(&self, offset: usize) -> Option<Span> {
+        let i = self
+            .source_map
+            .partition_point(|(start, _)| *start <= offset);
+        i.checked_sub(1).map(|i| self.source_map[i].1)
+    }
+}
+
+impl Object {
+    // Arithmetic that leaves the range of i64 goes through here, and so does coming back into it.
+    pub fn from_big_integer(value: BigInt) -> Object {
+        match value.to_i64() {
+            Some(value) => Object::Integer(value),
+            None => Object::BigInteger(Rc::new(value)),
+        }
+    }
+
+    pub fn as_big_integer(&self) -> Option<Cow<'_, BigInt>> {
+        match self {
+            Object::Integer(value) => Some(Cow::Owned(BigInt::from(*value))),
+            Object::BigInteger(value) => Some(Cow::Borrowed(value)),
+            _ => None,
+        }
+    }
+
+    // Arrays, hashes, functions and closures can end up in cycles, so they're always made through
+    // these, which let the collector see them.
+    pub fn new_array(elements: Vec<Object>) -> Object {
+        let array = Rc::new(RefCell::new(Elements(elements)));
+        gc::track_array(&array);
+        Object::Array(array)
+    }
+
+    pub fn new_hash(hash: Hash) -> Object {
+        let hash = Rc::new(RefCell::new(hash));
+        gc::track_hash(&hash);
+        Object::Hash(hash)
+    }
+
+    pub fn new_function(function: Function) -> Object {
+        let function = Rc::new(function);
+        gc::track_function(&function);
+        Object::Function(function)
+    }
+
+    pub fn new_closure(closure: Closure) -> Object {
+        Object::Closure(new_closure(closure))
+    }
+
+    pub fn object_type(&self) -> ObjectType {
+        match self {
+            Object::Integer(_) | Object::BigInteger(_) => INTEGER_OBJ,
+            Object::Boolean(_) => BOOLEAN_OBJ,
+            Object::Null => NULL_OBJ,
+            Object::String(_) => STRING_OBJ,
+            Object::Array(_) => ARRAY_OBJ,
+            Object::Range(_, _) => RANGE_OBJ,
+            Object::Hash(_) => HASH_OBJ,
+            Object::ReturnValue(_) => RETURN_VALUE_OBJ,
+            Object::Error(_) => ERROR_OBJ,
+            Object::Function(_) => FUNCTION_OBJ,
+            Object::Builtin(_, _) => BUILTIN_OBJ,
+            Object::Break => BREAK_OBJ,
+            Object::Continue => CONTINUE_OBJ,
+            Object::TailCall(_) => TAIL_CALL_OBJ,
+            Object::CompiledFunction(_) => COMPILED_FUNCTION_OBJ,
+            Object::Closure(_) => FUNCTION_OBJ,
+        }
+    }
+
+    // Walks nested arrays and hashes with a stack of its own rather than by recursing, so no
+    // depth of nesting overflows the native stack. A value inside itself prints as `[...]` or
+    // `{...}` where it repeats.
+    pub fn inspect(&self) -> String {
+        let mut out = String::new();
+        let mut open: HashSet<*const ()> = HashSet::new();
+        let mut work = vec![Inspect::Value(self.clone())];
+
+        while let Some(item) = work.pop() {
+            match item {
+                Inspect::Text(text) => out.push_str(text),
+                Inspect::Close(container, text) => {
+                    open.remove(&container);
+                    out.push_str(text);
+                }
+                Inspect::Value(Object::Array(elements)) => {
+                    let container = Rc::as_ptr(&elements) as *const ();
+                    if !open.insert(container) {
+                        out.push_str("[...]");
+                        continue;
+                    }
+
+                    out.push('[');
+                    work.push(Inspect::Close(container, "]"));
+                    for (i, element) in elements.borrow().iter().enumerate().rev() {
+                        work.push(Inspect::Value(element.clone()));
+                        if i > 0 {
+                            work.push(Inspect::Text(", "));
+                        }
+                    }
+                }
+                Inspect::Value(Object::Hash(hash)) => {
+                    let container = Rc::as_ptr(&hash) as *const ();
+                    if !open.insert(container) {
+                        out.push_str("{...}");
+                        continue;
+                    }
+
+                    out.push('{');
+                    work.push(Inspect::Close(container, "}"));
+                    for (i, pair) in hash.borrow().iter().enumerate().rev() {
+                        work.push(Inspect::Value(pair.value.clone()));
+                        work.push(Inspect::Text(": "));
+                        work.push(Inspect::Value(pair.key.clone()));
+                        if i > 0 {
+                            work.push(Inspect::Text(", "));
+                        }
+                    }
+                }
+                Inspect::Value(Object::ReturnValue(value)) => work.push(Inspect::Value(*value)),
+                Inspect::Value(value) => out.push_str(&value.inspect_leaf()),
+            }
+        }
+
+        out
+    }
+
+    // How `inspect` prints values that don't hold others.
+    fn inspect_leaf(&self) -> String {
+        match self {
+            Object::Integer(value) => value.to_string(),
+            Object::BigInteger(value) => value.to_string(),
+            Object::Boolean(value) => value.to_string(),
+            Object::Null => String::from("null"),
+            Object::String(value) => value.to_string(),
+            Object::Range(start, end) => format!("{}..{}", start, end),
+            Object::Array(_) | Object::Hash(_) | Object::ReturnValue(_) => self.inspect(),
+            Object::Error(err) => format!("ERROR: {}", err.message),
+            Object::Function(function) => {
+                let params: Vec<String> = function
+                    .parameters
+                    .iter()
+                    .map(|p| p.value.to_string())
+                    .collect();
+
+                format!(
+                    "fn({}) {{\n{}\n}}",
+                    params.join(", "),
+                    function.body.string()
+                )
+            }
+            Object::Builtin(name, _) => format!("builtin function {}", name),
+            Object::Break => String::from("break"),
+            Object::Continue => String::from("continue"),
+            Object::TailCall(call) => format!("tail call {}", call.name),
+            Object::CompiledFunction(function) => format!("compiled function {}", function.name),
+            Object::Closure(closure) => format!("fn {}", closure.function.name),
+        }
+    }
+
+    fn holds_values(&self) -> bool {
+        matches!(self, Object::Array(_) | Object::Hash(_))
+    }
+
+    pub fn hash_key(&self) -> Option<HashKey> {
+        match self {
+            Object::Integer(value) => Some(HashKey::Integer(*value)),
+            Object::BigInteger(value) => Some(HashKey::BigInteger(BigInt::clone(value))),
+            Object::String(value) => Some(HashKey::String(Rc::clone(value))),
+            Object::Boolean(value) => Some(HashKey::Boolean(*value)),
+            _ => None,
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Object::Null => false,
+            Object::Boolean(value) => *value,
+            _ => true,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Object::Error(_))
+    }
+}
@@ -0,0 +1,1017 @@
+use super::{ARRAY_OBJ, HASH_OBJ, Hash, HashKey, INTEGER_OBJ, Object, RuntimeError, STRING_OBJ};
+use std::cmp::Ordering;
+use std::mem::size_of;
+
+// Lets builtins such as `map` invoke the Monkey functions they're handed as callbacks.
+pub trait Caller {
+    fn call(&mut self, function: &Object, args: Vec<Object>) -> Object;
+
+    // Lets a builtin refuse an allocation over the memory limit before making it.
+    fn reserve(&mut self, bytes: usize) -> Option<Object>;
+
+    // Charges fuel for work a builtin does, checking the deadline too.
+    fn consume(&mut self, steps: u64) -> Option<Object>;
+}
+
+pub type BuiltinFunction = fn(&mut dyn Caller, Vec<Object>) -> Object;
+
+// Work is paid for in batches of this many steps, so a long loop only checks the fuel and the
+// deadline once per batch.
+const WORK_BATCH: usize = 1024;
+
+// Copying bytes is far cheaper than evaluating an expression, so strings cost a step per this
+// many bytes.
+const BYTES_PER_STEP: usize = 64;
+
+// Counts the steps a builtin has taken, a step per item it handles, and charges them to the
+// caller as they add up. Builtins call `finish` on the way out to pay for the last partial batch.
+#[derive(Default)]
+struct Work {
+    steps: usize,
+    bytes: usize,
+}
+
+impl Work {
+    fn items(&mut self, caller: &mut dyn Caller, count: usize) -> Option<Object> {
+        self.steps = self.steps.saturating_add(count);
+        self.pay_batch(caller)
+    }
+
+    fn bytes(&mut self, caller: &mut dyn Caller, count: usize) -> Option<Object> {
+        self.bytes = self.bytes.saturating_add(count);
+        self.pay_batch(caller)
+    }
+
+    fn pay_batch(&mut self, caller: &mut dyn Caller) -> Option<Object> {
+        if self.steps.saturating_add(self.bytes / BYTES_PER_STEP) < WORK_BATCH {
+            return None;
+        }
+
+        self.finish(caller)
+    }
+
+    fn finish(&mut self, caller: &mut dyn Caller) -> Option<Object> {
+        let steps = self
+            .steps
+            .saturating_add(self.bytes.div_ceil(BYTES_PER_STEP));
+        *self = Work::default();
+        if steps == 0 {
+            return None;
+        }
+
+        caller.consume(steps as u64)
+    }
+}
+
+// Charges up front for work done in one go, like copying a whole string.
+fn consume_bytes(caller: &mut dyn Caller, count: usize) -> Option<Object> {
+    caller.consume(count.div_ceil(BYTES_PER_STEP) as u64)
+}
+
+// Copies items one at a time, paying for them as it goes.
+fn collect_items(
+    caller: &mut dyn Caller,
+    items: impl Iterator<Item = Object>,
+) -> Result<Vec<Object>, Object> {
+    let mut work = Work::default();
+    let mut collected = Vec::new();
+    for item in items {
+        collected.push(item);
+        if let Some(err) = work.items(caller, 1) {
+            return Err(err);
+        }
+    }
+
+    match work.finish(caller) {
+        Some(err) => Err(err),
+        None => Ok(collected),
+    }
+}
+
+pub const BUILTINS: [(&str, BuiltinFunction); 31] = [
+    ("len", len),
+    ("first", first),
+    ("last", last),
+    ("rest", rest),
+    ("push", push),
+    ("puts", puts),
+    ("split", split),
+    ("join", join),
+    ("trim", trim),
+    ("upper", upper),
+    ("lower", lower),
+    ("contains", contains),
+    ("replace", replace),
+    ("starts_with", starts_with),
+    ("map", map),
+    ("filter", filter),
+    ("reduce", reduce),
+    ("sort", sort),
+    ("reverse", reverse),
+    ("zip", zip),
+    ("enumerate", enumerate),
+    ("range", range),
+    ("any", any),
+    ("all", all),
+    ("flatten", flatten),
+    ("keys", keys),
+    ("values", values),
+    ("has", has),
+    ("delete", delete),
+    ("merge", merge),
+    ("entries", entries),
+];
+
+pub fn lookup_builtin(name: &str) -> Option<Object> {
+    for (k, f) in BUILTINS.iter() {
+        if *k == name {
+            return Some(Object::Builtin(k, *f));
+        }
+    }
+
+    None
+}
+
+fn new_error(message: String) -> Object {
+    Object::Error(Box::new(RuntimeError::new(message)))
+}
+
+fn check_arg_count(args: &[Object], want: usize) -> Option<Object> {
+    if args.len() != want {
+        return Some(new_error(format!(
+            "wrong number of arguments: want={}, got={}",
+            want,
+            args.len()
+        )));
+    }
+
+    None
+}
+
+fn string_arg<'a>(name: &str, args: &'a [Object], i: usize) -> Result<&'a str, Object> {
+    match &args[i] {
+        Object::String(value) => Ok(value),
+        other => Err(new_error(format!(
+            "argument to `{}` must be {}, got {}",
+            name,
+            STRING_OBJ,
+            other.object_type()
+        ))),
+    }
+}
+
+fn check_arg_range(args: &[Object], min: usize, max: usize) -> Option<Object> {
+    if args.len() < min || args.len() > max {
+        return Some(new_error(format!(
+            "wrong number of arguments: want={}..{}, got={}",
+            min,
+            max,
+            args.len()
+        )));
+    }
+
+    None
+}
+
+// Collects the items of anything a `for` loop can walk: arrays, ranges, strings and hash keys.
+fn iterable_arg(
+    caller: &mut dyn Caller,
+    name: &str,
+    args: &[Object],
+    i: usize,
+) -> Result<Vec<Object>, Object> {
+    match &args[i] {
+        Object::Array(elements) => collect_items(caller, elements.borrow().iter().cloned()),
+        Object::Range(start, end) => {
+            let len = end.saturating_sub(*start).max(0) as usize;
+            if let Some(err) = caller.reserve(len.saturating_mul(size_of::<Object>())) {
+                return Err(err);
+            }
+
+            collect_items(caller, (*start..*end).map(Object::Integer))
+        }
+        Object::String(value) => collect_items(
+            caller,
+            value.chars().map(|c| Object::String(c.to_string().into())),
+        ),
+        Object::Hash(hash) => {
+            collect_items(caller, hash.borrow().iter().map(|pair| pair.key.clone()))
+        }
+        other => Err(new_error(format!(
+            "argument to `{}` must be iterable, got {}",
+            name,
+            other.object_type()
+        ))),
+    }
+}
+
+fn function_arg(name: &str, args: &[Object], i: usize) -> Result<Object, Object> {
+    match &args[i] {
+        f @ (Object::Function(_) | Object::Closure(_) | Object::Builtin(_, _)) => Ok(f.clone()),
+        other => Err(new_error(format!(
+            "argument to `{}` must be FUNCTION, got {}",
+            name,
+            other.object_type()
+        ))),
+    }
+}
+
+fn integer_arg(name: &str, args: &[Object], i: usize) -> Result<i64, Object> {
+    match &args[i] {
+        Object::Integer(value) => Ok(*value),
+        Object::BigInteger(value) => Err(new_error(format!(
+            "argument to `{}` out of range: {}",
+            name, value
+        ))),
+        other => Err(new_error(format!(
+            "argument to `{}` must be {}, got {}",
+            name,
+            INTEGER_OBJ,
+            other.object_type()
+        ))),
+    }
+}
+
+fn hash_arg(
+    caller: &mut dyn Caller,
+    name: &str,
+    args: &[Object],
+    i: usize,
+) -> Result<Hash, Object> {
+    match &args[i] {
+        Object::Hash(hash) => match caller.consume(hash.borrow().len() as u64) {
+            Some(err) => Err(err),
+            None => Ok(hash.borrow().clone()),
+        },
+        other => Err(new_error(format!(
+            "argument to `{}` must be {}, got {}",
+            name,
+            HASH_OBJ,
+            other.object_type()
+        ))),
+    }
+}
+
+fn hash_key_arg(args: &[Object], i: usize) -> Result<HashKey, Object> {
+    match args[i].hash_key() {
+        Some(key) => Ok(key),
+        None => Err(new_error(format!(
+            "unusable as hash key: {}",
+            args[i].object_type()
+        ))),
+    }
+}
+
+fn len(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 1) {
+        return err;
+    }
+
+    match &args[0] {
+        Object::String(value) => match consume_bytes(caller, value.len()) {
+            Some(err) => err,
+            None => Object::Integer(value.chars().count() as i64),
+        },
+        Object::Array(elements) => Object::Integer(elements.borrow().len() as i64),
+        Object::Hash(hash) => Object::Integer(hash.borrow().len() as i64),
+        other => new_error(format!(
+            "argument to `len` not supported, got {}",
+            other.object_type()
+        )),
+    }
+}
+
+fn first(_caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 1) {
+        return err;
+    }
+
+    match &args[0] {
+        Object::Array(elements) => elements.borrow().first().cloned().unwrap_or(Object::Null),
+        other => new_error(format!(
+            "argument to `first` must be {}, got {}",
+            ARRAY_OBJ,
+            other.object_type()
+        )),
+    }
+}
+
+fn last(_caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 1) {
+        return err;
+    }
+
+    match &args[0] {
+        Object::Array(elements) => elements.borrow().last().cloned().unwrap_or(Object::Null),
+        other => new_error(format!(
+            "argument to `last` must be {}, got {}",
+            ARRAY_OBJ,
+            other.object_type()
+        )),
+    }
+}
+
+fn rest(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 1) {
+        return err;
+    }
+
+    match &args[0] {
+        Object::Array(elements) => {
+            let elements = elements.borrow();
+            if elements.is_empty() {
+                return Object::Null;
+            }
+
+            match collect_items(caller, elements[1..].iter().cloned()) {
+                Ok(rest) => Object::new_array(rest),
+                Err(err) => err,
+            }
+        }
+        other => new_error(format!(
+            "argument to `rest` must be {}, got {}",
+            ARRAY_OBJ,
+            other.object_type()
+        )),
+    }
+}
+
+// Returns a new array; the argument is left untouched.
+fn push(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 2) {
+        return err;
+    }
+
+    match &args[0] {
+        Object::Array(elements) => {
+            let mut new_elements = match collect_items(caller, elements.borrow().iter().cloned()) {
+                Ok(elements) => elements,
+                Err(err) => return err,
+            };
+            new_elements.push(args[1].clone());
+
+            Object::new_array(new_elements)
+        }
+        other => new_error(format!(
+            "argument to `push` must be {}, got {}",
+            ARRAY_OBJ,
+            other.object_type()
+        )),
+    }
+}
+
+fn puts(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    for arg in args.iter() {
+        let text = arg.inspect();
+        if let Some(err) = consume_bytes(caller, text.len()) {
+            return err;
+        }
+        println!("{}", text);
+    }
+
+    Object::Null
+}
+
+fn split(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 2) {
+        return err;
+    }
+
+    let (value, sep) = match (string_arg("split", &args, 0), string_arg("split", &args, 1)) {
+        (Ok(value), Ok(sep)) => (value, sep),
+        (Err(err), _) | (_, Err(err)) => return err,
+    };
+    if let Some(err) = consume_bytes(caller, value.len()) {
+        return err;
+    }
+
+    let parts = if sep.is_empty() {
+        collect_items(
+            caller,
+            value.chars().map(|c| Object::String(c.to_string().into())),
+        )
+    } else {
+        collect_items(
+            caller,
+            value
+                .split(sep)
+                .map(|p| Object::String(p.to_string().into())),
+        )
+    };
+
+    match parts {
+        Ok(parts) => Object::new_array(parts),
+        Err(err) => err,
+    }
+}
+
+fn join(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 2) {
+        return err;
+    }
+
+    let sep = match string_arg("join", &args, 1) {
+        Ok(sep) => sep,
+        Err(err) => return err,
+    };
+
+    match &args[0] {
+        Object::Array(elements) => {
+            let mut work = Work::default();
+            let mut joined = String::new();
+            for (i, element) in elements.borrow().iter().enumerate() {
+                if i > 0 {
+                    joined.push_str(sep);
+                }
+                let part = element.inspect();
+                joined.push_str(&part);
+
+                if let Some(err) = work
+                    .items(caller, 1)
+                    .or_else(|| work.bytes(caller, sep.len() + part.len()))
+                {
+                    return err;
+                }
+            }
+            if let Some(err) = work.finish(caller) {
+                return err;
+            }
+
+            Object::String(joined.into())
+        }
+        other => new_error(format!(
+            "argument to `join` must be {}, got {}",
+            ARRAY_OBJ,
+            other.object_type()
+        )),
+    }
+}
+
+fn map_string(
+    caller: &mut dyn Caller,
+    name: &str,
+    args: Vec<Object>,
+    f: fn(&str) -> String,
+) -> Object {
+    if let Some(err) = check_arg_count(&args, 1) {
+        return err;
+    }
+
+    match string_arg(name, &args, 0) {
+        Ok(value) => match consume_bytes(caller, value.len()) {
+            Some(err) => err,
+            None => Object::String(f(value).into()),
+        },
+        Err(err) => err,
+    }
+}
+
+fn trim(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    map_string(caller, "trim", args, |s| s.trim().to_string())
+}
+
+fn upper(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    map_string(caller, "upper", args, str::to_uppercase)
+}
+
+fn lower(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    map_string(caller, "lower", args, str::to_lowercase)
+}
+
+fn contains(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 2) {
+        return err;
+    }
+
+    match (
+        string_arg("contains", &args, 0),
+        string_arg("contains", &args, 1),
+    ) {
+        (Ok(value), Ok(sub)) => match consume_bytes(caller, value.len()) {
+            Some(err) => err,
+            None => Object::Boolean(value.contains(sub)),
+        },
+        (Err(err), _) | (_, Err(err)) => err,
+    }
+}
+
+fn replace(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 3) {
+        return err;
+    }
+
+    let strings: Result<Vec<&str>, Object> =
+        (0..3).map(|i| string_arg("replace", &args, i)).collect();
+    let (value, from, to) = match strings.as_deref() {
+        Ok([value, from, to]) => (*value, *from, *to),
+        Ok(_) => unreachable!(),
+        Err(err) => return err.clone(),
+    };
+
+    // The same as str::replace, but paying for each match as it's made.
+    let mut work = Work::default();
+    let mut replaced = String::new();
+    let mut last = 0;
+    for (start, part) in value.match_indices(from) {
+        replaced.push_str(&value[last..start]);
+        replaced.push_str(to);
+        if let Some(err) = work
+            .items(caller, 1)
+            .or_else(|| work.bytes(caller, start - last + to.len()))
+        {
+            return err;
+        }
+        last = start + part.len();
+    }
+    replaced.push_str(&value[last..]);
+    if let Some(err) = work
+        .bytes(caller, value.len() - last)
+        .or_else(|| work.finish(caller))
+    {
+        return err;
+    }
+
+    Object::String(replaced.into())
+}
+
+fn starts_with(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 2) {
+        return err;
+    }
+
+    match (
+        string_arg("starts_with", &args, 0),
+        string_arg("starts_with", &args, 1),
+    ) {
+        (Ok(value), Ok(prefix)) => match consume_bytes(caller, prefix.len()) {
+            Some(err) => err,
+            None => Object::Boolean(value.starts_with(prefix)),
+        },
+        (Err(err), _) | (_, Err(err)) => err,
+    }
+}
+
+fn map(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 2) {
+        return err;
+    }
+
+    let (items, f) = match (
+        iterable_arg(caller, "map", &args, 0),
+        function_arg("map", &args, 1),
+    ) {
+        (Ok(items), Ok(f)) => (items, f),
+        (Err(err), _) | (_, Err(err)) => return err,
+    };
+
+    let mut mapped = Vec::with_capacity(items.len());
+    for item in items {
+        let result = caller.call(&f, vec![item]);
+        if result.is_error() {
+            return result;
+        }
+        mapped.push(result);
+    }
+
+    Object::new_array(mapped)
+}
+
+fn filter(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 2) {
+        return err;
+    }
+
+    let (items, f) = match (
+        iterable_arg(caller, "filter", &args, 0),
+        function_arg("filter", &args, 1),
+    ) {
+        (Ok(items), Ok(f)) => (items, f),
+        (Err(err), _) | (_, Err(err)) => return err,
+    };
+
+    let mut kept = Vec::new();
+    for item in items {
+        let result = caller.call(&f, vec![item.clone()]);
+        if result.is_error() {
+            return result;
+        }
+        if result.is_truthy() {
+            kept.push(item);
+        }
+    }
+
+    Object::new_array(kept)
+}
+
+// `reduce(xs, f)` seeds the accumulator with the first item; `reduce(xs, f, init)` with `init`.
+fn reduce(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_range(&args, 2, 3) {
+        return err;
+    }
+
+    let (items, f) = match (
+        iterable_arg(caller, "reduce", &args, 0),
+        function_arg("reduce", &args, 1),
+    ) {
+        (Ok(items), Ok(f)) => (items, f),
+        (Err(err), _) | (_, Err(err)) => return err,
+    };
+
+    let mut items = items.into_iter();
+    let mut acc = match args.get(2) {
+        Some(init) => init.clone(),
+        None => match items.next() {
+            Some(first) => first,
+            None => {
+                return new_error(String::from(
+                    "reduce of empty sequence with no initial value",
+                ));
+            }
+        },
+    };
+
+    for item in items {
+        acc = caller.call(&f, vec![acc, item]);
+        if acc.is_error() {
+            return acc;
+        }
+    }
+
+    acc
+}
+
+fn compare(a: &Object, b: &Object) -> Result<Ordering, Object> {
+    match (a, b) {
+        (Object::Integer(a), Object::Integer(b)) => Ok(a.cmp(b)),
+        (
+            Object::Integer(_) | Object::BigInteger(_),
+            Object::Integer(_) | Object::BigInteger(_),
+        ) => Ok(a.as_big_integer().cmp(&b.as_big_integer())),
+        (Object::String(a), Object::String(b)) => Ok(a.cmp(b)),
+        (Object::Boolean(a), Object::Boolean(b)) => Ok(a.cmp(b)),
+        _ => Err(new_error(format!(
+            "cannot compare {} and {}",
+            a.object_type(),
+            b.object_type()
+        ))),
+    }
+}
+
+// Sorts into a new array. An optional comparator receives two items and returns an INTEGER
+// that is negative, zero or positive, like `a - b`.
+fn sort(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_range(&args, 1, 2) {
+        return err;
+    }
+
+    let mut items = match iterable_arg(caller, "sort", &args, 0) {
+        Ok(items) => items,
+        Err(err) => return err,
+    };
+    let comparator = match args.len() {
+        2 => match function_arg("sort", &args, 1) {
+            Ok(f) => Some(f),
+            Err(err) => return err,
+        },
+        _ => None,
+    };
+
+    // Without a comparator, reject mixed or unordered items up front so the error names the
+    // types in the order they appear.
+    if let (None, Some(first)) = (&comparator, items.first()) {
+        for item in items.iter() {
+            if let Err(err) = compare(first, item) {
+                return err;
+            }
+        }
+    }
+
+    // sort_by can't bail out early, so remember the first failure and report it afterwards.
+    let mut failure = None;
+    let mut work = Work::default();
+    items.sort_by(|a, b| {
+        if failure.is_some() {
+            return Ordering::Equal;
+        }
+        if let Some(err) = work.items(caller, 1) {
+            failure = Some(err);
+            return Ordering::Equal;
+        }
+
+        let ordering = match &comparator {
+            Some(f) => match caller.call(f, vec![a.clone(), b.clone()]) {
+                Object::Integer(value) => Ok(value.cmp(&0)),
+                // Never zero, or it would be an Integer.
+                Object::BigInteger(value) if value.is_negative() => Ok(Ordering::Less),
+                Object::BigInteger(_) => Ok(Ordering::Greater),
+                err @ Object::Error(_) => Err(err),
+                other => Err(new_error(format!(
+                    "sort comparator must return {}, got {}",
+                    INTEGER_OBJ,
+                    other.object_type()
+                ))),
+            },
+            None => compare(a, b),
+        };
+
+        ordering.unwrap_or_else(|err| {
+            failure = Some(err);
+            Ordering::Equal
+        })
+    });
+
+    match failure.or_else(|| work.finish(caller)) {
+        Some(err) => err,
+        None => Object::new_array(items),
+    }
+}
+
+fn reverse(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 1) {
+        return err;
+    }
+
+    if let Object::String(value) = &args[0] {
+        return match consume_bytes(caller, value.len()) {
+            Some(err) => err,
+            None => Object::String(value.chars().rev().collect::<String>().into()),
+        };
+    }
+
+    match iterable_arg(caller, "reverse", &args, 0) {
+        Ok(mut items) => {
+            items.reverse();
+            Object::new_array(items)
+        }
+        Err(err) => err,
+    }
+}
+
+// Pairs up items from two or more sequences, stopping at the shortest.
+fn zip(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if args.len() < 2 {
+        return new_error(format!(
+            "wrong number of arguments: want=2.., got={}",
+            args.len()
+        ));
+    }
+
+    let sequences: Result<Vec<Vec<Object>>, Object> = (0..args.len())
+        .map(|i| iterable_arg(caller, "zip", &args, i))
+        .collect();
+    let sequences = match sequences {
+        Ok(sequences) => sequences,
+        Err(err) => return err,
+    };
+
+    let shortest = sequences.iter().map(|s| s.len()).min().unwrap_or(0);
+    let tuples =
+        (0..shortest).map(|i| Object::new_array(sequences.iter().map(|s| s[i].clone()).collect()));
+
+    match collect_items(caller, tuples) {
+        Ok(tuples) => Object::new_array(tuples),
+        Err(err) => err,
+    }
+}
+
+fn enumerate(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 1) {
+        return err;
+    }
+
+    let items = match iterable_arg(caller, "enumerate", &args, 0) {
+        Ok(items) => items,
+        Err(err) => return err,
+    };
+    let pairs = items
+        .into_iter()
+        .enumerate()
+        .map(|(i, item)| Object::new_array(vec![Object::Integer(i as i64), item]));
+
+    match collect_items(caller, pairs) {
+        Ok(pairs) => Object::new_array(pairs),
+        Err(err) => err,
+    }
+}
+
+// `range(end)`, `range(start, end)` or `range(start, end, step)`, with `end` exclusive.
+fn range(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_range(&args, 1, 3) {
+        return err;
+    }
+
+    let bounds: Result<Vec<i64>, Object> = (0..args.len())
+        .map(|i| integer_arg("range", &args, i))
+        .collect();
+    let (start, end, step) = match bounds.as_deref() {
+        Ok([end]) => (0, *end, 1),
+        Ok([start, end]) => (*start, *end, 1),
+        Ok([start, end, step]) => (*start, *end, *step),
+        Ok(_) => unreachable!(),
+        Err(err) => return err.clone(),
+    };
+
+    if step == 0 {
+        return new_error(String::from("range step cannot be zero"));
+    }
+
+    let len = (end as i128 - start as i128) / step as i128;
+    if len > 0
+        && let Some(err) = caller.reserve((len as usize).saturating_mul(size_of::<Object>()))
+    {
+        return err;
+    }
+
+    let mut work = Work::default();
+    let mut values = Vec::new();
+    let mut i = start;
+    while (step > 0 && i < end) || (step < 0 && i > end) {
+        values.push(Object::Integer(i));
+        if let Some(err) = work.items(caller, 1) {
+            return err;
+        }
+        i = match i.checked_add(step) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    if let Some(err) = work.finish(caller) {
+        return err;
+    }
+
+    Object::new_array(values)
+}
+
+// Shared body of `any` and `all`: applies the optional predicate (or plain truthiness) to each
+// item and stops at the first item whose result equals `stop_on`.
+fn any_all(caller: &mut dyn Caller, name: &str, args: Vec<Object>, stop_on: bool) -> Object {
+    if let Some(err) = check_arg_range(&args, 1, 2) {
+        return err;
+    }
+
+    let items = match iterable_arg(caller, name, &args, 0) {
+        Ok(items) => items,
+        Err(err) => return err,
+    };
+    let predicate = match args.len() {
+        2 => match function_arg(name, &args, 1) {
+            Ok(f) => Some(f),
+            Err(err) => return err,
+        },
+        _ => None,
+    };
+
+    for item in items {
+        let result = match &predicate {
+            Some(f) => caller.call(f, vec![item]),
+            None => item,
+        };
+        if result.is_error() {
+            return result;
+        }
+        if result.is_truthy() == stop_on {
+            return Object::Boolean(stop_on);
+        }
+    }
+
+    Object::Boolean(!stop_on)
+}
+
+fn any(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    any_all(caller, "any", args, true)
+}
+
+fn all(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    any_all(caller, "all", args, false)
+}
+
+// Flattens one level of nesting; non-array items are kept as they are.
+fn flatten(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 1) {
+        return err;
+    }
+
+    let items = match iterable_arg(caller, "flatten", &args, 0) {
+        Ok(items) => items,
+        Err(err) => return err,
+    };
+
+    let mut work = Work::default();
+    let mut flat = Vec::new();
+    for item in items {
+        match item {
+            Object::Array(inner) => {
+                for element in inner.borrow().iter() {
+                    flat.push(element.clone());
+                    if let Some(err) = work.items(caller, 1) {
+                        return err;
+                    }
+                }
+            }
+            other => flat.push(other),
+        }
+    }
+    if let Some(err) = work.finish(caller) {
+        return err;
+    }
+
+    Object::new_array(flat)
+}
+
+fn keys(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 1) {
+        return err;
+    }
+
+    match hash_arg(caller, "keys", &args, 0) {
+        Ok(hash) => Object::new_array(hash.iter().map(|pair| pair.key.clone()).collect()),
+        Err(err) => err,
+    }
+}
+
+fn values(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 1) {
+        return err;
+    }
+
+    match hash_arg(caller, "values", &args, 0) {
+        Ok(hash) => Object::new_array(hash.iter().map(|pair| pair.value.clone()).collect()),
+        Err(err) => err,
+    }
+}
+
+fn has(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 2) {
+        return err;
+    }
+
+    let hash = match hash_arg(caller, "has", &args, 0) {
+        Ok(hash) => hash,
+        Err(err) => return err,
+    };
+
+    match hash_key_arg(&args, 1) {
+        Ok(key) => Object::Boolean(hash.contains_key(&key)),
+        Err(err) => err,
+    }
+}
+
+// Returns a copy of the hash without the key; the original is left untouched.
+fn delete(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 2) {
+        return err;
+    }
+
+    let mut hash = match hash_arg(caller, "delete", &args, 0) {
+        Ok(hash) => hash,
+        Err(err) => return err,
+    };
+
+    match hash_key_arg(&args, 1) {
+        Ok(key) => {
+            hash.remove(&key);
+            Object::new_hash(hash)
+        }
+        Err(err) => err,
+    }
+}
+
+// Keys from the second hash win; new keys are appended in their original order.
+fn merge(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 2) {
+        return err;
+    }
+
+    let mut merged = match hash_arg(caller, "merge", &args, 0) {
+        Ok(hash) => hash,
+        Err(err) => return err,
+    };
+
+    let other = match hash_arg(caller, "merge", &args, 1) {
+        Ok(hash) => hash,
+        Err(err) => return err,
+    };
+
+    for pair in other.iter() {
+        if let Some(key) = pair.key.hash_key() {
+            merged.insert(key, pair.key.clone(), pair.value.clone());
+        }
+    }
+
+    Object::new_hash(merged)
+}
+
+fn entries(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if let Some(err) = check_arg_count(&args, 1) {
+        return err;
+    }
+
+    match hash_arg(caller, "entries", &args, 0) {
+        Ok(hash) => Object::new_array(
+            hash.iter()
+                .map(|pair| Object::new_array(vec![pair.key.clone(), pair.value.clone()]))
+                .collect(),
+        ),
+        Err(err) => err,
+    }
+}
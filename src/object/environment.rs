@@ -0,0 +1,108 @@
+use super::{Object, gc};
+use crate::symbol::Symbol;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub struct Environment {
+    store: HashMap<Symbol, Object>,
+    // Names the resolver placed in this scope, by slot. A slot stays empty until its `let` runs.
+    slots: Vec<Option<Object>>,
+    outer: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    pub fn new() -> Rc<RefCell<Environment>> {
+        Environment::with_outer(None)
+    }
+
+    pub fn new_enclosed(outer: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        Environment::with_outer(Some(outer))
+    }
+
+    fn with_outer(outer: Option<Rc<RefCell<Environment>>>) -> Rc<RefCell<Environment>> {
+        let env = Rc::new(RefCell::new(Environment {
+            store: HashMap::new(),
+            slots: Vec::new(),
+            outer,
+        }));
+        gc::track_environment(&env);
+        env
+    }
+
+    pub fn get(&self, name: &Symbol) -> Option<Object> {
+        match self.store.get(name) {
+            Some(obj) => Some(obj.clone()),
+            None => match &self.outer {
+                Some(outer) => outer.borrow().get(name),
+                None => None,
+            },
+        }
+    }
+
+    pub fn set(&mut self, name: Symbol, val: Object) {
+        self.store.insert(name, val);
+    }
+
+    pub fn get_slot(&self, depth: usize, index: usize) -> Option<Object> {
+        if depth > 0 {
+            return self.outer.as_ref()?.borrow().get_slot(depth - 1, index);
+        }
+
+        self.slots.get(index).cloned().flatten()
+    }
+
+    pub fn set_slot(&mut self, index: usize, val: Object) {
+        if index >= self.slots.len() {
+            self.slots.resize(index + 1, None);
+        }
+        self.slots[index] = Some(val);
+    }
+
+    // Like `assign`, only rebinds a slot whose `let` has already run. Hands the value back
+    // otherwise, so the caller can try another.
+    pub fn assign_slot(&mut self, depth: usize, index: usize, val: Object) -> Result<(), Object> {
+        if depth > 0 {
+            return match &self.outer {
+                Some(outer) => outer.borrow_mut().assign_slot(depth - 1, index, val),
+                None => Err(val),
+            };
+        }
+
+        match self.slots.get_mut(index) {
+            Some(Some(slot)) => {
+                *slot = val;
+                Ok(())
+            }
+            _ => Err(val),
+        }
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Object> {
+        self.store.values().chain(self.slots.iter().flatten())
+    }
+
+    // Drops every binding; the collector uses this to break cycles through an unreachable scope.
+    pub(crate) fn clear(&mut self) {
+        self.store.clear();
+        self.slots.clear();
+    }
+
+    pub fn outer(&self) -> Option<&Rc<RefCell<Environment>>> {
+        self.outer.as_ref()
+    }
+
+    // Rebinds an existing name in the nearest scope that declares it. Returns false when no
+    // enclosing scope has the name, so callers can report assignment to an undeclared variable.
+    pub fn assign(&mut self, name: &Symbol, val: Object) -> bool {
+        if let Some(slot) = self.store.get_mut(name) {
+            *slot = val;
+            return true;
+        }
+
+        match &self.outer {
+            Some(outer) => outer.borrow_mut().assign(name, val),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,338 @@
+#[cfg(test)]
+mod tests;
+
+use super::environment::Environment;
+use super::{Cell, Closure, Elements, Function, Hash, Object};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::{Rc, Weak};
+
+// Values are reference counted, so anything not in a cycle is freed as soon as its last reference
+// goes. A closure stored in the scope it captured, or an array put inside itself, keeps itself
+// alive instead. The collector tracks every value that can hold others and periodically finds
+// the groups of them only reachable from each other.
+//
+// It needs no list of roots: a value with more references than the tracked values account for
+// is held from outside, by an engine's stack, globals or a host's variables, and everything it
+// reaches is live. Whatever is left is unreachable and gets emptied, which breaks its cycles and
+// lets reference counting free it.
+
+// Collect once this many values have been tracked, or twice as many as survived the last
+// collection, whichever is larger.
+const MIN_THRESHOLD: usize = 10_000;
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap::default());
+}
+
+#[derive(Default)]
+struct Heap {
+    tracked: Vec<Tracked>,
+    threshold: usize,
+    collections: u64,
+    freed: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    // Values that can hold other values and are still alive.
+    pub objects: usize,
+    pub collections: u64,
+    pub freed: u64,
+}
+
+pub fn stats() -> Stats {
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        Stats {
+            objects: heap.tracked.iter().filter(|t| t.is_alive()).count(),
+            collections: heap.collections,
+            freed: heap.freed,
+        }
+    })
+}
+
+enum Tracked {
+    Environment(Weak<RefCell<Environment>>),
+    Array(Weak<RefCell<Elements>>),
+    Hash(Weak<RefCell<Hash>>),
+    Function(Weak<Function>),
+    Closure(Weak<Closure>),
+    Cell(Weak<RefCell<Option<Object>>>),
+}
+
+// A tracked value held alive for the length of a collection.
+enum Node {
+    Environment(Rc<RefCell<Environment>>),
+    Array(Rc<RefCell<Elements>>),
+    Hash(Rc<RefCell<Hash>>),
+    Function(Rc<Function>),
+    Closure(Rc<Closure>),
+    Cell(Cell),
+}
+
+pub(crate) fn track_environment(env: &Rc<RefCell<Environment>>) {
+    track(Tracked::Environment(Rc::downgrade(env)));
+}
+
+pub(crate) fn track_array(array: &Rc<RefCell<Elements>>) {
+    track(Tracked::Array(Rc::downgrade(array)));
+}
+
+pub(crate) fn track_hash(hash: &Rc<RefCell<Hash>>) {
+    track(Tracked::Hash(Rc::downgrade(hash)));
+}
+
+pub(crate) fn track_function(function: &Rc<Function>) {
+    track(Tracked::Function(Rc::downgrade(function)));
+}
+
+pub(crate) fn track_closure(closure: &Rc<Closure>) {
+    track(Tracked::Closure(Rc::downgrade(closure)));
+}
+
+pub(crate) fn track_cell(cell: &Cell) {
+    track(Tracked::Cell(Rc::downgrade(cell)));
+}
+
+// Collecting while a value is being built is safe: whoever is building it holds a reference the
+// heap can't account for, so it and everything it reaches count as live.
+fn track(tracked: Tracked) {
+    let full = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.tracked.push(tracked);
+        heap.tracked.len() >= heap.threshold.max(MIN_THRESHOLD)
+    });
+
+    if full {
+        collect();
+    }
+}
+
+// Frees every value only reachable through cycles. Returns how many tracked values that was.
+pub fn collect() -> usize {
+    let tracked = HEAP.with(|heap| mem::take(&mut heap.borrow_mut().tracked));
+    let nodes: Vec<Node> = tracked.iter().filter_map(Tracked::upgrade).collect();
+    drop(tracked);
+
+    let index: HashMap<*const (), usize> = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| (node.as_ptr(), i))
+        .collect();
+
+    // Take away every reference one tracked value holds to another; whatever remains comes from
+    // outside the heap. `nodes` itself holds one more.
+    let mut external: Vec<usize> = nodes.iter().map(|node| node.strong_count() - 1).collect();
+    let mut edges: Vec<Vec<usize>> = Vec::with_capacity(nodes.len());
+    let mut roots = Vec::new();
+
+    for (i, node) in nodes.iter().enumerate() {
+        let mut children = Vec::new();
+        // A value being changed right now can't be looked into. It's in use, so keep it and,
+        // since its references weren't taken away, everything it holds.
+        if node.children(&mut children).is_err() {
+            roots.push(i);
+        }
+
+        let children: Vec<usize> = children
+            .iter()
+            .filter_map(|child| index.get(child).copied())
+            .collect();
+        for child in children.iter() {
+            external[*child] -= 1;
+        }
+        edges.push(children);
+    }
+
+    roots.extend((0..nodes.len()).filter(|i| external[*i] > 0));
+
+    let mut live = vec![false; nodes.len()];
+    while let Some(i) = roots.pop() {
+        if !live[i] {
+            live[i] = true;
+            roots.extend(edges[i].iter().copied());
+        }
+    }
+
+    let mut survivors = Vec::new();
+    let mut garbage = Vec::new();
+    for (node, live) in nodes.into_iter().zip(live) {
+        if live {
+            survivors.push(node.downgrade());
+        } else {
+            garbage.push(node);
+        }
+    }
+
+    // Emptying the garbage drops the references that formed its cycles; the values themselves go
+    // once `garbage` is dropped too.
+    let freed = garbage.len();
+    garbage.iter().for_each(Node::clear);
+    drop(garbage);
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        // Anything made while the garbage was dropped was tracked in the meantime.
+        survivors.append(&mut heap.tracked);
+        heap.tracked = survivors;
+        heap.threshold = heap.tracked.len() * 2;
+        heap.collections += 1;
+        heap.freed += freed as u64;
+    });
+
+    freed
+}
+
+impl Tracked {
+    fn is_alive(&self) -> bool {
+        match self {
+            Tracked::Environment(weak) => weak.strong_count() > 0,
+            Tracked::Array(weak) => weak.strong_count() > 0,
+            Tracked::Hash(weak) => weak.strong_count() > 0,
+            Tracked::Function(weak) => weak.strong_count() > 0,
+            Tracked::Closure(weak) => weak.strong_count() > 0,
+            Tracked::Cell(weak) => weak.strong_count() > 0,
+        }
+    }
+
+    fn upgrade(&self) -> Option<Node> {
+        match self {
+            Tracked::Environment(weak) => weak.upgrade().map(Node::Environment),
+            Tracked::Array(weak) => weak.upgrade().map(Node::Array),
+            Tracked::Hash(weak) => weak.upgrade().map(Node::Hash),
+            Tracked::Function(weak) => weak.upgrade().map(Node::Function),
+            Tracked::Closure(weak) => weak.upgrade().map(Node::Closure),
+            Tracked::Cell(weak) => weak.upgrade().map(Node::Cell),
+        }
+    }
+}
+
+impl Node {
+    fn downgrade(&self) -> Tracked {
+        match self {
+            Node::Environment(rc) => Tracked::Environment(Rc::downgrade(rc)),
+            Node::Array(rc) => Tracked::Array(Rc::downgrade(rc)),
+            Node::Hash(rc) => Tracked::Hash(Rc::downgrade(rc)),
+            Node::Function(rc) => Tracked::Function(Rc::downgrade(rc)),
+            Node::Closure(rc) => Tracked::Closure(Rc::downgrade(rc)),
+            Node::Cell(rc) => Tracked::Cell(Rc::downgrade(rc)),
+        }
+    }
+
+    fn as_ptr(&self) -> *const () {
+        match self {
+            Node::Environment(rc) => Rc::as_ptr(rc) as *const (),
+            Node::Array(rc) => Rc::as_ptr(rc) as *const (),
+            Node::Hash(rc) => Rc::as_ptr(rc) as *const (),
+            Node::Function(rc) => Rc::as_ptr(rc) as *const (),
+            Node::Closure(rc) => Rc::as_ptr(rc) as *const (),
+            Node::Cell(rc) => Rc::as_ptr(rc) as *const (),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Environment(rc) => Rc::strong_count(rc),
+            Node::Array(rc) => Rc::strong_count(rc),
+            Node::Hash(rc) => Rc::strong_count(rc),
+            Node::Function(rc) => Rc::strong_count(rc),
+            Node::Closure(rc) => Rc::strong_count(rc),
+            Node::Cell(rc) => Rc::strong_count(rc),
+        }
+    }
+
+    // Adds the values this one holds a reference to. Fails if it's borrowed for writing.
+    fn children(&self, out: &mut Vec<*const ()>) -> Result<(), ()> {
+        match self {
+            Node::Environment(env) => {
+                let env = env.try_borrow().map_err(|_| ())?;
+                for value in env.values() {
+                    object_children(value, out);
+                }
+                if let Some(outer) = env.outer() {
+                    out.push(Rc::as_ptr(outer) as *const ());
+                }
+            }
+            Node::Array(elements) => {
+                for element in elements.try_borrow().map_err(|_| ())?.iter() {
+                    object_children(element, out);
+                }
+            }
+            Node::Hash(hash) => {
+                for pair in hash.try_borrow().map_err(|_| ())?.iter() {
+                    object_children(&pair.key, out);
+                    object_children(&pair.value, out);
+                }
+            }
+            Node::Function(function) => out.push(Rc::as_ptr(&function.env) as *const ()),
+            Node::Closure(closure) => {
+                out.extend(
+                    closure
+                        .free
+                        .iter()
+                        .map(|cell| Rc::as_ptr(cell) as *const ()),
+                );
+            }
+            Node::Cell(cell) => {
+                if let Some(value) = &*cell.try_borrow().map_err(|_| ())? {
+                    object_children(value, out);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Drops everything this value holds. Functions and closures can't change, but what they hold
+    // is garbage too and gets emptied in turn.
+    fn clear(&self) {
+        match self {
+            Node::Environment(env) => {
+                if let Ok(mut env) = env.try_borrow_mut() {
+                    env.clear();
+                }
+            }
+            Node::Array(elements) => {
+                if let Ok(mut elements) = elements.try_borrow_mut() {
+                    elements.clear();
+                }
+            }
+            Node::Hash(hash) => {
+                if let Ok(mut hash) = hash.try_borrow_mut() {
+                    *hash = Hash::new();
+                }
+            }
+            Node::Function(_) | Node::Closure(_) => (),
+            Node::Cell(cell) => {
+                if let Ok(mut cell) = cell.try_borrow_mut() {
+                    *cell = None;
+                }
+            }
+        }
+    }
+}
+
+// The tracked values an object refers to, looking through the wrappers that aren't tracked.
+fn object_children(obj: &Object, out: &mut Vec<*const ()>) {
+    match obj {
+        Object::Array(elements) => out.push(Rc::as_ptr(elements) as *const ()),
+        Object::Hash(hash) => out.push(Rc::as_ptr(hash) as *const ()),
+        Object::Function(function) => out.push(Rc::as_ptr(function) as *const ()),
+        Object::Closure(closure) => out.push(Rc::as_ptr(closure) as *const ()),
+        Object::ReturnValue(value) => object_children(value, out),
+        Object::Error(err) => {
+            if let Some(thrown) = &err.thrown {
+                object_children(thrown, out);
+            }
+        }
+        Object::TailCall(call) => {
+            out.push(Rc::as_ptr(&call.function) as *const ());
+            for arg in call.args.iter() {
+                object_children(arg, out);
+            }
+        }
+        _ => (),
+    }
+}
@@ -0,0 +1,87 @@
+use super::*;
+use crate::evaluator::Evaluator;
+use crate::test_helpers::{parse, run_vm};
+
+// Each test runs on its own thread, so each starts with an empty heap of its own.
+
+// Runs the program in a scope of its own and hands back the scope with the result.
+fn eval(input: &str) -> (Rc<RefCell<Environment>>, Object) {
+    let env = Environment::new();
+    let result = Evaluator::new().eval_program(&parse(input), &env);
+    (env, result)
+}
+
+#[test]
+fn test_collect_cycles() {
+    let tests = [
+        "let f = fn(n) { if (n == 0) { 0 } else { f(n - 1) } }; f(10)",
+        "let a = [1, 2]; a[0] = a; len(a)",
+        "let h = {}; h[\"self\"] = h; len(h)",
+        "let a = []; let h = {\"a\": a}; a = push(a, h); h[\"a\"] = a; 1",
+        "let make = fn() { let g = fn() { g }; g }; let g = make(); 1",
+    ];
+
+    for input in tests.iter() {
+        let (env, result) = eval(input);
+        assert!(!result.is_error(), "input: {}: {}", input, result.inspect());
+
+        drop((env, result));
+        assert!(stats().objects > 0, "input: {}: nothing leaked", input);
+
+        assert!(collect() > 0, "input: {}", input);
+        assert_eq!(stats().objects, 0, "input: {}", input);
+    }
+}
+
+#[test]
+fn test_collect_keeps_reachable_values() {
+    let (env, _) = eval(
+        "let f = fn(n) { if (n == 0) { 0 } else { n + f(n - 1) } };
+         let a = [1]; a[0] = a;",
+    );
+
+    collect();
+    assert_eq!(collect(), 0);
+
+    let result = Evaluator::new().eval_program(&parse("f(4) + len(a[0][0])"), &env);
+    assert_eq!(result.inspect(), "11");
+
+    drop(env);
+    assert!(collect() > 0);
+    assert_eq!(stats().objects, 0);
+}
+
+#[test]
+fn test_collect_vm_closures() {
+    let input = "let make = fn() {
+        let count = fn(n) { if (n == 0) { 0 } else { 1 + count(n - 1) } };
+        count
+    };
+    let counter = make();
+    counter(5) + make()(3)";
+
+    assert_eq!(run_vm(&parse(input)).inspect(), "8");
+    assert!(stats().objects > 0);
+
+    assert!(collect() > 0);
+    assert_eq!(stats().objects, 0);
+}
+
+#[test]
+fn test_collection_is_automatic() {
+    let program = parse("let f = fn(n) { if (n == 0) { 0 } else { f(n - 1) } }; f(20)");
+
+    for _ in 0..2_000 {
+        let result = Evaluator::new().eval_program(&program, &Environment::new());
+        assert_eq!(result.inspect(), "0");
+    }
+
+    let stats = stats();
+    assert!(stats.collections > 0);
+    assert!(stats.freed > 0);
+    assert!(
+        stats.objects < 2 * MIN_THRESHOLD,
+        "{} objects still alive",
+        stats.objects
+    );
+}
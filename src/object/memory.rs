@@ -0,0 +1,145 @@
+use super::environment::Environment;
+use super::{Cell, HashKey, HashPair, Object};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::rc::Rc;
+
+// Rough costs: enough to bound what a script can hold, not to mirror the allocator exactly.
+const HASH_ENTRY_SIZE: usize = size_of::<HashPair>() + size_of::<HashKey>() + size_of::<usize>();
+
+// Bytes for the value itself, excluding whatever its elements point to.
+pub fn shallow_size(obj: &Object) -> usize {
+    match obj {
+        Object::String(value) => value.len(),
+        Object::BigInteger(value) => value.bits().div_ceil(8),
+        Object::Array(elements) => elements.borrow().len() * size_of::<Object>(),
+        Object::Hash(hash) => hash.borrow().len() * HASH_ENTRY_SIZE,
+        _ => 0,
+    }
+}
+
+pub fn hash_entry_size() -> usize {
+    HASH_ENTRY_SIZE
+}
+
+// Everything reachable from the given scopes and values, counting shared values only once.
+pub fn reachable_size(roots: &[Rc<RefCell<Environment>>], values: &[Object]) -> usize {
+    let mut walker = Walker {
+        seen: HashSet::new(),
+        total: 0,
+    };
+
+    for env in roots.iter() {
+        walker.environment(env);
+    }
+    for value in values.iter() {
+        walker.object(value);
+    }
+
+    walker.total
+}
+
+// Everything reachable from the given values and variable cells, as the VM holds them.
+pub fn reachable_size_of<'a>(
+    values: impl IntoIterator<Item = &'a Object>,
+    cells: impl IntoIterator<Item = &'a Cell>,
+) -> usize {
+    let mut walker = Walker {
+        seen: HashSet::new(),
+        total: 0,
+    };
+
+    for value in values {
+        walker.object(value);
+    }
+    for cell in cells {
+        walker.cell(cell);
+    }
+
+    walker.total
+}
+
+struct Walker {
+    seen: HashSet<*const ()>,
+    total: usize,
+}
+
+impl Walker {
+    fn first_visit<T: ?Sized>(&mut self, rc: &Rc<T>) -> bool {
+        self.seen.insert(Rc::as_ptr(rc) as *const ())
+    }
+
+    fn environment(&mut self, env: &Rc<RefCell<Environment>>) {
+        if !self.first_visit(env) {
+            return;
+        }
+
+        // A scope being updated right now can't be read; its contents were counted when charged.
+        let env = match env.try_borrow() {
+            Ok(env) => env,
+            Err(_) => return,
+        };
+
+        for value in env.values() {
+            self.object(value);
+        }
+        if let Some(outer) = env.outer() {
+            self.environment(outer);
+        }
+    }
+
+    fn cell(&mut self, cell: &Cell) {
+        if !self.first_visit(cell) {
+            return;
+        }
+
+        if let Ok(value) = cell.try_borrow()
+            && let Some(value) = &*value
+        {
+            self.object(value);
+        }
+    }
+
+    fn object(&mut self, obj: &Object) {
+        match obj {
+            Object::String(value) if self.first_visit(value) => self.total += shallow_size(obj),
+            Object::BigInteger(value) if self.first_visit(value) => self.total += shallow_size(obj),
+            Object::Array(elements) => {
+                if !self.first_visit(elements) {
+                    return;
+                }
+                self.total += shallow_size(obj);
+
+                if let Ok(elements) = elements.try_borrow() {
+                    for element in elements.iter() {
+                        self.object(element);
+                    }
+                }
+            }
+            Object::Hash(hash) => {
+                if !self.first_visit(hash) {
+                    return;
+                }
+                self.total += shallow_size(obj);
+
+                if let Ok(hash) = hash.try_borrow() {
+                    for pair in hash.iter() {
+                        self.object(&pair.key);
+                        self.object(&pair.value);
+                    }
+                }
+            }
+            Object::Function(function) if self.first_visit(function) => {
+                self.environment(&function.env);
+            }
+            Object::Closure(closure) if self.first_visit(closure) => {
+                for cell in closure.free.iter() {
+                    self.cell(cell);
+                }
+            }
+            Object::ReturnValue(value) => self.object(value),
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,248 @@
+#[cfg(test)]
+mod tests;
+
+use crate::ast::{self, Expression, Statement};
+use crate::evaluator::{eval_infix_expression, eval_prefix_expression, integer_growth};
+use crate::object::Object;
+use crate::symbol::Symbol;
+use crate::token::{self, Span, Token};
+use std::rc::Rc;
+
+// The largest big integer folding produces. Anything bigger is left to run time, where fuel,
+// timeouts and memory limits apply to working it out.
+const MAX_FOLDED_BITS: u64 = 256;
+
+// Folds operators on literals into the literal they evaluate to, and drops `if` branches a
+// literal condition rules out. Folding runs the engines' own operators, so a folded program
+// produces the same values and errors; an operation that fails, like `1 / 0`, is left in place
+// to fail when it runs, at the same spot. What changes is the cost: folded work burns no fuel,
+// takes no time and charges no memory at run time, so a program near one of those limits can
+// finish folded and run out unfolded.
+pub fn fold_constants(program: &mut ast::Program) {
+    fold_statements(&mut program.statements);
+}
+
+fn fold_statements(statements: &mut [Box<dyn Statement>]) {
+    for stmt in statements.iter_mut() {
+        fold_statement(stmt.as_mut());
+    }
+}
+
+fn fold_block(block: &mut ast::BlockStatement) {
+    fold_statements(&mut block.statements);
+}
+
+fn fold_statement(stmt: &mut dyn Statement) {
+    let any = stmt.as_any_mut();
+
+    if let Some(ls) = any.downcast_mut::<ast::LetStatement>() {
+        fold_expression(&mut ls.value);
+    } else if let Some(rs) = any.downcast_mut::<ast::ReturnStatement>() {
+        fold_expression(&mut rs.value);
+    } else if let Some(es) = any.downcast_mut::<ast::ExpressionStatement>() {
+        fold_expression(&mut es.expression);
+    } else if let Some(block) = any.downcast_mut::<ast::BlockStatement>() {
+        fold_block(block);
+    } else if let Some(stmt) = any.downcast_mut::<ast::AssignStatement>() {
+        fold_expression(&mut stmt.value);
+        fold_expression(&mut stmt.target);
+    } else if let Some(ws) = any.downcast_mut::<ast::WhileStatement>() {
+        fold_expression(&mut ws.condition);
+        fold_block(&mut ws.body);
+    } else if let Some(fs) = any.downcast_mut::<ast::ForStatement>() {
+        fold_expression(&mut fs.iterable);
+        fold_block(&mut fs.body);
+    } else if let Some(ts) = any.downcast_mut::<ast::ThrowStatement>() {
+        fold_expression(&mut ts.value);
+    } else if let Some(ts) = any.downcast_mut::<ast::TryStatement>() {
+        fold_block(&mut ts.body);
+        fold_block(&mut ts.handler);
+    }
+}
+
+fn fold_expression(slot: &mut Box<dyn Expression>) {
+    let any = slot.as_any_mut();
+
+    let replacement = if let Some(array) = any.downcast_mut::<ast::ArrayLiteral>() {
+        array.elements.iter_mut().for_each(fold_expression);
+        None
+    } else if let Some(hash) = any.downcast_mut::<ast::HashLiteral>() {
+        for (key, value) in hash.pairs.iter_mut() {
+            fold_expression(key);
+            fold_expression(value);
+        }
+        None
+    } else if let Some(func) = any.downcast_mut::<ast::FunctionLiteral>() {
+        // A freshly parsed body has no other owner yet.
+        if let Some(body) = Rc::get_mut(&mut func.body) {
+            fold_block(body);
+        }
+        None
+    } else if let Some(call) = any.downcast_mut::<ast::CallExpression>() {
+        fold_expression(&mut call.function);
+        call.arguments.iter_mut().for_each(fold_expression);
+        None
+    } else if let Some(ie) = any.downcast_mut::<ast::IndexExpression>() {
+        fold_expression(&mut ie.left);
+        fold_expression(&mut ie.index);
+        None
+    } else if let Some(se) = any.downcast_mut::<ast::SliceExpression>() {
+        fold_expression(&mut se.left);
+        for bound in [&mut se.start, &mut se.end, &mut se.step] {
+            bound.iter_mut().for_each(fold_expression);
+        }
+        None
+    } else if let Some(pe) = any.downcast_mut::<ast::PrefixExpression>() {
+        fold_expression(&mut pe.right);
+        literal_value(pe.right.as_ref()).and_then(|right| {
+            literal(
+                eval_prefix_expression(pe.operator.as_str(), right),
+                pe.span(),
+            )
+        })
+    } else if let Some(ie) = any.downcast_mut::<ast::InfixExpression>() {
+        fold_expression(&mut ie.left);
+        fold_expression(&mut ie.right);
+        match (
+            literal_value(ie.left.as_ref()),
+            literal_value(ie.right.as_ref()),
+        ) {
+            (Some(left), Some(right)) if is_cheap(ie.operator.as_str(), &left, &right) => literal(
+                eval_infix_expression(ie.operator.as_str(), left, right),
+                ie.span(),
+            ),
+            _ => None,
+        }
+    } else if let Some(ie) = any.downcast_mut::<ast::IfExpression>() {
+        match fold_if_expression(ie) {
+            FoldedIf::Kept => None,
+            FoldedIf::Null => literal(Object::Null, ie.span()),
+            FoldedIf::Alternative(alt) => Some(alt as Box<dyn Expression>),
+        }
+    } else {
+        None
+    };
+
+    if let Some(replacement) = replacement {
+        *slot = replacement;
+    }
+}
+
+// What's left of an `if` once a literal condition has picked its branch.
+enum FoldedIf {
+    Kept,
+    Null,
+    Alternative(Box<ast::IfExpression>),
+}
+
+// An `if` whose condition is a literal keeps only the branch it takes. A taken `else` block
+// becomes the consequence of an `if (true)`, since a block on its own isn't an expression.
+fn fold_if_expression(ie: &mut ast::IfExpression) -> FoldedIf {
+    fold_expression(&mut ie.condition);
+    fold_block(&mut ie.consequence);
+
+    let folded_alt = match &mut ie.alternative {
+        Some(ast::Alternative::Block(block)) => {
+            fold_block(block);
+            FoldedIf::Kept
+        }
+        Some(ast::Alternative::If(alt)) => fold_if_expression(alt),
+        None => FoldedIf::Kept,
+    };
+    match folded_alt {
+        FoldedIf::Kept => (),
+        // An `else if` that can only produce null does what no `else` at all does.
+        FoldedIf::Null => ie.alternative = None,
+        FoldedIf::Alternative(alt) => ie.alternative = Some(ast::Alternative::If(alt)),
+    }
+
+    let condition = match literal_value(ie.condition.as_ref()) {
+        Some(condition) => condition,
+        None => return FoldedIf::Kept,
+    };
+    if condition.is_truthy() {
+        ie.alternative = None;
+        return FoldedIf::Kept;
+    }
+
+    match ie.alternative.take() {
+        None => FoldedIf::Null,
+        Some(ast::Alternative::If(alt)) => FoldedIf::Alternative(alt),
+        Some(ast::Alternative::Block(block)) => {
+            ie.condition = Box::new(ast::Boolean {
+                token: Token {
+                    token_type: token::TRUE,
+                    literal: Symbol::intern("true"),
+                    span: ie.condition.span(),
+                },
+                value: true,
+            });
+            ie.consequence = block;
+            FoldedIf::Kept
+        }
+    }
+}
+
+fn literal_value(expr: &dyn Expression) -> Option<Object> {
+    let any = expr.as_any();
+
+    if let Some(lit) = any.downcast_ref::<ast::IntegerLiteral>() {
+        Some(Object::Integer(lit.value))
+    } else if let Some(lit) = any.downcast_ref::<ast::BigIntegerLiteral>() {
+        Some(Object::BigInteger(Rc::clone(&lit.value)))
+    } else if let Some(lit) = any.downcast_ref::<ast::StringLiteral>() {
+        Some(Object::String(lit.value.text()))
+    } else if let Some(lit) = any.downcast_ref::<ast::Boolean>() {
+        Some(Object::Boolean(lit.value))
+    } else if any.is::<ast::NullLiteral>() {
+        Some(Object::Null)
+    } else {
+        None
+    }
+}
+
+// Whether an operation's result is small enough to fold, judged before working it out.
+fn is_cheap(operator: &str, left: &Object, right: &Object) -> bool {
+    integer_growth(operator, left, right).is_none_or(|(bits, _)| bits <= MAX_FOLDED_BITS)
+}
+
+// The literal for a folded value, at the span of the expression it replaces. Errors and values
+// with no literal form, like ranges, aren't folded.
+fn literal(value: Object, span: Span) -> Option<Box<dyn Expression>> {
+    let token = |token_type, literal: Symbol| Token {
+        token_type,
+        literal,
+        span,
+    };
+
+    match value {
+        Object::Integer(value) => Some(Box::new(ast::IntegerLiteral {
+            token: token(token::INT, Symbol::uninterned(value.to_string())),
+            value,
+        })),
+        Object::BigInteger(value) if value.bits() as u64 <= MAX_FOLDED_BITS => {
+            Some(Box::new(ast::BigIntegerLiteral {
+                token: token(token::INT, Symbol::uninterned(value.to_string())),
+                value,
+            }))
+        }
+        Object::String(value) => {
+            let value = Symbol::uninterned(value);
+            Some(Box::new(ast::StringLiteral {
+                token: token(token::STRING, value.clone()),
+                value,
+            }))
+        }
+        Object::Boolean(value) => Some(Box::new(ast::Boolean {
+            token: token(
+                if value { token::TRUE } else { token::FALSE },
+                Symbol::from(value.to_string()),
+            ),
+            value,
+        })),
+        Object::Null => Some(Box::new(ast::NullLiteral {
+            token: token(token::NULL, Symbol::intern("null")),
+        })),
+        _ => None,
+    }
+}
@@ -0,0 +1,167 @@
+use super::*;
+use crate::evaluator::Evaluator;
+use crate::object::environment::Environment;
+use crate::test_helpers::{describe, parse, run_vm};
+
+fn folded(input: &str) -> ast::Program {
+    let mut program = parse(input);
+    fold_constants(&mut program);
+    program
+}
+
+#[test]
+fn test_fold_constants() {
+    let tests = [
+        ("60 * 60 * 24", "86400"),
+        ("1 + 2 * x", "(1 + (2 * x))"),
+        ("x + 2 * 3", "(x + 6)"),
+        ("-(3 - 5)", "2"),
+        ("~0 & 255 | 1 << 8", "511"),
+        ("!true", "false"),
+        ("!!5", "true"),
+        ("!null", "true"),
+        ("1 < 2 == (3 >= 4)", "false"),
+        ("null == null", "true"),
+        ("\"foo\" + \"bar\" + \"!\"", "\"foobar!\""),
+        ("\"a\" < \"b\"", "true"),
+        ("[1 + 1, {2 * 2: 3 - 3}][0]", "([2, {4: 0}][0])"),
+        ("a[1 + 1:10 - 2:-1]", "(a[2:8:-1])"),
+        (
+            "let f = fn(x) { return x * (2 + 2); };",
+            "let f = fn(x) return (x * 4);;",
+        ),
+        ("if (1 > 2) { a }", "null"),
+        ("if (2 > 1) { a } else { b }", "if true a"),
+        ("if (1 > 2) { a } else { b }", "if true b"),
+        (
+            "if (false) { a } else if (x) { b } else { c }",
+            "if x belse c",
+        ),
+        ("if (x) { a } else if (false) { b }", "if x a"),
+        (
+            "if (x) { a } else if (true) { b } else { c }",
+            "if x aelse if true b",
+        ),
+        ("while (1 < 2) { x += 2 * 3; }", "while true x += 6;"),
+        ("f(1 + 2)[3 - 3] = 4 * 5;", "(f(3)[0]) = 20;"),
+        ("0..5 + 1", "(0 .. 6)"),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_eq!(
+            folded(input).string().trim_end(),
+            *expected,
+            "input: {}",
+            input
+        );
+    }
+}
+
+#[test]
+fn test_failing_operations_are_not_folded() {
+    let tests = [
+        ("1 / 0", "(1 / 0)"),
+        ("60 * 60 % (2 - 2)", "(3600 % 0)"),
+        ("5 + true", "(5 + true)"),
+        ("1 << 64", "(1 << 64)"),
+        ("2 ** -1", "(2 ** -1)"),
+        ("2 ** 64 + 1", "18446744073709551617"),
+        ("-true", "(-true)"),
+        ("\"a\" - \"b\"", "(\"a\" - \"b\")"),
+        ("if (1 / 0) { a } else { b }", "if (1 / 0) aelse b"),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_eq!(
+            folded(input).string().trim_end(),
+            *expected,
+            "input: {}",
+            input
+        );
+    }
+}
+
+#[test]
+fn test_costly_operations_are_not_folded() {
+    let tests = [
+        (
+            "2 ** 200",
+            "1606938044258990275541962092341162602522202993782792835301376",
+        ),
+        ("2 ** 256", "(2 ** 256)"),
+        ("2 ** 262000", "(2 ** 262000)"),
+        ("1 ** 1000000", "1"),
+        (
+            "9223372036854775807 * 9223372036854775807",
+            "85070591730234615847396907784232501249",
+        ),
+        (
+            "(2 ** 200) * (2 ** 200)",
+            "(1606938044258990275541962092341162602522202993782792835301376 * 1606938044258990275541962092341162602522202993782792835301376)",
+        ),
+    ];
+
+    for (input, expected) in tests.iter() {
+        assert_eq!(
+            folded(input).string().trim_end(),
+            *expected,
+            "input: {}",
+            input
+        );
+    }
+}
+
+#[test]
+fn test_folded_values_are_not_interned() {
+    let program = folded("\"folded_\" + \"text\"");
+    let es = program.statements[0]
+        .as_any()
+        .downcast_ref::<ast::ExpressionStatement>()
+        .unwrap();
+    let lit = es
+        .expression
+        .as_any()
+        .downcast_ref::<ast::StringLiteral>()
+        .unwrap();
+
+    assert_eq!(lit.value, "folded_text");
+    assert_ne!(lit.value, Symbol::intern("folded_text"));
+}
+
+#[test]
+fn test_folding_preserves_behaviour() {
+    let tests = [
+        "let day = 60 * 60 * 24; day * 7",
+        "let f = fn(x) { if (2 > 3) { x } else { x * (10 - 8) } }; f(21)",
+        "let s = \"a\" + \"b\"; s + \"c\"",
+        "if (!true) { 1 } else if (1 == 1) { 2 } else { 3 }",
+        "let x = 1; if (false) { let x = 2; } x",
+        "let n = 0; while (1 > 2) { n += 1; } n",
+        "let f = fn() {\n  1 + 2 / (3 - 3)\n};\nf()",
+        "let f = fn(x) {\n  x + (2 * 3 + true)\n};\nf(1)",
+        "(60 * 60) / 0",
+        "let x = 1 << (60 + 10); x",
+        "[1, 2, 3][1 - 1:-1 + 3]",
+        "len(1 + 1)",
+        "try { 10 / (5 - 5); } catch (e) { \"caught: \" + e }",
+        "if (false) { 1 }",
+        "-9223372036854775807 - 1",
+        "let x = 9223372036854775807 + 1; x - 1",
+        "let x = 2 ** 300; x / 2 ** 299 + (2 ** 200) * (2 ** 200) % 7",
+    ];
+
+    for input in tests.iter() {
+        let program = parse(input);
+        let optimised = folded(input);
+
+        let expected = describe(&Evaluator::new().eval_program(&program, &Environment::new()));
+        assert_eq!(
+            describe(&Evaluator::new().eval_program(&optimised, &Environment::new())),
+            expected,
+            "input: {}",
+            input
+        );
+        assert_eq!(describe(&run_vm(&program)), expected, "input: {}", input);
+        assert_eq!(describe(&run_vm(&optimised)), expected, "input: {}", input);
+    }
+}
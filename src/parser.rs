@@ -1,74 +1,157 @@
+#[cfg(test)]
+mod tests;
+
 use crate::ast;
+use crate::bigint::BigInt;
 use crate::lexer;
 use crate::token;
+use std::cell::OnceCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 const LOWEST: u8 = 1;
 const EQUALS: u8 = 2;
 const LESSGREATER: u8 = 3;
-const SUM: u8 = 4;
-const PRODUCT: u8 = 5;
-const PREFIX: u8 = 6;
-const CALL: u8 = 7;
-const INDEX: u8 = 8;
-
-const PRECEDENCES: [(token::TokenType, u8); 10] = [
+const RANGE: u8 = 4;
+const BIT_OR: u8 = 5;
+const BIT_XOR: u8 = 6;
+const BIT_AND: u8 = 7;
+const SHIFT: u8 = 8;
+const SUM: u8 = 9;
+const PRODUCT: u8 = 10;
+const PREFIX: u8 = 11;
+const POWER: u8 = 12;
+const CALL: u8 = 13;
+const INDEX: u8 = 14;
+
+const PRECEDENCES: [(token::TokenType, u8); 20] = [
     (token::EQ, EQUALS),
     (token::NOT_EQ, EQUALS),
-    (token::LT, EQUALS),
-    (token::GT, EQUALS),
-    (token::PLUS, EQUALS),
-    (token::MINUS, EQUALS),
-    (token::SLASH, EQUALS),
-    (token::ASTERISK, EQUALS),
-    (token::LPAREN, EQUALS),
-    (token::RPAREN, EQUALS),
+    (token::LT, LESSGREATER),
+    (token::GT, LESSGREATER),
+    (token::LT_EQ, LESSGREATER),
+    (token::GT_EQ, LESSGREATER),
+    (token::DOTDOT, RANGE),
+    (token::PIPE, BIT_OR),
+    (token::CARET, BIT_XOR),
+    (token::AMPERSAND, BIT_AND),
+    (token::LSHIFT, SHIFT),
+    (token::RSHIFT, SHIFT),
+    (token::PLUS, SUM),
+    (token::MINUS, SUM),
+    (token::SLASH, PRODUCT),
+    (token::ASTERISK, PRODUCT),
+    (token::PERCENT, PRODUCT),
+    (token::POWER, POWER),
+    (token::LPAREN, CALL),
+    (token::LBRACKET, INDEX),
 ];
 
+const ASSIGNMENT_OPERATORS: [token::TokenType; 12] = [
+    token::ASSIGN,
+    token::PLUS_ASSIGN,
+    token::MINUS_ASSIGN,
+    token::ASTERISK_ASSIGN,
+    token::SLASH_ASSIGN,
+    token::PERCENT_ASSIGN,
+    token::POWER_ASSIGN,
+    token::AMPERSAND_ASSIGN,
+    token::PIPE_ASSIGN,
+    token::CARET_ASSIGN,
+    token::LSHIFT_ASSIGN,
+    token::RSHIFT_ASSIGN,
+];
+
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
 type PrefixParseFn = fn(&mut Parser) -> Option<Box<dyn ast::Expression>>;
-type InfixParseFn = fn(&mut Parser, &Box<dyn ast::Expression>) -> Option<Box<dyn ast::Expression>>;
+type InfixParseFn = fn(&mut Parser, Box<dyn ast::Expression>) -> Option<Box<dyn ast::Expression>>;
 
 pub struct Parser {
-    l: Box<lexer::Lexer>,
+    l: lexer::Lexer,
     errors: Vec<String>,
 
-    cur_token: Box<token::Token>,
-    peek_token: Box<token::Token>,
+    cur_token: token::Token,
+    peek_token: token::Token,
+
+    loop_depth: usize,
+    depth: usize,
+    max_depth: usize,
+    too_deep: bool,
 
     prefix_parse_fns: HashMap<token::TokenType, PrefixParseFn>,
     infix_parse_fns: HashMap<token::TokenType, InfixParseFn>,
 }
 
 impl Parser {
-    pub fn new(mut l: Box<lexer::Lexer>) -> Parser {
+    pub fn new(mut l: lexer::Lexer) -> Parser {
         let tok = l.next_token();
 
         let mut p = Parser {
             l,
             errors: Vec::new(),
-            cur_token: Box::new(token::Token::new(token::ILLEGAL, String::from(""))),
-            peek_token: Box::new(token::Token::new(tok.token_type, tok.literal)),
+            cur_token: token::Token::new(token::ILLEGAL, String::from("")),
+            peek_token: tok,
+            loop_depth: 0,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            too_deep: false,
             prefix_parse_fns: HashMap::new(),
             infix_parse_fns: HashMap::new(),
         };
 
-        p.next_token();
         p.next_token();
 
-        // TODO: Need to figure out how to give multiple possible options for the parameter (option
-        // version and non-option version)
         p.register_prefix_fns(token::IDENT, Parser::parse_identifier);
         p.register_prefix_fns(token::INT, Parser::parse_integer_literal);
-        p.register_prefix_fns(token::INT, Parser::parse_function_literal);
-        p.register_prefix_fns(token::INT, Parser::parse_string_literal);
-        p.register_prefix_fns(token::INT, Parser::parse_array_literal);
+        p.register_prefix_fns(token::TRUE, Parser::parse_boolean);
+        p.register_prefix_fns(token::FALSE, Parser::parse_boolean);
+        p.register_prefix_fns(token::NULL, Parser::parse_null_literal);
+        p.register_prefix_fns(token::BANG, Parser::parse_prefix_expression);
+        p.register_prefix_fns(token::MINUS, Parser::parse_prefix_expression);
+        p.register_prefix_fns(token::TILDE, Parser::parse_prefix_expression);
+        p.register_prefix_fns(token::LPAREN, Parser::parse_grouped_expression);
+        p.register_prefix_fns(token::IF, Parser::parse_if_expression);
+        p.register_prefix_fns(token::FUNCTION, Parser::parse_function_literal);
+        p.register_prefix_fns(token::STRING, Parser::parse_string_literal);
+        p.register_prefix_fns(token::LBRACKET, Parser::parse_array_literal);
+        p.register_prefix_fns(token::LBRACE, Parser::parse_hash_literal);
+
+        for (tt, prec) in PRECEDENCES.iter() {
+            if *prec < CALL {
+                p.register_infix_fns(tt, Parser::parse_infix_expression);
+            }
+        }
+        p.register_infix_fns(token::LPAREN, Parser::parse_call_expression);
+        p.register_infix_fns(token::LBRACKET, Parser::parse_index_expression);
 
         p
     }
 
+    // Caps how deeply expressions may nest, so hostile input can't exhaust the native stack.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn errors(&self) -> &Vec<String> {
+        &self.errors
+    }
+
+    pub fn parse_program(&mut self) -> ast::Program {
+        let mut statements = Vec::new();
+
+        while !self.cur_token_is(token::EOF) {
+            if let Some(stmt) = self.parse_statement() {
+                statements.push(stmt);
+            }
+            self.next_token();
+        }
+
+        ast::Program { statements }
+    }
+
     fn next_token(&mut self) {
-        self.cur_token = self.peek_token.clone();
-        self.peek_token = Box::new(self.l.next_token());
+        self.cur_token = std::mem::replace(&mut self.peek_token, self.l.next_token());
     }
 
     fn register_prefix_fns(&mut self, tok: token::TokenType, f: PrefixParseFn) {
@@ -81,54 +164,76 @@ impl Parser {
 
     fn parse_identifier(&mut self) -> Option<Box<dyn ast::Expression>> {
         Some(Box::new(ast::Identifier {
-            token: *self.cur_token.clone(),
+            token: self.cur_token.clone(),
             value: self.cur_token.literal.clone(),
+            binding: OnceCell::new(),
         }))
     }
 
     fn parse_integer_literal(&mut self) -> Option<Box<dyn ast::Expression>> {
-        Some(Box::new(ast::IntegerLiteral {
-            token: *self.cur_token.clone(),
-            value: self
-                .cur_token
-                .literal
-                .parse::<i64>()
-                .expect("Integer literal was not an integer"),
-        }))
+        let literal = self.cur_token.literal.as_str();
+
+        if let Ok(value) = literal.parse::<i64>() {
+            Some(Box::new(ast::IntegerLiteral {
+                token: self.cur_token.clone(),
+                value,
+            }))
+        } else if let Some(value) = BigInt::parse(literal) {
+            Some(Box::new(ast::BigIntegerLiteral {
+                token: self.cur_token.clone(),
+                value: Rc::new(value),
+            }))
+        } else {
+            let msg = format!("could not parse {} as integer", literal);
+            self.new_error(msg.as_str());
+            None
+        }
     }
 
     fn parse_string_literal(&mut self) -> Option<Box<dyn ast::Expression>> {
         Some(Box::new(ast::StringLiteral {
-            token: *self.cur_token.clone(),
+            token: self.cur_token.clone(),
             value: self.cur_token.literal.clone(),
         }))
     }
 
-    fn parse_array_literal(&mut self) -> Option<Box<dyn ast::Expression>> {
-        Some(Box::new(ast::ArrayLiteral {
-            token: *self.cur_token.clone(),
-            elements: self
-                .parse_expression_list(token::RBRACKET)
-                .expect("Unable to parse expression list"),
+    fn parse_boolean(&mut self) -> Option<Box<dyn ast::Expression>> {
+        Some(Box::new(ast::Boolean {
+            token: self.cur_token.clone(),
+            value: self.cur_token_is(token::TRUE),
+        }))
+    }
+
+    fn parse_null_literal(&mut self) -> Option<Box<dyn ast::Expression>> {
+        Some(Box::new(ast::NullLiteral {
+            token: self.cur_token.clone(),
         }))
     }
 
+    fn parse_array_literal(&mut self) -> Option<Box<dyn ast::Expression>> {
+        let token = self.cur_token.clone();
+        let elements = self.parse_expression_list(token::RBRACKET)?;
+
+        Some(Box::new(ast::ArrayLiteral { token, elements }))
+    }
+
     fn parse_function_literal(&mut self) -> Option<Box<dyn ast::Expression>> {
-        let token = *self.cur_token.clone();
+        let token = self.cur_token.clone();
 
         if !self.expect_peek(token::LPAREN) {
             return None;
         }
 
-        let parameters = self
-            .parse_function_parameters()
-            .expect("Unable to parse function parameters");
+        let parameters = self.parse_function_parameters()?;
 
         if !self.expect_peek(token::LBRACE) {
             return None;
         }
 
-        let body = self.parse_block_statement();
+        // A function body starts a fresh loop context: `break` can't escape a call.
+        let loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+        let body = Rc::new(self.parse_block_statement());
+        self.loop_depth = loop_depth;
 
         Some(Box::new(ast::FunctionLiteral {
             token,
@@ -137,47 +242,216 @@ impl Parser {
         }))
     }
 
-    // TODO: Need to figure out how to impl PartialEq and Eq to make this work
-    // fn parse_hash_literal(&mut self) -> Option<Box<dyn ast::Expression>> {
-    //     let token = *self.cur_token.clone();
-    //
-    //     let mut pairs: HashMap<
-    //         Box<dyn ast::Expression + 'static>,
-    //         Box<dyn ast::Expression + 'static>,
-    //     > = HashMap::new();
-    //
-    //     while !self.peek_token_is(token::RBRACE) {
-    //         self.next_token();
-    //
-    //         let key = self
-    //             .parse_expression(LOWEST)
-    //             .expect("Unable to parse Hash key");
-    //
-    //         if !self.expect_peek(token::COLON) {
-    //             return None;
-    //         }
-    //
-    //         self.next_token();
-    //         let value = self
-    //             .parse_expression(LOWEST)
-    //             .expect("Unable to parse Hash value");
-    //
-    //         pairs.insert(key, value);
-    //     }
-    //
-    //     None
-    // }
+    fn parse_prefix_expression(&mut self) -> Option<Box<dyn ast::Expression>> {
+        let token = self.cur_token.clone();
+        let operator = self.cur_token.literal.clone();
+
+        self.next_token();
+
+        let right = self.parse_expression(PREFIX)?;
+
+        Some(Box::new(ast::PrefixExpression {
+            token,
+            operator,
+            right,
+        }))
+    }
+
+    fn parse_infix_expression(
+        &mut self,
+        left: Box<dyn ast::Expression>,
+    ) -> Option<Box<dyn ast::Expression>> {
+        let token = self.cur_token.clone();
+        let operator = self.cur_token.literal.clone();
+
+        // `**` is right-associative, so its right operand binds one level looser.
+        let mut prec = self.cur_precedence();
+        if self.cur_token_is(token::POWER) {
+            prec -= 1;
+        }
+
+        self.next_token();
+
+        let right = self.parse_expression(prec)?;
+
+        Some(Box::new(ast::InfixExpression {
+            token,
+            operator,
+            left,
+            right,
+        }))
+    }
+
+    fn parse_grouped_expression(&mut self) -> Option<Box<dyn ast::Expression>> {
+        self.next_token();
+
+        let exp = self.parse_expression(LOWEST)?;
+
+        if !self.expect_peek(token::RPAREN) {
+            return None;
+        }
+
+        Some(exp)
+    }
+
+    fn parse_if_expression(&mut self) -> Option<Box<dyn ast::Expression>> {
+        Some(Box::new(self.parse_if()?))
+    }
+
+    fn parse_if(&mut self) -> Option<ast::IfExpression> {
+        let token = self.cur_token.clone();
+
+        if !self.expect_peek(token::LPAREN) {
+            return None;
+        }
+
+        self.next_token();
+        let condition = self.parse_expression(LOWEST)?;
+
+        if !self.expect_peek(token::RPAREN) {
+            return None;
+        }
+
+        if !self.expect_peek(token::LBRACE) {
+            return None;
+        }
+
+        let consequence = Box::new(self.parse_block_statement());
+
+        let mut alternative = None;
+        if self.peek_token_is(token::ELSE) {
+            self.next_token();
+
+            if self.peek_token_is(token::IF) {
+                self.next_token();
+                alternative = Some(ast::Alternative::If(Box::new(self.parse_if()?)));
+            } else {
+                if !self.expect_peek(token::LBRACE) {
+                    return None;
+                }
+
+                alternative = Some(ast::Alternative::Block(Box::new(
+                    self.parse_block_statement(),
+                )));
+            }
+        }
+
+        Some(ast::IfExpression {
+            token,
+            condition,
+            consequence,
+            alternative,
+        })
+    }
+
+    fn parse_call_expression(
+        &mut self,
+        function: Box<dyn ast::Expression>,
+    ) -> Option<Box<dyn ast::Expression>> {
+        let token = self.cur_token.clone();
+        let arguments = self.parse_expression_list(token::RPAREN)?;
+
+        Some(Box::new(ast::CallExpression {
+            token,
+            function,
+            arguments,
+        }))
+    }
+
+    fn parse_index_expression(
+        &mut self,
+        left: Box<dyn ast::Expression>,
+    ) -> Option<Box<dyn ast::Expression>> {
+        let token = self.cur_token.clone();
+
+        let mut start = None;
+        if !self.peek_token_is(token::COLON) {
+            self.next_token();
+            start = Some(self.parse_expression(LOWEST)?);
+        }
+
+        if self.peek_token_is(token::COLON) {
+            self.next_token();
+
+            let mut end = None;
+            if !self.peek_token_is(token::RBRACKET) && !self.peek_token_is(token::COLON) {
+                self.next_token();
+                end = Some(self.parse_expression(LOWEST)?);
+            }
+
+            let mut step = None;
+            if self.peek_token_is(token::COLON) {
+                self.next_token();
+
+                if !self.peek_token_is(token::RBRACKET) {
+                    self.next_token();
+                    step = Some(self.parse_expression(LOWEST)?);
+                }
+            }
+
+            if !self.expect_peek(token::RBRACKET) {
+                return None;
+            }
+
+            return Some(Box::new(ast::SliceExpression {
+                token,
+                left,
+                start,
+                end,
+                step,
+            }));
+        }
+
+        if !self.expect_peek(token::RBRACKET) {
+            return None;
+        }
+
+        let index = start?;
+
+        Some(Box::new(ast::IndexExpression { token, left, index }))
+    }
+
+    fn parse_hash_literal(&mut self) -> Option<Box<dyn ast::Expression>> {
+        let token = self.cur_token.clone();
+
+        let mut pairs = Vec::new();
+
+        while !self.peek_token_is(token::RBRACE) {
+            self.next_token();
+            let key = self.parse_expression(LOWEST)?;
+
+            if !self.expect_peek(token::COLON) {
+                return None;
+            }
+
+            self.next_token();
+            let value = self.parse_expression(LOWEST)?;
+
+            pairs.push((key, value));
+
+            if !self.peek_token_is(token::RBRACE) && !self.expect_peek(token::COMMA) {
+                return None;
+            }
+        }
+
+        if !self.expect_peek(token::RBRACE) {
+            return None;
+        }
+
+        Some(Box::new(ast::HashLiteral { token, pairs }))
+    }
 
     fn parse_let_statement(&mut self) -> Option<Box<dyn ast::Statement>> {
-        let token = *self.cur_token.clone();
+        let token = self.cur_token.clone();
 
         if !self.expect_peek(token::IDENT) {
             return None;
         }
 
         let name = Box::new(ast::Identifier {
-            token,
+            token: self.cur_token.clone(),
             value: self.cur_token.literal.clone(),
+            binding: OnceCell::new(),
         });
 
         if !self.expect_peek(token::ASSIGN) {
@@ -186,29 +460,21 @@ impl Parser {
 
         self.next_token();
 
-        let value = self
-            .parse_expression(LOWEST)
-            .expect("Unable to parse expression");
+        let value = self.parse_expression(LOWEST)?;
 
         if self.peek_token_is(token::SEMICOLON) {
             self.next_token();
         }
 
-        Some(Box::new(ast::LetStatement {
-            token: *self.cur_token.clone(),
-            name,
-            value,
-        }))
+        Some(Box::new(ast::LetStatement { token, name, value }))
     }
 
     fn parse_return_statement(&mut self) -> Option<Box<dyn ast::Statement>> {
-        let token = *self.cur_token.clone();
+        let token = self.cur_token.clone();
 
         self.next_token();
 
-        let value = self
-            .parse_expression(LOWEST)
-            .expect("Unable to parse expression");
+        let value = self.parse_expression(LOWEST)?;
 
         if self.peek_token_is(token::SEMICOLON) {
             self.next_token();
@@ -217,73 +483,278 @@ impl Parser {
         Some(Box::new(ast::ReturnStatement { token, value }))
     }
 
+    fn parse_assign_statement(
+        &mut self,
+        target: Box<dyn ast::Expression>,
+    ) -> Option<Box<dyn ast::Statement>> {
+        let any = target.as_any();
+        if !any.is::<ast::Identifier>() && !any.is::<ast::IndexExpression>() {
+            let msg = format!("cannot assign to {}", target.string());
+            self.new_error(msg.as_str());
+            return None;
+        }
+
+        self.next_token();
+        let token = self.cur_token.clone();
+        let operator = self.cur_token.literal.clone();
+
+        self.next_token();
+        let value = self.parse_expression(LOWEST)?;
+
+        if self.peek_token_is(token::SEMICOLON) {
+            self.next_token();
+        }
+
+        Some(Box::new(ast::AssignStatement {
+            token,
+            target,
+            operator,
+            value,
+        }))
+    }
+
+    fn parse_while_statement(&mut self) -> Option<Box<dyn ast::Statement>> {
+        let token = self.cur_token.clone();
+
+        if !self.expect_peek(token::LPAREN) {
+            return None;
+        }
+
+        self.next_token();
+        let condition = self.parse_expression(LOWEST)?;
+
+        if !self.expect_peek(token::RPAREN) {
+            return None;
+        }
+
+        if !self.expect_peek(token::LBRACE) {
+            return None;
+        }
+
+        let body = Box::new(self.parse_loop_body());
+
+        Some(Box::new(ast::WhileStatement {
+            token,
+            condition,
+            body,
+        }))
+    }
+
+    fn parse_for_statement(&mut self) -> Option<Box<dyn ast::Statement>> {
+        let token = self.cur_token.clone();
+
+        if !self.expect_peek(token::LPAREN) {
+            return None;
+        }
+
+        if !self.expect_peek(token::IDENT) {
+            return None;
+        }
+
+        let variable = ast::Identifier {
+            token: self.cur_token.clone(),
+            value: self.cur_token.literal.clone(),
+            binding: OnceCell::new(),
+        };
+
+        if !self.expect_peek(token::IN) {
+            return None;
+        }
+
+        self.next_token();
+        let iterable = self.parse_expression(LOWEST)?;
+
+        if !self.expect_peek(token::RPAREN) {
+            return None;
+        }
+
+        if !self.expect_peek(token::LBRACE) {
+            return None;
+        }
+
+        let body = Box::new(self.parse_loop_body());
+
+        Some(Box::new(ast::ForStatement {
+            token,
+            variable,
+            iterable,
+            body,
+        }))
+    }
+
+    fn parse_try_statement(&mut self) -> Option<Box<dyn ast::Statement>> {
+        let token = self.cur_token.clone();
+
+        if !self.expect_peek(token::LBRACE) {
+            return None;
+        }
+
+        let body = Box::new(self.parse_block_statement());
+
+        if !self.expect_peek(token::CATCH) {
+            return None;
+        }
+
+        if !self.expect_peek(token::LPAREN) {
+            return None;
+        }
+
+        if !self.expect_peek(token::IDENT) {
+            return None;
+        }
+
+        let parameter = ast::Identifier {
+            token: self.cur_token.clone(),
+            value: self.cur_token.literal.clone(),
+            binding: OnceCell::new(),
+        };
+
+        if !self.expect_peek(token::RPAREN) {
+            return None;
+        }
+
+        if !self.expect_peek(token::LBRACE) {
+            return None;
+        }
+
+        let handler = Box::new(self.parse_block_statement());
+
+        Some(Box::new(ast::TryStatement {
+            token,
+            body,
+            parameter,
+            handler,
+        }))
+    }
+
+    fn parse_throw_statement(&mut self) -> Option<Box<dyn ast::Statement>> {
+        let token = self.cur_token.clone();
+
+        self.next_token();
+
+        let value = self.parse_expression(LOWEST)?;
+
+        if self.peek_token_is(token::SEMICOLON) {
+            self.next_token();
+        }
+
+        Some(Box::new(ast::ThrowStatement { token, value }))
+    }
+
+    fn parse_loop_body(&mut self) -> ast::BlockStatement {
+        self.loop_depth += 1;
+        let body = self.parse_block_statement();
+        self.loop_depth -= 1;
+
+        body
+    }
+
+    fn parse_loop_control_statement(&mut self) -> Option<Box<dyn ast::Statement>> {
+        let token = self.cur_token.clone();
+
+        if self.peek_token_is(token::SEMICOLON) {
+            self.next_token();
+        }
+
+        // The statement is still consumed whole, so the error is the only one it causes.
+        if self.loop_depth == 0 {
+            let msg = format!("{} outside of loop", token.literal);
+            self.new_error(msg.as_str());
+            return None;
+        }
+
+        if token.token_type == token::BREAK {
+            Some(Box::new(ast::BreakStatement { token }))
+        } else {
+            Some(Box::new(ast::ContinueStatement { token }))
+        }
+    }
+
     fn parse_expression_statement(&mut self) -> Option<Box<dyn ast::Statement>> {
-        let expression = self
-            .parse_expression(LOWEST)
-            .expect("Unable to parse expression");
+        let token = self.cur_token.clone();
 
-        let stmt: Option<Box<dyn ast::Statement>> = Some(Box::new(ast::ExpressionStatement {
-            token: *self.cur_token.clone(),
-            expression,
-        }));
+        let expression = self.parse_expression(LOWEST)?;
+
+        if ASSIGNMENT_OPERATORS.contains(&self.peek_token.token_type) {
+            return self.parse_assign_statement(expression);
+        }
 
         if self.peek_token_is(token::SEMICOLON) {
             self.next_token();
         }
 
-        stmt
+        Some(Box::new(ast::ExpressionStatement { token, expression }))
+    }
+
+    // Expressions and blocks are where parsing recurses, so both count towards the depth limit.
+    fn enter_nesting(&mut self) -> bool {
+        if self.depth >= self.max_depth {
+            let msg = format!("nesting exceeds limit of {}", self.max_depth);
+            self.new_error(msg.as_str());
+
+            // Give up on the rest of the input rather than report a cascade of follow-on errors.
+            self.too_deep = true;
+            while !self.cur_token_is(token::EOF) {
+                self.next_token();
+            }
+            return false;
+        }
+
+        self.depth += 1;
+        true
     }
 
     fn parse_expression(&mut self, prec: u8) -> Option<Box<dyn ast::Expression>> {
-        let prefix = self.prefix_parse_fns.get(self.cur_token.token_type);
-
-        match prefix {
-            Some(prefix) => {
-                let mut left_exp = prefix(self);
-                let mut left: Box<dyn ast::Expression>;
-
-                match left_exp {
-                    Some(l) => left = l,
-                    None => {
-                        return None;
-                    }
-                }
+        let depth = self.depth;
+        if !self.enter_nesting() {
+            return None;
+        }
 
-                while !self.peek_token_is(token::SEMICOLON) && prec < self.peek_precedence() {
-                    let infix_fns = self.infix_parse_fns.clone();
-                    let infix = infix_fns.get(self.peek_token.token_type);
-
-                    match infix {
-                        Some(infix) => {
-                            self.next_token();
-                            left_exp = infix(self, &left);
-                            match left_exp {
-                                Some(l) => left = l,
-                                None => {
-                                    self.new_error("Unable to parse using infix operator");
-                                }
-                            }
-                        }
-                        None => {
-                            return Some(left);
-                        }
-                    }
-                }
+        let expression = self.parse_expression_with_prefix(prec);
+        self.depth = depth;
 
-                return Some(left);
-            }
+        expression
+    }
+
+    fn parse_expression_with_prefix(&mut self, prec: u8) -> Option<Box<dyn ast::Expression>> {
+        let prefix = match self.prefix_parse_fns.get(self.cur_token.token_type) {
+            Some(prefix) => *prefix,
             None => {
                 self.no_prefix_parse_fn_error(self.cur_token.token_type);
                 return None;
             }
+        };
+
+        let mut left = prefix(self)?;
+
+        while !self.peek_token_is(token::SEMICOLON) && prec < self.peek_precedence() {
+            let infix = match self.infix_parse_fns.get(self.peek_token.token_type) {
+                Some(infix) => *infix,
+                None => return Some(left),
+            };
+
+            // Each link of a chain like `a + b + c` nests the expression so far one level deeper.
+            if !self.enter_nesting() {
+                return None;
+            }
+
+            self.next_token();
+            left = infix(self, left)?;
         }
+
+        Some(left)
     }
 
     fn parse_statement(&mut self) -> Option<Box<dyn ast::Statement>> {
         match self.cur_token.token_type {
-            token::LET => return self.parse_let_statement(),
-            token::RETURN => return self.parse_return_statement(),
-            _ => return self.parse_expression_statement(),
+            token::LET => self.parse_let_statement(),
+            token::RETURN => self.parse_return_statement(),
+            token::WHILE => self.parse_while_statement(),
+            token::FOR => self.parse_for_statement(),
+            token::BREAK | token::CONTINUE => self.parse_loop_control_statement(),
+            token::TRY => self.parse_try_statement(),
+            token::THROW => self.parse_throw_statement(),
+            _ => self.parse_expression_statement(),
         }
     }
 
@@ -299,49 +770,52 @@ impl Parser {
         }
 
         self.next_token();
-        list.push(
-            self.parse_expression(LOWEST)
-                .expect("Unable to parse expression"),
-        );
+        list.push(self.parse_expression(LOWEST)?);
 
         while self.peek_token_is(token::COMMA) {
             self.next_token();
             self.next_token();
-            list.push(
-                self.parse_expression(LOWEST)
-                    .expect("Unable to parse expression"),
-            );
+            list.push(self.parse_expression(LOWEST)?);
         }
 
         if !self.expect_peek(end) {
-            self.new_error(format!("Did not find {} at end of expression list", end).as_str());
             return None;
         }
+
         Some(list)
     }
 
-    fn parse_block_statement(&mut self) -> Box<ast::BlockStatement> {
+    fn parse_block_statement(&mut self) -> ast::BlockStatement {
         let mut stmts = Vec::new();
 
-        let token = *self.cur_token.clone();
+        let token = self.cur_token.clone();
+
+        if !self.enter_nesting() {
+            return ast::BlockStatement {
+                token,
+                statements: stmts,
+            };
+        }
+
+        self.next_token();
 
         while !self.cur_token_is(token::RBRACE) && !self.cur_token_is(token::EOF) {
-            let stmt = self.parse_statement();
-            match stmt {
-                Some(stmt) => stmts.push(stmt),
-                None => (),
+            if let Some(stmt) = self.parse_statement() {
+                stmts.push(stmt);
             }
 
             self.next_token();
         }
 
-        Box::new(ast::BlockStatement {
+        self.depth -= 1;
+
+        ast::BlockStatement {
             token,
             statements: stmts,
-        })
+        }
     }
 
-    fn parse_function_parameters(&mut self) -> Option<Vec<Box<ast::Identifier>>> {
+    fn parse_function_parameters(&mut self) -> Option<Vec<ast::Identifier>> {
         let mut idents = Vec::new();
 
         if self.peek_token_is(token::RPAREN) {
@@ -349,24 +823,28 @@ impl Parser {
             return Some(idents);
         }
 
-        self.next_token();
+        if !self.expect_peek(token::IDENT) {
+            return None;
+        }
 
-        let ident = Box::new(ast::Identifier {
-            token: *self.cur_token.clone(),
+        idents.push(ast::Identifier {
+            token: self.cur_token.clone(),
             value: self.cur_token.literal.clone(),
+            binding: OnceCell::new(),
         });
 
-        idents.push(ident);
-
         while self.peek_token_is(token::COMMA) {
             self.next_token();
-            self.next_token();
 
-            let ident = Box::new(ast::Identifier {
-                token: *self.cur_token.clone(),
+            if !self.expect_peek(token::IDENT) {
+                return None;
+            }
+
+            idents.push(ast::Identifier {
+                token: self.cur_token.clone(),
                 value: self.cur_token.literal.clone(),
+                binding: OnceCell::new(),
             });
-            idents.push(ident);
         }
 
         if !self.expect_peek(token::RPAREN) {
@@ -387,37 +865,46 @@ impl Parser {
     fn expect_peek(&mut self, tt: token::TokenType) -> bool {
         if self.peek_token_is(tt) {
             self.next_token();
-            return true;
+            true
         } else {
             self.peek_error(tt);
-            return false;
+            false
         }
     }
 
-    fn peek_precedence(&self) -> u8 {
+    fn precedence_of(tt: token::TokenType) -> u8 {
         for prec in PRECEDENCES.iter() {
-            if prec.0 == self.peek_token.token_type {
+            if prec.0 == tt {
                 return prec.1;
             }
         }
-        return LOWEST;
+        LOWEST
+    }
+
+    fn peek_precedence(&self) -> u8 {
+        Parser::precedence_of(self.peek_token.token_type)
+    }
+
+    fn cur_precedence(&self) -> u8 {
+        Parser::precedence_of(self.cur_token.token_type)
     }
 
     fn new_error(&mut self, msg: &str) {
-        self.errors.push(String::from(msg));
+        if !self.too_deep {
+            self.errors.push(String::from(msg));
+        }
     }
 
     fn no_prefix_parse_fn_error(&mut self, tt: token::TokenType) {
-        self.errors.push(String::from(format!(
-            "no prefix parse function for {} found",
-            tt
-        )));
+        let msg = format!("no prefix parse function for {} found", tt);
+        self.new_error(msg.as_str());
     }
 
     fn peek_error(&mut self, tt: token::TokenType) {
-        self.errors.push(String::from(format!(
+        let msg = format!(
             "Expected token to be {}, got {} instead",
             tt, self.peek_token.token_type
-        )));
+        );
+        self.new_error(msg.as_str());
     }
 }
@@ -0,0 +1,351 @@
+use super::*;
+use crate::ast::Statement;
+
+fn parse(input: &str) -> ast::Program {
+    let l = lexer::new(String::from(input));
+    let mut p = Parser::new(l);
+    let program = p.parse_program();
+    check_parser_errors(&p);
+
+    program
+}
+
+fn check_parser_errors(p: &Parser) {
+    if p.errors().is_empty() {
+        return;
+    }
+
+    for msg in p.errors().iter() {
+        println!("parser error: {}", msg);
+    }
+    panic!("parser has {} errors", p.errors().len());
+}
+
+#[test]
+fn test_let_statements() {
+    let program = parse("let x = 5; let y = true; let foobar = y;");
+
+    let expected = ["let x = 5;", "let y = true;", "let foobar = y;"];
+
+    assert_eq!(program.statements.len(), expected.len());
+    for (stmt, expected) in program.statements.iter().zip(expected.iter()) {
+        assert_eq!(stmt.token_literal(), "let");
+        assert_eq!(stmt.string(), *expected);
+    }
+}
+
+#[test]
+fn test_return_statements() {
+    let program = parse("return 5; return 10; return add(15);");
+
+    assert_eq!(program.statements.len(), 3);
+    for stmt in program.statements.iter() {
+        assert_eq!(stmt.token_literal(), "return");
+    }
+}
+
+#[test]
+fn test_operator_precedence_parsing() {
+    let tests = vec![
+        ("-a * b", "((-a) * b)"),
+        ("!-a", "(!(-a))"),
+        ("a + b - c", "((a + b) - c)"),
+        ("a * b / c", "((a * b) / c)"),
+        ("a + b * c + d / e - f", "(((a + (b * c)) + (d / e)) - f)"),
+        ("5 > 4 == 3 < 4", "((5 > 4) == (3 < 4))"),
+        (
+            "3 + 4 * 5 == 3 * 1 + 4 * 5",
+            "((3 + (4 * 5)) == ((3 * 1) + (4 * 5)))",
+        ),
+        ("true != !false", "(true != (!false))"),
+        ("1 + (2 + 3) + 4", "((1 + (2 + 3)) + 4)"),
+        ("-(5 + 5)", "(-(5 + 5))"),
+        ("a + add(b * c) + d", "((a + add((b * c))) + d)"),
+        (
+            "add(a, b, 1, 2 * 3, 4 + 5, add(6, 7 * 8))",
+            "add(a, b, 1, (2 * 3), (4 + 5), add(6, (7 * 8)))",
+        ),
+        ("a % b * c", "((a % b) * c)"),
+        ("a + b % c", "(a + (b % c))"),
+        ("2 ** 3 ** 2", "(2 ** (3 ** 2))"),
+        ("-2 ** 2", "(-(2 ** 2))"),
+        ("a * b ** c", "(a * (b ** c))"),
+        ("a | b ^ c & d", "(a | (b ^ (c & d)))"),
+        ("a & b == c", "((a & b) == c)"),
+        ("1 << 2 + 3", "(1 << (2 + 3))"),
+        ("a >> b << c", "((a >> b) << c)"),
+        ("a & b < c | d", "((a & b) < (c | d))"),
+        ("~a & b", "((~a) & b)"),
+    ];
+
+    for (input, expected) in tests.iter() {
+        let program = parse(input);
+        assert_eq!(
+            program.string(),
+            format!("{}\n", expected),
+            "input: {}",
+            input
+        );
+    }
+}
+
+#[test]
+fn test_if_expression() {
+    let program = parse("if (x < y) { x } else { y }");
+
+    assert_eq!(program.statements.len(), 1);
+    assert_eq!(program.string(), "if (x < y) xelse y\n");
+}
+
+#[test]
+fn test_function_literal_parsing() {
+    let program = parse("fn(x, y) { x + y; }");
+
+    assert_eq!(program.string(), "fn(x, y) (x + y)\n");
+}
+
+#[test]
+fn test_parser_errors() {
+    let l = lexer::new(String::from("let = 5; let x 5;"));
+    let mut p = Parser::new(l);
+    p.parse_program();
+
+    assert!(!p.errors().is_empty());
+}
+
+#[test]
+fn test_loop_statements() {
+    let program = parse("while (x < 10) { break; } for (i in 0..n + 1) { continue; }");
+
+    assert_eq!(program.statements.len(), 2);
+    assert_eq!(
+        program.string(),
+        "while (x < 10) break;\nfor (i in (0 .. (n + 1))) continue;\n"
+    );
+}
+
+#[test]
+fn test_loop_control_outside_loop() {
+    let inputs = [
+        "break;",
+        "continue;",
+        "break",
+        "while (true) { fn() { break; } }",
+    ];
+
+    for input in inputs.iter() {
+        let l = lexer::new(String::from(*input));
+        let mut p = Parser::new(l);
+        p.parse_program();
+
+        assert_eq!(p.errors().len(), 1, "input: {}", input);
+        assert!(
+            p.errors()[0].ends_with("outside of loop"),
+            "input: {}",
+            input
+        );
+    }
+}
+
+#[test]
+fn test_assign_statements() {
+    let tests = [
+        ("x = 5;", "x = 5;"),
+        ("x += y * 2", "x += (y * 2);"),
+        ("x **= 2;", "x **= 2;"),
+        ("a[i + 1] = 3;", "(a[(i + 1)]) = 3;"),
+        ("m[0][1] <<= 2;", "((m[0])[1]) <<= 2;"),
+    ];
+
+    for (input, expected) in tests.iter() {
+        let program = parse(input);
+        assert_eq!(program.statements.len(), 1);
+        assert_eq!(
+            program.string(),
+            format!("{}\n", expected),
+            "input: {}",
+            input
+        );
+    }
+}
+
+#[test]
+fn test_invalid_assignment_target() {
+    let l = lexer::new(String::from("1 + 2 = 3;"));
+    let mut p = Parser::new(l);
+    p.parse_program();
+
+    assert_eq!(p.errors()[0], "cannot assign to (1 + 2)");
+}
+
+#[test]
+fn test_else_if_expression() {
+    let program = parse("if (a) { 1 } else if (b) { 2 } else if (c) { 3 } else { 4 }");
+
+    assert_eq!(program.statements.len(), 1);
+    assert_eq!(program.string(), "if a 1else if b 2else if c 3else 4\n");
+}
+
+#[test]
+fn test_null_literal() {
+    let program = parse("let x = null; x == null");
+
+    assert_eq!(program.string(), "let x = null;\n(x == null)\n");
+}
+
+#[test]
+fn test_index_and_slice_expressions() {
+    let tests = [
+        ("s[1]", "(s[1])"),
+        ("s[1 + 1:n]", "(s[(1 + 1):n])"),
+        ("s[:2]", "(s[:2])"),
+        ("s[2:]", "(s[2:])"),
+        ("s[:]", "(s[:])"),
+        ("a * s[0:1]", "(a * (s[0:1]))"),
+    ];
+
+    for (input, expected) in tests.iter() {
+        let program = parse(input);
+        assert_eq!(
+            program.string(),
+            format!("{}\n", expected),
+            "input: {}",
+            input
+        );
+    }
+}
+
+#[test]
+fn test_slice_step_expressions() {
+    let tests = [
+        ("a[::2]", "(a[::2])"),
+        ("a[1:5:-1]", "(a[1:5:(-1)])"),
+        ("a[:-1:]", "(a[:(-1)])"),
+        ("a[i::n * 2]", "(a[i::(n * 2)])"),
+    ];
+
+    for (input, expected) in tests.iter() {
+        let program = parse(input);
+        assert_eq!(
+            program.string(),
+            format!("{}\n", expected),
+            "input: {}",
+            input
+        );
+    }
+}
+
+#[test]
+fn test_hash_literals() {
+    let tests = [
+        ("{}", "{}"),
+        ("{\"a\": 1, 2: b}", "{\"a\": 1, 2: b}"),
+        ("{true: 1 + 2,}", "{true: (1 + 2)}"),
+        ("h[\"k\"] = {1: [2]}", "(h[\"k\"]) = {1: [2]};"),
+    ];
+
+    for (input, expected) in tests.iter() {
+        let program = parse(input);
+        assert_eq!(
+            program.string(),
+            format!("{}\n", expected),
+            "input: {}",
+            input
+        );
+    }
+}
+
+#[test]
+fn test_try_and_throw_statements() {
+    let tests = [
+        ("throw \"bad\";", "throw \"bad\";"),
+        (
+            "try { risky(); } catch (e) { puts(e); }",
+            "try risky() catch (e) puts(e)",
+        ),
+        (
+            "try { throw 1 + 2; } catch (err) { err }",
+            "try throw (1 + 2); catch (err) err",
+        ),
+    ];
+
+    for (input, expected) in tests.iter() {
+        let program = parse(input);
+        assert_eq!(
+            program.string(),
+            format!("{}\n", expected),
+            "input: {}",
+            input
+        );
+    }
+}
+
+#[test]
+fn test_try_statement_errors() {
+    let tests = [
+        ("try { 1 }", "Expected token to be CATCH, got EOF instead"),
+        (
+            "try { 1 } catch { 2 }",
+            "Expected token to be (, got { instead",
+        ),
+        (
+            "try { 1 } catch (1) { 2 }",
+            "Expected token to be IDENT, got INT instead",
+        ),
+    ];
+
+    for (input, expected) in tests.iter() {
+        let mut p = Parser::new(lexer::new(String::from(*input)));
+        p.parse_program();
+        assert_eq!(
+            p.errors().first().map(String::as_str),
+            Some(*expected),
+            "input: {}",
+            input
+        );
+    }
+}
+
+#[test]
+fn test_nesting_limit() {
+    let tests = [
+        format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000)),
+        format!("{}x", "-".repeat(100_000)),
+        format!("{}1{}", "[".repeat(100_000), "]".repeat(100_000)),
+        format!(
+            "{}{}",
+            "while (true) {".repeat(100_000),
+            "}".repeat(100_000)
+        ),
+        format!(
+            "let f = {}1{};",
+            "fn() {".repeat(100_000),
+            "}".repeat(100_000)
+        ),
+        // Chains nest to the left as they're parsed, however flat they look.
+        format!("x{}", " + x".repeat(100_000)),
+        format!("f{}", "()".repeat(100_000)),
+    ];
+
+    for input in tests.iter() {
+        let mut p = Parser::new(lexer::new(input.clone()));
+        p.parse_program();
+        assert_eq!(p.errors(), &["nesting exceeds limit of 256"]);
+    }
+}
+
+#[test]
+fn test_configurable_nesting_limit() {
+    let input = format!("{}1{}", "(".repeat(10), ")".repeat(10));
+
+    let mut p = Parser::new(lexer::new(input.clone()));
+    p.set_max_depth(10);
+    p.parse_program();
+    assert_eq!(p.errors(), &["nesting exceeds limit of 10"]);
+
+    let mut p = Parser::new(lexer::new(input));
+    p.set_max_depth(11);
+    let program = p.parse_program();
+    check_parser_errors(&p);
+    assert_eq!(program.string(), "1\n");
+}
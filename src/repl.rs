@@ -1,31 +1,74 @@
 use crate::ast;
+use crate::compiler::Compiler;
+use crate::evaluator::Evaluator;
 use crate::lexer;
-use crate::token;
+use crate::object::Object;
+use crate::object::environment::Environment;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::vm::VM;
 use std::io;
 use std::io::Write;
 
-pub fn start(reader: &mut io::Stdin) {
+pub fn start(reader: &mut io::Stdin, mut evaluator: Evaluator) {
+    let env = Environment::new();
+
+    read_eval_print(reader, |program| Ok(evaluator.eval_program(program, &env)));
+}
+
+// Compiles each line on top of the ones before, so definitions carry over between them.
+pub fn start_vm(reader: &mut io::Stdin, mut vm: VM) {
+    let mut compiler = Compiler::new();
+
+    read_eval_print(reader, |program| {
+        compiler.compile(program)?;
+        Ok(vm.run(&compiler.bytecode()))
+    });
+}
+
+fn read_eval_print(
+    reader: &mut io::Stdin,
+    mut eval: impl FnMut(&ast::Program) -> Result<Object, String>,
+) {
+    // Remembers the names earlier lines defined.
+    let mut resolver = Resolver::new();
+
     loop {
         print!(">> ");
         io::stdout().flush().unwrap();
 
         let mut buf = String::new();
-        reader
+        let read = reader
             .read_line(&mut buf)
             .expect("Couldn't read from reader");
+        if read == 0 {
+            break;
+        }
 
-        let mut l = lexer::new(buf);
+        let l = lexer::new(buf);
+        let mut p = Parser::new(l);
 
-        let mut out_buf = String::new();
+        let mut program = p.parse_program();
+        if !p.errors().is_empty() {
+            print_errors("parser errors:", p.errors());
+            continue;
+        }
+        if let Err(errors) = resolver.resolve_and_fold(&mut program, true) {
+            print_errors("resolver errors:", &errors);
+            continue;
+        }
 
-        loop {
-            let tok = l.next_token();
-            if tok.token_type == token::EOF {
-                break;
-            }
-            out_buf.push_str(format!("{} ", tok.literal).as_str());
+        match eval(&program) {
+            Ok(Object::Error(err)) => println!("{}", err.report()),
+            Ok(evaluated) => println!("{}", evaluated.inspect()),
+            Err(err) => println!("compile error: {}", err),
         }
+    }
+}
 
-        println!("{out_buf}");
+fn print_errors(heading: &str, errors: &[String]) {
+    println!("{}", heading);
+    for msg in errors.iter() {
+        println!("\t{msg}");
     }
 }
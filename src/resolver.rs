@@ -0,0 +1,270 @@
+#[cfg(test)]
+mod tests;
+
+use crate::ast::{self, Binding, Expression, Slot, Statement};
+use crate::object::builtins;
+use crate::optimizer;
+use crate::symbol::Symbol;
+use std::collections::{HashMap, HashSet};
+
+// Works out where every name in a program lives before it runs, so the evaluator can reach
+// locals by slot rather than searching scope after scope by name. Along the way it reports names
+// nothing defines and functions that repeat a parameter.
+//
+// Its scopes are the evaluator's: one per function call, per loop iteration and per `catch`,
+// holding the parameters, loop variable or caught value first and then every name a `let` binds
+// in it, `if` branches and plain blocks included. Top-level names stay in the global table and
+// are looked up by name, since the host and earlier REPL lines add to it too.
+#[derive(Default)]
+pub struct Resolver {
+    // Top-level names bound by programs resolved before, or by the host.
+    globals: HashSet<Symbol>,
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver::default()
+    }
+
+    // For names the host binds in the global scope itself.
+    pub fn define_global(&mut self, name: Symbol) {
+        self.globals.insert(name);
+    }
+
+    // Records a binding on every identifier. Its top-level names are only remembered for later
+    // programs if it resolves without errors.
+    pub fn resolve(&mut self, program: &ast::Program) -> Result<(), Vec<String>> {
+        let mut pass = Pass {
+            resolving: false,
+            scopes: Vec::new(),
+            stack: Vec::new(),
+            next_scope: 0,
+            globals: self.globals.clone(),
+            errors: Vec::new(),
+        };
+
+        // The first pass hands out slots, so the second can resolve a name used before the `let`
+        // that binds it, as mutually recursive local functions do.
+        pass.statements(&program.statements);
+        pass.resolving = true;
+        pass.next_scope = 0;
+        pass.statements(&program.statements);
+
+        if !pass.errors.is_empty() {
+            return Err(pass.errors);
+        }
+
+        self.globals = pass.globals;
+        Ok(())
+    }
+
+    // Resolves a freshly parsed program, then folds its constants if asked to. Resolving first
+    // keeps the diagnostics the same whether or not folding drops a branch; the identifiers
+    // folding leaves in place keep their bindings.
+    pub fn resolve_and_fold(
+        &mut self,
+        program: &mut ast::Program,
+        optimize: bool,
+    ) -> Result<(), Vec<String>> {
+        self.resolve(program)?;
+        if optimize {
+            optimizer::fold_constants(program);
+        }
+        Ok(())
+    }
+}
+
+struct Pass {
+    resolving: bool,
+    // The slot of each name in each scope, numbered in the order the scopes are entered.
+    scopes: Vec<HashMap<Symbol, usize>>,
+    // The scopes around the current point, innermost last. Empty at the top level.
+    stack: Vec<usize>,
+    next_scope: usize,
+    globals: HashSet<Symbol>,
+    errors: Vec<String>,
+}
+
+impl Pass {
+    fn statements(&mut self, statements: &[Box<dyn Statement>]) {
+        for stmt in statements.iter() {
+            self.statement(stmt.as_ref());
+        }
+    }
+
+    fn statement(&mut self, stmt: &dyn Statement) {
+        let any = stmt.as_any();
+
+        if let Some(ls) = any.downcast_ref::<ast::LetStatement>() {
+            self.expression(ls.value.as_ref());
+            self.declare(&ls.name);
+        } else if let Some(rs) = any.downcast_ref::<ast::ReturnStatement>() {
+            self.expression(rs.value.as_ref());
+        } else if let Some(es) = any.downcast_ref::<ast::ExpressionStatement>() {
+            self.expression(es.expression.as_ref());
+        } else if let Some(block) = any.downcast_ref::<ast::BlockStatement>() {
+            self.statements(&block.statements);
+        } else if let Some(stmt) = any.downcast_ref::<ast::AssignStatement>() {
+            self.expression(stmt.value.as_ref());
+            match stmt.target.as_any().downcast_ref::<ast::Identifier>() {
+                Some(ident) => self.reference(ident, "cannot assign to undeclared identifier"),
+                None => self.expression(stmt.target.as_ref()),
+            }
+        } else if let Some(ws) = any.downcast_ref::<ast::WhileStatement>() {
+            self.expression(ws.condition.as_ref());
+            self.enter_scope();
+            self.statements(&ws.body.statements);
+            self.stack.pop();
+        } else if let Some(fs) = any.downcast_ref::<ast::ForStatement>() {
+            self.expression(fs.iterable.as_ref());
+            self.enter_scope();
+            self.declare(&fs.variable);
+            self.statements(&fs.body.statements);
+            self.stack.pop();
+        } else if let Some(ts) = any.downcast_ref::<ast::ThrowStatement>() {
+            self.expression(ts.value.as_ref());
+        } else if let Some(ts) = any.downcast_ref::<ast::TryStatement>() {
+            self.statements(&ts.body.statements);
+            self.enter_scope();
+            self.declare(&ts.parameter);
+            self.statements(&ts.handler.statements);
+            self.stack.pop();
+        }
+    }
+
+    fn expression(&mut self, expr: &dyn Expression) {
+        let any = expr.as_any();
+
+        if let Some(ident) = any.downcast_ref::<ast::Identifier>() {
+            self.reference(ident, "identifier not found");
+        } else if let Some(array) = any.downcast_ref::<ast::ArrayLiteral>() {
+            for element in array.elements.iter() {
+                self.expression(element.as_ref());
+            }
+        } else if let Some(hash) = any.downcast_ref::<ast::HashLiteral>() {
+            for (key, value) in hash.pairs.iter() {
+                self.expression(key.as_ref());
+                self.expression(value.as_ref());
+            }
+        } else if let Some(func) = any.downcast_ref::<ast::FunctionLiteral>() {
+            self.enter_scope();
+            for param in func.parameters.iter() {
+                if !self.resolving && self.is_declared(&param.value) {
+                    self.error(param, "duplicate parameter");
+                }
+                self.declare(param);
+            }
+            self.statements(&func.body.statements);
+            self.stack.pop();
+        } else if let Some(call) = any.downcast_ref::<ast::CallExpression>() {
+            self.expression(call.function.as_ref());
+            for arg in call.arguments.iter() {
+                self.expression(arg.as_ref());
+            }
+        } else if let Some(ie) = any.downcast_ref::<ast::IndexExpression>() {
+            self.expression(ie.left.as_ref());
+            self.expression(ie.index.as_ref());
+        } else if let Some(se) = any.downcast_ref::<ast::SliceExpression>() {
+            self.expression(se.left.as_ref());
+            for bound in [&se.start, &se.end, &se.step].into_iter().flatten() {
+                self.expression(bound.as_ref());
+            }
+        } else if let Some(pe) = any.downcast_ref::<ast::PrefixExpression>() {
+            self.expression(pe.right.as_ref());
+        } else if let Some(ie) = any.downcast_ref::<ast::InfixExpression>() {
+            self.expression(ie.left.as_ref());
+            self.expression(ie.right.as_ref());
+        } else if let Some(ie) = any.downcast_ref::<ast::IfExpression>() {
+            self.expression(ie.condition.as_ref());
+            self.statements(&ie.consequence.statements);
+            match &ie.alternative {
+                Some(ast::Alternative::Block(block)) => self.statements(&block.statements),
+                Some(ast::Alternative::If(ie)) => self.expression(ie.as_ref()),
+                None => (),
+            }
+        }
+    }
+
+    fn enter_scope(&mut self) {
+        let scope = self.next_scope;
+        self.next_scope += 1;
+        if scope == self.scopes.len() {
+            self.scopes.push(HashMap::new());
+        }
+        self.stack.push(scope);
+    }
+
+    fn is_declared(&self, name: &Symbol) -> bool {
+        match self.stack.last() {
+            Some(scope) => self.scopes[*scope].contains_key(name),
+            None => self.globals.contains(name),
+        }
+    }
+
+    // Binding a name again in the same scope reuses its slot, as `let` rebinds in place.
+    fn declare(&mut self, ident: &ast::Identifier) {
+        let scope = self.stack.last().copied();
+
+        if !self.resolving {
+            match scope {
+                Some(scope) => {
+                    let slots = &mut self.scopes[scope];
+                    let next = slots.len();
+                    slots.entry(ident.value.clone()).or_insert(next);
+                }
+                None => {
+                    self.globals.insert(ident.value.clone());
+                }
+            }
+            return;
+        }
+
+        let binding = match scope {
+            Some(scope) => Binding::Local(vec![Slot {
+                depth: 0,
+                index: self.scopes[scope][&ident.value],
+            }]),
+            None => Binding::Global,
+        };
+        // Resolving the same program again finds the same bindings.
+        let _ = ident.binding.set(binding);
+    }
+
+    fn reference(&mut self, ident: &ast::Identifier, undefined: &str) {
+        if !self.resolving {
+            return;
+        }
+
+        // User bindings shadow builtins.
+        let name = &ident.value;
+        let slots: Vec<Slot> = self
+            .stack
+            .iter()
+            .rev()
+            .enumerate()
+            .filter_map(|(depth, scope)| {
+                let index = *self.scopes[*scope].get(name)?;
+                Some(Slot { depth, index })
+            })
+            .collect();
+
+        let builtin = builtins::BUILTINS.iter().position(|(k, _)| name == k);
+        let binding = if !slots.is_empty() {
+            Binding::Local(slots)
+        } else if self.globals.contains(name) {
+            Binding::Global
+        } else if let Some(index) = builtin {
+            Binding::Builtin(index)
+        } else {
+            self.error(ident, undefined);
+            Binding::Global
+        };
+
+        let _ = ident.binding.set(binding);
+    }
+
+    fn error(&mut self, ident: &ast::Identifier, msg: &str) {
+        self.errors
+            .push(format!("{}: {}: {}", ident.span(), msg, ident.value));
+    }
+}
@@ -0,0 +1,238 @@
+use super::*;
+use crate::evaluator::Evaluator;
+use crate::object::environment::Environment;
+use crate::test_helpers::parse;
+
+fn resolve(input: &str) -> (ast::Program, Result<(), Vec<String>>) {
+    let program = parse(input);
+    let result = Resolver::new().resolve(&program);
+    (program, result)
+}
+
+fn expression(stmt: &dyn Statement) -> &dyn Expression {
+    let any = stmt.as_any();
+    if let Some(es) = any.downcast_ref::<ast::ExpressionStatement>() {
+        es.expression.as_ref()
+    } else if let Some(ls) = any.downcast_ref::<ast::LetStatement>() {
+        ls.value.as_ref()
+    } else {
+        panic!("no expression in {}", stmt.string())
+    }
+}
+
+fn function(expr: &dyn Expression) -> &ast::FunctionLiteral {
+    expr.as_any()
+        .downcast_ref::<ast::FunctionLiteral>()
+        .unwrap_or_else(|| panic!("not a function: {}", expr.string()))
+}
+
+fn binding(expr: &dyn Expression) -> Binding {
+    let ident = expr
+        .as_any()
+        .downcast_ref::<ast::Identifier>()
+        .unwrap_or_else(|| panic!("not an identifier: {}", expr.string()));
+    ident.binding.get().cloned().expect("unresolved identifier")
+}
+
+fn local(slots: &[(usize, usize)]) -> Binding {
+    Binding::Local(
+        slots
+            .iter()
+            .map(|&(depth, index)| Slot { depth, index })
+            .collect(),
+    )
+}
+
+#[test]
+fn test_resolve_bindings() {
+    let (program, result) = resolve(
+        "let a = 1;
+         let f = fn(x, y) { let z = x; fn(w) { [a, x, z, w, len] } };",
+    );
+    assert_eq!(result, Ok(()));
+
+    let outer = function(expression(program.statements[1].as_ref()));
+    assert_eq!(outer.parameters[0].binding.get(), Some(&local(&[(0, 0)])));
+    assert_eq!(outer.parameters[1].binding.get(), Some(&local(&[(0, 1)])));
+
+    let inner = function(expression(outer.body.statements[1].as_ref()));
+    let array = expression(inner.body.statements[0].as_ref())
+        .as_any()
+        .downcast_ref::<ast::ArrayLiteral>()
+        .unwrap();
+    let bindings: Vec<Binding> = array.elements.iter().map(|e| binding(e.as_ref())).collect();
+
+    let len = builtins::BUILTINS.iter().position(|(k, _)| *k == "len");
+    assert_eq!(
+        bindings,
+        vec![
+            Binding::Global,
+            local(&[(1, 0)]),
+            local(&[(1, 2)]),
+            local(&[(0, 0)]),
+            Binding::Builtin(len.unwrap()),
+        ]
+    );
+}
+
+#[test]
+fn test_resolve_shadowed_names() {
+    // Until the inner `let` runs, `x` is still the outer one, so both slots are candidates.
+    let (program, result) = resolve("let f = fn(x) { fn() { let y = x; let x = 2; y } };");
+    assert_eq!(result, Ok(()));
+
+    let outer = function(expression(program.statements[0].as_ref()));
+    let inner = function(expression(outer.body.statements[0].as_ref()));
+    let x = expression(inner.body.statements[0].as_ref());
+    assert_eq!(binding(x), local(&[(0, 1), (1, 0)]));
+}
+
+#[test]
+fn test_resolve_errors() {
+    let tests = [
+        ("x", vec!["1:1: identifier not found: x"]),
+        (
+            "let f = fn() { y = 1; }",
+            vec!["1:16: cannot assign to undeclared identifier: y"],
+        ),
+        (
+            "let f = fn(a, b, a) { a }",
+            vec!["1:18: duplicate parameter: a"],
+        ),
+        (
+            "for (i in 0..3) { i } i",
+            vec!["1:23: identifier not found: i"],
+        ),
+        (
+            "try { 1 } catch (e) { e } e + q",
+            vec![
+                "1:27: identifier not found: e",
+                "1:31: identifier not found: q",
+            ],
+        ),
+    ];
+
+    for (input, expected) in tests.iter() {
+        let (_, result) = resolve(input);
+        assert_eq!(
+            result,
+            Err(expected.iter().map(|e| e.to_string()).collect())
+        );
+    }
+}
+
+#[test]
+fn test_resolve_forward_references() {
+    let tests = [
+        "let f = fn() { g() }; let g = fn() { 1 };",
+        "let f = fn() { let even = fn(n) { odd(n) }; let odd = fn(n) { even(n) }; even };",
+        "let f = fn(c) { if (c) { let x = 1; } x };",
+    ];
+
+    for input in tests.iter() {
+        let (_, result) = resolve(input);
+        assert_eq!(result, Ok(()), "input: {}", input);
+    }
+}
+
+#[test]
+fn test_resolver_remembers_globals() {
+    let mut resolver = Resolver::new();
+
+    assert!(resolver.resolve(&parse("let a = b;")).is_err());
+    assert!(resolver.resolve(&parse("a")).is_err());
+    assert_eq!(resolver.resolve(&parse("let a = 1;")), Ok(()));
+    assert_eq!(resolver.resolve(&parse("a + 1")), Ok(()));
+
+    resolver.define_global(Symbol::intern("host"));
+    assert_eq!(resolver.resolve(&parse("host")), Ok(()));
+}
+
+#[test]
+fn test_resolved_programs_run_the_same() {
+    let tests = [
+        (
+            "let x = 1; let f = fn() { let y = x; let x = 2; [y, x] }; f()",
+            "[1, 2]",
+        ),
+        (
+            "let f = fn(c) { let x = 1; if (c) { let x = 2; } x }; [f(true), f(false)]",
+            "[2, 1]",
+        ),
+        (
+            "let f = fn() { let n = 0; let g = fn() { n += 1; n }; g(); g() }; f()",
+            "2",
+        ),
+        (
+            "let f = fn() { let t = 0; for (i in 0..4) { let d = i * 2; t += d; } t }; f()",
+            "12",
+        ),
+        (
+            "let f = fn() { try { throw 5 } catch (e) { e * 2 } }; f()",
+            "10",
+        ),
+        (
+            "let f = fn() { let g = fn() { h() }; let h = fn() { 7 }; g() }; f()",
+            "7",
+        ),
+    ];
+
+    for (input, expected) in tests.iter() {
+        let program = parse(input);
+        assert_eq!(
+            Resolver::new().resolve(&program),
+            Ok(()),
+            "input: {}",
+            input
+        );
+
+        let result = Evaluator::new().eval_program(&program, &Environment::new());
+        assert_eq!(result.inspect(), *expected, "input: {}", input);
+    }
+
+    // The evaluator resolves programs itself and leaves errors to be reported when reached.
+    let program = parse("let f = fn(a, a) { a }; f(1, 2)");
+    let result = Evaluator::new().eval_program(&program, &Environment::new());
+    assert_eq!(result.inspect(), "2");
+}
+
+#[test]
+fn test_folding_keeps_resolver_results() {
+    let tests = [
+        (
+            "if (false) { foo() } puts(1);",
+            Some("1:14: identifier not found: foo"),
+        ),
+        (
+            "let f = fn() { if (1 > 2) { y = 1; } 3 }; f()",
+            Some("1:29: cannot assign to undeclared identifier: y"),
+        ),
+        (
+            "let f = fn(x) { if (1 > 2) { 0 } else { x * 2 } }; f(21)",
+            None,
+        ),
+        (
+            "let f = fn(x) { let y = x; if (true) { y + 1 } else { z } }; f(1)",
+            Some("1:55: identifier not found: z"),
+        ),
+    ];
+
+    for (input, expected) in tests.iter() {
+        let mut results = Vec::new();
+        for optimize in [false, true] {
+            let mut program = parse(input);
+            let result = Resolver::new().resolve_and_fold(&mut program, optimize);
+            let value = Evaluator::new().eval_program(&program, &Environment::new());
+            results.push((result, value.inspect()));
+        }
+
+        assert_eq!(results[0], results[1], "input: {}", input);
+        let expected = expected.map(|e| vec![e.to_string()]);
+        assert_eq!(
+            results[0].0,
+            expected.map_or(Ok(()), Err),
+            "input: {}",
+            input
+        );
+    }
+}
@@ -0,0 +1,147 @@
+#[cfg(test)]
+mod tests;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::rc::{Rc, Weak};
+
+// An interned string: names and literals from the source share one copy per distinct text, so
+// a name repeated thousands of times in a generated script costs one allocation, and comparing or
+// hashing two symbols only looks at the address of that copy.
+//
+// The interner only holds weak references, so text is freed with the last symbol that uses it,
+// once the programs and environments naming it are gone. Each thread interns on its own, like
+// the rest of the interpreter's shared data.
+#[derive(Clone)]
+pub struct Symbol(Rc<str>);
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::default());
+}
+
+#[derive(Default)]
+struct Interner {
+    hasher: RandomState,
+    // Keyed by a hash of the text, since a dead entry no longer has text to hash.
+    buckets: HashMap<u64, Vec<Weak<str>>>,
+    entries: usize,
+    // Entries that were live at the last sweep; dead ones are swept once there are as many again.
+    live_at_sweep: usize,
+}
+
+impl Interner {
+    fn intern(&mut self, text: &str) -> Symbol {
+        let hash = self.hasher.hash_one(text);
+        let bucket = self.buckets.entry(hash).or_default();
+
+        let before = bucket.len();
+        bucket.retain(|entry| entry.strong_count() > 0);
+        self.entries -= before - bucket.len();
+        if let Some(interned) = bucket
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|interned| **interned == *text)
+        {
+            return Symbol(interned);
+        }
+
+        let interned: Rc<str> = Rc::from(text);
+        bucket.push(Rc::downgrade(&interned));
+        self.entries += 1;
+
+        if self.entries > 2 * self.live_at_sweep.max(1024) {
+            self.sweep();
+        }
+        Symbol(interned)
+    }
+
+    fn sweep(&mut self) {
+        self.buckets.retain(|_, bucket| {
+            bucket.retain(|entry| entry.strong_count() > 0);
+            !bucket.is_empty()
+        });
+        self.entries = self.buckets.values().map(Vec::len).sum();
+        self.live_at_sweep = self.entries;
+    }
+}
+
+impl Symbol {
+    pub fn intern(text: &str) -> Symbol {
+        INTERNER.with(|interner| interner.borrow_mut().intern(text))
+    }
+
+    // A symbol for text a program computes rather than spells out, like a folded constant. It
+    // keeps its own copy and is only equal to itself, so it's for text that is never looked up
+    // by name.
+    pub fn uninterned(text: impl Into<Rc<str>>) -> Symbol {
+        Symbol(text.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    // The text itself, shared rather than copied, for string values made from a literal.
+    pub fn text(&self) -> Rc<str> {
+        Rc::clone(&self.0)
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Symbol) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_ptr().hash(state);
+    }
+}
+
+impl Default for Symbol {
+    fn default() -> Symbol {
+        Symbol::intern("")
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(text: &str) -> Symbol {
+        Symbol::intern(text)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(text: String) -> Symbol {
+        Symbol::intern(&text)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        *self.0 == *other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        *self.0 == **other
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
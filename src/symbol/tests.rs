@@ -0,0 +1,33 @@
+use super::*;
+
+#[test]
+fn test_intern() {
+    let a = Symbol::intern("configuration");
+    let b = Symbol::intern(&String::from("configuration"));
+    let c = Symbol::intern("configuration_2");
+
+    assert_eq!(a, b);
+    assert!(Rc::ptr_eq(&a.0, &b.0));
+    assert_ne!(a, c);
+    assert_eq!(a, "configuration");
+    assert_eq!(c.to_string(), "configuration_2");
+    assert_eq!(Symbol::default(), Symbol::from(""));
+}
+
+#[test]
+fn test_unused_symbols_are_freed() {
+    let symbol = Symbol::intern("freed_once_unused");
+    let weak = Rc::downgrade(&symbol.0);
+    drop(symbol);
+    assert!(weak.upgrade().is_none());
+
+    // Interning the same text again makes a new copy that is shared as before.
+    let a = Symbol::intern("freed_once_unused");
+    assert_eq!(a, Symbol::intern("freed_once_unused"));
+
+    // Dead entries are swept as more text is interned.
+    for i in 0..10_000 {
+        Symbol::intern(&format!("transient_{}", i));
+    }
+    INTERNER.with(|interner| assert!(interner.borrow().entries < 4096));
+}
@@ -0,0 +1,32 @@
+// Helpers the unit tests of several modules share.
+use crate::ast;
+use crate::compiler::Compiler;
+use crate::lexer;
+use crate::object::Object;
+use crate::parser::Parser;
+use crate::vm::VM;
+
+pub fn parse(input: &str) -> ast::Program {
+    let l = lexer::new(String::from(input));
+    let mut p = Parser::new(l);
+    let program = p.parse_program();
+    assert!(p.errors().is_empty(), "parser errors: {:?}", p.errors());
+    program
+}
+
+// Errors describe themselves with their spans and tracebacks, so comparing descriptions compares
+// those too.
+pub fn describe(obj: &Object) -> String {
+    match obj {
+        Object::Error(err) => err.report(),
+        other => other.inspect(),
+    }
+}
+
+pub fn run_vm(program: &ast::Program) -> Object {
+    let mut compiler = Compiler::new();
+    if let Err(err) = compiler.compile(program) {
+        panic!("compiler error: {}", err);
+    }
+    VM::new().run(&compiler.bytecode())
+}
@@ -1,19 +1,35 @@
-use std::collections::HashMap;
+use crate::symbol::Symbol;
+use std::fmt;
 
 pub type TokenType = &'static str;
 
+// Where a token starts in the source, both 1-based.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
 #[derive(Clone, PartialEq, Eq)]
 pub struct Token {
     pub token_type: TokenType,
-    pub literal: String,
+    pub literal: Symbol,
+    pub span: Span,
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, literal: String) -> Token {
-        return Token {
+    pub fn new(token_type: TokenType, literal: impl Into<Symbol>) -> Token {
+        Token {
             token_type,
-            literal,
-        };
+            literal: literal.into(),
+            span: Span::default(),
+        }
     }
 }
 
@@ -24,10 +40,29 @@ pub const IDENT: &str = "IDENT";
 pub const INT: &str = "INT";
 
 pub const ASSIGN: &str = "ASSIGN";
+pub const PLUS_ASSIGN: &str = "+=";
+pub const MINUS_ASSIGN: &str = "-=";
+pub const ASTERISK_ASSIGN: &str = "*=";
+pub const SLASH_ASSIGN: &str = "/=";
+pub const PERCENT_ASSIGN: &str = "%=";
+pub const POWER_ASSIGN: &str = "**=";
+pub const AMPERSAND_ASSIGN: &str = "&=";
+pub const PIPE_ASSIGN: &str = "|=";
+pub const CARET_ASSIGN: &str = "^=";
+pub const LSHIFT_ASSIGN: &str = "<<=";
+pub const RSHIFT_ASSIGN: &str = ">>=";
 pub const PLUS: &str = "+";
 pub const MINUS: &str = "-";
 pub const SLASH: &str = "/";
 pub const ASTERISK: &str = "*";
+pub const PERCENT: &str = "%";
+pub const POWER: &str = "**";
+pub const AMPERSAND: &str = "&";
+pub const PIPE: &str = "|";
+pub const CARET: &str = "^";
+pub const TILDE: &str = "~";
+pub const LSHIFT: &str = "<<";
+pub const RSHIFT: &str = ">>";
 pub const BANG: &str = "!";
 pub const LT: &str = "<";
 pub const GT: &str = ">";
@@ -39,6 +74,7 @@ pub const GT_EQ: &str = ">=";
 pub const COMMA: &str = ",";
 pub const SEMICOLON: &str = ";";
 pub const COLON: &str = ":";
+pub const DOTDOT: &str = "..";
 
 pub const LPAREN: &str = "(";
 pub const RPAREN: &str = ")";
@@ -51,19 +87,37 @@ pub const FUNCTION: &str = "FUNCTION";
 pub const LET: &str = "LET";
 pub const TRUE: &str = "true";
 pub const FALSE: &str = "false";
+pub const NULL: &str = "null";
 pub const IF: &str = "IF";
 pub const ELSE: &str = "ELSE";
 pub const RETURN: &str = "return";
 pub const STRING: &str = "STRING";
+pub const WHILE: &str = "WHILE";
+pub const FOR: &str = "FOR";
+pub const IN: &str = "IN";
+pub const BREAK: &str = "BREAK";
+pub const CONTINUE: &str = "CONTINUE";
+pub const TRY: &str = "TRY";
+pub const CATCH: &str = "CATCH";
+pub const THROW: &str = "THROW";
 
-const KEYWORDS: [(&str, TokenType); 7] = [
+const KEYWORDS: [(&str, TokenType); 16] = [
     ("fn", FUNCTION),
     ("let", LET),
     ("true", TRUE),
     ("false", FALSE),
+    ("null", NULL),
     ("if", IF),
     ("else", ELSE),
     ("return", RETURN),
+    ("while", WHILE),
+    ("for", FOR),
+    ("in", IN),
+    ("break", BREAK),
+    ("continue", CONTINUE),
+    ("try", TRY),
+    ("catch", CATCH),
+    ("throw", THROW),
 ];
 
 pub fn lookup_ident(ident: String) -> TokenType {
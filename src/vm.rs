@@ -0,0 +1,869 @@
+#[cfg(test)]
+mod tests;
+
+use crate::budget::Budget;
+use crate::code::{self, Opcode};
+use crate::compiler::Bytecode;
+use crate::evaluator::{
+    self, assign_index, eval_infix_expression, eval_prefix_expression, index_value, integer_growth,
+    new_error, slice_bound, slice_value, unusable_hash_key,
+};
+use crate::object::builtins;
+use crate::object::memory;
+use crate::object::{self, Cell, Closure, CompiledFunction, Elements, Object, RuntimeError};
+use crate::token::Span;
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+use std::time::Duration;
+
+pub struct VM {
+    strict: bool,
+    max_call_depth: usize,
+    budget: Budget,
+    constants: Vec<Object>,
+    // Globals persist from one run to the next, as the REPL needs. None until defined.
+    globals: Vec<Option<Object>>,
+    global_names: Vec<String>,
+    stack: Vec<Object>,
+    frames: Vec<Frame>,
+    handlers: Vec<Handler>,
+    iterators: Vec<Iter>,
+    // Where to log each executed instruction, when tracing.
+    trace: Option<Box<dyn Write>>,
+}
+
+// A running function. Its callee sits just below `base`; locals start at `base`.
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    // Start of the instruction being executed, for error spans.
+    current: usize,
+    base: usize,
+    cells: Vec<Cell>,
+    iterators: usize,
+    // The callee of the Monkey call that made this frame; frames started by builtins have none.
+    // The call's span is found from the caller's current instruction if a traceback needs it.
+    call_site: Option<Rc<CompiledFunction>>,
+    // The last tail call that replaced this frame's function.
+    tail_call: Option<(Rc<CompiledFunction>, Span)>,
+}
+
+// An active `try`: where to resume and what to drop when something is thrown.
+struct Handler {
+    frame: usize,
+    sp: usize,
+    iterators: usize,
+    address: usize,
+}
+
+enum Iter {
+    Array(Rc<RefCell<Elements>>, usize),
+    Items(std::vec::IntoIter<Object>),
+    Range(i64, i64),
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VM {
+    pub fn new() -> VM {
+        VM {
+            strict: false,
+            max_call_depth: evaluator::DEFAULT_MAX_CALL_DEPTH,
+            budget: Budget::new(),
+            constants: Vec::new(),
+            globals: Vec::new(),
+            global_names: Vec::new(),
+            stack: Vec::new(),
+            frames: Vec::new(),
+            handlers: Vec::new(),
+            iterators: Vec::new(),
+            trace: None,
+        }
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn set_max_call_depth(&mut self, max_call_depth: usize) {
+        self.max_call_depth = max_call_depth;
+    }
+
+    // Every executed instruction burns one unit of fuel.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.budget.set_fuel(fuel);
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.budget.fuel()
+    }
+
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.budget.set_timeout(timeout);
+    }
+
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.budget.set_memory_limit(limit);
+    }
+
+    pub fn set_max_integer_bits(&mut self, bits: Option<usize>) {
+        self.budget.set_max_integer_bits(bits);
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.budget.memory_used()
+    }
+
+    pub fn set_trace(&mut self, trace: Option<Box<dyn Write>>) {
+        self.trace = trace;
+    }
+
+    pub fn run(&mut self, bytecode: &Bytecode) -> Object {
+        self.constants = bytecode.constants.clone();
+        self.globals.resize(bytecode.globals.len(), None);
+        self.global_names = bytecode.globals.clone();
+        self.stack.clear();
+        self.frames.clear();
+        self.handlers.clear();
+        self.iterators.clear();
+        self.budget.start(live_size(
+            &self.globals,
+            &self.stack,
+            &self.frames,
+            &self.iterators,
+        ));
+
+        let main = object::new_closure(Closure {
+            function: Rc::clone(&bytecode.main),
+            free: Vec::new(),
+        });
+        self.stack.push(Object::Closure(Rc::clone(&main)));
+        if let Err(err) = self.push_frame(main, 0, None) {
+            return err;
+        }
+
+        match self.execute(0) {
+            Ok(result) => result,
+            Err(err) => Object::Error(err),
+        }
+    }
+
+    fn charge(&mut self, obj: Object) -> Object {
+        let (globals, stack, frames, iterators) =
+            (&self.globals, &self.stack, &self.frames, &self.iterators);
+        self.budget
+            .charge(obj, || live_size(globals, stack, frames, iterators))
+    }
+
+    fn allocate(&mut self, bytes: usize) -> Option<Object> {
+        let (globals, stack, frames, iterators) =
+            (&self.globals, &self.stack, &self.frames, &self.iterators);
+        self.budget
+            .allocate(bytes, || live_size(globals, stack, frames, iterators))
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().expect("no active frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("no active frame")
+    }
+
+    // A captured variable read before its `let` runs finds the global of the same name, as the
+    // evaluator does when it looks past an unset local.
+    fn unset_captured(&self, name: &str) -> Result<Object, Object> {
+        let index = self.global_names.iter().position(|global| global == name);
+        match index.and_then(|i| self.globals.get(i)?.clone()) {
+            Some(value) => Ok(value),
+            None => unset_variable(name),
+        }
+    }
+
+    fn pop(&mut self) -> Object {
+        self.stack.pop().expect("stack underflow")
+    }
+
+    // Starts `closure` on the `argc` arguments at the top of the stack.
+    fn push_frame(
+        &mut self,
+        closure: Rc<Closure>,
+        argc: usize,
+        call_site: Option<Rc<CompiledFunction>>,
+    ) -> Result<(), Object> {
+        // The top level runs in a frame of its own, which doesn't count as a call.
+        if self.frames.len() > self.max_call_depth {
+            return Err(new_error(format!(
+                "maximum call depth of {} exceeded",
+                self.max_call_depth
+            )));
+        }
+        let function = &closure.function;
+        check_arity(function, argc)?;
+
+        let base = self.stack.len() - argc;
+        self.stack.resize(base + function.num_locals, Object::Null);
+
+        let frame = Frame {
+            cells: new_cells(function.num_cells),
+            closure,
+            ip: 0,
+            current: 0,
+            base,
+            iterators: self.iterators.len(),
+            call_site,
+            tail_call: None,
+        };
+        self.frames.push(frame);
+
+        Ok(())
+    }
+
+    // Runs until the frame at `base_frame` returns, and hands back its value. Errors that no
+    // `try` inside those frames catches unwind them and come back as Err.
+    fn execute(&mut self, base_frame: usize) -> Result<Object, Box<RuntimeError>> {
+        loop {
+            let result = match self.budget.consume_fuel() {
+                Some(err) => Err(err),
+                None if self.trace.is_some() => self.traced_step(base_frame),
+                None => self.step(base_frame),
+            };
+
+            match result {
+                Ok(Some(value)) => return Ok(value),
+                Ok(None) => (),
+                Err(err) => self.handle_error(err, base_frame)?,
+            }
+        }
+    }
+
+    fn step(&mut self, base_frame: usize) -> Result<Option<Object>, Object> {
+        // Every instruction has at most four bytes of operands; they're read here as one
+        // big-endian number, and the arms below take the fields they need out of it.
+        let frame = self.frames.last_mut().expect("no active frame");
+        let ins = &frame.closure.function.instructions;
+        let ip = frame.ip;
+        let op = ins[ip];
+        let width =
+            code::operand_bytes(op).ok_or_else(|| new_error(format!("unknown opcode {}", op)))?;
+        let operand = ins[ip + 1..ip + 1 + width]
+            .iter()
+            .fold(0, |operand, byte| (operand << 8) | *byte as usize);
+        frame.current = ip;
+        frame.ip = ip + 1 + width;
+
+        match op {
+            code::OP_CONSTANT => {
+                self.stack.push(self.constants[operand].clone());
+            }
+            code::OP_POP => {
+                self.pop();
+            }
+            code::OP_TRUE => self.stack.push(Object::Boolean(true)),
+            code::OP_FALSE => self.stack.push(Object::Boolean(false)),
+            code::OP_NULL => self.stack.push(Object::Null),
+            code::OP_ADD..=code::OP_RANGE => {
+                let len = self.stack.len();
+                if let (Object::Integer(left), Object::Integer(right)) =
+                    (&self.stack[len - 2], &self.stack[len - 1])
+                    && let Some(result) = integer_operation(op, *left, *right)
+                {
+                    self.stack.truncate(len - 2);
+                    self.stack.push(result);
+                    return Ok(None);
+                }
+
+                let right = self.pop();
+                let left = self.pop();
+                let result = self.binary_operation(op, left, right)?;
+                self.stack.push(result);
+            }
+            code::OP_MINUS | code::OP_BANG | code::OP_BIT_NOT => {
+                let operator = match op {
+                    code::OP_MINUS => "-",
+                    code::OP_BANG => "!",
+                    _ => "~",
+                };
+                let right = self.pop();
+                let result = check(eval_prefix_expression(operator, right))?;
+                self.stack.push(result);
+            }
+            code::OP_JUMP => {
+                self.frame_mut().ip = operand;
+            }
+            code::OP_JUMP_NOT_TRUTHY => {
+                if !self.pop().is_truthy() {
+                    self.frame_mut().ip = operand;
+                }
+            }
+            code::OP_GET_GLOBAL => {
+                let index = operand;
+                let value = match &self.globals[index] {
+                    Some(value) => value.clone(),
+                    None => unset_variable(&self.global_names[index])?,
+                };
+                self.stack.push(value);
+            }
+            code::OP_SET_GLOBAL => {
+                self.globals[operand] = Some(self.pop());
+            }
+            code::OP_ASSIGN_GLOBAL => {
+                let index = operand;
+                let value = self.pop();
+                match &mut self.globals[index] {
+                    Some(global) => *global = value,
+                    None => {
+                        return Err(new_error(format!(
+                            "cannot assign to undeclared identifier: {}",
+                            self.global_names[index]
+                        )));
+                    }
+                }
+            }
+            code::OP_GET_LOCAL => {
+                let slot = self.frame().base + operand;
+                self.stack.push(self.stack[slot].clone());
+            }
+            code::OP_SET_LOCAL => {
+                let slot = self.frame().base + operand;
+                self.stack[slot] = self.pop();
+            }
+            code::OP_GET_CELL => {
+                let frame = self.frame();
+                let value = match frame.cells[operand].borrow().clone() {
+                    Some(value) => value,
+                    None => self.unset_captured(&frame.closure.function.cell_names[operand])?,
+                };
+                self.stack.push(value);
+            }
+            code::OP_SET_CELL => {
+                let value = self.pop();
+                *self.frame().cells[operand].borrow_mut() = Some(value);
+            }
+            code::OP_NEW_CELLS => {
+                let (start, count) = (operand >> 16, operand & 0xffff);
+                let frame = self.frame_mut();
+                for cell in frame.cells[start..start + count].iter_mut() {
+                    *cell = object::new_cell();
+                }
+            }
+            code::OP_GET_FREE => {
+                let closure = &self.frame().closure;
+                let value = match closure.free[operand].borrow().clone() {
+                    Some(value) => value,
+                    None => self.unset_captured(&closure.function.free_names[operand])?,
+                };
+                self.stack.push(value);
+            }
+            code::OP_SET_FREE => {
+                let value = self.pop();
+                *self.frame().closure.free[operand].borrow_mut() = Some(value);
+            }
+            code::OP_GET_BUILTIN => {
+                let (name, builtin) = builtins::BUILTINS[operand];
+                self.stack.push(Object::Builtin(name, builtin));
+            }
+            code::OP_ARRAY => {
+                let count = operand;
+                let elements = self.stack.split_off(self.stack.len() - count);
+                let array = self.charge(Object::new_array(elements));
+                self.stack.push(check(array)?);
+            }
+            code::OP_HASH => {
+                let count = operand;
+                let items = self.stack.split_off(self.stack.len() - 2 * count);
+
+                let mut hash = object::Hash::new();
+                let mut items = items.into_iter();
+                while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                    match key.hash_key() {
+                        Some(hash_key) => hash.insert(hash_key, key, value),
+                        None => return Err(unusable_hash_key(&key)),
+                    }
+                }
+
+                let hash = self.charge(Object::new_hash(hash));
+                self.stack.push(check(hash)?);
+            }
+            code::OP_INDEX => {
+                let index = self.pop();
+                let left = self.pop();
+                let result = check(index_value(left, index, self.strict))?;
+                self.stack.push(result);
+            }
+            code::OP_SET_INDEX | code::OP_UPDATE_INDEX => {
+                let index = self.pop();
+                let container = self.pop();
+                let mut value = self.pop();
+
+                if op == code::OP_UPDATE_INDEX {
+                    let current = index_value(container.clone(), index.clone(), self.strict);
+                    value = self.binary_operation(operand as Opcode, check(current)?, value)?;
+                }
+
+                check(assign_index(container, index, value, |bytes| {
+                    self.allocate(bytes)
+                }))?;
+            }
+            code::OP_SLICE => {
+                let present = operand;
+                let mut bounds = [None; 3];
+                for (i, bit) in [code::SLICE_START, code::SLICE_END, code::SLICE_STEP]
+                    .iter()
+                    .enumerate()
+                    .rev()
+                {
+                    if present & bit != 0 {
+                        bounds[i] = slice_bound(self.pop())?;
+                    }
+                }
+
+                let left = self.pop();
+                let slice = self.charge(slice_value(&left, bounds));
+                self.stack.push(check(slice)?);
+            }
+            code::OP_SWAP => {
+                let len = self.stack.len();
+                self.stack.swap(len - 1, len - 2);
+            }
+            code::OP_CLOSURE => {
+                let function = match &self.constants[operand] {
+                    Object::CompiledFunction(function) => Rc::clone(function),
+                    other => return Err(new_error(format!("not a function: {}", other.inspect()))),
+                };
+
+                let frame = self.frame();
+                let free = function
+                    .captures
+                    .iter()
+                    .map(|capture| match capture {
+                        object::Capture::Cell(i) => Rc::clone(&frame.cells[*i]),
+                        object::Capture::Free(i) => Rc::clone(&frame.closure.free[*i]),
+                    })
+                    .collect();
+
+                self.stack
+                    .push(Object::new_closure(Closure { function, free }));
+            }
+            code::OP_CALL => self.call(operand)?,
+            code::OP_TAIL_CALL => self.tail_call(operand)?,
+            code::OP_RETURN_VALUE => {
+                let value = self.pop();
+                let frame = self.frames.pop().expect("no active frame");
+                self.stack.truncate(frame.base - 1);
+                self.iterators.truncate(frame.iterators);
+                self.drop_handlers();
+
+                if self.frames.len() == base_frame {
+                    return Ok(Some(value));
+                }
+                self.stack.push(value);
+            }
+            code::OP_THROW => {
+                let value = self.pop();
+                return Err(Object::Error(Box::new(RuntimeError::thrown(value))));
+            }
+            code::OP_SETUP_TRY => {
+                let handler = Handler {
+                    frame: self.frames.len() - 1,
+                    sp: self.stack.len(),
+                    iterators: self.iterators.len(),
+                    address: operand,
+                };
+                self.handlers.push(handler);
+            }
+            code::OP_POP_TRY => {
+                self.handlers.pop();
+            }
+            code::OP_ITER => {
+                let iter = match self.pop() {
+                    Object::Array(elements) => Iter::Array(elements, 0),
+                    Object::String(value) => Iter::Items(
+                        value
+                            .chars()
+                            .map(|c| Object::String(c.to_string().into()))
+                            .collect::<Vec<_>>()
+                            .into_iter(),
+                    ),
+                    Object::Range(start, end) => Iter::Range(start, end),
+                    Object::Hash(hash) => Iter::Items(
+                        hash.borrow()
+                            .iter()
+                            .map(|pair| pair.key.clone())
+                            .collect::<Vec<_>>()
+                            .into_iter(),
+                    ),
+                    other => {
+                        return Err(new_error(format!("not iterable: {}", other.object_type())));
+                    }
+                };
+                self.iterators.push(iter);
+            }
+            code::OP_ITER_NEXT => {
+                let item = match self.iterators.last_mut().expect("no active iterator") {
+                    Iter::Array(elements, i) => {
+                        let item = elements.borrow().get(*i).cloned();
+                        *i += 1;
+                        item
+                    }
+                    Iter::Items(items) => items.next(),
+                    Iter::Range(start, end) if *start < *end => {
+                        *start += 1;
+                        Some(Object::Integer(*start - 1))
+                    }
+                    Iter::Range(_, _) => None,
+                };
+
+                match item {
+                    Some(item) => self.stack.push(item),
+                    None => self.frame_mut().ip = operand,
+                }
+            }
+            code::OP_ITER_POP => {
+                self.iterators.pop();
+            }
+            _ => return Err(new_error(format!("unknown opcode {}", op))),
+        }
+
+        Ok(None)
+    }
+
+    // Runs one instruction and logs it with the function it's in and the stack top it leaves.
+    fn traced_step(&mut self, base_frame: usize) -> Result<Option<Object>, Object> {
+        let frame = self.frame();
+        let function = Rc::clone(&frame.closure.function);
+        let (instruction, _) =
+            code::format_instruction(&function.instructions, frame.ip, &self.constants);
+
+        let result = self.step(base_frame);
+        let top = match &result {
+            Ok(Some(value)) => value.inspect(),
+            Ok(None) => self.stack.last().map_or(String::new(), Object::inspect),
+            Err(Object::Error(err)) => format!("error: {}", err.message),
+            Err(other) => other.inspect(),
+        };
+
+        if let Some(trace) = self.trace.as_mut() {
+            // Tracing is a debugging aid; a broken log shouldn't stop the program.
+            let _ = writeln!(trace, "{:<12} {:<40} | {}", function.name, instruction, top);
+        }
+
+        result
+    }
+
+    fn binary_operation(
+        &mut self,
+        op: Opcode,
+        left: Object,
+        right: Object,
+    ) -> Result<Object, Object> {
+        let operator = infix_operator(op);
+
+        // Big integer products are paid for before they're worked out, with the operands back
+        // on the stack so they still count as live.
+        if let Some((bits, work)) = integer_growth(operator, &left, &right) {
+            self.stack.extend([left.clone(), right.clone()]);
+            let (globals, stack, frames, iterators) =
+                (&self.globals, &self.stack, &self.frames, &self.iterators);
+            let err = self
+                .budget
+                .reserve_integer(bits, work, || live_size(globals, stack, frames, iterators));
+            self.stack.truncate(self.stack.len() - 2);
+            if let Some(err) = err {
+                return Err(err);
+            }
+        }
+
+        let result = eval_infix_expression(operator, left, right);
+        if let Object::BigInteger(value) = &result
+            && let Some(err) = self.budget.check_integer_bits(value.bits() as u64)
+        {
+            return Err(err);
+        }
+        check(self.charge(result))
+    }
+
+    fn call(&mut self, argc: usize) -> Result<(), Object> {
+        let callee = self.stack[self.stack.len() - 1 - argc].clone();
+
+        match callee {
+            Object::Closure(closure) => {
+                let call_site = Rc::clone(&closure.function);
+                self.push_frame(closure, argc, Some(call_site))
+            }
+            Object::Builtin(name, builtin) => {
+                let args = self.stack.split_off(self.stack.len() - argc);
+                self.pop();
+
+                let result = self.call_builtin(builtin, args);
+                let span = self.current_span();
+                self.stack.push(trace_builtin(result, name, span)?);
+                Ok(())
+            }
+            other => Err(new_error(format!(
+                "not a function: {}",
+                other.object_type()
+            ))),
+        }
+    }
+
+    // The arguments stay on the stack while the builtin runs, so recounts still see them.
+    fn call_builtin(&mut self, builtin: builtins::BuiltinFunction, args: Vec<Object>) -> Object {
+        let sp = self.stack.len();
+        self.stack.extend(args.iter().cloned());
+        let result = builtin(self, args);
+        self.stack.truncate(sp);
+
+        self.charge(result)
+    }
+
+    // A call in tail position replaces the calling frame instead of adding one.
+    fn tail_call(&mut self, argc: usize) -> Result<(), Object> {
+        let callee_pos = self.stack.len() - 1 - argc;
+        let closure = match &self.stack[callee_pos] {
+            Object::Closure(closure) => Rc::clone(closure),
+            _ => return self.call(argc),
+        };
+
+        check_arity(&closure.function, argc)?;
+        let span = self.current_span();
+
+        let frame = self.frames.pop().expect("no active frame");
+        self.stack.drain(frame.base - 1..callee_pos);
+        self.iterators.truncate(frame.iterators);
+        self.drop_handlers();
+
+        self.push_frame(closure, argc, frame.call_site)?;
+        let function = Rc::clone(&self.frame().closure.function);
+        self.frame_mut().tail_call = span.map(|span| (function, span));
+
+        Ok(())
+    }
+
+    // Handlers belong to frames; once a frame is gone, so are its handlers.
+    fn drop_handlers(&mut self) {
+        while self
+            .handlers
+            .last()
+            .is_some_and(|handler| handler.frame >= self.frames.len())
+        {
+            self.handlers.pop();
+        }
+    }
+
+    fn current_span(&self) -> Option<Span> {
+        let frame = self.frames.last()?;
+        frame.closure.function.span_at(frame.current)
+    }
+
+    // Resumes at the innermost handler inside the frames this execute call runs, or unwinds
+    // them all and reports the error with the calls that led to it.
+    fn handle_error(&mut self, err: Object, base_frame: usize) -> Result<(), Box<RuntimeError>> {
+        let mut err = match err {
+            Object::Error(err) => err,
+            other => Box::new(RuntimeError::new(other.inspect())),
+        };
+        if err.span.is_none() {
+            err.span = self.current_span();
+        }
+
+        if err.is_catchable()
+            && let Some(handler) = self.handlers.last()
+            && handler.frame >= base_frame
+        {
+            let handler = self.handlers.pop().expect("handler disappeared");
+            self.frames.truncate(handler.frame + 1);
+            self.stack.truncate(handler.sp);
+            self.iterators.truncate(handler.iterators);
+            self.stack.push(err.caught_value());
+            self.frame_mut().ip = handler.address;
+
+            return Ok(());
+        }
+
+        while self.frames.len() > base_frame {
+            let frame = self.frames.pop().expect("no active frame");
+            self.stack.truncate(frame.base.saturating_sub(1));
+            self.iterators.truncate(frame.iterators);
+
+            // The caller is now the innermost frame, still stopped at the call.
+            let call_site = frame
+                .call_site
+                .and_then(|function| Some((function, self.current_span()?)));
+            for (function, span) in [frame.tail_call, call_site].into_iter().flatten() {
+                err.traceback.push(object::TraceFrame {
+                    function: function.name.clone(),
+                    span,
+                });
+            }
+        }
+        self.drop_handlers();
+
+        Err(err)
+    }
+}
+
+impl builtins::Caller for VM {
+    fn call(&mut self, function: &Object, args: Vec<Object>) -> Object {
+        match function {
+            Object::Closure(closure) => {
+                let argc = args.len();
+                self.stack.push(function.clone());
+                self.stack.extend(args);
+
+                if let Err(err) = self.push_frame(Rc::clone(closure), argc, None) {
+                    self.stack.truncate(self.stack.len() - argc - 1);
+                    return err;
+                }
+
+                match self.execute(self.frames.len() - 1) {
+                    Ok(result) => result,
+                    Err(err) => Object::Error(err),
+                }
+            }
+            Object::Builtin(_, builtin) => self.call_builtin(*builtin, args),
+            other => new_error(format!("not a function: {}", other.object_type())),
+        }
+    }
+
+    fn reserve(&mut self, bytes: usize) -> Option<Object> {
+        let (globals, stack, frames, iterators) =
+            (&self.globals, &self.stack, &self.frames, &self.iterators);
+        self.budget
+            .reserve(bytes, || live_size(globals, stack, frames, iterators))
+    }
+
+    fn consume(&mut self, steps: u64) -> Option<Object> {
+        self.budget.consume(steps)
+    }
+}
+
+// Reading a variable before its `let` runs finds the builtin it shadows, as in the evaluator.
+fn unset_variable(name: &str) -> Result<Object, Object> {
+    builtins::lookup_builtin(name)
+        .ok_or_else(|| new_error(format!("identifier not found: {}", name)))
+}
+
+fn new_cells(count: usize) -> Vec<Cell> {
+    (0..count).map(|_| object::new_cell()).collect()
+}
+
+fn check_arity(function: &CompiledFunction, argc: usize) -> Result<(), Object> {
+    if function.num_parameters != argc {
+        return Err(new_error(format!(
+            "wrong number of arguments: want={}, got={}",
+            function.num_parameters, argc
+        )));
+    }
+
+    Ok(())
+}
+
+fn check(result: Object) -> Result<Object, Object> {
+    match result {
+        Object::Error(_) => Err(result),
+        result => Ok(result),
+    }
+}
+
+// A builtin's error that already has a span was raised in a function it called back.
+fn trace_builtin(result: Object, name: &str, span: Option<Span>) -> Result<Object, Object> {
+    match result {
+        Object::Error(mut err) => {
+            if err.span.is_some()
+                && let Some(span) = span
+            {
+                err.traceback.push(object::TraceFrame {
+                    function: name.to_string(),
+                    span,
+                });
+            }
+            Err(Object::Error(err))
+        }
+        result => Ok(result),
+    }
+}
+
+// Arithmetic and comparisons on two integers, without going through the evaluator's
+// operator strings. None leaves the cases that need more care, like overflow into a big
+// integer, division by zero and shifts, to eval_infix_expression.
+fn integer_operation(op: Opcode, left: i64, right: i64) -> Option<Object> {
+    let result = match op {
+        code::OP_ADD => Object::Integer(left.checked_add(right)?),
+        code::OP_SUB => Object::Integer(left.checked_sub(right)?),
+        code::OP_MUL => Object::Integer(left.checked_mul(right)?),
+        code::OP_DIV => Object::Integer(left.checked_div(right)?),
+        code::OP_MOD => Object::Integer(left.checked_rem(right)?),
+        code::OP_BIT_AND => Object::Integer(left & right),
+        code::OP_BIT_OR => Object::Integer(left | right),
+        code::OP_BIT_XOR => Object::Integer(left ^ right),
+        code::OP_EQUAL => Object::Boolean(left == right),
+        code::OP_NOT_EQUAL => Object::Boolean(left != right),
+        code::OP_LESS => Object::Boolean(left < right),
+        code::OP_LESS_EQUAL => Object::Boolean(left <= right),
+        code::OP_GREATER => Object::Boolean(left > right),
+        code::OP_GREATER_EQUAL => Object::Boolean(left >= right),
+        _ => return None,
+    };
+
+    Some(result)
+}
+
+fn infix_operator(op: Opcode) -> &'static str {
+    match op {
+        code::OP_ADD => "+",
+        code::OP_SUB => "-",
+        code::OP_MUL => "*",
+        code::OP_DIV => "/",
+        code::OP_MOD => "%",
+        code::OP_POW => "**",
+        code::OP_BIT_AND => "&",
+        code::OP_BIT_OR => "|",
+        code::OP_BIT_XOR => "^",
+        code::OP_SHL => "<<",
+        code::OP_SHR => ">>",
+        code::OP_EQUAL => "==",
+        code::OP_NOT_EQUAL => "!=",
+        code::OP_LESS => "<",
+        code::OP_LESS_EQUAL => "<=",
+        code::OP_GREATER => ">",
+        code::OP_GREATER_EQUAL => ">=",
+        code::OP_RANGE => "..",
+        _ => "?",
+    }
+}
+
+// What the VM still holds: the roots when counting what memory is reachable.
+fn live_size(
+    globals: &[Option<Object>],
+    stack: &[Object],
+    frames: &[Frame],
+    iterators: &[Iter],
+) -> usize {
+    let iterated: Vec<Object> = iterators
+        .iter()
+        .filter_map(|iter| match iter {
+            Iter::Array(elements, _) => Some(Object::Array(Rc::clone(elements))),
+            _ => None,
+        })
+        .collect();
+    let closures: Vec<Object> = frames
+        .iter()
+        .map(|frame| Object::Closure(Rc::clone(&frame.closure)))
+        .collect();
+
+    memory::reachable_size_of(
+        globals
+            .iter()
+            .flatten()
+            .chain(stack)
+            .chain(&iterated)
+            .chain(&closures),
+        frames.iter().flat_map(|frame| frame.cells.iter()),
+    )
+}
@@ -0,0 +1,393 @@
+use super::*;
+use crate::compiler::Compiler;
+use crate::evaluator::Evaluator;
+use crate::object::environment::Environment;
+use crate::test_helpers::{describe, parse};
+
+fn run_with(vm: &mut VM, input: &str) -> Object {
+    let mut compiler = Compiler::new();
+    if let Err(err) = compiler.compile(&parse(input)) {
+        panic!("compiler error: {}", err);
+    }
+    vm.run(&compiler.bytecode())
+}
+
+fn test_run(input: &str) -> Object {
+    run_with(&mut VM::new(), input)
+}
+
+fn test_eval(input: &str) -> Object {
+    Evaluator::new().eval_program(&parse(input), &Environment::new())
+}
+
+fn assert_error(obj: &Object, expected: &str) {
+    match obj {
+        Object::Error(err) => assert_eq!(err.message, expected),
+        other => panic!("no error object returned. got={}", other.inspect()),
+    }
+}
+
+#[test]
+fn test_matches_evaluator() {
+    let tests = [
+        "1 + 2 * 3 - 4 / 2 % 3",
+        "[9223372036854775807 + 1, -(2 ** 70) / 3, 2 ** 64 % 10, 18446744073709551616 > 1]",
+        "[-7 / 2, -7 % 2, (-9223372036854775807 - 1) / -1, -9223372036854775807 - 2, 3037000500 * 3037000500]",
+        "2 ** 10 + (7 & 3) + (1 | 4) + (5 ^ 1) + (1 << 4) + (256 >> 2) + ~5",
+        "-5 + 10; !true; !!5",
+        "1 < 2 == true != (3 >= 4) == (5 <= 5)",
+        "\"foo\" + \"bar\"",
+        "[1, 2 + 3, \"x\"][1]",
+        "{\"a\": 1, 2: [3], true: fn(x) { x }}[2]",
+        "let a = [1, 2, 3, 4, 5]; [a[1:], a[:-2], a[::2], a[::-1], \"hello\"[1:3]]",
+        "0..5",
+        "if (1 > 2) { 10 } else if (false) { 20 } else { 30 }",
+        "if (false) { 1 }",
+        "let x = 5; let y = x * 2; x + y",
+        "let x = 1; x = x + 1; x += 10; x *= 2; x",
+        "let a = [1, 2]; a[0] = 5; a[1] += 3; a",
+        "let h = {}; h[\"k\"] = 1; h[\"k\"] += 41; h[\"k\"]",
+        "let f = fn(a, b) { a * b }; f(6, 7)",
+        "let f = fn() { return 1; 2 }; f()",
+        "let add = fn(x) { fn(y) { x + y } }; add(2)(3)",
+        "let counter = fn() { let n = 0; fn() { n += 1; n } }; let c = counter(); c(); c(); c()",
+        "let make = fn() { let n = 0; [fn() { n += 1 }, fn() { n }] };
+        let fs = make(); fs[0](); fs[0](); fs[1]()",
+        "let fs = []; for (i in 0..3) { fs = push(fs, fn() { i }); } map(fs, fn(f) { f() })",
+        "let fs = []; let i = 0;
+        while (i < 3) { let j = i * 10; fs = push(fs, fn() { j }); i += 1; }
+        map(fs, fn(f) { f() })",
+        "let outer = fn() {
+            let even = fn(n) { if (n == 0) { true } else { odd(n - 1) } };
+            let odd = fn(n) { if (n == 0) { false } else { even(n - 1) } };
+            [even(10), odd(7)]
+        };
+        outer()",
+        "let f = fn() { g() }; let g = fn() { 7 }; f()",
+        "let f = fn(x) { let g = fn() { x = x * 2; }; g(); g(); x }; f(3)",
+        "let a = fn(x) { fn(y) { fn(z) { x + y + z } } }; a(1)(2)(3)",
+        "let n = 0; let i = 0; while (i < 10) { i += 1; if (i % 2 == 0) { continue; } n += i; } n",
+        "let n = 0; for (x in [1, 2, 3, 4, 5]) { if (x == 4) { break; } n += x; } n",
+        "let s = \"\"; for (c in \"abc\") { s = c + s; } s",
+        "let n = 0; for (k in {\"a\": 1, \"b\": 2}) { n += len(k); } n",
+        "let n = 0; for (i in 0..4) { for (j in 0..4) { if (j > i) { break; } n += 1; } } n",
+        "let f = fn() { for (x in [1, 2, 3]) { if (x == 2) { return x * 10; } } 0 }; f()",
+        "map([1, 2, 3], fn(x) { x * x })",
+        "filter(1..10, fn(x) { x % 3 == 0 })",
+        "reduce([1, 2, 3, 4], 0, fn(acc, x) { acc + x })",
+        "sort([3, 1, 2])",
+        "len(\"hello\") + len([1, 2]) + len({1: 2})",
+        "let len = fn(x) { 42 }; len(\"abc\")",
+        "let f = fn(first) { first * 2 }; f(7)",
+        "let n = 0; for (rest in [1, 2]) { n += rest; } n",
+        "let r = 0; try { throw 5; } catch (len) { r = len; } r",
+        "let x = len(\"a\"); let len = 5; [x, len]",
+        "let f = fn() { len(\"ab\") }; let a = f(); let len = fn(x) { 9 }; [a, f()]",
+        "let f = fn() { let g = fn() { len(\"ab\") }; let a = g(); let len = fn(x) { 9 }; [a, g()] }; f()",
+        "puts = 1",
+        "let r = 0; try { r = 1; } catch (e) { r = 2; } r",
+        "let r = 0; try { throw 5; r = 1; } catch (e) { r = e * 2; } r",
+        "let r = \"\"; try { 5 + true; } catch (e) { r = e; } r",
+        "let r = \"\"; try { len(1); } catch (e) { r = e; } r",
+        "let f = fn(x) { if (x > 2) { throw \"too big\"; } x };
+        let total = 0;
+        for (x in [1, 2, 3, 2]) { try { total += f(x); } catch (e) { total += 100; } }
+        total",
+        "let f = fn() { try { return 1; } catch (e) { 2 } 3 }; f()",
+        "let r = 0; try { try { throw 1; } catch (e) { throw e + 1; } } catch (e) { r = e; } r",
+        "let n = 0; while (true) { try { n += 1; if (n == 3) { break; } } catch (e) { } } n",
+        "let r = 0; try { map([1, 2], fn(x) { throw x; }); } catch (e) { r = e; } r",
+        "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15)",
+        "let x = 1; let x = x + 1; x",
+        "null",
+        "let f = fn() { }; f()",
+        "len",
+        "5 + true",
+        "-true",
+        "missing",
+        "missing = 1",
+        "let x = 1; x(2)",
+        "let f = fn(a) { a }; f(1, 2)",
+        "len(1, 2)",
+        "[1, 2][\"a\"]",
+        "{[1]: 2}",
+        "for (x in 5) { }",
+        "let f = fn() { g() }; f()",
+        "let f = fn() { let g = fn() { k }; let r = g(); let k = 2; r }; f()",
+        "let f = fn() { let g = fn() { let h = fn() { k }; h() }; let r = g(); let k = 2; r }; f()",
+        "let f = fn() { let g = fn() { k }; let k = 2; g() }; f()",
+        "let k = 1; let f = fn() { let g = fn() { k }; let r = g(); let k = 2; r }; f()",
+        "let len = fn(x) { 9 };
+        let f = fn() { let g = fn() { len(\"ab\") }; let a = g(); let len = fn(x) { 7 }; [a, g()] };
+        f()",
+        "throw \"boom\"",
+        "let f = fn() {\n  throw \"boom\";\n};\nf()",
+        "let inner = fn(x) {\n  x + true\n};\nlet outer = fn(n) {\n  inner(n)\n};\nouter(1);",
+        "let f = fn(n) { if (n == 0) { n + true } else { f(n - 1) } };\nf(3)",
+        "let g = fn(x) { x + true };\nlet f = fn(n) { if (n == 0) { g(n) } else { f(n - 1) } };\nf(3)",
+        "let g = fn(x) {\n  x / 0\n};\nmap([1], g)",
+        "try { 1 } catch (e) { e + true } 1 + null",
+        "[1, 2, 3][::0]",
+    ];
+
+    for input in tests.iter() {
+        assert_eq!(
+            describe(&test_run(input)),
+            describe(&test_eval(input)),
+            "input: {}",
+            input
+        );
+    }
+}
+
+#[test]
+fn test_tail_calls() {
+    let tests = [
+        (
+            "let count = fn(n, acc) { if (n == 0) { acc } else { count(n - 1, acc + 1) } };
+            count(50000, 0)",
+            50000,
+        ),
+        (
+            "let even = fn(n) { if (n == 0) { return true; } return odd(n - 1); };
+            let odd = fn(n) { if (n == 0) { return false; } return even(n - 1); };
+            if (even(50001)) { 1 } else { 0 }",
+            0,
+        ),
+        (
+            "let find = fn(n) { while (true) { if (n > 50000) { break; } return find(n + 1); } n };
+            find(0)",
+            50001,
+        ),
+        (
+            "let f = fn(n) { for (x in [1]) { if (n > 1000) { return n; } return f(n + 1); } 0 };
+            f(0)",
+            1001,
+        ),
+    ];
+
+    for (input, expected) in tests.iter() {
+        let mut vm = VM::new();
+        vm.set_max_call_depth(50);
+        assert_eq!(
+            run_with(&mut vm, input).inspect(),
+            expected.to_string(),
+            "input: {}",
+            input
+        );
+    }
+}
+
+#[test]
+fn test_call_depth_limit() {
+    let recurse = "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } };";
+    let run_limited = |input: &str| {
+        let mut vm = VM::new();
+        vm.set_max_call_depth(50);
+        run_with(&mut vm, input)
+    };
+
+    assert_eq!(run_limited(&format!("{} f(49)", recurse)).inspect(), "49");
+    assert_error(
+        &run_limited(&format!("{} f(50)", recurse)),
+        "maximum call depth of 50 exceeded",
+    );
+    assert_error(
+        &run_limited("let g = fn(xs) { map(xs, fn(x) { g(xs) }) }; g([1])"),
+        "maximum call depth of 50 exceeded",
+    );
+    assert_eq!(
+        run_limited(&format!(
+            "{} let r = 0; try {{ f(100); }} catch (e) {{ r = f(40); }} r",
+            recurse
+        ))
+        .inspect(),
+        "40"
+    );
+
+    let report = match run_limited(&format!("{}\nf(100)", recurse)) {
+        Object::Error(err) => err.report(),
+        other => panic!("object is not Error. got={}", other.inspect()),
+    };
+    assert_eq!(
+        report,
+        "error: maximum call depth of 50 exceeded\n  at 1:47\n  in f, called at 1:47\n  ... repeated 48 more times\n  in f, called at 2:2"
+    );
+
+    // The VM keeps its frames on the heap, so the default limit needs no large thread stack.
+    assert_error(
+        &test_run("let f = fn(n) { 1 + f(n + 1) }; f(0)"),
+        &format!(
+            "maximum call depth of {} exceeded",
+            evaluator::DEFAULT_MAX_CALL_DEPTH
+        ),
+    );
+}
+
+#[test]
+fn test_globals_persist_between_runs() {
+    let mut vm = VM::new();
+    let mut compiler = Compiler::new();
+
+    for (input, expected) in [
+        ("let x = 40;", "null"),
+        ("let f = fn() { x + y };", "null"),
+        (
+            "f()",
+            "error: identifier not found: y\n  at 1:20\n  in f, called at 1:2",
+        ),
+        ("let y = 2; f()", "42"),
+    ] {
+        compiler.compile(&parse(input)).unwrap();
+        let result = vm.run(&compiler.bytecode());
+        let (symbol_table, constants) = compiler.into_state();
+        compiler = Compiler::new_with_state(symbol_table, constants);
+
+        assert_eq!(describe(&result), expected, "input: {}", input);
+    }
+}
+
+#[test]
+fn test_budgets() {
+    let mut vm = VM::new();
+    vm.set_fuel(Some(1000));
+    match run_with(
+        &mut vm,
+        "let f = fn() { while (true) { } }; try { f(); } catch (e) { 1 }",
+    ) {
+        Object::Error(err) => {
+            assert_eq!(err.kind, object::ErrorKind::BudgetExhausted);
+            assert_eq!(err.message, "budget exhausted: out of fuel");
+        }
+        other => panic!("object is not Error. got={}", other.inspect()),
+    }
+    assert_eq!(vm.fuel(), Some(0));
+
+    let mut vm = VM::new();
+    vm.set_timeout(Some(Duration::from_millis(20)));
+    assert_error(
+        &run_with(&mut vm, "while (true) { }"),
+        "budget exhausted: deadline exceeded",
+    );
+    assert_error(
+        &run_with(&mut vm, "let x = range(0, 100000000); sort(reverse(x))"),
+        "budget exhausted: deadline exceeded",
+    );
+
+    let mut vm = VM::new();
+    vm.set_fuel(Some(20));
+    assert_error(
+        &run_with(&mut vm, "let x = range(0, 100000000); sort(reverse(x))"),
+        "budget exhausted: out of fuel",
+    );
+
+    let mut vm = VM::new();
+    vm.set_fuel(Some(1000));
+    assert_error(
+        &run_with(&mut vm, "let x = 2 ** 10000; x * x * x"),
+        "budget exhausted: out of fuel",
+    );
+
+    let mut vm = VM::new();
+    vm.set_max_integer_bits(Some(1 << 18));
+    assert_error(
+        &run_with(&mut vm, "let x = 2 ** 200000; x * x"),
+        "integer too large: over 262144 bits",
+    );
+
+    let tests = [
+        "2 ** 1000000000000",
+        "let s = \"x\"; while (true) { s += s; }",
+        "let a = []; while (true) { a = push(a, [1, 2, 3]); }",
+        "let h = {}; let i = 0; while (true) { h[i] = i; i += 1; }",
+        "map(0..1000000000000, fn(x) { x })",
+        "let s = \"x\"; try { while (true) { s += s; } } catch (e) { 0 }",
+    ];
+    for input in tests.iter() {
+        let mut vm = VM::new();
+        vm.set_memory_limit(Some(64 * 1024));
+        match run_with(&mut vm, input) {
+            Object::Error(err) => assert_eq!(err.kind, object::ErrorKind::OutOfMemory),
+            other => panic!("object is not Error. got={}", other.inspect()),
+        }
+    }
+
+    let mut vm = VM::new();
+    vm.set_memory_limit(Some(64 * 1024));
+    let input = "let total = 0;
+    for (i in 0..5000) {
+        let s = \"abcdefghijklmnopqrstuvwxyz\" + \"abcdefghijklmnopqrstuvwxyz\";
+        total += len(split(s, \"\"));
+    }
+    total";
+    assert_eq!(run_with(&mut vm, input).inspect(), "260000");
+
+    let mk = "let mk = fn(n) { let s = \"a\"; let i = 0; while (i < n) { s = s + s; i += 1; } s };";
+    let tests = [
+        format!("{} let a = [{}]; len(a)", mk, vec!["mk(12)"; 40].join(", ")),
+        format!("{} puts({})", mk, vec!["mk(12)"; 40].join(", ")),
+    ];
+    for input in tests.iter() {
+        let mut vm = VM::new();
+        vm.set_memory_limit(Some(64 * 1024));
+        match run_with(&mut vm, input) {
+            Object::Error(err) => assert_eq!(err.kind, object::ErrorKind::OutOfMemory),
+            other => panic!("object is not Error. got={}", other.inspect()),
+        }
+    }
+
+    // Most of the limit stays live, so garbage has to be recounted away before each refusal.
+    let mut vm = VM::new();
+    vm.set_memory_limit(Some(64 * 1024));
+    let input = format!(
+        "{} let kept = [mk(15), mk(14)];
+        let s = mk(10);
+        let i = 0;
+        while (i < 200) {{ let t = s + s + s + s + s; i += 1; }}
+        i",
+        mk
+    );
+    assert_eq!(run_with(&mut vm, &input).inspect(), "200");
+}
+
+// Collects trace output where the test can read it back.
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_trace() {
+    let buffer = SharedBuffer::default();
+    let mut vm = VM::new();
+    vm.set_trace(Some(Box::new(buffer.clone())));
+    assert_eq!(
+        run_with(&mut vm, "let f = fn(x) { x * 2 }; f(4) + 1").inspect(),
+        "9"
+    );
+
+    let trace = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+    let lines: Vec<&str> = trace.lines().collect();
+    assert_eq!(lines.len(), 12, "{}", trace);
+    assert_eq!(
+        lines[3],
+        format!("{:<12} {:<40} | 4", "<main>", "0009 OpConstant 2 ; 4")
+    );
+    assert_eq!(
+        lines[5],
+        format!("{:<12} {:<40} | 4", "f", "0000 OpGetLocal 0")
+    );
+    assert_eq!(lines[7], format!("{:<12} {:<40} | 8", "f", "0006 OpMul"));
+    assert_eq!(
+        lines[11],
+        format!("{:<12} {:<40} | 9", "<main>", "0018 OpReturnValue")
+    );
+}